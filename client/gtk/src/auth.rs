@@ -37,7 +37,13 @@ impl Client {
         device: DeviceId,
         token: AuthToken,
     ) -> Result<AuthenticatedWs> {
-        let request = serde_urlencoded::to_string(Login { device, token: token.clone() })
+        let login = Login {
+            device,
+            token: token.clone(),
+            protocol: Some(PROTOCOL_VERSION),
+            compression: true,
+        };
+        let request = serde_urlencoded::to_string(login)
             .expect("failed to encode authenticate request");
 
         let url = self.server.url().join(&format!("authenticate?{}", request))?;
@@ -165,6 +171,18 @@ impl Client {
         }
     }
 
+    /// Gets the limits the server puts on usernames, passwords and such
+    pub async fn account_rules(&self) -> Result<AccountRules> {
+        let url = self.server.url().join("account_rules")?;
+        let response = self.client.get(url.as_str().parse::<hyper::Uri>()?).await?;
+        let bytes = hyper::body::to_bytes(response.into_body()).await?;
+
+        match AuthResponse::from_protobuf_bytes(&bytes)?? {
+            AuthOk::AccountRules(rules) => Ok(rules),
+            _ => Err(Error::UnexpectedMessage),
+        }
+    }
+
     async fn post_auth(&self, request: AuthRequest, url: Url) -> Result<AuthResponse> {
         let request = hyper::Request::builder()
             .uri(url.as_str().parse::<hyper::Uri>()?)
@@ -1,12 +1,16 @@
+use std::pin::Pin;
 use std::rc::Rc;
 use std::sync::Mutex;
 
+use chrono::{DateTime, Utc};
 use futures::{FutureExt, Stream, StreamExt};
 use futures::future::{Abortable, AbortHandle};
-use futures::channel::mpsc::{self, UnboundedSender};
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
 
+pub use avatar::*;
 pub use chat::*;
 pub use community::*;
+pub use member::*;
 pub use message::*;
 pub use notification::*;
 pub use profile::*;
@@ -14,13 +18,16 @@ pub use room::*;
 pub use user::*;
 use vertex::prelude::*;
 
-use crate::{config, net, scheduler, screen, SharedMut, WeakSharedMut, window};
+use crate::config::NotificationLevel;
+use crate::{auth, config, net, scheduler, screen, Server, SharedMut, WeakSharedMut, window};
 use crate::{Error, Result};
 use url::Url;
 use crate::screen::active::dialog::show_generic_error;
 use crate::screen::active::Ui;
 
+mod avatar;
 mod community;
+mod member;
 mod room;
 mod user;
 mod message;
@@ -28,8 +35,20 @@ mod profile;
 mod chat;
 mod notification;
 
+pub mod command;
+
 pub const HEARTBEAT_INTERVAL: tokio::time::Duration = tokio::time::Duration::from_secs(2);
 
+/// Most rooms asked for in one room updates request, matching the server's default limit
+const ROOM_UPDATE_BATCH_SIZE: usize = 50;
+
+/// How long to wait before first trying to reconnect after losing connection. This doubles with
+/// every failed attempt, up to `RECONNECT_MAX_DELAY`.
+const RECONNECT_MIN_DELAY: tokio::time::Duration = tokio::time::Duration::from_secs(1);
+const RECONNECT_MAX_DELAY: tokio::time::Duration = tokio::time::Duration::from_secs(60);
+
+type EventStream = Pin<Box<dyn Stream<Item = tungstenite::Result<ServerEvent>>>>;
+
 lazy_static::lazy_static! {
     /// Channel through which messages to the invite-listener are sent, to allow for following invite
     /// links from other apps through the `vertex://` protocol
@@ -62,8 +81,11 @@ pub struct ClientState {
     pub communities: Vec<CommunityEntry>,
     pub chat: Option<Chat>,
     pub selected_room: Option<RoomEntry>,
+    pub members: Option<MemberList>,
     pub message_entry_is_empty: bool,
     pub admin_perms: AdminPermissionFlags,
+    /// Longest message the server accepts, in bytes
+    pub max_message_len: u32,
 }
 
 #[derive(Clone)]
@@ -73,6 +95,7 @@ pub struct Client {
     pub ui: Ui,
     pub user: User,
     pub profiles: ProfileCache,
+    pub avatars: AvatarCache,
     pub embeds: EmbedCache,
 
     notifier: Notifier,
@@ -83,7 +106,7 @@ pub struct Client {
 }
 
 impl Client {
-    pub async fn start(ws: net::AuthenticatedWs, ui: Ui, https: bool) -> Result<Client> {
+    pub async fn start(ws: net::AuthenticatedWs, ui: Ui, server: &Server) -> Result<Client> {
         let (sender, receiver) = net::from_ws(ws.stream);
 
         let req_manager = net::RequestManager::new();
@@ -91,7 +114,7 @@ impl Client {
         let request = req_manager.sender(sender);
         let request = Rc::new(request);
 
-        let mut event_receiver = req_manager.receive_from(receiver);
+        let mut event_receiver: EventStream = Box::pin(req_manager.receive_from(receiver));
 
         let ready = client_ready(&mut event_receiver).await?;
 
@@ -104,25 +127,30 @@ impl Client {
         );
 
         let profiles = ProfileCache::new(request.clone(), user.clone());
+        let avatars = AvatarCache::new(server.url().clone());
         let embeds = EmbedCache::new();
 
         let state = SharedMut::new(ClientState {
             communities: Vec::new(),
             chat: None,
             selected_room: None,
+            members: None,
             message_entry_is_empty: true,
             admin_perms: ready.admin_permissions,
+            max_message_len: ready.max_message_len,
         });
 
         let (abort_signal, abort_handle) = futures::future::abortable(futures::future::pending());
+        let (activated_tx, activated_rx) = mpsc::unbounded();
 
         let client = Client {
             request,
             ui,
             user,
             profiles,
+            avatars,
             embeds,
-            notifier: Notifier::new(),
+            notifier: Notifier::new(activated_tx),
             abort_handle,
             state: state.downgrade(),
         };
@@ -134,11 +162,19 @@ impl Client {
             client.add_community(community).await;
         }
 
+        if let Some((community, room)) = ready.selected_room {
+            if let Some(community) = client.community_by_id(community).await {
+                community.select_room_by_id(room).await;
+            }
+        }
+
         scheduler::spawn(ClientLoop {
             client: client.clone(),
-            https,
+            server: server.clone(),
+            req_manager,
             event_receiver,
             abort_signal,
+            notification_activated: activated_rx,
             _state: state,
         }.run());
 
@@ -150,22 +186,46 @@ impl Client {
             ServerEvent::AddCommunity(structure) => {
                 self.add_community(structure).await;
             }
+            ServerEvent::RemoveCommunity { id, .. } => self.remove_community(id).await,
             ServerEvent::AddRoom { community, structure } => self.handle_add_room(community, structure).await,
             ServerEvent::AddMessage { community, room, message } => self.handle_add_message(community, room, message).await,
             ServerEvent::SessionLoggedOut => {
+                self.stop().await;
                 let screen = screen::login::build().await;
                 window::set_screen(&screen.main);
-                self.abort_handle.abort();
             }
             ServerEvent::AdminPermissionsChanged(new_perms) => {
                 let state = self.state.upgrade().unwrap();
                 state.write().await.admin_perms = new_perms;
             }
+            ServerEvent::CommunityUpdated { id, name, description, icon } => {
+                self.handle_community_updated(id, name, description, icon).await
+            }
+            ServerEvent::RoomUpdated { community, room, topic, slow_mode_secs, archived } => {
+                self.handle_room_updated(community, room, topic, slow_mode_secs, archived).await
+            }
+            ServerEvent::Mentioned { community, room, author, .. } => {
+                self.handle_mentioned(community, room, author).await
+            }
+            ServerEvent::PinsUpdated { community, room } => {
+                self.handle_pins_updated(community, room).await
+            }
+            ServerEvent::RoomReadStateChanged { community, room, last_read } => {
+                self.handle_room_read_state_changed(community, room, last_read).await
+            }
+            ServerEvent::RoomsReadStateChanged(rooms) => {
+                for RoomReadState { community, room, last_read } in rooms {
+                    self.handle_room_read_state_changed(community, room, last_read).await;
+                }
+            }
+            ServerEvent::PresenceUpdate { user, status } => {
+                self.handle_presence_update(user, status).await
+            }
             unexpected => log::warn!("unhandled server event: {:?}", unexpected),
         }
     }
 
-    async fn handle_network_err(&self, err: tungstenite::Error) {
+    async fn handle_network_err(&self, err: Error) {
         log::warn!("network error: {:?}", err);
 
         let error = format!("{}", err);
@@ -183,6 +243,98 @@ impl Client {
         }
     }
 
+    async fn handle_community_updated(
+        &self,
+        id: CommunityId,
+        name: String,
+        description: String,
+        icon: Option<AttachmentId>,
+    ) {
+        if let Some(community) = self.community_by_id(id).await {
+            community.widget.set_details(&name, &description);
+            community.state.write().await.name = name;
+
+            if let Some(icon) = icon {
+                self.load_community_icon(&community, icon);
+            }
+        } else {
+            log::warn!("received CommunityUpdated for invalid community: {:?}", id);
+        }
+    }
+
+    async fn handle_room_updated(
+        &self,
+        community: CommunityId,
+        room: RoomId,
+        topic: Option<String>,
+        slow_mode_secs: u32,
+        archived: bool,
+    ) {
+        let community = match self.community_by_id(community).await {
+            Some(community) => community,
+            None => {
+                log::warn!("received RoomUpdated for invalid community: {:?}", community);
+                return;
+            }
+        };
+
+        match community.room_by_id(room).await {
+            Some(room) => room.set_details(topic, slow_mode_secs, archived).await,
+            None => log::warn!("received RoomUpdated for invalid room: {:?}", room),
+        }
+    }
+
+    async fn handle_pins_updated(&self, community: CommunityId, room: RoomId) {
+        let community = match self.community_by_id(community).await {
+            Some(community) => community,
+            None => {
+                log::warn!("received PinsUpdated for invalid community: {:?}", community);
+                return;
+            }
+        };
+
+        match community.room_by_id(room).await {
+            Some(room) => room.invalidate_pins().await,
+            None => log::warn!("received PinsUpdated for invalid room: {:?}", room),
+        }
+    }
+
+    async fn handle_room_read_state_changed(
+        &self,
+        community: CommunityId,
+        room: RoomId,
+        last_read: Option<MessageId>,
+    ) {
+        let community = match self.community_by_id(community).await {
+            Some(community) => community,
+            None => {
+                log::warn!("received RoomReadStateChanged for invalid community: {:?}", community);
+                return;
+            }
+        };
+
+        match community.room_by_id(room).await {
+            Some(room) => room.set_last_read(last_read).await,
+            None => log::warn!("received RoomReadStateChanged for invalid room: {:?}", room),
+        }
+    }
+
+    fn load_community_icon(&self, community: &CommunityEntry, icon: AttachmentId) {
+        let avatars = self.avatars.clone();
+        let widget = community.widget.clone();
+        scheduler::spawn(async move {
+            if let Some(icon) = avatars.get(icon).await {
+                widget.set_icon(&icon);
+            }
+        });
+    }
+
+    async fn handle_presence_update(&self, user: UserId, status: PresenceStatus) {
+        if let Some(members) = self.member_list().await {
+            members.set_online(user, status != PresenceStatus::Offline).await;
+        }
+    }
+
     async fn handle_add_message(&self, community: CommunityId, room: RoomId, message: Message) {
         if let Some(community) = self.community_by_id(community).await {
             if let Some(room) = community.room_by_id(room).await {
@@ -192,9 +344,14 @@ impl Client {
                 // Read it out if looking at the room, but in short form
                 let a11y_narration = focused && selected && config::get().narrate_new_messages;
 
-                if (!focused || !selected) || a11y_narration {
+                // Mentions get their own notification through the Mentioned event
+                let mentions_us = self.is_mentioned_in(&message).await;
+                let watching = self.notification_level(&room).await == WatchLevel::All;
+
+                if ((!focused || !selected) && watching && !mentions_us) || a11y_narration {
                     let profile = self.profiles.get_or_default(message.author, message.author_profile_version).await;
                     self.notifier.notify_message(
+                        (room.community, room.id),
                         &profile,
                         &community.state.read().await.name,
                         &room.name,
@@ -216,6 +373,67 @@ impl Client {
         log::warn!("received message for invalid room: {:?}#{:?}", community, room);
     }
 
+    async fn handle_mentioned(&self, community: CommunityId, room: RoomId, author: UserId) {
+        if self.ui.window_focused() && self.is_selected(community, room).await {
+            return;
+        }
+
+        let community = match self.community_by_id(community).await {
+            Some(community) => community,
+            None => {
+                log::warn!("received Mentioned for invalid community: {:?}", community);
+                return;
+            }
+        };
+
+        let room = match community.room_by_id(room).await {
+            Some(room) => room,
+            None => {
+                log::warn!("received Mentioned for invalid room: {:?}", room);
+                return;
+            }
+        };
+
+        if self.notification_level(&room).await == WatchLevel::Nothing {
+            return;
+        }
+
+        let profile = match self.profiles.get_existing(author, None).await {
+            Some(profile) => profile,
+            None => self.profiles.get_or_default(author, ProfileVersion(0)).await,
+        };
+
+        self.notifier.notify_mention(
+            (room.community, room.id),
+            &profile,
+            &community.state.read().await.name,
+            &room.name,
+        ).await;
+    }
+
+    /// What to notify the user of in the room: the stricter of the room's watch level and the
+    /// notification level set locally for its community
+    async fn notification_level(&self, room: &RoomEntry) -> WatchLevel {
+        let community = config::get().notification_level(room.community);
+        match (room.watch_level().await, community) {
+            (WatchLevel::Nothing, _) | (_, NotificationLevel::Nothing) => WatchLevel::Nothing,
+            (WatchLevel::MentionsOnly, _) | (_, NotificationLevel::Mentions) => {
+                WatchLevel::MentionsOnly
+            }
+            (WatchLevel::All, NotificationLevel::All) => WatchLevel::All,
+        }
+    }
+
+    async fn is_mentioned_in(&self, message: &Message) -> bool {
+        let username = self.user.profile().await.username.to_lowercase();
+        match &message.content {
+            Some(content) => {
+                mentioned_usernames(content).any(|name| name.to_lowercase() == username)
+            }
+            None => false,
+        }
+    }
+
     pub async fn create_community(&self, name: &str) -> Result<CommunityEntry> {
         let request = ClientRequest::CreateCommunity { name: name.to_owned() };
         let request = self.request.send(request).await;
@@ -248,6 +466,10 @@ impl Client {
 
         entry.widget.bind_events(&entry);
 
+        if let Some(icon) = community.icon {
+            self.load_community_icon(&entry, icon);
+        }
+
         for room in community.rooms {
             entry.add_room(room).await;
         }
@@ -261,6 +483,37 @@ impl Client {
         }
     }
 
+    pub async fn leave_community(&self, id: CommunityId) -> Result<()> {
+        let request = self.request.send(ClientRequest::LeaveCommunity(id)).await;
+        request.response().await?;
+
+        self.remove_community(id).await;
+        Ok(())
+    }
+
+    async fn remove_community(&self, id: CommunityId) {
+        let state = match self.state.upgrade() {
+            Some(state) => state,
+            None => return,
+        };
+
+        let mut state = state.write().await;
+        let idx = match state.communities.iter().position(|community| community.id == id) {
+            Some(idx) => idx,
+            None => return,
+        };
+
+        let community = state.communities.remove(idx);
+        self.ui.remove_community(&community.widget);
+
+        let selected_here = state.selected_room.as_ref().map_or(false, |room| room.community == id);
+        drop(state);
+
+        if selected_here {
+            self.deselect_room().await;
+        }
+    }
+
     pub async fn community_by_id(&self, id: CommunityId) -> Option<CommunityEntry> {
         match self.state.upgrade() {
             Some(state) => {
@@ -273,6 +526,11 @@ impl Client {
     }
 
     pub async fn select_room(&self, room: RoomEntry) {
+        let community = match self.member_list().await {
+            Some(members) if members.community.id == room.community => None,
+            _ => self.community_by_id(room.community).await,
+        };
+
         let chat = self.ui.select_room(&room);
         let chat = Chat::new(
             self.clone(),
@@ -293,17 +551,33 @@ impl Client {
             }
         }
 
+        if let Some(community) = community {
+            self.show_members(community).await;
+        }
+
         self.request.send(ClientRequest::SelectRoom {
             community: room.community,
             room: room.id,
         }).await;
     }
 
+    /// Brings the window to the front with the room selected, such as when its notification is
+    /// clicked
+    pub async fn focus_room(&self, community: CommunityId, room: RoomId) {
+        window::present();
+        window::set_screen(&self.ui.main);
+
+        if let Some(community) = self.community_by_id(community).await {
+            community.select_room_by_id(room).await;
+        }
+    }
+
     pub async fn deselect_room(&self) {
         if let Some(state) = self.state.upgrade() {
             let mut state = state.write().await;
             state.selected_room = None;
             state.chat = None;
+            state.members = None;
         }
 
         self.ui.deselect_room();
@@ -318,6 +592,14 @@ impl Client {
         }
     }
 
+    /// Longest message the server accepts, in bytes
+    pub async fn max_message_len(&self) -> usize {
+        match self.state.upgrade() {
+            Some(state) => state.read().await.max_message_len as usize,
+            None => 0,
+        }
+    }
+
     pub async fn selected_room(&self) -> Option<RoomEntry> {
         match self.state.upgrade() {
             Some(state) => {
@@ -328,6 +610,30 @@ impl Client {
         }
     }
 
+    /// Shows the first page of the community's members in the member list
+    async fn show_members(&self, community: CommunityEntry) {
+        let widget = self.ui.show_members();
+        let members = MemberList::new(self.clone(), widget, community);
+
+        if let Some(state) = self.state.upgrade() {
+            state.write().await.members = Some(members.clone());
+        }
+
+        if let Err(err) = members.load_more().await {
+            log::warn!("failed to load community members: {:?}", err);
+        }
+    }
+
+    pub async fn member_list(&self) -> Option<MemberList> {
+        match self.state.upgrade() {
+            Some(state) => {
+                let state = state.read().await;
+                state.members.as_ref().cloned()
+            }
+            None => None,
+        }
+    }
+
     pub async fn is_selected(&self, community: CommunityId, room: RoomId) -> bool {
         match self.selected_room().await {
             Some(selected) => selected.id == room && selected.community == community,
@@ -335,6 +641,115 @@ impl Client {
         }
     }
 
+    /// Switches over to a new connection after reconnecting, adding any communities which were
+    /// joined from other devices while the connection was down
+    async fn resume(
+        &self,
+        ws: net::AuthenticatedWs,
+        req_manager: &net::RequestManager,
+    ) -> Result<EventStream> {
+        let (sender, receiver) = net::from_ws(ws.stream);
+        self.request.net().replace(sender);
+
+        let mut event_receiver: EventStream = Box::pin(req_manager.receive_from(receiver));
+        let ready = client_ready(&mut event_receiver).await?;
+
+        if let Some(state) = self.state.upgrade() {
+            state.write().await.max_message_len = ready.max_message_len;
+        }
+
+        for community in ready.communities {
+            if self.community_by_id(community.id).await.is_none() {
+                self.add_community(community).await;
+            }
+        }
+
+        // The new session does not know which room is being looked at until it is told again
+        if let Some(room) = self.selected_room().await {
+            self.request.send(ClientRequest::SelectRoom {
+                community: room.community,
+                room: room.id,
+            }).await;
+        }
+
+        Ok(event_receiver)
+    }
+
+    /// Sends the messages which could not be sent while the connection was down
+    async fn retry_outboxes(&self) {
+        let communities = match self.state.upgrade() {
+            Some(state) => state.read().await.communities.clone(),
+            None => return,
+        };
+
+        for community in communities {
+            for room in community.rooms().await {
+                scheduler::spawn(async move { room.retry_outbox().await });
+            }
+        }
+    }
+
+    /// Fetches any history missed while the connection was down, comparing the newest message of
+    /// each room with what we have
+    pub async fn resync(&self) -> Result<()> {
+        let request = self.request.send(ClientRequest::Resync).await;
+        let rooms = match request.response().await? {
+            OkResponse::Resync(rooms) => rooms,
+            _ => return Err(Error::UnexpectedMessage),
+        };
+
+        let mut stale = Vec::new();
+        for sync in rooms {
+            let room = match self.community_by_id(sync.community).await {
+                Some(community) => community.room_by_id(sync.room).await,
+                None => None,
+            };
+
+            let room = match room {
+                Some(room) => room,
+                None => {
+                    log::warn!("received Resync for unknown room: {:?}", sync.room);
+                    continue;
+                }
+            };
+
+            if room.newest_message().await != sync.newest_message {
+                stale.push(room);
+            }
+        }
+
+        for batch in stale.chunks(ROOM_UPDATE_BATCH_SIZE) {
+            let mut rooms = Vec::with_capacity(batch.len());
+            for room in batch {
+                rooms.push(room.update_request().await);
+            }
+
+            let request = self.request.send(ClientRequest::GetRoomUpdates {
+                rooms,
+                message_count: MESSAGE_PAGE_SIZE as u64,
+            }).await;
+
+            let updates = match request.response().await? {
+                OkResponse::RoomUpdates(updates) => updates,
+                _ => return Err(Error::UnexpectedMessage),
+            };
+
+            for (room, update) in batch.iter().zip(updates) {
+                match self.chat_for(room.id).await {
+                    Some(chat) => chat.update(update).await,
+                    None => room.update(&update).await,
+                }
+            }
+        }
+
+        // Presence updates were missed too, so the member list may be out of date
+        if let Some(members) = self.member_list().await {
+            self.show_members(members.community).await;
+        }
+
+        Ok(())
+    }
+
     pub async fn chat_for(&self, room: RoomId) -> Option<Chat> {
         match self.chat().await {
             Some(chat) if chat.accepts(room) => Some(chat),
@@ -352,6 +767,12 @@ impl Client {
         }
     }
 
+    /// Disconnects and stops handling events, such as before switching to another account
+    pub async fn stop(&self) {
+        self.abort_handle.abort();
+        self.request.net().close().await;
+    }
+
     pub async fn log_out(&self) {
         self.request.send(ClientRequest::LogOut).await;
     }
@@ -386,6 +807,16 @@ impl Client {
         }
     }
 
+    pub async fn get_audit_log(&self, after: Option<i64>, limit: u32) -> Result<Vec<AuditEvent>> {
+        let req = ClientRequest::AdminAction(AdminRequest::GetAuditLog { after, limit });
+        let req = self.request.send(req).await;
+
+        match req.response().await? {
+            OkResponse::Admin(AdminResponse::AuditLog(events)) => Ok(events),
+            _ => Err(Error::UnexpectedMessage)
+        }
+    }
+
     pub async fn search_reports(&self, criteria: SearchCriteria) -> Result<Vec<Report>> {
         let req = ClientRequest::AdminAction(AdminRequest::SearchForReports(criteria));
         let req = self.request.send(req).await;
@@ -416,10 +847,19 @@ impl Client {
         Ok(results)
     }
 
-    pub async fn ban_users(&self, users: Vec<UserId>) -> Result<Vec<(UserId, Error)>> {
+    pub async fn ban_users(
+        &self,
+        users: Vec<UserId>,
+        reason: &str,
+        until: Option<DateTime<Utc>>,
+    ) -> Result<Vec<(UserId, Error)>> {
         self.do_to_many(
             users,
-            |user| ClientRequest::AdminAction(AdminRequest::Ban(user))
+            |user| ClientRequest::AdminAction(AdminRequest::Ban {
+                user,
+                reason: reason.to_string(),
+                until,
+            })
         ).await
     }
 
@@ -486,6 +926,15 @@ impl Client {
         }
     }
 
+    pub async fn resolve_report(&self, id: i32, action: ReportAction) -> Result<()> {
+        let request = ClientRequest::AdminAction(AdminRequest::ResolveReport { id, action });
+        let request = self.request.send(request).await;
+        match request.response().await? {
+            OkResponse::NoData => Ok(()),
+            _ => Err(Error::UnexpectedMessage),
+        }
+    }
+
     pub async fn set_compromised(&self, typ: SetCompromisedType) -> Result<()> {
         let request = ClientRequest::AdminAction(AdminRequest::SetAccountsCompromised(typ));
         let request = self.request.send(request).await;
@@ -496,48 +945,21 @@ impl Client {
     }
 }
 
-struct ClientLoop<S> {
+struct ClientLoop {
     client: Client,
-    https: bool,
-    event_receiver: S,
+    server: Server,
+    req_manager: net::RequestManager,
+    event_receiver: EventStream,
     abort_signal: Abortable<futures::future::Pending<()>>,
+    /// Rooms of the notifications which have been clicked
+    notification_activated: UnboundedReceiver<(CommunityId, RoomId)>,
     _state: SharedMut<ClientState>,
 }
 
-impl<S> ClientLoop<S>
-    where S: Stream<Item=tungstenite::Result<ServerEvent>> + Unpin
-{
+impl ClientLoop {
     async fn run(self) {
         let client = &self.client;
-        let https = self.https;
-        let event_receiver = self.event_receiver;
-
-        let request = client.request.clone();
-
-        let mut receiver = Box::pin(
-            async move {
-                let mut event_receiver = event_receiver;
-                while let Some(result) = event_receiver.next().await {
-                    let client = client.clone();
-                    scheduler::spawn(async move {
-                        match result {
-                            Ok(event) => client.handle_event(event).await,
-                            Err(err) => client.handle_network_err(err).await,
-                        }
-                    });
-                }
-            }.fuse()
-        );
-
-        let mut keep_alive = Box::pin(
-            async move {
-                let mut ticker = tokio::time::interval(HEARTBEAT_INTERVAL);
-                loop {
-                    request.net().ping().await;
-                    ticker.tick().await;
-                }
-            }.fuse()
-        );
+        let https = self.server.url().scheme() == "https";
 
         let (invite_tx, mut invite_rx) = mpsc::unbounded();
         *INVITE_SENDER.lock().unwrap() = Some(invite_tx);
@@ -565,11 +987,107 @@ impl<S> ClientLoop<S>
             }.fuse()
         );
 
-        futures::select! {
-            _ = keep_alive => {},
-            _ = invite_listener => {},
-            _ = receiver => {},
-            _ = self.abort_signal.fuse() => {}
+        let mut notification_activated = self.notification_activated;
+        let mut notification_listener = Box::pin(
+            async move {
+                while let Some((community, room)) = notification_activated.next().await {
+                    client.focus_room(community, room).await;
+                }
+            }.fuse()
+        );
+
+        let mut abort_signal = self.abort_signal.fuse();
+        let mut event_receiver = self.event_receiver;
+
+        loop {
+            let mut receiver = Box::pin(receive_events(client, event_receiver).fuse());
+            let mut keep_alive = Box::pin(keep_alive(client.request.clone()).fuse());
+
+            let err = futures::select! {
+                err = receiver => err,
+                _ = keep_alive => return,
+                _ = invite_listener => return,
+                _ = notification_listener => return,
+                _ = abort_signal => return,
+            };
+
+            log::warn!("lost connection: {:?}", err);
+            client.ui.set_reconnecting(true);
+
+            let mut reconnect = Box::pin(
+                reconnect(client, &self.server, &self.req_manager).fuse()
+            );
+
+            event_receiver = futures::select! {
+                result = reconnect => match result {
+                    Ok(event_receiver) => event_receiver,
+                    Err(err) => {
+                        client.handle_network_err(err).await;
+                        return;
+                    }
+                },
+                _ = abort_signal => return,
+            };
+
+            client.ui.set_reconnecting(false);
+
+            if let Err(err) = client.resync().await {
+                log::warn!("failed to resync after reconnecting: {:?}", err);
+            }
+
+            client.retry_outboxes().await;
         }
     }
 }
+
+/// Handles events until the connection fails, returning why it did
+async fn receive_events(client: &Client, event_receiver: EventStream) -> tungstenite::Error {
+    let mut event_receiver = event_receiver;
+    while let Some(result) = event_receiver.next().await {
+        match result {
+            Ok(event) => {
+                let client = client.clone();
+                scheduler::spawn(async move { client.handle_event(event).await });
+            }
+            Err(err) => return err,
+        }
+    }
+
+    tungstenite::Error::ConnectionClosed
+}
+
+async fn keep_alive(request: Rc<net::RequestSender>) {
+    let mut ticker = tokio::time::interval(HEARTBEAT_INTERVAL);
+    loop {
+        request.net().ping().await;
+        ticker.tick().await;
+    }
+}
+
+/// Logs in again with the client's token, retrying with exponential backoff until the server can
+/// be reached. Only gives up if the server refuses the token.
+async fn reconnect(
+    client: &Client,
+    server: &Server,
+    req_manager: &net::RequestManager,
+) -> Result<EventStream> {
+    let auth = auth::Client::new(server.clone());
+    let mut delay = RECONNECT_MIN_DELAY;
+
+    loop {
+        tokio::time::delay_for(delay).await;
+
+        let result = match auth.login(client.user.device(), client.user.token()).await {
+            Ok(ws) => client.resume(ws, req_manager).await,
+            Err(err) => Err(err),
+        };
+
+        match result {
+            Ok(event_receiver) => return Ok(event_receiver),
+            Err(err @ Error::AuthErrorResponse(_)) => return Err(err),
+            Err(err) => log::warn!("failed to reconnect: {:?}", err),
+        }
+
+        delay = (delay * 2).min(RECONNECT_MAX_DELAY);
+    }
+}
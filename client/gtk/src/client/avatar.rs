@@ -0,0 +1,68 @@
+use std::collections::HashMap;
+
+use gdk_pixbuf::InterpType;
+use gio::Cancellable;
+use url::Url;
+
+use vertex::prelude::*;
+
+use crate::{Error, Result, SharedMut};
+
+use super::message::req;
+
+pub const AVATAR_SIZE: i32 = 40;
+const MAX_AVATAR_BYTES: usize = 8 * 1024 * 1024;
+
+/// Cache of user avatars and community icons, scaled down to icon size
+// TODO: drop old entries
+#[derive(Clone)]
+pub struct AvatarCache {
+    server: Url,
+    cache: SharedMut<HashMap<AttachmentId, Option<gdk_pixbuf::Pixbuf>>>,
+}
+
+impl AvatarCache {
+    pub fn new(server: Url) -> AvatarCache {
+        AvatarCache {
+            server,
+            cache: SharedMut::new(HashMap::new()),
+        }
+    }
+
+    pub async fn get(&self, id: AttachmentId) -> Option<gdk_pixbuf::Pixbuf> {
+        if let Some(avatar) = self.get_existing(id).await {
+            return avatar;
+        }
+
+        let avatar = match self.load(id).await {
+            Ok(avatar) => Some(avatar),
+            Err(err) => {
+                log::warn!("failed to load avatar {:?}: {:?}", id, err);
+                None
+            }
+        };
+
+        let mut cache = self.cache.write().await;
+        cache.insert(id, avatar.clone());
+
+        avatar
+    }
+
+    async fn get_existing(&self, id: AttachmentId) -> Option<Option<gdk_pixbuf::Pixbuf>> {
+        let cache = self.cache.read().await;
+        cache.get(&id).cloned()
+    }
+
+    async fn load(&self, id: AttachmentId) -> Result<gdk_pixbuf::Pixbuf> {
+        // The server url points at the client endpoints, which sit beside the attachments
+        let url = self.server.join(&format!("../attachment/{}", id.0))?;
+        let bytes = req(url.as_str(), MAX_AVATAR_BYTES).await?;
+
+        let bytes = glib::Bytes::from_owned(bytes);
+        let input_stream = gio::MemoryInputStream::new_from_bytes(&bytes);
+        let pixbuf = gdk_pixbuf::Pixbuf::new_from_stream(&input_stream, None::<&Cancellable>)?;
+
+        pixbuf.scale_simple(AVATAR_SIZE, AVATAR_SIZE, InterpType::Bilinear)
+            .ok_or(Error::InvalidImage)
+    }
+}
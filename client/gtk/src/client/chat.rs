@@ -1,39 +1,23 @@
-use std::collections::LinkedList;
+use std::collections::{HashMap, LinkedList};
 use std::rc::Rc;
 use std::sync::atomic::{AtomicBool, Ordering};
 
+use vertex::formatting;
 use vertex::prelude::*;
 
 use crate::{Client, SharedMut, Result, scheduler};
-use crate::client::RoomEntry;
+use crate::client::{OutgoingMessage, RoomEntry};
 use crate::screen::active::message::MessageEntryWidget;
 use crate::screen::active::ChatWidget;
 
 use super::message::*;
-use uuid::Uuid;
+use chrono::Utc;
 
 pub const MESSAGE_DROP_THRESHOLD: usize = MESSAGE_PAGE_SIZE * 4;
 pub const MESSAGE_DROP_COUNT: usize = MESSAGE_PAGE_SIZE * 2;
 
-pub struct PendingMessageHandle<'a> {
-    chat: &'a Chat,
-    widget: MessageEntryWidget,
-    fake_id: MessageId,
-}
-
-impl<'a> PendingMessageHandle<'a> {
-    pub async fn upgrade(self, message: Message) {
-        let mut state = self.chat.state.write().await;
-        state.widget.remove_message(self.fake_id);
-        drop(state);
-
-        self.chat.push(message).await;
-    }
-
-    pub fn set_error(self) {
-        self.widget.set_status(MessageStatus::Err);
-    }
-}
+/// How often the relative times shown on messages are updated
+const TIMESTAMP_REFRESH_INTERVAL: tokio::time::Duration = tokio::time::Duration::from_secs(30);
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum ChatSide {
@@ -52,6 +36,14 @@ pub struct ChatState {
     client: Client,
     widget: ChatWidget,
     entries: LinkedList<ChatEntry>,
+    /// Whether the oldest message in the room is shown, so there is nothing older to load
+    reached_oldest: bool,
+    /// Whether older or newer messages are being loaded. Further requests to load them while one is
+    /// in flight are dropped, since it will load the same messages.
+    loading_older: bool,
+    loading_newer: bool,
+    /// Widgets of the messages in the room's outbox, by their local IDs
+    pending: HashMap<MessageId, MessageEntryWidget>,
 }
 
 impl ChatState {
@@ -60,6 +52,10 @@ impl ChatState {
             client,
             widget,
             entries: LinkedList::new(),
+            reached_oldest: false,
+            loading_older: false,
+            loading_newer: false,
+            pending: HashMap::new(),
         }
     }
 
@@ -110,6 +106,7 @@ impl ChatState {
 
         let mut dropped = match side {
             ChatSide::Front => {
+                self.reached_oldest = false;
                 self.entries.split_off(self.entries.len() - MESSAGE_DROP_COUNT)
             }
             ChatSide::Back => {
@@ -126,6 +123,8 @@ impl ChatState {
     fn clear(&mut self) {
         self.widget.clear();
         self.entries.clear();
+        self.pending.clear();
+        self.reached_oldest = false;
     }
 
     fn flush(&mut self) {
@@ -162,16 +161,54 @@ impl Chat {
             reading_new: Rc::new(AtomicBool::new(true)),
         };
         chat.extend(history, ChatSide::Front).await;
+        chat.show_outbox().await;
+
+        // Stops once the chat is closed and its state dropped
+        let state = chat.state.downgrade();
+        scheduler::spawn(async move {
+            let mut interval = tokio::time::interval(TIMESTAMP_REFRESH_INTERVAL);
+            interval.tick().await;
+
+            loop {
+                interval.tick().await;
+                match state.upgrade() {
+                    Some(state) => state.read().await.widget.refresh_timestamps(),
+                    None => break,
+                }
+            }
+        });
 
         chat
     }
 
     async fn build_content(&self, message: &Message) -> MessageContent {
+        let reply = match &message.reply_to {
+            Some(reply_to) => Some(self.build_reply_quote(reply_to).await),
+            None => None,
+        };
+
         MessageContent {
             author: message.author,
             profile: self.client.profiles.get_or_default(message.author, message.author_profile_version).await,
             text: message.content.clone(),
             time: message.time_sent,
+            reply,
+            kind: message.kind,
+        }
+    }
+
+    async fn build_reply_quote(&self, reply_to: &ReplyPreview) -> String {
+        let author = match self.client.profiles.get_existing(reply_to.author, None).await {
+            Some(profile) => profile.display_name,
+            None => "Unknown user".to_string(),
+        };
+
+        match &reply_to.content {
+            Some(content) => {
+                let content = formatting::plain_text(&formatting::parse(content));
+                format!("Replying to {}: {}", author, content)
+            }
+            None => format!("Replying to {}: message deleted", author),
         }
     }
 
@@ -186,19 +223,58 @@ impl Chat {
         widget
     }
 
-    pub async fn push_pending(&self, content: MessageContent) -> PendingMessageHandle<'_> {
-        let mut state = self.state.write().await;
+    /// Shows a message from the room's outbox, which has not been confirmed by the server yet
+    pub async fn push_pending(&self, message: &OutgoingMessage) {
+        let content = MessageContent {
+            author: self.client.user.id,
+            profile: self.client.user.profile().await,
+            text: Some(message.content.clone()),
+            time: Utc::now(),
+            reply: None,
+            kind: MessageKind::Normal,
+        };
 
-        let fake_id = MessageId(Uuid::new_v4()); // Chance of collision is too small
-        let widget = state.push_widget(content, ChatSide::Front, fake_id);
+        let mut state = self.state.write().await;
+        let widget = state.push_widget(content, ChatSide::Front, message.id);
         state.flush();
 
-        widget.set_status(MessageStatus::Pending);
+        if message.failed {
+            widget.set_send_failed(self.room.clone(), message.id);
+        } else {
+            widget.set_status(MessageStatus::Pending);
+        }
 
-        PendingMessageHandle {
-            chat: self,
-            widget,
-            fake_id,
+        state.pending.insert(message.id, widget);
+    }
+
+    /// Replaces a pending message with the message the server confirmed
+    pub async fn confirm_pending(&self, id: MessageId, message: Message) {
+        self.remove_pending(id).await;
+        self.push(message).await;
+    }
+
+    pub async fn remove_pending(&self, id: MessageId) {
+        let mut state = self.state.write().await;
+        if state.pending.remove(&id).is_some() {
+            state.widget.remove_message(id);
+        }
+    }
+
+    pub async fn set_pending_failed(&self, id: MessageId, failed: bool) {
+        let state = self.state.read().await;
+        if let Some(widget) = state.pending.get(&id) {
+            if failed {
+                widget.set_send_failed(self.room.clone(), id);
+            } else {
+                widget.set_status(MessageStatus::Pending);
+            }
+        }
+    }
+
+    async fn show_outbox(&self) {
+        let outbox = self.room.state.read().await.outbox.clone();
+        for message in &outbox {
+            self.push_pending(message).await;
         }
     }
 
@@ -220,6 +296,10 @@ impl Chat {
         self.room.update(&update).await;
 
         self.extend(update.new_messages.buffer, ChatSide::Front).await;
+
+        if !update.continuous {
+            self.show_outbox().await;
+        }
     }
 
     async fn extend(&self, messages: Vec<Message>, side: ChatSide) {
@@ -238,29 +318,76 @@ impl Chat {
     }
 
     pub async fn extend_older(&self) -> Result<()> {
-        let oldest_message = self.state.read().await.oldest_message();
-        if let Some(oldest_message) = oldest_message {
-            let selector = MessageSelector::Before(Bound::Exclusive(oldest_message));
+        let oldest_message = {
+            let mut state = self.state.write().await;
+            if state.reached_oldest || state.loading_older {
+                return Ok(());
+            }
+
+            match state.oldest_message() {
+                Some(oldest_message) => {
+                    state.loading_older = true;
+                    oldest_message
+                }
+                None => return Ok(()),
+            }
+        };
+
+        let bound = Bound::Exclusive(MessagePosition::Id(oldest_message));
+        let selector = MessageSelector::Before(bound);
+        let history = self.room.request_messages(selector, MESSAGE_PAGE_SIZE).await;
 
-            let history = self.room.request_messages(selector, MESSAGE_PAGE_SIZE).await?;
-            self.extend(history.buffer, ChatSide::Back).await;
+        let mut state = self.state.write().await;
+        state.loading_older = false;
+        let history = history?;
+
+        // The chat may have been cleared while the messages were loading
+        if state.oldest_message() != Some(oldest_message) {
+            return Ok(());
+        }
+        drop(state);
+
+        self.extend(history.buffer, ChatSide::Back).await;
+
+        if !history.cursor.has_more {
+            self.state.write().await.reached_oldest = true;
         }
 
         Ok(())
     }
 
     pub async fn extend_newer(&self) -> Result<()> {
-        let newest_message = self.state.read().await.newest_message();
-        if newest_message == self.room.newest_message().await {
-            return Ok(());
-        }
+        let room_newest = self.room.newest_message().await;
+        let newest_message = {
+            let mut state = self.state.write().await;
+            let newest_message = state.newest_message();
+            if state.loading_newer || newest_message == room_newest {
+                return Ok(());
+            }
 
-        if let Some(newest_message) = newest_message {
-            let selector = MessageSelector::After(Bound::Exclusive(newest_message));
+            match newest_message {
+                Some(newest_message) => {
+                    state.loading_newer = true;
+                    newest_message
+                }
+                None => return Ok(()),
+            }
+        };
+
+        let bound = Bound::Exclusive(MessagePosition::Id(newest_message));
+        let selector = MessageSelector::After(bound);
+        let history = self.room.request_messages(selector, MESSAGE_PAGE_SIZE).await;
+
+        let mut state = self.state.write().await;
+        state.loading_newer = false;
+        let history = history?;
 
-            let history = self.room.request_messages(selector, MESSAGE_PAGE_SIZE).await?;
-            self.extend(history.buffer, ChatSide::Front).await;
+        if state.newest_message() != Some(newest_message) {
+            return Ok(());
         }
+        drop(state);
+
+        self.extend(history.buffer, ChatSide::Front).await;
 
         Ok(())
     }
@@ -0,0 +1,101 @@
+//! Slash commands typed into the message entry, such as `/nick <name>`. A message is only read as
+//! a command if it starts with `/` followed by a word, so that text like `/usr/bin` or `/ shrug`
+//! is still sent as it is.
+
+use crate::{Client, Error, Result};
+use crate::screen::active::dialog;
+
+use super::RoomEntry;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// Leave the community of the selected room
+    Leave,
+    /// Create an invite to the community of the selected room
+    Invite,
+    /// Change the user's display name
+    Nick(String),
+    /// Create a room in the community of the selected room
+    CreateRoom(String),
+}
+
+/// The commands which can be typed, along with how they are used
+const COMMANDS: &[(&str, &str)] = &[
+    ("leave", "/leave"),
+    ("invite", "/invite"),
+    ("nick", "/nick <name>"),
+    ("create-room", "/create-room <name>"),
+];
+
+/// Parses the message as a command. Returns `None` if it is not one and should be sent as a
+/// message, or an error to show if it names a command which does not exist or is missing its
+/// argument.
+pub fn parse(content: &str) -> Option<Result<Command>> {
+    let content = content.trim();
+    let rest = content.strip_prefix('/')?;
+
+    let (name, argument) = match rest.find(char::is_whitespace) {
+        Some(idx) => (&rest[..idx], rest[idx..].trim()),
+        None => (rest, ""),
+    };
+
+    let is_word = name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+    if !is_word {
+        return None;
+    }
+
+    let usage = match COMMANDS.iter().find(|(command, _)| command.eq_ignore_ascii_case(name)) {
+        Some((_, usage)) => usage,
+        None => {
+            let names: Vec<String> = COMMANDS.iter()
+                .map(|(name, _)| format!("/{}", name))
+                .collect();
+            let reason = format!("Unknown command /{}. Try one of {}", name, names.join(", "));
+            return Some(Err(Error::InvalidInput(reason)));
+        }
+    };
+
+    let argument = || {
+        if argument.is_empty() {
+            Err(Error::InvalidInput(format!("Usage: {}", usage)))
+        } else {
+            Ok(argument.to_owned())
+        }
+    };
+
+    let command = match name.to_ascii_lowercase().as_str() {
+        "leave" => Ok(Command::Leave),
+        "invite" => Ok(Command::Invite),
+        "nick" => argument().map(Command::Nick),
+        "create-room" => argument().map(Command::CreateRoom),
+        _ => unreachable!("command listed without being handled"),
+    };
+
+    Some(command)
+}
+
+impl Command {
+    /// Runs the command from the given room
+    pub async fn run(self, client: &Client, room: &RoomEntry) -> Result<()> {
+        match self {
+            Command::Leave => client.leave_community(room.community).await,
+            Command::Invite => {
+                let community = community_of(client, room).await?;
+                let invite = community.create_invite(None).await?;
+                dialog::show_invite_dialog(invite);
+                Ok(())
+            }
+            Command::Nick(name) => client.user.change_display_name(name).await,
+            Command::CreateRoom(name) => {
+                let community = community_of(client, room).await?;
+                community.create_room(&name).await.map(|_| ())
+            }
+        }
+    }
+}
+
+async fn community_of(client: &Client, room: &RoomEntry) -> Result<super::CommunityEntry> {
+    client.community_by_id(room.community).await
+        .ok_or_else(|| Error::InvalidInput("The room's community no longer exists".to_owned()))
+}
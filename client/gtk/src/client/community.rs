@@ -1,4 +1,5 @@
 use chrono::prelude::*;
+use gtk::prelude::*;
 
 use vertex::prelude::*;
 
@@ -42,7 +43,12 @@ impl CommunityEntry {
         &self,
         expiration_datetime: Option<DateTime<Utc>>
     ) -> Result<InviteCode> {
-        let request = ClientRequest::CreateInvite { community: self.id, expiration_datetime };
+        let request = ClientRequest::CreateInvite {
+            community: self.id,
+            expiration_datetime,
+            max_uses: None,
+            vanity: None,
+        };
         let request = self.client.request.send(request).await;
 
         match request.response().await? {
@@ -63,26 +69,49 @@ impl CommunityEntry {
         }
     }
 
+    /// Gets a page of the community's members, ordered by display name
+    pub async fn get_members(
+        &self,
+        after: Option<UserId>,
+        limit: usize,
+    ) -> Result<Vec<MemberInfo>> {
+        let request = ClientRequest::GetCommunityMembers { community: self.id, after, limit };
+        let request = self.client.request.send(request).await;
+
+        match request.response().await? {
+            OkResponse::CommunityMembers(members) => Ok(members),
+            _ => Err(Error::UnexpectedMessage),
+        }
+    }
+
     pub async fn room_by_id(&self, id: RoomId) -> Option<RoomEntry> {
         self.state.read().await.rooms.iter()
             .find(|&room| room.id == id)
             .cloned()
     }
 
+    /// Selects the room in the room list, which selects it in the client too
+    pub async fn select_room_by_id(&self, id: RoomId) {
+        let index = self.state.read().await.rooms.iter().position(|room| room.id == id);
+        let row = index.and_then(|index| self.widget.room_list.get_row_at_index(index as i32));
+
+        if let Some(row) = row {
+            self.widget.room_list.select_row(Some(&row));
+        }
+    }
+
+    pub async fn rooms(&self) -> Vec<RoomEntry> {
+        self.state.read().await.rooms.clone()
+    }
+
     #[inline]
     pub async fn get_room(&self, index: usize) -> Option<RoomEntry> {
         self.state.read().await.rooms.get(index).cloned()
     }
 
     pub(super) async fn add_room(&self, room: RoomStructure) -> RoomEntry {
-        let widget = self.widget.add_room(room.name.clone());
-        let entry = RoomEntry::new(
-            self.client.clone(),
-            widget,
-            self.id,
-            room.id,
-            room.name,
-        );
+        let widget = self.widget.add_room(room.name.clone(), room.topic.as_deref());
+        let entry = RoomEntry::new(self.client.clone(), widget, self.id, room);
 
         let mut state = self.state.write().await;
         state.rooms.push(entry);
@@ -0,0 +1,104 @@
+use vertex::prelude::*;
+
+use crate::{Client, Result, SharedMut};
+use crate::screen::active::{MemberEntryWidget, MemberListWidget};
+
+use super::community::*;
+
+/// Most members loaded at a time, within the server's default limit. More are loaded as the list
+/// is scrolled down.
+pub const MEMBER_PAGE_SIZE: usize = 100;
+
+struct MemberEntry {
+    id: UserId,
+    online: bool,
+    widget: MemberEntryWidget,
+}
+
+struct MemberListState {
+    /// The loaded members, in the order given by the server
+    members: Vec<MemberEntry>,
+    /// Whether every member of the community has been loaded
+    reached_end: bool,
+    loading: bool,
+}
+
+#[derive(Clone)]
+pub struct MemberList {
+    client: Client,
+    pub community: CommunityEntry,
+    widget: MemberListWidget,
+    state: SharedMut<MemberListState>,
+}
+
+impl MemberList {
+    pub(super) fn new(client: Client, widget: MemberListWidget, community: CommunityEntry) -> Self {
+        let state = SharedMut::new(MemberListState {
+            members: Vec::new(),
+            reached_end: false,
+            loading: false,
+        });
+
+        let members = MemberList { client, community, widget, state };
+        members.widget.bind_events(&members);
+
+        members
+    }
+
+    /// Loads the next page of members. Requests made while a page is already loading are dropped.
+    pub async fn load_more(&self) -> Result<()> {
+        let after = {
+            let mut state = self.state.write().await;
+            if state.reached_end || state.loading {
+                return Ok(());
+            }
+
+            state.loading = true;
+            state.members.last().map(|member| member.id)
+        };
+
+        let members = self.community.get_members(after, MEMBER_PAGE_SIZE).await;
+
+        let mut state = self.state.write().await;
+        state.loading = false;
+        let members = members?;
+
+        state.reached_end = members.len() < MEMBER_PAGE_SIZE;
+
+        for member in members {
+            let widget = self.widget.add_member(&self.client, &member);
+            state.members.push(MemberEntry {
+                id: member.user,
+                online: member.online,
+                widget,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Moves the member between the online and offline groups. Members who are not loaded yet are
+    /// ignored, since they will be loaded with their current status.
+    pub async fn set_online(&self, user: UserId, online: bool) {
+        let mut state = self.state.write().await;
+
+        let index = match state.members.iter().position(|member| member.id == user) {
+            Some(index) => index,
+            None => return,
+        };
+
+        if state.members[index].online == online {
+            return;
+        }
+
+        // Keep the groups in the same order as the server gave them
+        let position = state.members[..index]
+            .iter()
+            .filter(|member| member.online == online)
+            .count();
+
+        let member = &mut state.members[index];
+        member.online = online;
+        self.widget.move_member(&member.widget, online, position);
+    }
+}
@@ -25,6 +25,9 @@ pub struct MessageContent {
     pub profile: Profile,
     pub text: Option<String>, // TODO properly handle deletion
     pub time: DateTime<Utc>,
+    /// The quoted message this is a reply to, as it should be shown
+    pub reply: Option<String>,
+    pub kind: MessageKind,
 }
 
 pub struct MessageRingBuffer {
@@ -70,7 +73,7 @@ impl MessageRingBuffer {
         self.newest_index.and_then(|index| self.buffer.get(index)).map(|m| m.id)
     }
 
-    pub fn iter<'a>(&'a self) -> impl Iterator<Item = &'a Message> + 'a {
+    pub fn iter(&self) -> impl Iterator<Item = &Message> {
         let (new, old) = self.buffer.split_at(self.write_index);
         old.iter().chain(new.iter())
     }
@@ -1,4 +1,5 @@
 use linkify::{LinkFinder, LinkKind};
+use vertex::formatting::{self, Span};
 
 use crate::client::EmbedCache;
 use crate::client::message::MessageEmbed;
@@ -33,6 +34,13 @@ impl RichMessage {
         !self.links.is_empty()
     }
 
+    /// Formats the text as Pango markup to be shown in a label
+    pub fn markup(&self) -> String {
+        let mut markup = String::new();
+        push_markup(&mut markup, &formatting::parse(self.text.trim()));
+        markup
+    }
+
     pub async fn load_embeds(&self, cache: &EmbedCache) -> impl Iterator<Item = MessageEmbed> {
         let embeds = self.links.iter().cloned()
             .map(|url| {
@@ -47,3 +55,31 @@ impl RichMessage {
             .filter_map(|e| e)
     }
 }
+
+fn push_markup(markup: &mut String, spans: &[Span]) {
+    for span in spans {
+        match span {
+            Span::Text(text) => markup.push_str(&glib::markup_escape_text(text)),
+            Span::Bold(inner) => {
+                markup.push_str("<b>");
+                push_markup(markup, inner);
+                markup.push_str("</b>");
+            }
+            Span::Italic(inner) => {
+                markup.push_str("<i>");
+                push_markup(markup, inner);
+                markup.push_str("</i>");
+            }
+            Span::Code(code) => {
+                markup.push_str("<tt>");
+                markup.push_str(&glib::markup_escape_text(code));
+                markup.push_str("</tt>");
+            }
+            Span::Link { text, url } => {
+                markup.push_str(&format!("<a href=\"{}\">", glib::markup_escape_text(url)));
+                push_markup(markup, text);
+                markup.push_str("</a>");
+            }
+        }
+    }
+}
@@ -1,33 +1,63 @@
 use std::cell::RefCell;
-use std::env;
+use std::path::Path;
 use std::rc::Rc;
+use std::sync::Once;
 
 use ears::{AudioController, Sound};
+use futures::channel::mpsc::UnboundedSender;
 
+use vertex::formatting;
 use vertex::prelude::*;
-use crate::resource;
+use crate::{config, resource};
+
+/// Makes sure the warning about notifications being silent is only logged once, rather than every
+/// time a client is started
+static WARN_SILENT: Once = Once::new();
 
 #[derive(Clone)]
 pub struct Notifier {
+    /// The sound played with notifications. Notifications are silent if it could not be loaded.
     sound: Option<Rc<RefCell<Sound>>>,
-}
-
-impl Default for Notifier {
-    fn default() -> Self {
-        Self::new()
-    }
+    /// The icon shown with notifications, if it exists
+    icon: Option<String>,
+    /// Where the room of a notification is sent when the notification is clicked
+    #[cfg_attr(not(unix), allow(dead_code))]
+    activated: UnboundedSender<(CommunityId, RoomId)>,
 }
 
 impl Notifier {
-    pub fn new() -> Self {
-        let sound = Sound::new(&resource("notification_sound_clearly.ogg")).ok();
-        Notifier {
-            sound: sound.map(|sound| Rc::new(RefCell::new(sound))),
-        }
+    pub fn new(activated: UnboundedSender<(CommunityId, RoomId)>) -> Self {
+        let path = resource("notification_sound_clearly.ogg");
+        let sound = if Path::new(&path).is_file() {
+            Sound::new(&path)
+        } else {
+            Err(format!("{} does not exist", path))
+        };
+
+        let sound = match sound {
+            Ok(sound) => Some(Rc::new(RefCell::new(sound))),
+            Err(err) => {
+                WARN_SILENT.call_once(|| {
+                    log::warn!("notifications will be silent: failed to load sound: {}", err);
+                });
+                None
+            }
+        };
+
+        let icon = resource("icon.png");
+        let icon = if Path::new(&icon).is_file() {
+            Some(icon)
+        } else {
+            log::warn!("notifications will have no icon: {} does not exist", icon);
+            None
+        };
+
+        Notifier { sound, icon, activated }
     }
 
     pub async fn notify_message(
         &self,
+        room: (CommunityId, RoomId),
         author: &Profile,
         community_name: &str,
         room_name: &str,
@@ -41,20 +71,67 @@ impl Notifier {
         };
 
         let content = if let Some(content) = content {
+            let content = formatting::plain_text(&formatting::parse(content));
             format!("{}: {}", author.display_name, content)
         } else {
             format!("{}: <Deleted>", author.display_name) // TODO deletion
         };
 
-        let mut icon_path = env::current_dir().unwrap();
-        icon_path.push("res");
-        icon_path.push("icon.png");
+        self.notify(room, title, content, a11y_narration);
+    }
+
+    pub async fn notify_mention(
+        &self,
+        room: (CommunityId, RoomId),
+        author: &Profile,
+        community_name: &str,
+        room_name: &str,
+    ) {
+        let title = format!("{} mentioned you", author.display_name);
+        let content = format!("In {} in {}", room_name, community_name);
+        self.notify(room, title, content, false);
+    }
+
+    /// Shows a notification and plays the notification sound, as each is turned on in the config.
+    /// Narrations for screen readers are shown regardless, since they are turned on separately.
+    fn notify(
+        &self,
+        room: (CommunityId, RoomId),
+        title: String,
+        content: String,
+        a11y_narration: bool,
+    ) {
+        let config = config::get();
+        if config.show_notifications || a11y_narration {
+            self.show(room, title, content);
+        }
+
+        if config.notification_sound {
+            self.play_sound();
+        }
+    }
+
+    /// Shows a notification for the room. Clicking it opens the room where the platform lets us
+    /// know about clicks, which Windows toasts do not.
+    fn show(&self, room: (CommunityId, RoomId), title: String, content: String) {
+        let icon = self.icon.clone();
+        #[cfg(unix)]
+        let activated = self.activated.clone();
+        #[cfg(not(unix))]
+        let _ = room;
 
         #[cfg(windows)]
         tokio::task::spawn_blocking(move || {
             // TODO: AppId when we have installer
-            let _ = winrt_notification::Toast::new(winrt_notification::Toast::POWERSHELL_APP_ID)
-                .icon(icon_path.as_path(), winrt_notification::IconCrop::Circular, "Vertex")
+            let mut toast = winrt_notification::Toast::new(
+                winrt_notification::Toast::POWERSHELL_APP_ID,
+            );
+            if let Some(icon) = &icon {
+                let crop = winrt_notification::IconCrop::Circular;
+                toast = toast.icon(Path::new(icon), crop, "Vertex");
+            }
+
+            let _ = toast
                 .title(&title)
                 .text1(&content)
                 .sound(None)
@@ -64,18 +141,26 @@ impl Notifier {
 
         #[cfg(unix)]
         tokio::task::spawn_blocking(move || {
-            let res = notify_rust::Notification::new()
-                .summary(&title)
-                .appname("Vertex")
-                .icon(&icon_path.to_str().unwrap())
-                .body(&content)
-                .show();
+            let mut notification = notify_rust::Notification::new();
+            notification.summary(&title).appname("Vertex").body(&content);
+            notification.action("default", "Open");
+            if let Some(icon) = &icon {
+                notification.icon(icon);
+            }
+
+            let res = notification.show();
 
             if let Ok(handle) = res {
-                handle.on_close(|| {});
+                handle.wait_for_action(|action| {
+                    if action == "default" {
+                        let _ = activated.unbounded_send(room);
+                    }
+                });
             }
         });
+    }
 
+    fn play_sound(&self) {
         if let Some(sound) = &self.sound {
             if let Ok(mut sound) = sound.try_borrow_mut() {
                 sound.play();
@@ -11,6 +11,8 @@ fn create_default_profile(user: UserId) -> Profile {
         version: ProfileVersion(0),
         username: name.clone(),
         display_name: name,
+        avatar: None,
+        last_seen: None,
     }
 }
 
@@ -56,6 +58,15 @@ impl ProfileCache {
         Ok(profile)
     }
 
+    /// Fetches the newest version of the profile from the server, even if one is already cached
+    pub async fn get_latest(&self, id: UserId) -> Result<Profile> {
+        if id == self.user.id {
+            return Ok(self.user.profile().await);
+        }
+
+        self.request(id).await
+    }
+
     pub async fn get_existing(&self, id: UserId, version: Option<ProfileVersion>) -> Option<Profile> {
         let cache = self.cache.read().await;
         cache.get(&id).and_then(|profile| {
@@ -1,4 +1,6 @@
-use chrono::Utc;
+use std::collections::VecDeque;
+
+use uuid::Uuid;
 
 use vertex::prelude::*;
 use crate::{Client, Error, Result, SharedMut};
@@ -6,9 +8,41 @@ use crate::{Client, Error, Result, SharedMut};
 use super::message::*;
 use crate::screen::active::{RoomEntryWidget};
 
+/// How long to wait before sending a message again after it fails to send. This doubles with every
+/// failed attempt, up to `SEND_RETRY_MAX_DELAY`.
+const SEND_RETRY_MIN_DELAY: tokio::time::Duration = tokio::time::Duration::from_secs(2);
+const SEND_RETRY_MAX_DELAY: tokio::time::Duration = tokio::time::Duration::from_secs(30);
+
+/// Times a message is tried before it is shown as failed, to be retried by the user or once the
+/// connection comes back
+const SEND_ATTEMPTS: u32 = 5;
+
+/// A message which has not been confirmed by the server yet. It keeps the same nonce every time it
+/// is sent, so that the server does not add it twice if an earlier attempt got through.
+#[derive(Debug, Clone)]
+pub struct OutgoingMessage {
+    /// Local ID for the pending message, until it is given a real one
+    pub id: MessageId,
+    pub content: String,
+    nonce: MessageNonce,
+    /// Whether sending has been given up on until the message is retried
+    pub failed: bool,
+}
+
 pub struct RoomState {
     pub message_buffer: MessageRingBuffer,
     pub last_read: Option<MessageId>,
+    pub topic: Option<String>,
+    pub slow_mode_secs: u32,
+    pub archived: bool,
+    pub watch_level: WatchLevel,
+    /// Pinned messages, newest first, or `None` if they have not been fetched since they changed
+    pub pinned: Option<Vec<Message>>,
+    /// Messages waiting to be sent, in the order they were written. They are kept across
+    /// reconnects.
+    pub outbox: VecDeque<OutgoingMessage>,
+    /// Whether the outbox is being sent, so that only one task sends from it at a time
+    sending: bool,
 }
 
 #[derive(Clone)]
@@ -30,24 +64,130 @@ impl RoomEntry {
         client: Client,
         widget: RoomEntryWidget,
         community: CommunityId,
-        id: RoomId,
-        name: String,
+        room: RoomStructure,
     ) -> Self {
         let state = SharedMut::new(RoomState {
             message_buffer: MessageRingBuffer::new(MESSAGE_PAGE_SIZE),
             last_read: None,
+            topic: room.topic,
+            slow_mode_secs: room.slow_mode_secs,
+            archived: room.archived,
+            watch_level: room.watch_level,
+            pinned: None,
+            outbox: VecDeque::new(),
+            sending: false,
         });
 
-        RoomEntry { client, widget, community, id, name, state }
+        RoomEntry { client, widget, community, id: room.id, name: room.name, state }
     }
 
-    pub(crate) async fn get_updates(&self) -> Result<RoomUpdate> {
-        let last_received = self.state.read().await.message_buffer.last();
+    pub async fn set_details(&self, topic: Option<String>, slow_mode_secs: u32, archived: bool) {
+        self.widget.set_topic(topic.as_deref());
 
-        let request = self.client.request.send(ClientRequest::GetRoomUpdate {
+        let mut state = self.state.write().await;
+        state.topic = topic;
+        state.slow_mode_secs = slow_mode_secs;
+        state.archived = archived;
+    }
+
+    pub async fn watch_level(&self) -> WatchLevel {
+        self.state.read().await.watch_level
+    }
+
+    pub async fn set_watch_level(&self, level: WatchLevel) -> Result<()> {
+        let request = self.client.request.send(ClientRequest::SetWatchLevel {
+            community: self.community,
+            room: self.id,
+            level,
+        }).await;
+
+        match request.response().await? {
+            OkResponse::NoData => {
+                self.state.write().await.watch_level = level;
+                Ok(())
+            }
+            _ => Err(Error::UnexpectedMessage),
+        }
+    }
+
+    pub async fn pin_message(&self, message: MessageId) -> Result<()> {
+        let request = self.client.request.send(ClientRequest::PinMessage {
+            community: self.community,
+            room: self.id,
+            message,
+        }).await;
+
+        match request.response().await? {
+            OkResponse::NoData => Ok(()),
+            _ => Err(Error::UnexpectedMessage),
+        }
+    }
+
+    pub async fn edit_message(&self, message: MessageId, new_content: String) -> Result<()> {
+        let request = self.client.request.send(ClientRequest::EditMessage(Edit {
+            message,
             community: self.community,
             room: self.id,
-            last_received,
+            new_content,
+        })).await;
+
+        match request.response().await? {
+            OkResponse::NoData => Ok(()),
+            _ => Err(Error::UnexpectedMessage),
+        }
+    }
+
+    pub async fn unpin_message(&self, message: MessageId) -> Result<()> {
+        let request = self.client.request.send(ClientRequest::UnpinMessage {
+            community: self.community,
+            room: self.id,
+            message,
+        }).await;
+
+        match request.response().await? {
+            OkResponse::NoData => Ok(()),
+            _ => Err(Error::UnexpectedMessage),
+        }
+    }
+
+    pub async fn pinned_messages(&self) -> Result<Vec<Message>> {
+        if let Some(pinned) = &self.state.read().await.pinned {
+            return Ok(pinned.clone());
+        }
+
+        let request = self.client.request.send(ClientRequest::GetPinnedMessages {
+            community: self.community,
+            room: self.id,
+        }).await;
+
+        match request.response().await? {
+            OkResponse::PinnedMessages(pinned) => {
+                self.state.write().await.pinned = Some(pinned.clone());
+                Ok(pinned)
+            }
+            _ => Err(Error::UnexpectedMessage),
+        }
+    }
+
+    pub async fn invalidate_pins(&self) {
+        self.state.write().await.pinned = None;
+    }
+
+    pub(crate) async fn update_request(&self) -> RoomUpdateRequest {
+        RoomUpdateRequest {
+            community: self.community,
+            room: self.id,
+            last_received: self.state.read().await.message_buffer.last(),
+        }
+    }
+
+    pub(crate) async fn get_updates(&self) -> Result<RoomUpdate> {
+        let update = self.update_request().await;
+
+        let request = self.client.request.send(ClientRequest::GetRoomUpdate {
+            community: update.community,
+            room: update.room,
+            last_received: update.last_received,
             message_count: MESSAGE_PAGE_SIZE as u64,
         }).await;
 
@@ -58,44 +198,172 @@ impl RoomEntry {
     }
 
     pub async fn send_message(&self, content: String) {
-        let user = self.client.user.id;
-        let profile = self.client.user.profile().await;
-        let profile_version = profile.version;
+        let message = OutgoingMessage {
+            id: MessageId(Uuid::new_v4()), // Chance of collision is too small
+            content,
+            nonce: MessageNonce(Uuid::new_v4()),
+            failed: false,
+        };
 
         if let Some(chat) = self.client.chat_for(self.id).await {
-            let pending = chat.push_pending(
-                MessageContent {
-                    author: user,
-                    profile,
-                    text: Some(content.clone()),
-                    time: Utc::now(),
+            chat.push_pending(&message).await;
+        }
+
+        self.state.write().await.outbox.push_back(message);
+        self.send_outbox().await;
+    }
+
+    /// Sends the messages in the outbox in order. A message which keeps failing to send holds back
+    /// the ones after it until it is retried or cancelled.
+    pub async fn send_outbox(&self) {
+        {
+            let mut state = self.state.write().await;
+            if state.sending {
+                return;
+            }
+            state.sending = true;
+        }
+
+        loop {
+            let next = self.state.read().await.outbox.front().cloned();
+            let outgoing = match next {
+                Some(outgoing) if !outgoing.failed => outgoing,
+                _ => break,
+            };
+
+            match self.send_with_retries(&outgoing).await {
+                Ok(Some(confirmation)) => self.confirm_outgoing(outgoing, confirmation).await,
+                // Cancelled while it was being sent
+                Ok(None) => {}
+                Err(err) => {
+                    log::warn!("giving up on sending message: {:?}", err);
+                    self.set_outgoing_failed(outgoing.id, true).await;
+                    break;
+                }
+            }
+        }
+
+        self.state.write().await.sending = false;
+    }
+
+    async fn send_with_retries(
+        &self,
+        outgoing: &OutgoingMessage,
+    ) -> Result<Option<MessageConfirmation>> {
+        let mut delay = SEND_RETRY_MIN_DELAY;
+        let mut attempts = 0;
+
+        loop {
+            let queued = self.state.read().await.outbox.iter().any(|m| m.id == outgoing.id);
+            if !queued {
+                return Ok(None);
+            }
+
+            match self.send_message_request(outgoing).await {
+                Ok(confirmation) => return Ok(Some(confirmation)),
+                // The server will take the message once the rate limit has passed, so wait for as
+                // long as it says rather than failing or counting this as an attempt
+                Err(Error::ErrorResponse(vertex::responses::Error::RateLimited {
+                    retry_after_secs,
+                })) => {
+                    let ready_in = tokio::time::Duration::from_secs(retry_after_secs as u64);
+                    tokio::time::delay_for(ready_in.max(SEND_RETRY_MIN_DELAY)).await;
+                    continue;
                 }
-            ).await;
-
-            let result = self.send_message_request(content.clone()).await;
-            match result {
-                Ok(confirmation) => {
-                    let message = Message {
-                        id: confirmation.id,
-                        author: user,
-                        author_profile_version: profile_version,
-                        time_sent: confirmation.time_sent,
-                        content: Some(content),
-                    };
-
-                    pending.upgrade(message.clone()).await;
-                    self.push_message(message).await;
+                // The server refused the message, so sending it again would not help
+                Err(err @ Error::ErrorResponse(_)) => return Err(err),
+                Err(err) => {
+                    attempts += 1;
+                    if attempts >= SEND_ATTEMPTS {
+                        return Err(err);
+                    }
+                    log::warn!("failed to send message, retrying: {:?}", err);
                 }
-                Err(_) => pending.set_error(),
             }
+
+            tokio::time::delay_for(delay).await;
+            delay = (delay * 2).min(SEND_RETRY_MAX_DELAY);
+        }
+    }
+
+    async fn confirm_outgoing(&self, outgoing: OutgoingMessage, confirmation: MessageConfirmation) {
+        self.state.write().await.outbox.retain(|m| m.id != outgoing.id);
+
+        let message = Message {
+            id: confirmation.id,
+            author: self.client.user.id,
+            author_profile_version: self.client.user.profile().await.version,
+            time_sent: confirmation.time_sent,
+            content: Some(outgoing.content),
+            reactions: Vec::new(),
+            attachments: Vec::new(),
+            reply_to: None,
+            kind: MessageKind::Normal,
+            seq: confirmation.seq,
+            signature: None,
+        };
+
+        if let Some(chat) = self.client.chat_for(self.id).await {
+            chat.confirm_pending(outgoing.id, message.clone()).await;
+        }
+        self.push_message(message).await;
+    }
+
+    async fn set_outgoing_failed(&self, id: MessageId, failed: bool) {
+        {
+            let mut state = self.state.write().await;
+            if let Some(outgoing) = state.outbox.iter_mut().find(|m| m.id == id) {
+                outgoing.failed = failed;
+            }
+        }
+
+        if let Some(chat) = self.client.chat_for(self.id).await {
+            chat.set_pending_failed(id, failed).await;
+        }
+    }
+
+    /// Tries sending a message which failed to send again
+    pub async fn retry_message(&self, id: MessageId) {
+        self.set_outgoing_failed(id, false).await;
+        self.send_outbox().await;
+    }
+
+    /// Retries every message which failed to send, such as after reconnecting
+    pub async fn retry_outbox(&self) {
+        let failed: Vec<MessageId> = self.state.read().await.outbox.iter()
+            .filter(|m| m.failed)
+            .map(|m| m.id)
+            .collect();
+
+        for id in failed {
+            self.set_outgoing_failed(id, false).await;
+        }
+
+        self.send_outbox().await;
+    }
+
+    /// Gives up on a message which failed to send, letting the messages after it be sent
+    pub async fn cancel_message(&self, id: MessageId) {
+        self.state.write().await.outbox.retain(|m| m.id != id);
+
+        if let Some(chat) = self.client.chat_for(self.id).await {
+            chat.remove_pending(id).await;
         }
+
+        self.send_outbox().await;
     }
 
-    async fn send_message_request(&self, content: String) -> Result<MessageConfirmation> {
+    async fn send_message_request(
+        &self,
+        outgoing: &OutgoingMessage,
+    ) -> Result<MessageConfirmation> {
         let request = ClientRequest::SendMessage(ClientSentMessage {
             to_community: self.community,
             to_room: self.id,
-            content,
+            content: outgoing.content.clone(),
+            attachments: Vec::new(),
+            reply_to: None,
+            nonce: Some(outgoing.nonce),
         });
 
         let request = self.client.request.send(request).await;
@@ -143,6 +411,10 @@ impl RoomEntry {
         }).await;
     }
 
+    pub async fn set_last_read(&self, last_read: Option<MessageId>) {
+        self.state.write().await.last_read = last_read;
+    }
+
     pub async fn has_unread_messages(&self) -> bool {
         let state = self.state.read().await;
         match state.message_buffer.last() {
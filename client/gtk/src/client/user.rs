@@ -38,6 +38,14 @@ impl User {
         }
     }
 
+    pub(super) fn device(&self) -> DeviceId {
+        self.device
+    }
+
+    pub(super) fn token(&self) -> AuthToken {
+        self.token.clone()
+    }
+
     pub async fn change_username(&self, username: String) -> Result<()> {
         let request = ClientRequest::ChangeUsername { new_username: username.clone() };
         let request = self.request.send(request).await;
@@ -1,9 +1,28 @@
+use std::collections::HashMap;
 use std::sync::Arc;
 
 use arc_swap::ArcSwapOption;
 use serde::{Deserialize, Serialize};
 use once_cell::sync::Lazy;
 use log::Level;
+use uuid::Uuid;
+
+use vertex::prelude::*;
+
+/// What to show notifications for in a community. This is stored locally, and rooms whose watch
+/// level on the server is stricter still follow that instead.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NotificationLevel {
+    All,
+    Mentions,
+    Nothing,
+}
+
+impl Default for NotificationLevel {
+    fn default() -> Self {
+        NotificationLevel::All
+    }
+}
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Config {
@@ -14,7 +33,33 @@ pub struct Config {
     pub high_contrast_css: bool,
     pub screen_reader_message_list: bool,
     pub message_editor_tweaks: bool,
+    /// Whether to show system notifications for new messages and mentions
+    #[serde(default = "enabled")]
+    pub show_notifications: bool,
+    /// Whether to play a sound for new messages and mentions, even if no notification is shown
+    #[serde(default = "enabled")]
+    pub notification_sound: bool,
     pub log_level: Level,
+    /// Emojis picked most recently in the emoji picker, newest first
+    #[serde(default)]
+    pub recent_emojis: Vec<String>,
+    /// Servers most recently logged in to or registered with, newest first, as they were typed
+    #[serde(default)]
+    pub recent_servers: Vec<String>,
+    /// Notification levels of communities, keyed by their id. This has to come last, since TOML
+    /// needs tables after plain values.
+    #[serde(default)]
+    pub community_notifications: HashMap<Uuid, NotificationLevel>,
+}
+
+impl Config {
+    pub fn notification_level(&self, community: CommunityId) -> NotificationLevel {
+        self.community_notifications.get(&community.0).copied().unwrap_or_default()
+    }
+}
+
+fn enabled() -> bool {
+    true
 }
 
 impl Default for Config {
@@ -27,14 +72,19 @@ impl Default for Config {
             high_contrast_css: false,
             screen_reader_message_list: false,
             message_editor_tweaks: true,
+            show_notifications: true,
+            notification_sound: true,
             log_level: Level::Info,
+            recent_emojis: Vec::new(),
+            recent_servers: Vec::new(),
+            community_notifications: HashMap::new(),
         }
     }
 }
 
 const CONFIG_NAME: &str = "vertex-client";
 
-static CONFIG: Lazy<ArcSwapOption<Config>> = Lazy::new(|| ArcSwapOption::empty());
+static CONFIG: Lazy<ArcSwapOption<Config>> = Lazy::new(ArcSwapOption::empty);
 
 pub fn modify<F: FnOnce(&mut Config)>(f: F) {
     let mut config = (*get()).clone();
@@ -15,8 +15,10 @@ use std::rc::{Rc, Weak};
 use std::sync::Arc;
 use std::fmt;
 
+use directories::ProjectDirs;
 use gio::prelude::*;
 use gtk::prelude::*;
+use once_cell::sync::Lazy;
 use tokio::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
 use url::Url;
 use serde::{Serialize, Deserialize};
@@ -53,16 +55,10 @@ impl Glade {
         let mut source = String::new();
         file.read_to_string(&mut source)?;
 
-        #[allow(unused_mut)]
-        let mut glade_string = source;
-
-        // Replace res/* with relative link
-        #[cfg(feature = "deploy")]
-        {
-            let res_path = resources_path().into_os_string().into_string().unwrap();
-            let res_path = format!("{}/", res_path);
-            glade_string = glade_string.replace("res/", &res_path);
-        }
+        // Replace res/* with the path the resources were found at
+        let res_path = resources_path().into_os_string().into_string().unwrap();
+        let res_path = format!("{}/", res_path);
+        let glade_string = source.replace("res/", &res_path);
 
         Ok(Glade(Arc::new(glade_string)))
     }
@@ -112,6 +108,7 @@ impl<T> WeakSharedMut<T> {
     }
 }
 
+#[allow(clippy::result_unit_err)]
 pub trait TryGetText {
     fn try_get_text(&self) -> std::result::Result<String, ()>;
 }
@@ -166,22 +163,46 @@ pub async fn start() {
     }
 }
 
-fn resources_path() -> PathBuf {
-    let mut path;
+/// Where the resources were found. This is looked up once, so that the client can be run from
+/// any directory.
+static RESOURCES_PATH: Lazy<PathBuf> = Lazy::new(find_resources_path);
 
-    #[cfg(not(feature = "deploy"))]
-    {
-        path = PathBuf::new();
-    }
+fn resources_path() -> PathBuf {
+    RESOURCES_PATH.clone()
+}
 
-    #[cfg(feature = "deploy")]
-    {
-        path = std::env::current_exe().unwrap();
-        path.pop();
+/// Looks for the `res` directory next to the executable, then in the source tree for development
+/// builds, then in the user's data directory (e.g. `~/.local/share/vertex/res`). The working
+/// directory is used if none of them have it.
+fn find_resources_path() -> PathBuf {
+    let next_to_exe = std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(|dir| dir.join("res")));
+
+    let source_tree = if cfg!(feature = "deploy") {
+        None
+    } else {
+        Some(Path::new(env!("CARGO_MANIFEST_DIR")).join("res"))
+    };
+
+    let data_dir = ProjectDirs::from("cf", "vertex", "vertex")
+        .map(|dirs| dirs.data_dir().join("res"));
+
+    let found = next_to_exe.into_iter()
+        .chain(source_tree)
+        .chain(data_dir)
+        .find(|path| path.is_dir());
+
+    match found {
+        Some(path) => {
+            log::info!("loading resources from {}", path.display());
+            path
+        }
+        None => {
+            log::warn!("could not find resources; looking in the working directory instead");
+            PathBuf::from("res")
+        }
     }
-
-    path.push("res");
-    path
 }
 
 fn resource<P: AsRef<Path>>(rest: P) -> String {
@@ -295,6 +316,9 @@ pub enum Error {
     AuthErrorResponse(AuthError),
     UnexpectedMessage,
     DeserializeError(DeserializeError),
+    InvalidImage,
+    /// Details entered by the user which the server would refuse
+    InvalidInput(String),
 }
 
 impl fmt::Display for Error {
@@ -317,6 +341,8 @@ impl fmt::Display for Error {
             AuthErrorResponse(err) => write!(f, "{}", err),
             UnexpectedMessage => write!(f, "Received unexpected message"),
             DeserializeError(_) => write!(f, "Failed to deserialize message"),
+            InvalidImage => write!(f, "Invalid image"),
+            InvalidInput(reason) => write!(f, "{}", reason),
         }
     }
 }
@@ -336,6 +362,10 @@ impl From<AuthError> for Error {
     fn from(error: AuthError) -> Self { Error::AuthErrorResponse(error) }
 }
 
+impl From<glib::Error> for Error {
+    fn from(_: glib::Error) -> Self { Error::InvalidImage }
+}
+
 impl From<url::ParseError> for Error {
     fn from(_: url::ParseError) -> Self { Error::InvalidUrl }
 }
@@ -54,6 +54,11 @@ impl Sender {
     pub async fn close(&self) {
         self.send_raw(tungstenite::Message::Close(None)).await
     }
+
+    /// Sends through a new connection from now on, such as after reconnecting
+    pub fn replace(&self, sender: Sender) {
+        *self.0.borrow_mut() = sender.0.into_inner();
+    }
 }
 
 pub struct Receiver {
@@ -68,8 +73,9 @@ impl Receiver {
         futures::stream::select(self.stream, error)
             .filter_map(move |result| futures::future::ready(
                 match result {
+                    // Messages are always framed, since compression is asked for when logging in
                     Ok(tungstenite::Message::Binary(bytes)) => {
-                        match vertex::prelude::ServerMessage::from_protobuf_bytes(&bytes) {
+                        match vertex::prelude::ServerMessage::from_frame(&bytes) {
                             Ok(message) => Some(Ok(message)),
                             Err(_) => Some(Err(tungstenite::Error::Protocol(Cow::Borrowed("malformed message")))),
                         }
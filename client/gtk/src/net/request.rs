@@ -119,11 +119,13 @@ impl RequestManager {
                     }
                     None
                 }
+                // The server carries on after a malformed message, so we do too. The request it
+                // was for times out, since the server cannot tell which one it was.
                 Ok(ServerMessage::MalformedMessage) => {
                     log::error!(
                         "Server has informed us that we have sent a malformed message! Out of date?"
                     );
-                    panic!("Malformed message")
+                    None
                 },
                 Ok(e) => {
                     log::error!("Unimplemented server message {:#?}", e);
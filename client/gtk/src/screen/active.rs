@@ -3,10 +3,11 @@ use lazy_static::lazy_static;
 use gtk::prelude::*;
 
 use crate::{AuthParameters, Client, Error, Result, token_store, scheduler, config};
+use crate::token_store::AccountKey;
 use crate::auth;
-use crate::client::RoomEntry;
+use crate::client::{command, RoomEntry};
 use crate::connect::AsConnector;
-use crate::Glade;
+use crate::{Glade, resource};
 use crate::screen;
 use crate::window;
 use std::time::{Instant, Duration};
@@ -17,6 +18,8 @@ use vertex::requests::AuthError;
 
 pub mod community;
 pub mod dialog;
+pub mod emoji;
+pub mod member;
 pub mod message;
 pub mod room;
 pub mod chat;
@@ -24,6 +27,8 @@ pub mod chat;
 pub use chat::*;
 pub use community::*;
 pub use dialog::*;
+pub use emoji::*;
+pub use member::*;
 pub use message::*;
 pub use room::*;
 
@@ -58,6 +63,11 @@ pub struct Ui {
     pub message_scroll: gtk::ScrolledWindow,
     pub message_list: gtk::ListBox,
     pub message_entry: gtk::TextView,
+    emoji_button: gtk::Button,
+    member_list: gtk::Box,
+    member_list_button: gtk::ToggleButton,
+    member_list_revealer: gtk::Revealer,
+    reconnecting: gtk::Label,
 
     message_scroll_state: Rc<RwLock<MessageScrollState>>,
 }
@@ -67,12 +77,39 @@ impl Ui {
         lazy_static! {
             static ref GLADE: Glade = Glade::open("active/main.glade").unwrap();
         }
+        thread_local! {
+            static MEMBERS_ICON: gdk_pixbuf::Pixbuf = gdk_pixbuf::Pixbuf::new_from_file_at_size(
+                &resource("feather/users.svg"),
+                18,
+                18,
+            ).expect("Error loading users.svg!");
+            static EMOJI_ICON: gdk_pixbuf::Pixbuf = gdk_pixbuf::Pixbuf::new_from_file_at_size(
+                &resource("feather/smile.svg"),
+                18,
+                18,
+            ).expect("Error loading smile.svg!");
+        }
 
         let builder: gtk::Builder = GLADE.builder();
         let message_entry: gtk::TextView = builder.get_object("message_entry").unwrap();
 
+        let main: gtk::Box = builder.get_object("main").unwrap();
+        let reconnecting = gtk::LabelBuilder::new()
+            .name("reconnecting")
+            .label("Connection lost. Reconnecting...")
+            .no_show_all(true)
+            .build();
+        main.pack_start(&reconnecting, false, false, 0);
+        main.reorder_child(&reconnecting, 1); // Just below the toolbar
+
+        let member_list_icon: gtk::Image = builder.get_object("member_list_icon").unwrap();
+        MEMBERS_ICON.with(|icon| member_list_icon.set_from_pixbuf(Some(&icon)));
+
+        let emoji_icon: gtk::Image = builder.get_object("emoji_icon").unwrap();
+        EMOJI_ICON.with(|icon| emoji_icon.set_from_pixbuf(Some(&icon)));
+
         Ui {
-            main: builder.get_object("main").unwrap(),
+            main,
             content: builder.get_object("content").unwrap(),
             communities: builder.get_object("communities").unwrap(),
             settings_button: builder.get_object("settings_button").unwrap(),
@@ -83,6 +120,11 @@ impl Ui {
             message_scroll: builder.get_object("message_scroll").unwrap(),
             message_list: builder.get_object("message_list").unwrap(),
             message_entry,
+            emoji_button: builder.get_object("emoji_button").unwrap(),
+            member_list: builder.get_object("member_list").unwrap(),
+            member_list_button: builder.get_object("member_list_button").unwrap(),
+            member_list_revealer: builder.get_object("member_list_revealer").unwrap(),
+            reconnecting,
             message_scroll_state: Rc::new(RwLock::new(MessageScrollState::default())),
         }
     }
@@ -92,6 +134,12 @@ impl Ui {
             self.message_list.remove(&child);
         }
     }
+
+    fn clear_members(&self) {
+        for child in self.member_list.get_children() {
+            self.member_list.remove(&child);
+        }
+    }
 }
 
 impl Ui {
@@ -105,6 +153,27 @@ impl Ui {
                 .build_cloned_consumer()
         );
 
+        self.emoji_button.connect_clicked(
+            client.connector()
+                .do_sync(|client, button: gtk::Button| {
+                    let picker = build_emoji_picker(move |emoji| {
+                        let (client, emoji) = (client.clone(), emoji.to_string());
+                        scheduler::spawn(async move {
+                            client.ui.insert_emoji(&client, &emoji).await
+                        });
+                    });
+
+                    picker.set_relative_to(Some(&button));
+                    picker.show();
+                })
+                .build_cloned_consumer()
+        );
+
+        let revealer = self.member_list_revealer.clone();
+        self.member_list_button.connect_toggled(move |button| {
+            revealer.set_reveal_child(button.get_active());
+        });
+
         self.add_community_button.connect_clicked(
             client.connector()
                 .do_sync(|screen, _| show_add_community(screen))
@@ -193,7 +262,20 @@ impl Ui {
                         let content = buf.get_text(begin, end, false);
                         let content = content.as_ref().map(|c| c.as_str()).unwrap_or_default();
 
-                        if !content.trim().is_empty() {
+                        if let Some(command) = command::parse(content) {
+                            let res = match command {
+                                Ok(command) => command.run(&client, &selected_room).await,
+                                Err(err) => Err(err),
+                            };
+
+                            match res {
+                                Ok(()) => buf.set_text(""),
+                                Err(err) => client.ui.show_entry_error(&err),
+                            }
+                        } else if content.len() > client.max_message_len().await {
+                            let err = vertex::responses::Error::MessageTooLong;
+                            dialog::show_generic_error(&Error::ErrorResponse(err));
+                        } else if !content.trim().is_empty() {
                             buf.set_text("");
                             selected_room.send_message(content.to_string()).await;
                         }
@@ -334,6 +416,17 @@ impl Ui {
         }
 
         self.room_name.set_text("");
+        self.clear_members();
+    }
+
+    /// Replaces the shown member list with an empty one
+    pub fn show_members(&self) -> MemberListWidget {
+        self.clear_members();
+
+        let members = MemberListWidget::build();
+        self.member_list.add(&members.main);
+
+        members
     }
 
     pub fn add_community(&self, name: String, description: String) -> CommunityEntryWidget {
@@ -344,6 +437,51 @@ impl Ui {
         entry
     }
 
+    pub fn remove_community(&self, entry: &CommunityEntryWidget) {
+        if let Some(row) = entry.widget.get_parent() {
+            self.communities.remove(&row);
+        }
+    }
+
+    /// Shows an error next to the message entry, such as for a command which could not be run
+    pub fn show_entry_error<E: std::fmt::Display>(&self, error: &E) {
+        let popover = gtk::Popover::new(Some(&self.message_entry));
+        let label = gtk::Label::new(Some(&error.to_string()));
+        label.set_margin_start(6);
+        label.set_margin_end(6);
+        label.set_margin_top(6);
+        label.set_margin_bottom(6);
+        popover.add(&label);
+        popover.show_all();
+    }
+
+    /// Inserts the emoji into the message entry at the cursor
+    async fn insert_emoji(&self, client: &Client, emoji: &str) {
+        if !self.message_entry.get_editable() {
+            return;
+        }
+
+        let buffer = self.message_entry.get_buffer().unwrap();
+
+        // Clear out the placeholder text first, if it is being shown
+        if config::get().message_editor_tweaks {
+            if let Some(state) = client.state.upgrade() {
+                let mut state = state.write().await;
+                if state.message_entry_is_empty {
+                    buffer.set_text("");
+                    state.message_entry_is_empty = false;
+                }
+            }
+        }
+
+        buffer.insert_at_cursor(emoji);
+        self.message_entry.grab_focus();
+    }
+
+    pub fn set_reconnecting(&self, reconnecting: bool) {
+        self.reconnecting.set_visible(reconnecting);
+    }
+
     pub fn window_focused(&self) -> bool {
         window::is_focused()
     }
@@ -361,13 +499,17 @@ pub async fn start(parameters: AuthParameters) {
             log::error!("encountered error connecting client: {:?}", error);
 
             match error {
-                Error::AuthErrorResponse(e) => {
+                // The token is still good if the server just speaks another protocol version
+                Error::AuthErrorResponse(e)
+                    if !matches!(e, AuthError::IncompatibleProtocol { .. }) =>
+                {
                     if e != AuthError::TokenInUse || e != AuthError::UserCompromised {
                         token_store::forget_token();
                     }
 
                     if e == AuthError::UserCompromised {
-                        let screen = screen::compromised::build(parameters).await;
+                        let AuthParameters { instance, username, .. } = parameters;
+                        let screen = screen::compromised::build(instance, username).await;
                         window::set_screen(&screen.main);
                     } else {
                         let screen = screen::login::build().await;
@@ -384,11 +526,28 @@ pub async fn start(parameters: AuthParameters) {
     }
 }
 
+/// Stops the client and starts one for the given account. Without an account, the login screen is
+/// shown so that another account can be added.
+pub async fn switch_account(client: Client, account: Option<AccountKey>) {
+    client.stop().await;
+
+    match account.and_then(|account| token_store::get_account_token(&account)) {
+        Some(parameters) => {
+            token_store::set_active_account(Some(AccountKey::from(&parameters)));
+            start(parameters).await;
+        }
+        None => {
+            let screen = screen::login::build().await;
+            window::set_screen(&screen.main);
+        }
+    }
+}
+
 async fn try_start(parameters: AuthParameters) -> Result<Client> {
     let auth = auth::Client::new(parameters.instance);
     let ws = auth.login(parameters.device, parameters.token).await?;
 
-    Ok(Client::start(ws, Ui::build(), auth.server.url().scheme() == "https").await?)
+    Ok(Client::start(ws, Ui::build(), &auth.server).await?)
 }
 
 fn describe_error(error: Error) -> String {
@@ -400,6 +559,20 @@ fn describe_error(error: Error) -> String {
         Error::AuthErrorResponse(err) => match err {
             AuthError::Internal => "Internal server error".to_string(),
             AuthError::InvalidToken => "Invalid token".to_string(),
+            AuthError::IncompatibleProtocol { server } => {
+                let update = if server > vertex::PROTOCOL_VERSION {
+                    "Please update your client."
+                } else {
+                    "The server needs to be updated."
+                };
+
+                format!(
+                    "This client speaks version {} of the protocol, but the server speaks {}. {}",
+                    vertex::PROTOCOL_VERSION,
+                    server,
+                    update,
+                )
+            }
             _ => "Unknown auth error".to_string(),
         },
 
@@ -20,10 +20,20 @@ pub struct ChatWidget {
 }
 
 impl ChatWidget {
-    fn add_group(&mut self, author: UserId, profile: Profile, time: DateTime<Utc>, side: ChatSide) {
+    fn add_group(
+        &mut self,
+        client: &Client,
+        author: UserId,
+        profile: Profile,
+        kind: MessageKind,
+        time: DateTime<Utc>,
+        side: ChatSide,
+    ) {
         let group = MessageGroupWidget::build(
+            client,
             author,
             profile,
+            kind,
             time,
             true,
             config::get().screen_reader_message_list,
@@ -36,10 +46,18 @@ impl ChatWidget {
         }
     }
 
-    fn next_group(&mut self, author: UserId, profile: Profile, time: DateTime<Utc>, side: ChatSide) -> &mut MessageGroupWidget {
+    fn next_group(
+        &mut self,
+        client: &Client,
+        author: UserId,
+        profile: Profile,
+        kind: MessageKind,
+        time: DateTime<Utc>,
+        side: ChatSide,
+    ) -> &mut MessageGroupWidget {
         match self.group_for(side) {
-            Some(group) if group.can_combine(author, time) => {}
-            _ => self.add_group(author, profile, time, side),
+            Some(group) if group.can_combine(author, kind, time, side) => {}
+            _ => self.add_group(client, author, profile, kind, time, side),
         }
 
         self.group_for(side).unwrap()
@@ -71,13 +89,27 @@ impl ChatWidget {
         id: MessageId,
     ) -> MessageEntryWidget {
         let msg_list = self.message_list.clone();
-        let group = self.next_group(content.author, content.profile, content.time, side);
-        group.add_message(
+        let group = self.next_group(
+            &client,
+            content.author,
+            content.profile,
+            content.kind,
+            content.time,
+            side,
+        );
+        let entry = group.add_message(
             content.text,
             id,
+            content.time,
             side,
             &msg_list, client
-        )
+        );
+
+        if let Some(reply) = content.reply {
+            entry.set_reply(&reply);
+        }
+
+        entry
     }
 
     pub fn remove_message(&mut self, id: MessageId) {
@@ -92,6 +124,12 @@ impl ChatWidget {
         }
     }
 
+    pub fn refresh_timestamps(&self) {
+        for group in &self.groups {
+            group.refresh_timestamp();
+        }
+    }
+
     pub fn flush(&mut self) {
         self.message_list.show_all();
     }
@@ -14,6 +14,9 @@ pub struct CommunityEntryWidget {
     pub widget: gtk::Box,
     pub room_list: gtk::ListBox,
 
+    name: gtk::Label,
+    description: gtk::Label,
+    icon: gtk::Frame,
     menu_button: gtk::Button,
 }
 
@@ -49,9 +52,26 @@ impl CommunityEntryWidget {
         CommunityEntryWidget {
             widget: community_entry,
             room_list,
+            name: community_name,
+            description: community_description,
+            icon: builder.get_object("community_icon").unwrap(),
             menu_button: builder.get_object("menu_button").unwrap(),
         }
     }
+
+    pub fn set_details(&self, name: &str, description: &str) {
+        self.name.set_text(name);
+        self.description.set_text(description);
+    }
+
+    pub fn set_icon(&self, icon: &gdk_pixbuf::Pixbuf) {
+        for child in self.icon.get_children() {
+            self.icon.remove(&child);
+        }
+
+        self.icon.add(&gtk::Image::new_from_pixbuf(Some(icon)));
+        self.icon.show_all();
+    }
 }
 
 impl CommunityEntryWidget {
@@ -92,8 +112,9 @@ impl CommunityEntryWidget {
         );
     }
 
-    pub fn add_room(&self, name: String) -> RoomEntryWidget {
+    pub fn add_room(&self, name: String, topic: Option<&str>) -> RoomEntryWidget {
         let widget = RoomEntryWidget::build(name);
+        widget.set_topic(topic);
         self.room_list.add(&widget.container);
         self.room_list.show_all();
 
@@ -112,6 +133,7 @@ fn build_menu(community_entry: client::CommunityEntry) -> gtk::Popover {
     let invite_button: gtk::Button = builder.get_object("invite_button").unwrap();
     let create_channel_button: gtk::Button = builder.get_object("create_channel_button").unwrap();
     let _settings_button: gtk::Button = builder.get_object("settings_button").unwrap();
+    let notifications_button: gtk::Button = builder.get_object("notifications_button").unwrap();
 
     invite_button.connect_clicked(
         (menu.clone(), community_entry.clone()).connector()
@@ -126,6 +148,15 @@ fn build_menu(community_entry: client::CommunityEntry) -> gtk::Popover {
             .build_cloned_consumer()
     );
 
+    notifications_button.connect_clicked(
+        (menu.clone(), community_entry.clone()).connector()
+            .do_sync(move |(menu, community_entry), _| {
+                menu.hide();
+                dialog::show_notification_settings(community_entry);
+            })
+            .build_cloned_consumer()
+    );
+
     create_channel_button.connect_clicked(
         (menu.clone(), community_entry).connector()
             .do_sync(move |(menu, community_entry), _| {
@@ -2,7 +2,8 @@ use gtk::prelude::*;
 
 use vertex::prelude::*;
 
-use crate::{Client, Result, TryGetText, client};
+use crate::{Client, TryGetText, client, config};
+use crate::config::NotificationLevel;
 use crate::connect::AsConnector;
 use crate::window;
 
@@ -50,13 +51,6 @@ pub fn show_add_community(client: Client) {
     });
 }
 
-async fn create_community(client: Client, name: &str) -> Result<()> {
-    let community = client.create_community(name).await?;
-    community.create_room("General").await?;
-    community.create_room("Off Topic").await?;
-    Ok(())
-}
-
 pub fn show_create_community(client: Client) {
     window::show_dialog(|window| {
         let dialog = gtk::Dialog::new_with_buttons(
@@ -77,7 +71,7 @@ pub fn show_create_community(client: Client) {
             .child(&label)
             .build();
 
-        entry.clone().connect_activate(
+        entry.connect_activate(
             dialog.connector()
                 .do_sync(|dialog, _| dialog.response(ResponseType::Apply))
                 .build_cloned_consumer()
@@ -99,7 +93,7 @@ pub fn show_create_community(client: Client) {
                         }
 
                         if let Ok(name) = entry.try_get_text() {
-                            if let Err(err) = create_community(client, &name).await {
+                            if let Err(err) = client.create_community(&name).await {
                                 show_generic_error(&err);
                             }
                         }
@@ -134,7 +128,7 @@ pub fn show_join_community(client: Client) {
             .child(&label)
             .build();
 
-        entry.clone().connect_activate(
+        entry.connect_activate(
             dialog.connector()
                 .do_sync(|dialog, _| dialog.response(ResponseType::Apply))
                 .build_cloned_consumer()
@@ -220,6 +214,90 @@ pub fn show_invite_dialog(invite: InviteCode) {
     });
 }
 
+pub fn show_profile(profile: Profile) {
+    window::show_dialog(|window| {
+        let dialog = gtk::Dialog::new_with_buttons(
+            None,
+            Some(&window.window),
+            DialogFlags::MODAL | DialogFlags::DESTROY_WITH_PARENT,
+            &[("Ok", ResponseType::Ok)],
+        );
+
+        let label = Label::new(Some(&profile.display_name));
+        label.get_style_context().add_class("title");
+        let title_box = gtk::BoxBuilder::new()
+            .orientation(gtk::Orientation::Horizontal)
+            .hexpand(true)
+            .child(&label)
+            .build();
+
+        let username = Label::new(Some(&format!("@{}", profile.username)));
+        username.get_style_context().add_class("profile_username");
+
+        let content = dialog.get_content_area();
+        content.add(&title_box);
+        content.add(&username);
+
+        dialog.connect_response(|dialog, _| dialog.emit_close());
+        (dialog, title_box)
+    });
+}
+
+pub fn show_notification_settings(community: client::CommunityEntry) {
+    window::show_dialog(|window| {
+        let dialog = gtk::Dialog::new_with_buttons(
+            None,
+            Some(&window.window),
+            DialogFlags::MODAL | DialogFlags::DESTROY_WITH_PARENT,
+            &[("Save", ResponseType::Apply), ("Cancel", ResponseType::Cancel)],
+        );
+
+        let label = Label::new(Some("Notifications"));
+        label.get_style_context().add_class("title");
+        let title_box = gtk::BoxBuilder::new()
+            .orientation(gtk::Orientation::Horizontal)
+            .hexpand(true)
+            .child(&label)
+            .build();
+
+        let all = gtk::RadioButton::new_with_label("All messages");
+        let mentions = gtk::RadioButton::new_with_label_from_widget(&all, "Only mentions");
+        let nothing = gtk::RadioButton::new_with_label_from_widget(&all, "Nothing");
+
+        match config::get().notification_level(community.id) {
+            NotificationLevel::All => all.set_active(true),
+            NotificationLevel::Mentions => mentions.set_active(true),
+            NotificationLevel::Nothing => nothing.set_active(true),
+        }
+
+        let content = dialog.get_content_area();
+        content.add(&title_box);
+        content.add(&all);
+        content.add(&mentions);
+        content.add(&nothing);
+
+        dialog.connect_response(move |dialog, response_type| {
+            if response_type == ResponseType::Apply {
+                let level = if mentions.get_active() {
+                    NotificationLevel::Mentions
+                } else if nothing.get_active() {
+                    NotificationLevel::Nothing
+                } else {
+                    NotificationLevel::All
+                };
+
+                config::modify(|config| {
+                    config.community_notifications.insert(community.id.0, level);
+                });
+            }
+
+            dialog.emit_close();
+        });
+
+        (dialog, title_box)
+    });
+}
+
 pub fn show_create_room(community: client::CommunityEntry) {
     window::show_dialog(|window| {
         let dialog = gtk::Dialog::new_with_buttons(
@@ -240,7 +318,7 @@ pub fn show_create_room(community: client::CommunityEntry) {
             .child(&label)
             .build();
 
-        entry.clone().connect_activate(
+        entry.connect_activate(
             dialog.connector()
                 .do_sync(|dialog, _| dialog.response(ResponseType::Apply))
                 .build_cloned_consumer()
@@ -355,7 +433,7 @@ pub fn show_report_message(client: Client, msg: MessageId) {
     });
 }
 
-pub fn show_choose_report_action(client: Client, user: UserId) {
+pub fn show_choose_report_action(client: Client, report: i32, reason: String) {
     window::show_dialog(|window| {
         let dialog = gtk::Dialog::new_with_buttons(
             None,
@@ -378,11 +456,12 @@ pub fn show_choose_report_action(client: Client, user: UserId) {
         dialog.connect_response(
             client.connector()
                 .do_async(move |client, (dialog, response_type): (gtk::Dialog, ResponseType)| {
+                    let reason = reason.clone();
                     async move {
                         if let ResponseType::Other(1) = response_type {
-                            match client.ban_users(vec![user]).await.map(|mut v| v.pop()) {
-                                Err(ref e) | Ok(Some((_, ref e))) => show_generic_error(&e),
-                                _ => {}
+                            let action = ReportAction::Ban { reason, until: None };
+                            if let Err(e) = client.resolve_report(report, action).await {
+                                show_generic_error(&e);
                             }
                         }
 
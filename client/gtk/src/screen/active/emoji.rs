@@ -0,0 +1,307 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use gtk::prelude::*;
+
+use crate::config;
+
+/// Emojis offered by the picker, along with the names they can be searched by
+const EMOJIS: &[(&str, &str)] = &[
+    ("😀", "grinning face"),
+    ("😃", "smiling face with big eyes"),
+    ("😄", "smiling face with smiling eyes"),
+    ("😁", "beaming face"),
+    ("😆", "laughing face"),
+    ("😅", "sweat smile"),
+    ("🤣", "rolling on the floor laughing"),
+    ("😂", "tears of joy"),
+    ("🙂", "slightly smiling face"),
+    ("🙃", "upside down face"),
+    ("😉", "winking face"),
+    ("😊", "blushing smile"),
+    ("😇", "halo angel"),
+    ("🥰", "smiling face with hearts"),
+    ("😍", "heart eyes"),
+    ("🤩", "star struck"),
+    ("😘", "blowing a kiss"),
+    ("😋", "yum tongue"),
+    ("😛", "tongue out"),
+    ("😜", "winking tongue"),
+    ("🤪", "zany face"),
+    ("🤔", "thinking face"),
+    ("🤨", "raised eyebrow"),
+    ("😐", "neutral face"),
+    ("😑", "expressionless face"),
+    ("😶", "no mouth"),
+    ("😏", "smirking face"),
+    ("😒", "unamused face"),
+    ("🙄", "rolling eyes"),
+    ("😬", "grimacing face"),
+    ("😌", "relieved face"),
+    ("😔", "pensive face"),
+    ("😪", "sleepy face"),
+    ("😴", "sleeping face"),
+    ("😷", "face with medical mask"),
+    ("🤒", "sick thermometer"),
+    ("🤯", "exploding head mind blown"),
+    ("🥳", "party face"),
+    ("😎", "cool sunglasses"),
+    ("🤓", "nerd face"),
+    ("😕", "confused face"),
+    ("😟", "worried face"),
+    ("😮", "open mouth surprised"),
+    ("😲", "astonished face"),
+    ("😳", "flushed face"),
+    ("🥺", "pleading face"),
+    ("😢", "crying face"),
+    ("😭", "loudly crying face sob"),
+    ("😱", "screaming in fear"),
+    ("😤", "triumph steam"),
+    ("😡", "pouting angry face"),
+    ("🤬", "cursing face"),
+    ("💀", "skull"),
+    ("💩", "pile of poo"),
+    ("🤡", "clown face"),
+    ("👻", "ghost"),
+    ("👽", "alien"),
+    ("🤖", "robot"),
+    ("😺", "grinning cat"),
+    ("🙈", "see no evil monkey"),
+    ("👋", "waving hand wave"),
+    ("👌", "ok hand"),
+    ("✌", "victory hand peace"),
+    ("🤞", "crossed fingers"),
+    ("🤘", "sign of the horns rock"),
+    ("👈", "pointing left"),
+    ("👉", "pointing right"),
+    ("👆", "pointing up"),
+    ("👇", "pointing down"),
+    ("👍", "thumbs up"),
+    ("👎", "thumbs down"),
+    ("👏", "clapping hands"),
+    ("🙌", "raising hands"),
+    ("🙏", "folded hands please thanks"),
+    ("💪", "flexed biceps strong"),
+    ("👀", "eyes"),
+    ("🧠", "brain"),
+    ("❤", "red heart"),
+    ("🧡", "orange heart"),
+    ("💛", "yellow heart"),
+    ("💚", "green heart"),
+    ("💙", "blue heart"),
+    ("💜", "purple heart"),
+    ("🖤", "black heart"),
+    ("💔", "broken heart"),
+    ("💯", "hundred points"),
+    ("💥", "collision boom"),
+    ("💤", "zzz sleeping"),
+    ("💬", "speech balloon"),
+    ("🔥", "fire"),
+    ("✨", "sparkles"),
+    ("⭐", "star"),
+    ("🌈", "rainbow"),
+    ("☀", "sun"),
+    ("🌙", "crescent moon"),
+    ("⚡", "high voltage lightning"),
+    ("❄", "snowflake"),
+    ("🎉", "party popper tada"),
+    ("🎂", "birthday cake"),
+    ("🎁", "wrapped gift present"),
+    ("🏆", "trophy"),
+    ("⚽", "soccer ball football"),
+    ("🎮", "video game"),
+    ("🎵", "musical note"),
+    ("🍕", "pizza"),
+    ("🍔", "hamburger"),
+    ("🍟", "french fries"),
+    ("🍿", "popcorn"),
+    ("🍩", "doughnut"),
+    ("🍪", "cookie"),
+    ("🍎", "red apple"),
+    ("🍺", "beer mug"),
+    ("☕", "hot beverage coffee tea"),
+    ("🐶", "dog face"),
+    ("🐱", "cat face"),
+    ("🦊", "fox"),
+    ("🐻", "bear"),
+    ("🐼", "panda"),
+    ("🐸", "frog"),
+    ("🐢", "turtle"),
+    ("🦀", "crab rust"),
+    ("🐛", "bug"),
+    ("🌵", "cactus"),
+    ("🌻", "sunflower"),
+    ("🚀", "rocket"),
+    ("🚗", "car"),
+    ("✈", "airplane"),
+    ("⏰", "alarm clock"),
+    ("💡", "light bulb idea"),
+    ("📌", "pushpin pin"),
+    ("📎", "paperclip"),
+    ("🔒", "locked"),
+    ("🔔", "bell"),
+    ("📷", "camera"),
+    ("💻", "laptop computer"),
+    ("✅", "check mark button"),
+    ("❌", "cross mark"),
+    ("❓", "question mark"),
+    ("❗", "exclamation mark"),
+    ("⚠", "warning"),
+    ("🚫", "prohibited"),
+    ("➕", "plus"),
+    ("➖", "minus"),
+];
+
+/// Most recently used emojis remembered by the picker
+const MAX_RECENT_EMOJIS: usize = 16;
+
+/// Builds a popover to pick an emoji from. It can be searched by name, and shows the most recently
+/// used emojis first. The chosen emoji is passed to `on_select`, and the popover is hidden.
+pub fn build_emoji_picker<F>(on_select: F) -> gtk::Popover
+where
+    F: Fn(&str) + 'static,
+{
+    let popover = gtk::PopoverBuilder::new()
+        .name("emoji_picker")
+        .position(gtk::PositionType::Top)
+        .build();
+
+    let on_select: Rc<dyn Fn(&str)> = {
+        let popover = popover.clone();
+        Rc::new(move |emoji: &str| {
+            remember_emoji(emoji);
+            popover.hide();
+            on_select(emoji);
+        })
+    };
+
+    let search = gtk::SearchEntryBuilder::new()
+        .placeholder_text("Search emoji...")
+        .build();
+
+    let recent_emojis = config::get().recent_emojis.clone();
+    let recent_heading = gtk::LabelBuilder::new()
+        .label("Recently used")
+        .xalign(0.0)
+        .build();
+    recent_heading.get_style_context().add_class("emoji_heading");
+    let recent = recent_emojis.iter().map(|emoji| (emoji.as_str(), name_of(emoji)));
+    let recent = build_grid(recent, &on_select);
+
+    let all = build_grid(EMOJIS.iter().copied(), &on_select);
+
+    let query = Rc::new(RefCell::new(String::new()));
+    {
+        let query = query.clone();
+        all.set_filter_func(Some(Box::new(move |child| {
+            let query = query.borrow();
+            let matches = child.get_tooltip_text().map_or(false, |name| name.contains(&*query));
+            query.is_empty() || matches
+        })));
+    }
+
+    let scroll = gtk::ScrolledWindowBuilder::new()
+        .hscrollbar_policy(gtk::PolicyType::Never)
+        .min_content_height(200)
+        .child(&all)
+        .build();
+
+    let main = gtk::BoxBuilder::new()
+        .orientation(gtk::Orientation::Vertical)
+        .spacing(4)
+        .build();
+
+    main.add(&search);
+    main.add(&recent_heading);
+    main.add(&recent);
+    main.add(&scroll);
+    popover.add(&main);
+    main.show_all();
+
+    let has_recent = !recent_emojis.is_empty();
+    recent_heading.set_visible(has_recent);
+    recent.set_visible(has_recent);
+
+    // Pressing enter in the search picks the first emoji that matches
+    let all_cloned = all.clone();
+    search.connect_activate(move |_| {
+        let first = all_cloned.get_children()
+            .into_iter()
+            .find(|child| child.get_child_visible())
+            .and_then(|child| child.downcast::<gtk::FlowBoxChild>().ok());
+
+        if let Some(child) = first {
+            child.emit_activate();
+        }
+    });
+
+    search.connect_search_changed(move |search| {
+        let text = search.get_text().map(|text| text.to_lowercase()).unwrap_or_default();
+        recent_heading.set_visible(has_recent && text.is_empty());
+        recent.set_visible(has_recent && text.is_empty());
+
+        *query.borrow_mut() = text;
+        all.invalidate_filter();
+    });
+
+    let popover_cloned = popover.clone();
+    search.connect_stop_search(move |_| popover_cloned.hide());
+
+    popover.connect_show(move |_| search.grab_focus());
+
+    popover
+}
+
+fn build_grid<'a, I>(emojis: I, on_select: &Rc<dyn Fn(&str)>) -> gtk::FlowBox
+where
+    I: Iterator<Item = (&'a str, &'a str)>,
+{
+    let grid = gtk::FlowBoxBuilder::new()
+        .min_children_per_line(8)
+        .max_children_per_line(8)
+        .selection_mode(gtk::SelectionMode::Single)
+        .activate_on_single_click(true)
+        .homogeneous(true)
+        .build();
+
+    for (emoji, name) in emojis {
+        let label = gtk::Label::new(Some(emoji));
+        let child = gtk::FlowBoxChild::new();
+        child.add(&label);
+        child.get_style_context().add_class("emoji");
+
+        if !name.is_empty() {
+            child.set_tooltip_text(Some(name));
+        }
+
+        grid.add(&child);
+    }
+
+    let on_select = on_select.clone();
+    grid.connect_child_activated(move |_, child| {
+        let emoji = child.get_child()
+            .and_then(|label| label.downcast::<gtk::Label>().ok())
+            .and_then(|label| label.get_text());
+
+        if let Some(emoji) = emoji {
+            on_select(emoji.as_str());
+        }
+    });
+
+    grid
+}
+
+fn name_of(emoji: &str) -> &'static str {
+    EMOJIS.iter()
+        .find(|(known, _)| *known == emoji)
+        .map_or("", |(_, name)| *name)
+}
+
+/// Moves the emoji to the front of the recently used list, which is stored in the config
+fn remember_emoji(emoji: &str) {
+    config::modify(|config| {
+        config.recent_emojis.retain(|recent| recent != emoji);
+        config.recent_emojis.insert(0, emoji.to_string());
+        config.recent_emojis.truncate(MAX_RECENT_EMOJIS);
+    });
+}
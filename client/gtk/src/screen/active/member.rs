@@ -0,0 +1,126 @@
+use gtk::prelude::*;
+
+use vertex::prelude::*;
+
+use crate::client::MemberList;
+
+use super::*;
+
+#[derive(Clone)]
+pub struct MemberListWidget {
+    pub main: gtk::ScrolledWindow,
+    online: gtk::ListBox,
+    offline: gtk::ListBox,
+}
+
+#[derive(Clone)]
+pub struct MemberEntryWidget {
+    row: gtk::ListBoxRow,
+}
+
+impl MemberListWidget {
+    pub fn build() -> Self {
+        let online = gtk::ListBoxBuilder::new()
+            .name("online_members")
+            .selection_mode(gtk::SelectionMode::None)
+            .build();
+
+        let offline = gtk::ListBoxBuilder::new()
+            .name("offline_members")
+            .selection_mode(gtk::SelectionMode::None)
+            .build();
+
+        let list = gtk::BoxBuilder::new()
+            .orientation(gtk::Orientation::Vertical)
+            .build();
+
+        list.add(&build_heading("Online"));
+        list.add(&online);
+        list.add(&build_heading("Offline"));
+        list.add(&offline);
+
+        let main = gtk::ScrolledWindowBuilder::new()
+            .name("members")
+            .hscrollbar_policy(gtk::PolicyType::Never)
+            .vexpand(true)
+            .child(&list)
+            .build();
+
+        main.show_all();
+
+        MemberListWidget { main, online, offline }
+    }
+
+    pub fn bind_events(&self, members: &MemberList) {
+        self.main.connect_edge_reached(
+            members.clone().connector()
+                .do_async(|members, (_scroll, position)| async move {
+                    if position == gtk::PositionType::Bottom {
+                        if let Err(err) = members.load_more().await {
+                            log::warn!("failed to load community members: {:?}", err);
+                        }
+                    }
+                })
+                .build_widget_and_owned_listener()
+        );
+    }
+
+    pub fn add_member(&self, client: &Client, member: &MemberInfo) -> MemberEntryWidget {
+        let name = gtk::LabelBuilder::new()
+            .label(&member.display_name)
+            .xalign(0.0)
+            .ellipsize(pango::EllipsizeMode::End)
+            .build();
+
+        let button = gtk::ButtonBuilder::new()
+            .name("member")
+            .child(&name)
+            .relief(gtk::ReliefStyle::None)
+            .build();
+
+        let user = member.user;
+        button.connect_clicked(
+            client.connector()
+                .do_async(move |client, _| async move {
+                    match client.profiles.get_latest(user).await {
+                        Ok(profile) => dialog::show_profile(profile),
+                        Err(err) => dialog::show_generic_error(&err),
+                    }
+                })
+                .build_cloned_consumer()
+        );
+
+        let row = gtk::ListBoxRowBuilder::new()
+            .child(&button)
+            .build();
+
+        row.show_all();
+        self.list_for(member.online).add(&row);
+
+        MemberEntryWidget { row }
+    }
+
+    /// Moves the member into the online or offline group, at the given position in it
+    pub fn move_member(&self, member: &MemberEntryWidget, online: bool, position: usize) {
+        self.list_for(!online).remove(&member.row);
+        self.list_for(online).insert(&member.row, position as i32);
+    }
+
+    fn list_for(&self, online: bool) -> &gtk::ListBox {
+        if online {
+            &self.online
+        } else {
+            &self.offline
+        }
+    }
+}
+
+fn build_heading(text: &str) -> gtk::Label {
+    let heading = gtk::LabelBuilder::new()
+        .label(text)
+        .xalign(0.0)
+        .build();
+
+    heading.get_style_context().add_class("member_heading");
+    heading
+}
@@ -1,20 +1,32 @@
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
 use chrono::{DateTime, Utc, Duration, Datelike, Local};
+use gdk::enums::key;
 use gtk::prelude::*;
 
 use vertex::prelude::*;
 
-use crate::client::{ChatSide, InviteEmbed, MessageEmbed, MessageStatus, OpenGraphEmbed};
-use crate::{Glade, resource};
+use crate::client::{
+    ChatSide, InviteEmbed, MessageEmbed, MessageStatus, OpenGraphEmbed, RichMessage, RoomEntry,
+};
+use crate::{Glade, TryGetText, resource, scheduler};
 
 use super::*;
 use pango::WrapMode;
 use ordinal::Ordinal;
 use atk::AtkObjectExt;
 
+/// Longest gap between two messages from the same author for them to be shown in one group
+const MAX_GROUP_GAP_MINUTES: i64 = 10;
+
 #[derive(Clone, PartialEq, Eq)]
 pub struct MessageGroupWidget {
     author: UserId,
-    origin_time: DateTime<Utc>,
+    kind: MessageKind,
+    /// When the oldest and newest messages in the group were sent
+    first_time: DateTime<Utc>,
+    last_time: DateTime<Utc>,
     interactable: bool,
     flavour: MessageGroupFlavour,
     messages: Vec<MessageId>,
@@ -25,6 +37,7 @@ enum MessageGroupFlavour {
     Widget {
         widget: gtk::Box,
         entry_list: gtk::ListBox,
+        timestamp: gtk::Label,
     },
     Inline {
         title: gtk::Label,
@@ -51,8 +64,10 @@ impl Eq for MessageGroupFlavour {}
 
 impl MessageGroupWidget {
     pub fn build(
+        client: &Client,
         author: UserId,
         profile: Profile,
+        kind: MessageKind,
         origin_time: DateTime<Utc>,
         interactable: bool,
         is_inline: bool,
@@ -61,6 +76,16 @@ impl MessageGroupWidget {
             static ref GLADE: Glade = Glade::open("active/message_entry.glade").unwrap();
         }
 
+        // System messages are shown as coming from the server rather than the admin who sent them
+        let profile = match kind {
+            MessageKind::System => Profile {
+                display_name: "System".to_string(),
+                avatar: None,
+                ..profile
+            },
+            MessageKind::Normal => profile,
+        };
+
         if is_inline {
             let title = format!(
                 "{} at {} said",
@@ -78,7 +103,9 @@ impl MessageGroupWidget {
 
             MessageGroupWidget {
                 author,
-                origin_time,
+                kind,
+                first_time: origin_time,
+                last_time: origin_time,
                 flavour,
                 messages: Vec::new(),
                 interactable
@@ -93,20 +120,36 @@ impl MessageGroupWidget {
             author_name.set_text(&profile.display_name);
             author_name.set_can_focus(false);
 
-            let timestamp: gtk::Label = builder.get_object("timestamp").unwrap();
+            if let Some(avatar) = profile.avatar {
+                let author_icon: gtk::Frame = builder.get_object("author_icon").unwrap();
+                let client = client.clone();
+                scheduler::spawn(async move {
+                    if let Some(avatar) = client.avatars.get(avatar).await {
+                        author_icon.add(&gtk::Image::new_from_pixbuf(Some(&avatar)));
+                        author_icon.show_all();
+                    }
+                });
+            }
 
-            let time_text = pretty_date(origin_time);
-            timestamp.set_text(&time_text);
+            let timestamp: gtk::Label = builder.get_object("timestamp").unwrap();
+            set_timestamp(&timestamp, origin_time);
             widget.hide();
 
+            if kind == MessageKind::System {
+                widget.get_style_context().add_class("system_message");
+            }
+
             let flavour = MessageGroupFlavour::Widget {
                 widget,
-                entry_list
+                entry_list,
+                timestamp,
             };
 
             MessageGroupWidget {
                 author,
-                origin_time,
+                kind,
+                first_time: origin_time,
+                last_time: origin_time,
                 flavour,
                 messages: Vec::new(),
                 interactable,
@@ -114,19 +157,42 @@ impl MessageGroupWidget {
         }
     }
 
-    pub fn can_combine(&self, user: UserId, time: DateTime<Utc>) -> bool {
-        self.author == user && (time - self.origin_time).num_minutes().abs() < 10
+    /// Whether a message can join the group on the given side. It must be from the same author,
+    /// and sent soon enough after the message it would be next to.
+    pub fn can_combine(
+        &self,
+        user: UserId,
+        kind: MessageKind,
+        time: DateTime<Utc>,
+        side: ChatSide,
+    ) -> bool {
+        let neighbour = match side {
+            ChatSide::Front => self.last_time,
+            ChatSide::Back => self.first_time,
+        };
+
+        self.author == user
+            && self.kind == kind
+            && (time - neighbour).num_minutes().abs() < MAX_GROUP_GAP_MINUTES
     }
 
     pub fn add_message(
         &mut self,
         content: Option<String>,
         id: MessageId,
+        time: DateTime<Utc>,
         side: ChatSide,
         list: &gtk::ListBox,
         client: Client,
     ) -> MessageEntryWidget {
-        let entry = MessageEntryWidget::build(client, content, id, self.interactable);
+        let editable = self.author == client.user.id && self.kind == MessageKind::Normal;
+        let entry = MessageEntryWidget::build(client, content, id, self.interactable, editable);
+
+        if time < self.first_time {
+            self.first_time = time;
+            self.refresh_timestamp();
+        }
+        self.last_time = self.last_time.max(time);
 
         match &mut self.flavour {
             MessageGroupFlavour::Inline { title, messages } => {
@@ -170,6 +236,13 @@ impl MessageGroupWidget {
         self.messages.is_empty()
     }
 
+    /// Updates how long ago the group is shown as being sent
+    pub fn refresh_timestamp(&self) {
+        if let MessageGroupFlavour::Widget { timestamp, .. } = &self.flavour {
+            set_timestamp(timestamp, self.first_time);
+        }
+    }
+
     pub fn position_of(&self, id: &MessageId) -> Option<usize> {
         self.messages.iter().position(|i| i == id)
     }
@@ -216,7 +289,7 @@ impl MessageGroupWidget {
         id: MessageId,
         client: Client,
     ) {
-        let entry = MessageEntryWidget::build(client, content, id, self.interactable);
+        let entry = MessageEntryWidget::build(client, content, id, self.interactable, false);
 
         match &self.flavour {
             MessageGroupFlavour::Inline { title, .. } => {
@@ -265,15 +338,21 @@ impl MessageGroupWidget {
 #[derive(Clone, PartialEq, Eq)]
 pub struct MessageEntryWidget {
     widget: gtk::Box,
+    body: gtk::Box,
     text: gtk::Label,
+    content: Rc<RefCell<Option<String>>>,
+    edited: Rc<Cell<bool>>,
+    /// Buttons to retry or cancel sending the message, shown when it fails to send
+    send_controls: Rc<RefCell<Option<gtk::Box>>>,
 }
 
 impl MessageEntryWidget {
     pub fn build(
         client: Client,
-        text: Option<String>,
+        content: Option<String>,
         id: MessageId,
         interactable: bool,
+        editable: bool,
     ) -> Self {
         thread_local! {
             static ICON: gdk_pixbuf::Pixbuf = gdk_pixbuf::Pixbuf::new_from_file_at_size(
@@ -295,7 +374,8 @@ impl MessageEntryWidget {
 
         let text = gtk::LabelBuilder::new()
             .name("message_text")
-            .label(text.unwrap_or_else(|| "<Deleted>".to_string()).trim()) // TODO deletion
+            .label(&RichMessage::parse(content.clone()).markup())
+            .use_markup(true)
             .halign(gtk::Align::Start)
             .hexpand(true)
             .selectable(true)
@@ -311,6 +391,20 @@ impl MessageEntryWidget {
 
         let icon = ICON.with(|icon| gtk::Image::new_from_pixbuf(Some(&icon)));
 
+        hbox.add(&text);
+        hbox.add(&settings_vbox);
+        vbox.add(&hbox);
+
+        let editable = editable && content.is_some();
+        let entry = MessageEntryWidget {
+            widget: vbox,
+            body: hbox,
+            text,
+            content: Rc::new(RefCell::new(content)),
+            edited: Rc::new(Cell::new(false)),
+            send_controls: Rc::new(RefCell::new(None)),
+        };
+
         if interactable {
             let settings_button = gtk::ButtonBuilder::new()
                 .child(&icon)
@@ -321,14 +415,13 @@ impl MessageEntryWidget {
             settings_button.get_accessible().unwrap().set_name("Message menu");
 
             settings_button.connect_clicked(
-                client.connector()
-                    .do_sync(move |client, button: gtk::Button| {
+                (client, entry.clone()).connector()
+                    .do_sync(move |(client, entry), button: gtk::Button| {
                         button.get_style_context().add_class("active");
-                        let menu = Self::build_menu(client, id);
+                        let menu = entry.build_menu(client, id, editable);
                         menu.set_relative_to(Some(&button));
                         menu.show();
 
-                        let button = button.clone();
                         menu.connect_hide(move |popover| {
                             // weird gtk behavior: if we don't do this, it messes with dialog rendering order
                             popover.set_relative_to::<gtk::Widget>(None);
@@ -341,14 +434,10 @@ impl MessageEntryWidget {
             settings_vbox.add(&settings_button);
         }
 
-        hbox.add(&text);
-        hbox.add(&settings_vbox);
-        vbox.add(&hbox);
-
-        MessageEntryWidget { widget: vbox, text }
+        entry
     }
 
-    fn build_menu(client: Client, msg: MessageId) -> gtk::Popover {
+    fn build_menu(&self, client: Client, msg: MessageId, editable: bool) -> gtk::Popover {
         lazy_static! {
             static ref GLADE: Glade = Glade::open("active/message_menu.glade").unwrap();
         }
@@ -358,14 +447,61 @@ impl MessageEntryWidget {
                 18,
                 18,
             ).expect("Error loading flag.svg!");
+            static COPY_ICON: gdk_pixbuf::Pixbuf = gdk_pixbuf::Pixbuf::new_from_file_at_size(
+                &resource("feather/copy.svg"),
+                18,
+                18,
+            ).expect("Error loading copy.svg!");
+            static EDIT_ICON: gdk_pixbuf::Pixbuf = gdk_pixbuf::Pixbuf::new_from_file_at_size(
+                &resource("feather/edit-2.svg"),
+                18,
+                18,
+            ).expect("Error loading edit-2.svg!");
         }
 
         let builder: gtk::Builder = GLADE.builder();
         let menu: gtk::Popover = builder.get_object("message_menu").unwrap();
+        let copy_button: gtk::Button = builder.get_object("copy_button").unwrap();
+        let copy_img: gtk::Image = builder.get_object("copy_icon").unwrap();
+        let edit_button: gtk::Button = builder.get_object("edit_button").unwrap();
+        let edit_img: gtk::Image = builder.get_object("edit_icon").unwrap();
         let report_button: gtk::Button = builder.get_object("report_button").unwrap();
         let img: gtk::Image = builder.get_object("report_icon").unwrap();
 
         ICON.with(|icon| img.set_from_pixbuf(Some(&icon)));
+        COPY_ICON.with(|icon| copy_img.set_from_pixbuf(Some(&icon)));
+        EDIT_ICON.with(|icon| edit_img.set_from_pixbuf(Some(&icon)));
+
+        // Copies the text as it is now, so that edits are included
+        let content = self.content.clone();
+        if content.borrow().is_some() {
+            copy_button.connect_clicked(
+                menu.connector()
+                    .do_sync(move |menu, _| {
+                        if let Some(text) = content.borrow().as_ref() {
+                            let clipboard = gtk::Clipboard::get(&gdk::SELECTION_CLIPBOARD);
+                            clipboard.set_text(text);
+                        }
+                        menu.hide();
+                    })
+                    .build_cloned_consumer()
+            );
+        } else {
+            copy_button.hide();
+        }
+
+        if editable {
+            edit_button.connect_clicked(
+                (menu.clone(), client.clone(), self.clone()).connector()
+                    .do_sync(move |(menu, client, entry), _| {
+                        menu.hide();
+                        entry.start_editing(client, msg);
+                    })
+                    .build_cloned_consumer()
+            );
+        } else {
+            edit_button.hide();
+        }
 
         report_button.connect_clicked(
             (menu.clone(), client).connector()
@@ -379,6 +515,97 @@ impl MessageEntryWidget {
         menu
     }
 
+    /// Swaps the text of the message out for an entry to edit it in. The edit is shown straight
+    /// away and reverted if the server rejects it.
+    fn start_editing(&self, client: Client, id: MessageId) {
+        let original = match self.content.borrow().clone() {
+            Some(content) => content,
+            None => return,
+        };
+
+        let editor = gtk::EntryBuilder::new()
+            .name("message_editor")
+            .text(&original)
+            .hexpand(true)
+            .build();
+
+        self.text.hide();
+        self.body.add(&editor);
+        self.body.reorder_child(&editor, 0);
+        editor.show();
+        editor.grab_focus();
+
+        editor.connect_activate(
+            (self.clone(), client).connector()
+                .do_async(move |(entry, client), editor: gtk::Entry| {
+                    let original = original.clone();
+                    async move {
+                        let new_content = editor.try_get_text().unwrap_or_default();
+                        entry.stop_editing(&editor);
+
+                        if new_content.trim().is_empty() || new_content == original {
+                            return;
+                        }
+
+                        let was_edited = entry.edited.get();
+                        entry.set_content(new_content.clone(), true);
+
+                        let result = match client.selected_room().await {
+                            Some(room) => room.edit_message(id, new_content).await,
+                            None => Ok(()),
+                        };
+
+                        if let Err(err) = result {
+                            entry.set_content(original, was_edited);
+                            dialog::show_generic_error(&err);
+                        }
+                    }
+                })
+                .build_cloned_consumer()
+        );
+
+        let entry = self.clone();
+        editor.connect_key_press_event(move |editor, key_event| {
+            if key_event.get_keyval() == key::Escape {
+                entry.stop_editing(editor);
+                Inhibit(true)
+            } else {
+                Inhibit(false)
+            }
+        });
+    }
+
+    fn stop_editing(&self, editor: &gtk::Entry) {
+        self.body.remove(editor);
+        self.text.show();
+    }
+
+    /// Replaces the text of the message, marking it as edited if it has been
+    pub fn set_content(&self, content: String, edited: bool) {
+        let mut markup = RichMessage::parse(Some(content.clone())).markup();
+        if edited {
+            markup.push_str(" <small>(edited)</small>");
+        }
+
+        self.text.set_markup(&markup);
+        self.content.replace(Some(content));
+        self.edited.set(edited);
+    }
+
+    /// Shows the message that this one replies to above its text
+    pub fn set_reply(&self, quote: &str) {
+        let reply = gtk::LabelBuilder::new()
+            .name("message_reply")
+            .label(quote)
+            .halign(gtk::Align::Start)
+            .ellipsize(pango::EllipsizeMode::End)
+            .build();
+
+        self.widget.add(&reply);
+        self.widget.reorder_child(&reply, 0);
+        reply.show();
+    }
+
     pub fn push_embed(&self, client: &Client, embed: MessageEmbed) {
         let embed = build_embed(client, embed);
         if let Some(embed) = embed {
@@ -391,12 +618,49 @@ impl MessageEntryWidget {
         style.remove_class("pending");
         style.remove_class("error");
 
+        if let Some(controls) = self.send_controls.borrow_mut().take() {
+            self.widget.remove(&controls);
+        }
+
         match status {
             MessageStatus::Pending => style.add_class("pending"),
             MessageStatus::Err => style.add_class("error"),
             _ => (),
         }
     }
+
+    /// Marks the message as failed to send, letting the user send it again or give up on it
+    pub fn set_send_failed(&self, room: RoomEntry, id: MessageId) {
+        self.set_status(MessageStatus::Err);
+
+        let controls = gtk::BoxBuilder::new()
+            .name("message_send_failed")
+            .orientation(gtk::Orientation::Horizontal)
+            .spacing(6)
+            .halign(gtk::Align::Start)
+            .build();
+
+        let retry = gtk::Button::new_with_label("Retry");
+        retry.connect_clicked(
+            room.connector()
+                .do_async(move |room, _| async move { room.retry_message(id).await })
+                .build_cloned_consumer()
+        );
+
+        let cancel = gtk::Button::new_with_label("Cancel");
+        cancel.connect_clicked(
+            room.connector()
+                .do_async(move |room, _| async move { room.cancel_message(id).await })
+                .build_cloned_consumer()
+        );
+
+        controls.add(&retry);
+        controls.add(&cancel);
+        self.widget.add(&controls);
+        controls.show_all();
+
+        *self.send_controls.borrow_mut() = Some(controls);
+    }
 }
 
 fn build_embed(client: &Client, embed: MessageEmbed) -> Option<gtk::Widget> {
@@ -427,7 +691,7 @@ fn build_opengraph_embed(embed: OpenGraphEmbed) -> gtk::Widget {
 
     let image_box: gtk::Box = builder.get_object("image_box").unwrap();
 
-    if embed.description == "" {
+    if embed.description.is_empty() {
         opengraph.remove(&description);
     }
 
@@ -479,6 +743,25 @@ fn build_invite_embed(client: &Client, embed: InviteEmbed) -> gtk::Widget {
     invite.upcast()
 }
 
+fn set_timestamp(label: &gtk::Label, time: DateTime<Utc>) {
+    label.set_text(&relative_date(time));
+    label.set_tooltip_text(Some(&pretty_date(time)));
+}
+
+/// Describes how long ago a message was sent if that was within the hour, or when it was sent
+/// otherwise
+fn relative_date(msg: DateTime<Utc>) -> String {
+    let ago = Utc::now() - msg;
+
+    if ago < Duration::minutes(1) {
+        "Just now".to_string()
+    } else if ago < Duration::hours(1) {
+        format!("{} min ago", ago.num_minutes())
+    } else {
+        pretty_date(msg)
+    }
+}
+
 fn pretty_date(msg: DateTime<Utc>) -> String {
     let now = Local::now();
     let msg: DateTime<Local> = msg.into();
@@ -494,7 +777,7 @@ fn pretty_date(msg: DateTime<Utc>) -> String {
 
             if msg_week == week {
                 msg.format("%H:%M, %A").to_string() // e.g 13:34, Sunday
-            } else if msg_week - week == 1 {
+            } else if week - msg_week == 1 {
                 msg.format("%H:%M, %A, last week").to_string() // e.g 13:34, Sunday, last week
             } else {
                 let day = Ordinal(msg.day());
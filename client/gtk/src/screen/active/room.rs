@@ -39,4 +39,8 @@ impl RoomEntryWidget {
 
         RoomEntryWidget { container, label }
     }
+
+    pub fn set_topic(&self, topic: Option<&str>) {
+        self.container.set_tooltip_text(topic);
+    }
 }
@@ -2,7 +2,7 @@ use gtk::prelude::*;
 
 use lazy_static::lazy_static;
 
-use crate::{Result, Server, TryGetText, window, AuthParameters};
+use crate::{Result, Server, TryGetText, window};
 use crate::connect::AsConnector;
 use crate::Glade;
 use crate::screen;
@@ -21,7 +21,7 @@ pub struct Screen {
     spinner: gtk::Spinner,
 }
 
-pub async fn build(params: AuthParameters) -> Screen {
+pub async fn build(instance: Server, username: String) -> Screen {
     lazy_static! {
         static ref GLADE: Glade = Glade::open("login/compromised.glade").unwrap();
     }
@@ -40,15 +40,15 @@ pub async fn build(params: AuthParameters) -> Screen {
         spinner: builder.get_object("spinner").unwrap(),
     };
 
-    bind_events(&screen, params).await;
+    bind_events(&screen, instance, username).await;
 
     screen
 }
 
-async fn bind_events(screen: &Screen, params: AuthParameters) {
+async fn bind_events(screen: &Screen, instance: Server, username: String) {
     screen.change_button.connect_clicked(
-        (screen.clone(), params).connector()
-            .do_async(|(screen, params), _| async move {
+        (screen.clone(), instance, username).connector()
+            .do_async(|(screen, instance, username), _| async move {
                 let old = screen.old_password_entry.try_get_text().unwrap_or_default();
                 let new = screen.new_password_entry.try_get_text().unwrap_or_default();
                 let repeat = screen.repeat_password_entry.try_get_text().unwrap_or_default();
@@ -62,9 +62,6 @@ async fn bind_events(screen: &Screen, params: AuthParameters) {
                     return;
                 };
 
-                let username = params.username;
-
-                let instance = params.instance;
                 let res = change_password(username.clone(), old, new.clone(), instance.clone()).await;
                 if let Err(err) = res {
                     log::error!("Encountered error changing password: {:?}", err);
@@ -2,11 +2,15 @@ use gtk::prelude::*;
 
 use lazy_static::lazy_static;
 
-use crate::{AuthParameters, Error, Result, Server, token_store, TryGetText, window};
+use vertex::prelude::*;
+
+use crate::{AuthParameters, Error, Result, Server, config, token_store, TryGetText, window};
 use crate::connect::AsConnector;
 use crate::Glade;
 use crate::screen;
 
+const MAX_RECENT_SERVERS: usize = 8;
+
 #[derive(Clone)]
 pub struct Screen {
     pub main: gtk::Viewport,
@@ -39,6 +43,7 @@ pub async fn build() -> Screen {
         spinner: builder.get_object("spinner").unwrap(),
     };
 
+    bind_recent_servers(&screen.instance_entry);
     bind_events(&screen).await;
 
     screen
@@ -55,7 +60,7 @@ async fn bind_events(screen: &Screen) {
                 screen.status_stack.set_visible_child(&screen.spinner);
                 screen.error_label.set_text("");
 
-                let instance = match Server::parse(instance_ip) {
+                let instance = match Server::parse(instance_ip.clone()) {
                     Ok(instance) => instance,
                     Err(err) => {
                         log::error!("Encountered error during login: {:?}", err);
@@ -65,10 +70,16 @@ async fn bind_events(screen: &Screen) {
                     }
                 };
 
-                match login(instance, username, password).await {
+                match login(instance.clone(), username.clone(), password).await {
                     Ok(parameters) => {
+                        remember_server(&instance_ip);
                         screen::active::start(parameters).await;
                     }
+                    Err(Error::AuthErrorResponse(AuthError::PasswordResetRequired)) => {
+                        let screen = screen::compromised::build(instance, username).await;
+                        window::set_screen(&screen.main);
+                        return;
+                    }
                     Err(err) => {
                         log::error!("Encountered error during login: {:?}", err);
                         screen.error_label.set_text(&describe_error(err));
@@ -97,10 +108,14 @@ pub async fn login(
 ) -> Result<AuthParameters> {
     let auth = crate::auth::Client::new(instance.clone());
 
-    use vertex::prelude::*;
+    let options = TokenCreationOptions {
+        device_name: glib::get_host_name().map(String::from),
+        ..Default::default()
+    };
+
     let token = auth.create_token(
         Credentials::new(username.clone(), password),
-        TokenCreationOptions::default(),
+        options,
     ).await?;
 
     let parameters = AuthParameters {
@@ -115,10 +130,48 @@ pub async fn login(
     Ok(parameters)
 }
 
+/// Fills in the server last used, and suggests the other recent ones while typing
+pub fn bind_recent_servers(entry: &gtk::Entry) {
+    let recent = config::get().recent_servers.clone();
+    if let Some(last) = recent.first() {
+        entry.set_text(last);
+    }
+
+    let servers = gtk::ListStore::new(&[String::static_type()]);
+    for server in &recent {
+        servers.insert_with_values(None, &[0], &[server]);
+    }
+
+    let completion = gtk::EntryCompletion::new();
+    completion.set_model(Some(&servers));
+    completion.set_text_column(0);
+    completion.set_minimum_key_length(0);
+    entry.set_completion(Some(&completion));
+}
+
+pub fn remember_server(server: &str) {
+    let server = server.trim().to_owned();
+    config::modify(|config| {
+        config.recent_servers.retain(|recent| *recent != server);
+        config.recent_servers.insert(0, server);
+        config.recent_servers.truncate(MAX_RECENT_SERVERS);
+    });
+}
+
 pub fn describe_error(error: Error) -> String {
     match error {
-        Error::InvalidUrl => "Invalid instance ip".to_owned(),
-        Error::ProtocolError(_) => "Protocol error: check your server instance?".to_owned(),
+        Error::InvalidUrl => "Invalid server address".to_owned(),
+        Error::Http(http) if http.is_connect() => {
+            "Couldn't reach the server: check the address, and that it is running".to_owned()
+        }
+        Error::ProtocolError(_) | Error::DeserializeError(_) => {
+            "The server didn't respond like a Vertex server: check the address?".to_owned()
+        }
+        Error::AuthErrorResponse(AuthError::IncompatibleProtocol { server }) => format!(
+            "This client speaks version {} of the protocol, but the server speaks {}",
+            vertex::PROTOCOL_VERSION,
+            server,
+        ),
         error => format!("{}", error),
     }
 }
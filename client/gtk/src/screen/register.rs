@@ -7,6 +7,8 @@ use crate::connect::AsConnector;
 use crate::Glade;
 use crate::screen;
 
+use vertex::prelude::*;
+
 #[derive(Clone)]
 pub struct Screen {
     pub main: gtk::Viewport,
@@ -40,6 +42,7 @@ pub async fn build() -> Screen {
         spinner: builder.get_object("spinner").unwrap(),
     };
 
+    screen::login::bind_recent_servers(&screen.instance_entry);
     bind_events(&screen).await;
 
     screen
@@ -74,13 +77,14 @@ async fn bind_events(screen: &Screen) {
                     return;
                 };
 
-                match register(instance_ip, username, password).await {
+                match register(instance_ip.clone(), username, password).await {
                     Ok(parameters) => {
+                        screen::login::remember_server(&instance_ip);
                         screen::active::start(parameters).await;
                     }
                     Err(err) => {
                         log::error!("Encountered error during register: {:?}", err);
-                        screen.error_label.set_text(&describe_error(err));
+                        screen.error_label.set_text(&screen::login::describe_error(err));
                     }
                 }
 
@@ -95,13 +99,14 @@ async fn register(
     username: String,
     password: String,
 ) -> Result<AuthParameters> {
-    use vertex::prelude::*;
-
     let instance = Server::parse(instance)?;
     let credentials = Credentials::new(username.clone(), password);
 
     let auth = crate::auth::Client::new(instance.clone());
 
+    let rules = auth.account_rules().await?;
+    check_account_rules(&rules, &credentials)?;
+
     auth.register(credentials.clone(), None).await?;
 
     let token = auth.create_token(
@@ -121,10 +126,22 @@ async fn register(
     Ok(parameters)
 }
 
-fn describe_error(error: Error) -> String {
-    match error {
-        Error::InvalidUrl => "Invalid instance ip".to_owned(),
-        Error::ProtocolError(_) => "Protocol error: check your server instance?".to_owned(),
-        error => format!("{}", error),
+fn check_account_rules(rules: &AccountRules, credentials: &Credentials) -> Result<()> {
+    if !rules.valid_username(&credentials.username) {
+        return Err(Error::InvalidInput(format!(
+            "Usernames must be between {} and {} characters",
+            rules.min_username_len,
+            rules.max_username_len,
+        )));
     }
+
+    if !rules.valid_password(&credentials.password) {
+        return Err(Error::InvalidInput(format!(
+            "Passwords must be between {} and {} characters",
+            rules.min_password_len,
+            rules.max_password_len,
+        )));
+    }
+
+    Ok(())
 }
@@ -2,7 +2,7 @@ mod administration;
 
 use gtk::prelude::*;
 use lazy_static::lazy_static;
-use crate::{Client, SharedMut, token_store, window};
+use crate::{Client, SharedMut, screen, token_store, window};
 use crate::config;
 use crate::connect::AsConnector;
 use crate::Glade;
@@ -31,6 +31,16 @@ pub async fn build(client: Client) -> Screen {
     let log_out: gtk::Button = builder.get_object("log_out_button").unwrap();
     let category_list: gtk::ListBox = builder.get_object("category_list").unwrap();
 
+    let label = gtk::LabelBuilder::new()
+        .label("Accounts")
+        .halign(Align::Start)
+        .build();
+
+    let pos = category_list.get_children().len() as i32;
+    category_list.insert(&label, pos);
+    category_list.get_row_at_index(pos).unwrap().set_widget_name("accounts");
+    category_list.show_all();
+
     let perms = client.state.upgrade().unwrap().read().await.admin_perms;
     if !perms.is_empty() {
         let label = gtk::LabelBuilder::new()
@@ -92,7 +102,9 @@ fn bind_events(screen: &Screen, perms: vertex::requests::AdminPermissionFlags) {
 
                     let widget = match name.as_str() {
                         "admin" => Some(build_administration(screen.client, perms)),
+                        "notifications" => Some(build_notifications()),
                         "a11y" => Some(build_accessibility()),
+                        "accounts" => Some(build_accounts(screen.client)),
                         _ => None,
                     };
 
@@ -110,6 +122,76 @@ fn bind_events(screen: &Screen, perms: vertex::requests::AdminPermissionFlags) {
     );
 }
 
+fn build_accounts(client: Client) -> gtk::Widget {
+    let list = gtk::Box::new(Orientation::Vertical, 10);
+    let active = token_store::active_account();
+
+    for account in token_store::stored_accounts() {
+        let row = gtk::Box::new(Orientation::Horizontal, 10);
+
+        let host = account.instance.url().host_str().unwrap_or_default().to_owned();
+        let label = gtk::LabelBuilder::new()
+            .label(&format!("{} on {}", account.username, host))
+            .halign(Align::Start)
+            .hexpand(true)
+            .build();
+        row.add(&label);
+
+        if Some(&account) != active.as_ref() {
+            let switch = gtk::Button::new_with_label("Switch");
+            switch.connect_clicked(
+                (client.clone(), account).connector()
+                    .do_async(|(client, account), _| async move {
+                        screen::active::switch_account(client, Some(account)).await;
+                    })
+                    .build_cloned_consumer()
+            );
+            row.add(&switch);
+        }
+
+        list.add(&row);
+    }
+
+    let add = gtk::Button::new_with_label("Add account");
+    add.set_halign(Align::Start);
+    add.connect_clicked(
+        client.connector()
+            .do_async(|client, _| screen::active::switch_account(client, None))
+            .build_cloned_consumer()
+    );
+    list.add(&add);
+
+    list.show_all();
+    list.upcast()
+}
+
+fn build_notifications() -> gtk::Widget {
+    lazy_static! {
+        static ref GLADE: Glade = Glade::open("settings/notifications.glade").unwrap();
+    }
+
+    let builder: gtk::Builder = GLADE.builder();
+    let viewport: gtk::Box = builder.get_object("main").unwrap();
+
+    let show_notifications: gtk::Switch = builder.get_object("show_notifications").unwrap();
+    let notification_sound: gtk::Switch = builder.get_object("notification_sound").unwrap();
+
+    let config = config::get();
+    show_notifications.set_state(config.show_notifications);
+    notification_sound.set_state(config.notification_sound);
+
+    show_notifications.connect_state_set(|_switch, state| {
+        config::modify(|config| config.show_notifications = state);
+        gtk::Inhibit(false)
+    });
+    notification_sound.connect_state_set(|_switch, state| {
+        config::modify(|config| config.notification_sound = state);
+        gtk::Inhibit(false)
+    });
+
+    viewport.upcast()
+}
+
 fn build_accessibility() -> gtk::Widget {
     lazy_static! {
         static ref GLADE: Glade = Glade::open("settings/a11y.glade").unwrap();
@@ -138,7 +138,7 @@ async fn perform_action(
     drop(map); // Drop lock
 
     let res = match action {
-        Action::Ban => client.ban_users(selected).await,
+        Action::Ban => client.ban_users(selected, "", None).await,
         Action::Unban => client.unban_users(selected).await,
         Action::Unlock => client.unlock_users(selected).await,
         Action::Demote => client.demote_users(selected).await,
@@ -88,10 +88,14 @@ impl ReportsList {
                 version: ProfileVersion(0), // doesn't matter
                 username: report.reported.username.clone(),
                 display_name: report.reported.username, // its fine
+                avatar: None,
+                last_seen: None,
             };
             let msg = MessageGroupWidget::build(
+                &self.client,
                 report.reported.id,
                 profile,
+                MessageKind::Normal,
                 report.message.sent_at,
                 false,
                 config::get().screen_reader_message_list,
@@ -111,7 +115,7 @@ impl ReportsList {
                 status,
                 report.status,
                 report.id,
-                report.reported.id,
+                report.short_desc,
             );
 
             main.show_all();
@@ -135,22 +139,27 @@ fn build_buttons(
     status_label: gtk::Label,
     status: ReportStatus,
     id: i32,
-    user: UserId,
+    reason: String,
 ) {
     buttons.foreach(|w| buttons.remove(w));
     status_label.set_text(&format!("Status: {}", &status));
 
     if status == ReportStatus::Opened {
         let accept = gtk::Button::new_with_label("Accept (choose an action...)");
+        let accept_reason = reason.clone();
         accept.connect_clicked(
             (client.clone(), buttons.clone(), status_label.clone()).connector()
-                .do_async(move |(client, buttons, status_label), _| async move {
-                    let status = ReportStatus::Accepted;
-                    match client.set_report_status(id, status).await {
-                        Err(e) => dialog::show_generic_error(&e),
-                        Ok(_) => {
-                            dialog::show_choose_report_action(client.clone(), user);
-                            build_buttons(client, buttons, status_label, status, id, user);
+                .do_async(move |(client, buttons, status_label), _| {
+                    let reason = accept_reason.clone();
+                    async move {
+                        let status = ReportStatus::Accepted;
+                        match client.set_report_status(id, status).await {
+                            Err(e) => dialog::show_generic_error(&e),
+                            Ok(_) => {
+                                let client_clone = client.clone();
+                                dialog::show_choose_report_action(client_clone, id, reason.clone());
+                                build_buttons(client, buttons, status_label, status, id, reason);
+                            }
                         }
                     }
                 })
@@ -159,14 +168,18 @@ fn build_buttons(
 
         let deny = gtk::Button::new_with_label("Deny (do not take action...)");
         deny.connect_clicked(
-            (client.clone(), buttons.clone(), status_label).connector()
-                .do_async(move |(client, buttons, status_label), _| async move {
-                    let status = ReportStatus::Denied;
-                    match client.set_report_status(id, status).await {
-                        Err(e) => dialog::show_generic_error(&e),
-                        Ok(_) => build_buttons(client, buttons, status_label, status, id, user),
+            (client, buttons.clone(), status_label).connector()
+                .do_async(move |(client, buttons, status_label), _| {
+                    let reason = reason.clone();
+                    async move {
+                        let status = ReportStatus::Denied;
+                        match client.resolve_report(id, ReportAction::Dismiss).await {
+                            Err(e) => dialog::show_generic_error(&e),
+                            Ok(_) => {
+                                build_buttons(client, buttons, status_label, status, id, reason)
+                            }
+                        }
                     }
-
                 })
                 .build_cloned_consumer()
         );
@@ -176,12 +189,17 @@ fn build_buttons(
     } else {
         let reopen = gtk::Button::new_with_label("Re-open report");
         reopen.connect_clicked(
-            (client.clone(), buttons.clone(), status_label).connector()
-                .do_async(move |(client, buttons, status_label), _| async move {
-                    let status = ReportStatus::Opened;
-                    match client.set_report_status(id, status).await {
-                        Err(e) => dialog::show_generic_error(&e),
-                        Ok(_) => build_buttons(client, buttons, status_label, status, id, user),
+            (client, buttons.clone(), status_label).connector()
+                .do_async(move |(client, buttons, status_label), _| {
+                    let reason = reason.clone();
+                    async move {
+                        let status = ReportStatus::Opened;
+                        match client.set_report_status(id, status).await {
+                            Err(e) => dialog::show_generic_error(&e),
+                            Ok(_) => {
+                                build_buttons(client, buttons, status_label, status, id, reason)
+                            }
+                        }
                     }
                 })
                 .build_cloned_consumer()
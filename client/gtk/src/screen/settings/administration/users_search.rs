@@ -157,25 +157,25 @@ impl UsersSearch {
 
         all.connect_toggled(
             flags.connector()
-                .do_sync(|flags, _| flags.clone().borrow_mut().toggle(AdminPermissionFlags::ALL))
+                .do_sync(|flags, _| flags.borrow_mut().toggle(AdminPermissionFlags::ALL))
                 .build_cloned_consumer()
         );
 
         ban.connect_toggled(
             flags.connector()
-                .do_sync(|flags, _| flags.clone().borrow_mut().toggle(AdminPermissionFlags::BAN))
+                .do_sync(|flags, _| flags.borrow_mut().toggle(AdminPermissionFlags::BAN))
                 .build_cloned_consumer()
         );
 
         promote.connect_toggled(
             flags.connector()
-                .do_sync(|flags, _| flags.clone().borrow_mut().toggle(AdminPermissionFlags::PROMOTE))
+                .do_sync(|flags, _| flags.borrow_mut().toggle(AdminPermissionFlags::PROMOTE))
                 .build_cloned_consumer()
         );
 
         basic.connect_toggled(
             flags.connector()
-                .do_sync(|flags, _| flags.clone().borrow_mut().toggle(AdminPermissionFlags::IS_ADMIN))
+                .do_sync(|flags, _| flags.borrow_mut().toggle(AdminPermissionFlags::IS_ADMIN))
                 .build_cloned_consumer()
         );
 
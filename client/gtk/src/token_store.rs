@@ -1,26 +1,137 @@
 use keyring::{Keyring, KeyringError};
+use serde::{Deserialize, Serialize};
 
-use crate::AuthParameters;
+use crate::{AuthParameters, Server};
 
-fn keyring() -> Keyring<'static> {
-    Keyring::new("vertex_client_gtk", "")
+const SERVICE: &str = "vertex_client_gtk";
+
+/// Identifies a stored account. Each account's token is kept in its own keyring entry.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct AccountKey {
+    pub instance: Server,
+    pub username: String,
 }
 
-pub fn store_token(parameters: &AuthParameters) {
+impl AccountKey {
+    fn keyring_user(&self) -> String {
+        format!("{}#{}", self.instance.url(), self.username)
+    }
+}
+
+impl From<&AuthParameters> for AccountKey {
+    fn from(parameters: &AuthParameters) -> Self {
+        AccountKey {
+            instance: parameters.instance.clone(),
+            username: parameters.username.clone(),
+        }
+    }
+}
+
+/// The accounts which have tokens stored, and which one was last used. This is kept in the entry
+/// that used to hold the single stored token.
+#[derive(Default, Serialize, Deserialize)]
+struct AccountIndex {
+    accounts: Vec<AccountKey>,
+    active: Option<AccountKey>,
+}
+
+fn index_keyring() -> Keyring<'static> {
+    Keyring::new(SERVICE, "")
+}
+
+fn load_index() -> AccountIndex {
+    let stored = match index_keyring().get_password() {
+        Ok(stored) => stored,
+        Err(_) => return AccountIndex::default(),
+    };
+
+    if let Ok(index) = serde_json::from_str::<AccountIndex>(&stored) {
+        return index;
+    }
+
+    // Older versions only stored one token, in place of the index
+    match serde_json::from_str::<AuthParameters>(&stored) {
+        Ok(parameters) => {
+            let key = AccountKey::from(&parameters);
+            store_account_token(&key, &parameters);
+
+            let index = AccountIndex {
+                accounts: vec![key.clone()],
+                active: Some(key),
+            };
+            save_index(&index);
+            index
+        }
+        Err(_) => AccountIndex::default(),
+    }
+}
+
+fn save_index(index: &AccountIndex) {
+    let serialized = serde_json::to_string(index).expect("unable to serialize accounts");
+    index_keyring().set_password(&serialized)
+        .expect("unable to store accounts");
+}
+
+fn store_account_token(key: &AccountKey, parameters: &AuthParameters) {
+    let user = key.keyring_user();
     let serialized_token = serde_json::to_string(parameters).expect("unable to serialize token");
-    keyring().set_password(&serialized_token)
+    Keyring::new(SERVICE, &user).set_password(&serialized_token)
         .expect("unable to store token");
 }
 
+/// Stores the account's token and makes it the active account
+pub fn store_token(parameters: &AuthParameters) {
+    let key = AccountKey::from(parameters);
+    store_account_token(&key, parameters);
+
+    let mut index = load_index();
+    if !index.accounts.contains(&key) {
+        index.accounts.push(key.clone());
+    }
+    index.active = Some(key);
+    save_index(&index);
+}
+
+/// Gets the token of the active account
 pub fn get_stored_token() -> Option<AuthParameters> {
-    keyring().get_password().ok()
+    load_index().active.and_then(|key| get_account_token(&key))
+}
+
+pub fn get_account_token(key: &AccountKey) -> Option<AuthParameters> {
+    let user = key.keyring_user();
+    Keyring::new(SERVICE, &user).get_password().ok()
         .and_then(|token_str| serde_json::from_str::<AuthParameters>(&token_str).ok())
 }
 
+pub fn stored_accounts() -> Vec<AccountKey> {
+    load_index().accounts
+}
+
+pub fn active_account() -> Option<AccountKey> {
+    load_index().active
+}
+
+pub fn set_active_account(key: Option<AccountKey>) {
+    let mut index = load_index();
+    index.active = key;
+    save_index(&index);
+}
+
+/// Forgets the token of the active account. No account is active afterwards.
 pub fn forget_token() {
-    match keyring().delete_password() {
+    let mut index = load_index();
+    let key = match index.active.take() {
+        Some(key) => key,
+        None => return,
+    };
+
+    let user = key.keyring_user();
+    match Keyring::new(SERVICE, &user).delete_password() {
         Ok(_) => {},
         Err(KeyringError::NoPasswordFound) => {},
         Err(e) => Err(e).expect("unable to forget token"),
     };
+
+    index.accounts.retain(|account| *account != key);
+    save_index(&index);
 }
@@ -51,6 +51,14 @@ pub fn is_focused() -> bool {
     })
 }
 
+/// Raises the window and gives it focus
+pub fn present() {
+    WINDOW.with(|window| {
+        let window = window.get().expect("window not initialized on this thread");
+        window.window.present();
+    })
+}
+
 pub fn set_screen<W>(screen: &W)
     where W: glib::IsA<gtk::Widget>
 {
@@ -27,6 +27,12 @@ impl ServerMessage {
         let proto = proto::events::ServerMessage::decode(bytes)?;
         proto.try_into()
     }
+
+    /// Decodes a message sent to a client which asked for compression, as encoded by
+    /// [`frame::encode`](crate::frame::encode)
+    pub fn from_frame(frame: &[u8]) -> Result<Self, DeserializeError> {
+        Self::from_protobuf_bytes(&crate::frame::decode(frame)?)
+    }
 }
 
 impl From<ServerMessage> for proto::events::ServerMessage {
@@ -36,16 +42,23 @@ impl From<ServerMessage> for proto::events::ServerMessage {
 
         let inner = match msg {
             Event(event) => Message::Event(event.into()),
-            Response { id, result } => Message::Response(proto::responses::Response {
-                id: Some(id.into()),
-                response: Some(match result {
-                    Ok(ok) => proto::responses::response::Response::Ok(ok.into()),
-                    Err(err) => {
-                        let err: proto::responses::Error = err.into();
-                        proto::responses::response::Response::Error(err as i32)
-                    }
-                }),
-            }),
+            Response { id, result } => {
+                let retry_after_secs = match &result {
+                    Err(Error::RateLimited { retry_after_secs }) => *retry_after_secs,
+                    _ => 0,
+                };
+
+                Message::Response(proto::responses::Response {
+                    id: Some(id.into()),
+                    response: Some(match result {
+                        Ok(ok) => proto::responses::response::Response::Ok(ok.into()),
+                        Err(err) => {
+                            proto::responses::response::Response::Error(err.error_code() as i32)
+                        }
+                    }),
+                    retry_after_secs,
+                })
+            }
             MalformedMessage => Message::MalformedMessage(proto::types::None {}),
             RateLimited { ready_in } => Message::RateLimited(proto::events::RateLimited {
                 ready_in_ms: ready_in.as_millis().try_into().unwrap_or(std::u32::MAX),
@@ -73,7 +86,13 @@ impl TryFrom<proto::events::ServerMessage> for ServerMessage {
                     result: Ok(ok.try_into()?),
                 },
                 Response::Error(err) => {
-                    let err = proto::responses::Error::from_i32(err)?.try_into()?;
+                    let err = match proto::responses::Error::from_i32(err) {
+                        Some(proto::responses::Error::RateLimited) => Error::RateLimited {
+                            retry_after_secs: res.retry_after_secs,
+                        },
+                        Some(err) => err.try_into()?,
+                        None => Error::Unknown { code: err as u32 },
+                    };
 
                     ServerMessage::Response {
                         id: res.id?.into(),
@@ -111,23 +130,124 @@ pub enum ServerEvent {
         room: RoomId,
         message: Message,
     },
+    AddDirectMessage {
+        channel: DirectChannelId,
+        message: Message,
+    },
     NotifyMessageReady {
         community: CommunityId,
         room: RoomId,
     },
-    Edit(Edit),
+    Edit {
+        edit: Edit,
+        /// How many times the message has been edited, including this edit
+        edit_count: u32,
+    },
     Delete(Delete),
     SessionLoggedOut,
     AddRoom {
         community: CommunityId,
         structure: RoomStructure,
     },
+    RemoveRoom {
+        community: CommunityId,
+        room: RoomId,
+    },
+    PresenceUpdate {
+        user: UserId,
+        status: PresenceStatus,
+    },
     AddCommunity(CommunityStructure),
     RemoveCommunity {
         id: CommunityId,
         reason: RemoveCommunityReason,
     },
     AdminPermissionsChanged(AdminPermissionFlags),
+    ReactionUpdate {
+        community: CommunityId,
+        room: RoomId,
+        message: MessageId,
+        reactions: Vec<ReactionSummary>,
+    },
+    Typing {
+        community: CommunityId,
+        room: RoomId,
+        user: UserId,
+    },
+    StoppedTyping {
+        community: CommunityId,
+        room: RoomId,
+        user: UserId,
+    },
+    /// The server is shutting down and will close the connection soon
+    ServerShuttingDown,
+    /// A community's name, description or icon was changed
+    CommunityUpdated {
+        id: CommunityId,
+        name: String,
+        description: String,
+        icon: Option<AttachmentId>,
+    },
+    /// A room's topic or slow mode was changed, or it was archived or unarchived
+    RoomUpdated {
+        community: CommunityId,
+        room: RoomId,
+        topic: Option<String>,
+        slow_mode_secs: u32,
+        archived: bool,
+    },
+    /// The user was mentioned in a message. This is sent whether or not they are looking at the
+    /// room, alongside the usual `AddMessage`.
+    Mentioned {
+        community: CommunityId,
+        room: RoomId,
+        message: MessageId,
+        author: UserId,
+    },
+    /// A message was pinned or unpinned in a room. Clients showing the room's pins should fetch
+    /// them again.
+    PinsUpdated {
+        community: CommunityId,
+        room: RoomId,
+    },
+    /// The room was marked as read by another of the user's devices
+    RoomReadStateChanged {
+        community: CommunityId,
+        room: RoomId,
+        last_read: Option<MessageId>,
+    },
+    /// Several rooms were marked as read at once by another of the user's devices
+    RoomsReadStateChanged(Vec<RoomReadState>),
+}
+
+/// How far the user has read in a room
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RoomReadState {
+    pub community: CommunityId,
+    pub room: RoomId,
+    pub last_read: Option<MessageId>,
+}
+
+impl From<RoomReadState> for proto::events::RoomReadStateChanged {
+    fn from(state: RoomReadState) -> Self {
+        proto::events::RoomReadStateChanged {
+            community: Some(state.community.into()),
+            room: Some(state.room.into()),
+            last_read: state.last_read.map(Into::into),
+        }
+    }
+}
+
+impl TryFrom<proto::events::RoomReadStateChanged> for RoomReadState {
+    type Error = DeserializeError;
+
+    fn try_from(state: proto::events::RoomReadStateChanged) -> Result<Self, Self::Error> {
+        Ok(RoomReadState {
+            community: state.community?.try_into()?,
+            room: state.room?.try_into()?,
+            last_read: state.last_read.map(TryInto::try_into).transpose()?,
+        })
+    }
 }
 
 impl From<ServerEvent> for proto::events::ServerEvent {
@@ -146,13 +266,22 @@ impl From<ServerEvent> for proto::events::ServerEvent {
                 room: Some(room.into()),
                 message: Some(message.into()),
             }),
+            AddDirectMessage { channel, message } => {
+                Event::AddDirectMessage(proto::events::AddDirectMessage {
+                    channel: Some(channel.into()),
+                    message: Some(message.into()),
+                })
+            }
             NotifyMessageReady { community, room } => {
                 Event::NotifyMessageReady(proto::events::NotifyMessageReady {
                     community: Some(community.into()),
                     room: Some(room.into()),
                 })
             }
-            Edit(edit) => Event::Edit(edit.into()),
+            Edit { edit, edit_count } => Event::Edit(proto::events::MessageEdited {
+                edit: Some(edit.into()),
+                edit_count,
+            }),
             Delete(delete) => Event::Delete(delete.into()),
             SessionLoggedOut => Event::SessionLoggedOut(proto::types::None {}),
             AddRoom {
@@ -162,6 +291,16 @@ impl From<ServerEvent> for proto::events::ServerEvent {
                 community: Some(community.into()),
                 structure: Some(structure.into()),
             }),
+            RemoveRoom { community, room } => Event::RemoveRoom(proto::events::RemoveRoom {
+                community: Some(community.into()),
+                room: Some(room.into()),
+            }),
+            PresenceUpdate { user, status } => {
+                Event::PresenceUpdate(proto::events::PresenceUpdate {
+                    user: Some(user.into()),
+                    status: proto::structures::PresenceStatus::from(status) as i32,
+                })
+            }
             AddCommunity(structure) => Event::AddCommunity(structure.into()),
             RemoveCommunity { id, reason } => {
                 Event::RemoveCommunity(proto::events::RemoveCommunity {
@@ -170,7 +309,90 @@ impl From<ServerEvent> for proto::events::ServerEvent {
                 })
             }
             InternalError => Event::InternalError(proto::types::None {}),
+            ServerShuttingDown => Event::ServerShuttingDown(proto::types::None {}),
             AdminPermissionsChanged(new) => Event::AdminPermissionsChanged(new.bits()),
+            ReactionUpdate {
+                community,
+                room,
+                message,
+                reactions,
+            } => Event::ReactionUpdate(proto::events::ReactionUpdate {
+                community: Some(community.into()),
+                room: Some(room.into()),
+                message: Some(message.into()),
+                reactions: reactions.into_iter().map(Into::into).collect(),
+            }),
+            Typing {
+                community,
+                room,
+                user,
+            } => Event::Typing(proto::events::Typing {
+                community: Some(community.into()),
+                room: Some(room.into()),
+                user: Some(user.into()),
+            }),
+            StoppedTyping {
+                community,
+                room,
+                user,
+            } => Event::StoppedTyping(proto::events::Typing {
+                community: Some(community.into()),
+                room: Some(room.into()),
+                user: Some(user.into()),
+            }),
+            CommunityUpdated {
+                id,
+                name,
+                description,
+                icon,
+            } => Event::CommunityUpdated(proto::events::CommunityUpdated {
+                id: Some(id.into()),
+                name,
+                description,
+                icon: icon.map(Into::into),
+            }),
+            RoomUpdated {
+                community,
+                room,
+                topic,
+                slow_mode_secs,
+                archived,
+            } => Event::RoomUpdated(proto::events::RoomUpdated {
+                community: Some(community.into()),
+                room: Some(room.into()),
+                topic: topic.map(proto::events::room_updated::Topic::Text),
+                slow_mode_secs,
+                archived,
+            }),
+            Mentioned {
+                community,
+                room,
+                message,
+                author,
+            } => Event::Mentioned(proto::events::Mentioned {
+                community: Some(community.into()),
+                room: Some(room.into()),
+                message: Some(message.into()),
+                author: Some(author.into()),
+            }),
+            PinsUpdated { community, room } => Event::PinsUpdated(proto::events::PinsUpdated {
+                community: Some(community.into()),
+                room: Some(room.into()),
+            }),
+            RoomReadStateChanged {
+                community,
+                room,
+                last_read,
+            } => Event::RoomReadStateChanged(proto::events::RoomReadStateChanged {
+                community: Some(community.into()),
+                room: Some(room.into()),
+                last_read: last_read.map(Into::into),
+            }),
+            RoomsReadStateChanged(rooms) => {
+                Event::RoomsReadStateChanged(proto::events::RoomsReadStateChanged {
+                    rooms: rooms.into_iter().map(Into::into).collect(),
+                })
+            }
         };
 
         proto::events::ServerEvent { event: Some(inner) }
@@ -186,22 +408,78 @@ impl TryFrom<proto::events::ServerEvent> for ServerEvent {
         Ok(match event.event? {
             ClientReady(ready) => ServerEvent::ClientReady(ready.try_into()?),
             InternalError(_) => ServerEvent::InternalError,
+            ServerShuttingDown(_) => ServerEvent::ServerShuttingDown,
+            CommunityUpdated(update) => ServerEvent::CommunityUpdated {
+                id: update.id?.try_into()?,
+                name: update.name,
+                description: update.description,
+                icon: update.icon.map(TryInto::try_into).transpose()?,
+            },
+            RoomUpdated(update) => ServerEvent::RoomUpdated {
+                community: update.community?.try_into()?,
+                room: update.room?.try_into()?,
+                topic: update.topic.map(|proto::events::room_updated::Topic::Text(topic)| topic),
+                slow_mode_secs: update.slow_mode_secs,
+                archived: update.archived,
+            },
+            Mentioned(mention) => ServerEvent::Mentioned {
+                community: mention.community?.try_into()?,
+                room: mention.room?.try_into()?,
+                message: mention.message?.try_into()?,
+                author: mention.author?.try_into()?,
+            },
+            PinsUpdated(update) => ServerEvent::PinsUpdated {
+                community: update.community?.try_into()?,
+                room: update.room?.try_into()?,
+            },
+            RoomReadStateChanged(update) => ServerEvent::RoomReadStateChanged {
+                community: update.community?.try_into()?,
+                room: update.room?.try_into()?,
+                last_read: update.last_read.map(TryInto::try_into).transpose()?,
+            },
+            RoomsReadStateChanged(update) => ServerEvent::RoomsReadStateChanged(
+                update
+                    .rooms
+                    .into_iter()
+                    .map(TryInto::try_into)
+                    .collect::<Result<_, _>>()?,
+            ),
             AddMessage(add) => ServerEvent::AddMessage {
                 community: add.community?.try_into()?,
                 room: add.room?.try_into()?,
                 message: add.message?.try_into()?,
             },
+            AddDirectMessage(add) => ServerEvent::AddDirectMessage {
+                channel: add.channel?.try_into()?,
+                message: add.message?.try_into()?,
+            },
             NotifyMessageReady(notify) => ServerEvent::NotifyMessageReady {
                 community: notify.community?.try_into()?,
                 room: notify.room?.try_into()?,
             },
-            Edit(edit) => ServerEvent::Edit(edit.try_into()?),
+            Edit(edited) => ServerEvent::Edit {
+                edit: edited.edit?.try_into()?,
+                edit_count: edited.edit_count,
+            },
             Delete(delete) => ServerEvent::Delete(delete.try_into()?),
             SessionLoggedOut(_) => ServerEvent::SessionLoggedOut,
             AddRoom(room) => ServerEvent::AddRoom {
                 community: room.community?.try_into()?,
                 structure: room.structure?.try_into()?,
             },
+            RemoveRoom(remove) => ServerEvent::RemoveRoom {
+                community: remove.community?.try_into()?,
+                room: remove.room?.try_into()?,
+            },
+            PresenceUpdate(update) => {
+                let status = proto::structures::PresenceStatus::from_i32(update.status);
+                let status = status.ok_or(DeserializeError::InvalidEnumVariant)?;
+
+                ServerEvent::PresenceUpdate {
+                    user: update.user?.try_into()?,
+                    status: status.into(),
+                }
+            }
             AddCommunity(community) => ServerEvent::AddCommunity(community.try_into()?),
             RemoveCommunity(remove) => {
                 let reason = proto::events::RemoveCommunityReason::from_i32(remove.reason);
@@ -216,6 +494,22 @@ impl TryFrom<proto::events::ServerEvent> for ServerEvent {
                 let new = AdminPermissionFlags::from_bits_truncate(new);
                 ServerEvent::AdminPermissionsChanged(new)
             }
+            ReactionUpdate(update) => ServerEvent::ReactionUpdate {
+                community: update.community?.try_into()?,
+                room: update.room?.try_into()?,
+                message: update.message?.try_into()?,
+                reactions: update.reactions.into_iter().map(Into::into).collect(),
+            },
+            Typing(typing) => ServerEvent::Typing {
+                community: typing.community?.try_into()?,
+                room: typing.room?.try_into()?,
+                user: typing.user?.try_into()?,
+            },
+            StoppedTyping(typing) => ServerEvent::StoppedTyping {
+                community: typing.community?.try_into()?,
+                room: typing.room?.try_into()?,
+                user: typing.user?.try_into()?,
+            },
         })
     }
 }
@@ -225,6 +519,10 @@ impl TryFrom<proto::events::ServerEvent> for ServerEvent {
 pub enum RemoveCommunityReason {
     /// The community was deleted
     Deleted,
+    /// The user left the community
+    Left,
+    /// The user was kicked from the community by a moderator
+    Kicked,
 }
 
 impl From<RemoveCommunityReason> for proto::events::RemoveCommunityReason {
@@ -233,6 +531,8 @@ impl From<RemoveCommunityReason> for proto::events::RemoveCommunityReason {
 
         match delete {
             Deleted => proto::events::RemoveCommunityReason::Deleted,
+            Left => proto::events::RemoveCommunityReason::Left,
+            Kicked => proto::events::RemoveCommunityReason::Kicked,
         }
     }
 }
@@ -244,6 +544,8 @@ impl TryFrom<proto::events::RemoveCommunityReason> for RemoveCommunityReason {
         use proto::events::RemoveCommunityReason::*;
         match delete {
             Deleted => Ok(RemoveCommunityReason::Deleted),
+            Left => Ok(RemoveCommunityReason::Left),
+            Kicked => Ok(RemoveCommunityReason::Kicked),
         }
     }
 }
@@ -0,0 +1,335 @@
+//! Inline formatting of message text. Messages can contain `**bold**`, `_italic_`, `` `code` ``
+//! and `[links](https://example.com)`, and a backslash escapes any character which would otherwise
+//! be read as markup. Parsing never fails: markup which is unclosed, nested too deeply, or links
+//! to a disallowed URL is read as plain text instead.
+//!
+//! The server stores and sends message text in the canonical form given by [`sanitize`], so every
+//! client which reads it with [`parse`] sees the same structure.
+
+/// Most spans that can be nested inside each other. Any markup nested deeper is read as text.
+pub const MAX_FORMATTING_DEPTH: usize = 4;
+
+/// Prefixes of the URLs that links are allowed to point to
+const LINK_SCHEMES: &[&str] = &["https://", "http://", "mailto:"];
+
+/// Characters which can be escaped with a backslash
+const ESCAPABLE: &[char] = &['\\', '*', '_', '`', '[', ']'];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Span {
+    Text(String),
+    Bold(Vec<Span>),
+    Italic(Vec<Span>),
+    Code(String),
+    Link { text: Vec<Span>, url: String },
+}
+
+/// Parses formatted text into spans. Control characters other than newlines and tabs are dropped.
+pub fn parse(text: &str) -> Vec<Span> {
+    let chars: Vec<char> = text
+        .chars()
+        .filter(|c| !c.is_control() || *c == '\n' || *c == '\t')
+        .collect();
+
+    let mut parser = Parser::default();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let prev = if i > 0 { chars.get(i - 1).copied() } else { None };
+        let next = chars.get(i + 1).copied();
+
+        match chars[i] {
+            '\\' if next.map_or(false, |c| ESCAPABLE.contains(&c)) => {
+                parser.push_char(chars[i + 1]);
+                i += 2;
+            }
+            '*' if next == Some('*') => {
+                match parser.position(Delimiter::Bold) {
+                    Some(idx) => {
+                        parser.close(idx, "**", Span::Bold);
+                    }
+                    None => parser.open(Delimiter::Bold),
+                }
+                i += 2;
+            }
+            '_' => {
+                // Underscores inside words, like in snake_case, are not markup
+                let can_open = !prev.map_or(false, char::is_alphanumeric);
+                let can_close = !next.map_or(false, char::is_alphanumeric);
+
+                match parser.position(Delimiter::Italic) {
+                    Some(idx) if can_close => {
+                        parser.close(idx, "_", Span::Italic);
+                    }
+                    None if can_open => parser.open(Delimiter::Italic),
+                    _ => parser.push_char('_'),
+                }
+                i += 1;
+            }
+            '`' => {
+                let end = chars[i + 1..].iter().position(|c| *c == '`');
+                match end {
+                    Some(len) if len > 0 => {
+                        let code = chars[i + 1..i + 1 + len].iter().collect();
+                        parser.push_span(Span::Code(code));
+                        i += len + 2;
+                    }
+                    _ => {
+                        parser.push_char('`');
+                        i += 1;
+                    }
+                }
+            }
+            '[' => {
+                // Links cannot be nested, so an open link before this one was not really a link
+                if let Some(idx) = parser.position(Delimiter::Link) {
+                    parser.flatten_from(idx);
+                }
+                parser.open(Delimiter::Link);
+                i += 1;
+            }
+            ']' => {
+                let link = parser.position(Delimiter::Link);
+                let url = link_url(&chars[i + 1..]);
+
+                match (link, url) {
+                    (Some(idx), Some(url)) => {
+                        let len = url.chars().count();
+                        let closed = parser.close(idx, "]", |text| Span::Link { text, url });
+
+                        // The URL is read as text if the link had no text
+                        i += if closed { len + 3 } else { 1 };
+                    }
+                    _ => {
+                        parser.push_char(']');
+                        i += 1;
+                    }
+                }
+            }
+            c => {
+                parser.push_char(c);
+                i += 1;
+            }
+        }
+    }
+
+    parser.finish()
+}
+
+/// Reads the `(url)` part of a link, if it is there and the URL is allowed
+fn link_url(chars: &[char]) -> Option<String> {
+    if chars.first() != Some(&'(') {
+        return None;
+    }
+
+    let len = chars[1..].iter().position(|c| *c == ')' || c.is_whitespace())?;
+    if chars[1 + len] != ')' {
+        return None;
+    }
+
+    let url: String = chars[1..1 + len].iter().collect();
+    let lowercase = url.to_lowercase();
+    let allowed = LINK_SCHEMES
+        .iter()
+        .any(|scheme| lowercase.starts_with(scheme) && lowercase.len() > scheme.len());
+
+    if allowed {
+        Some(url)
+    } else {
+        None
+    }
+}
+
+/// Writes spans back out as formatted text, in the canonical form
+pub fn render(spans: &[Span]) -> String {
+    let mut out = String::new();
+    render_into(&mut out, spans);
+    out
+}
+
+fn render_into(out: &mut String, spans: &[Span]) {
+    for span in spans {
+        match span {
+            Span::Text(text) => escape_into(out, text),
+            Span::Bold(inner) => {
+                out.push_str("**");
+                render_into(out, inner);
+                out.push_str("**");
+            }
+            Span::Italic(inner) => {
+                out.push('_');
+                render_into(out, inner);
+                out.push('_');
+            }
+            Span::Code(code) => {
+                out.push('`');
+                out.push_str(code);
+                out.push('`');
+            }
+            Span::Link { text, url } => {
+                out.push('[');
+                render_into(out, text);
+                out.push_str("](");
+                out.push_str(url);
+                out.push(')');
+            }
+        }
+    }
+}
+
+/// Escapes the characters of the text which could otherwise be read as markup where they are
+fn escape_into(out: &mut String, text: &str) {
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        let next = chars.peek().copied();
+        let escape = match c {
+            '\\' => next.map_or(true, |n| ESCAPABLE.contains(&n)),
+            '*' => out.ends_with('*') || next.map_or(true, |n| n == '*'),
+            '_' => {
+                let prev_word = out.chars().next_back().map_or(false, char::is_alphanumeric);
+                let next_word = next.map_or(false, char::is_alphanumeric);
+                !(prev_word && next_word)
+            }
+            ']' => next.map_or(true, |n| n == '('),
+            '`' | '[' => true,
+            _ => false,
+        };
+
+        if escape {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+}
+
+/// Parses the text and writes it back out in the canonical form, dropping anything which is not
+/// allowed. Sanitizing text which has already been sanitized gives it back unchanged.
+pub fn sanitize(text: &str) -> String {
+    render(&parse(text))
+}
+
+/// Gets the text of the spans without any of their formatting
+pub fn plain_text(spans: &[Span]) -> String {
+    let mut out = String::new();
+    plain_text_into(&mut out, spans);
+    out
+}
+
+fn plain_text_into(out: &mut String, spans: &[Span]) {
+    for span in spans {
+        match span {
+            Span::Text(text) | Span::Code(text) => out.push_str(text),
+            Span::Bold(inner) | Span::Italic(inner) | Span::Link { text: inner, .. } => {
+                plain_text_into(out, inner)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Delimiter {
+    Bold,
+    Italic,
+    Link,
+}
+
+impl Delimiter {
+    fn opener(self) -> &'static str {
+        match self {
+            Delimiter::Bold => "**",
+            Delimiter::Italic => "_",
+            Delimiter::Link => "[",
+        }
+    }
+}
+
+struct Frame {
+    delimiter: Delimiter,
+    spans: Vec<Span>,
+}
+
+/// The spans parsed so far, along with the stack of spans which have been opened but not yet
+/// closed
+#[derive(Default)]
+struct Parser {
+    root: Vec<Span>,
+    open: Vec<Frame>,
+}
+
+impl Parser {
+    fn spans_mut(&mut self) -> &mut Vec<Span> {
+        match self.open.last_mut() {
+            Some(frame) => &mut frame.spans,
+            None => &mut self.root,
+        }
+    }
+
+    fn push_span(&mut self, span: Span) {
+        let spans = self.spans_mut();
+        match (spans.last_mut(), span) {
+            (Some(Span::Text(last)), Span::Text(text)) => last.push_str(&text),
+            (_, span) => spans.push(span),
+        }
+    }
+
+    fn push_text(&mut self, text: &str) {
+        self.push_span(Span::Text(text.to_string()));
+    }
+
+    fn push_char(&mut self, c: char) {
+        match self.spans_mut().last_mut() {
+            Some(Span::Text(last)) => last.push(c),
+            _ => self.push_span(Span::Text(c.to_string())),
+        }
+    }
+
+    fn position(&self, delimiter: Delimiter) -> Option<usize> {
+        self.open.iter().rposition(|frame| frame.delimiter == delimiter)
+    }
+
+    fn open(&mut self, delimiter: Delimiter) {
+        if self.open.len() < MAX_FORMATTING_DEPTH {
+            self.open.push(Frame {
+                delimiter,
+                spans: Vec::new(),
+            });
+        } else {
+            self.push_text(delimiter.opener());
+        }
+    }
+
+    /// Turns the open spans from the given index upwards back into text, since they were never
+    /// closed
+    fn flatten_from(&mut self, index: usize) {
+        while self.open.len() > index {
+            let frame = self.open.pop().unwrap();
+            self.push_text(frame.delimiter.opener());
+            frame.spans.into_iter().for_each(|span| self.push_span(span));
+        }
+    }
+
+    /// Closes the open span at the given index, returning whether it became a span. Empty spans
+    /// are turned back into text.
+    fn close<F>(&mut self, index: usize, closer: &str, build: F) -> bool
+    where
+        F: FnOnce(Vec<Span>) -> Span,
+    {
+        self.flatten_from(index + 1);
+        let frame = self.open.pop().unwrap();
+
+        if frame.spans.is_empty() {
+            self.push_text(frame.delimiter.opener());
+            self.push_text(closer);
+            false
+        } else {
+            self.push_span(build(frame.spans));
+            true
+        }
+    }
+
+    fn finish(mut self) -> Vec<Span> {
+        self.flatten_from(0);
+        self.root
+    }
+}
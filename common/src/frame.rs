@@ -0,0 +1,51 @@
+//! Framing of messages sent from the server to clients which asked for compression when they
+//! connected. Each frame starts with a header byte saying how the rest of it is encoded. Frames of
+//! at least `COMPRESSION_THRESHOLD` bytes are compressed with deflate, while smaller ones are sent
+//! as they are, since compressing them would cost more time than it saves in size.
+
+use std::borrow::Cow;
+
+use miniz_oxide::{deflate, inflate};
+
+use crate::proto::DeserializeError;
+
+/// Smallest message, in bytes, which is compressed
+pub const COMPRESSION_THRESHOLD: usize = 1024;
+
+/// Deflate level to compress at, from 0 to 10. This is a fair balance of speed and size.
+const COMPRESSION_LEVEL: u8 = 6;
+
+const HEADER_UNCOMPRESSED: u8 = 0;
+const HEADER_DEFLATE: u8 = 1;
+
+/// Wraps an encoded message in a frame, compressing it if it is large enough
+pub fn encode(message: Vec<u8>) -> Vec<u8> {
+    if message.len() >= COMPRESSION_THRESHOLD {
+        let compressed = deflate::compress_to_vec(&message, COMPRESSION_LEVEL);
+
+        // Data which does not compress well is sent as it is
+        if compressed.len() < message.len() {
+            return with_header(HEADER_DEFLATE, &compressed);
+        }
+    }
+
+    with_header(HEADER_UNCOMPRESSED, &message)
+}
+
+/// Gets the encoded message back out of a frame
+pub fn decode(frame: &[u8]) -> Result<Cow<'_, [u8]>, DeserializeError> {
+    match frame.split_first() {
+        Some((&HEADER_UNCOMPRESSED, message)) => Ok(Cow::Borrowed(message)),
+        Some((&HEADER_DEFLATE, compressed)) => inflate::decompress_to_vec(compressed)
+            .map(Cow::Owned)
+            .map_err(|_| DeserializeError::InvalidFrame),
+        _ => Err(DeserializeError::InvalidFrame),
+    }
+}
+
+fn with_header(header: u8, message: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(message.len() + 1);
+    frame.push(header);
+    frame.extend_from_slice(message);
+    frame
+}
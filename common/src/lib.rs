@@ -2,16 +2,22 @@
 
 #![feature(try_trait)]
 
+use std::path::Path;
 use std::time::Duration;
 use std::fs;
 use std::fs::OpenOptions;
 use chrono::SecondsFormat;
 use log::LevelFilter;
 
+use crate::types::ProtocolVersion;
+
 pub mod events;
+pub mod formatting;
+pub mod frame;
 pub mod proto;
 pub mod requests;
 pub mod responses;
+pub mod signing;
 pub mod structures;
 pub mod types;
 
@@ -22,20 +28,41 @@ pub mod prelude {
     pub use crate::structures::*;
     pub use crate::types::*;
     pub use crate::HEARTBEAT_TIMEOUT;
+    pub use crate::PROTOCOL_VERSION;
+    pub use crate::TYPING_TIMEOUT;
 }
 
 pub const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(15);
 
+/// The protocol version spoken by this build. Bump the minor version for backwards compatible
+/// changes, and the major version for anything which older clients or servers cannot understand.
+pub const PROTOCOL_VERSION: ProtocolVersion = ProtocolVersion { major: 1, minor: 0 };
+
 pub const RATELIMIT_BURST_PER_MIN: u32 = 120;
 
+/// How long a user is shown as typing for after their last `StartTyping` request, if they do not
+/// send a `StopTyping` request
+pub const TYPING_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Maximum length in bytes of a reaction's emoji. This is long enough for multi-codepoint emoji
+/// sequences, but short enough that arbitrary text cannot be stored as a reaction.
+pub const MAX_REACTION_EMOJI_LEN: usize = 32;
+
 pub fn setup_logging(
     name: &str,
     log_level: log::LevelFilter,
 ) {
     let dirs = directories_next::ProjectDirs::from("", "vertex_chat", name)
         .expect("Error getting project directories");
-    let dir = dirs.data_dir().join("logs");
+    setup_logging_in(name, &dirs.data_dir().join("logs"), log_level);
+}
 
+/// Logs to stdout and to a new file in the given directory, which is created if needed
+pub fn setup_logging_in(
+    name: &str,
+    dir: &Path,
+    log_level: log::LevelFilter,
+) {
     fs::create_dir_all(&dir)
         .unwrap_or_else(|_| panic!("Error creating log dirs ({})", dir.to_string_lossy()));
 
@@ -38,6 +38,8 @@ pub enum DeserializeError {
     InvalidEnumVariant,
     ProtobufError(prost::DecodeError),
     IntOutOfRange,
+    /// A frame had an unknown header or could not be decompressed
+    InvalidFrame,
 }
 
 impl From<uuid::Error> for DeserializeError {
@@ -45,6 +45,13 @@ pub struct ClientSentMessage {
     pub to_community: CommunityId,
     pub to_room: RoomId,
     pub content: String,
+    /// Attachments uploaded by the sender that have not been sent with another message yet
+    pub attachments: Vec<AttachmentId>,
+    /// A message in the same room which this is a reply to
+    pub reply_to: Option<MessageId>,
+    /// If a message with the same nonce was recently sent from this device, the server will
+    /// confirm that message again instead of sending another
+    pub nonce: Option<MessageNonce>,
 }
 
 impl From<ClientSentMessage> for proto::requests::active::ClientSentMessage {
@@ -53,6 +60,9 @@ impl From<ClientSentMessage> for proto::requests::active::ClientSentMessage {
             to_community: Some(msg.to_community.into()),
             to_room: Some(msg.to_room.into()),
             content: msg.content,
+            attachments: msg.attachments.into_iter().map(Into::into).collect(),
+            reply_to: msg.reply_to.map(Into::into),
+            nonce: msg.nonce.map(Into::into),
         }
     }
 }
@@ -65,6 +75,43 @@ impl TryFrom<proto::requests::active::ClientSentMessage> for ClientSentMessage {
             to_community: msg.to_community?.try_into()?,
             to_room: msg.to_room?.try_into()?,
             content: msg.content,
+            attachments: msg
+                .attachments
+                .into_iter()
+                .map(TryInto::try_into)
+                .collect::<Result<_, _>>()?,
+            reply_to: msg.reply_to.map(TryInto::try_into).transpose()?,
+            nonce: msg.nonce.map(TryInto::try_into).transpose()?,
+        })
+    }
+}
+
+/// A room to get an update for as part of `ClientRequest::GetRoomUpdates`
+#[derive(Debug, Clone)]
+pub struct RoomUpdateRequest {
+    pub community: CommunityId,
+    pub room: RoomId,
+    pub last_received: Option<MessageId>,
+}
+
+impl From<RoomUpdateRequest> for proto::requests::active::RoomUpdateRequest {
+    fn from(req: RoomUpdateRequest) -> Self {
+        proto::requests::active::RoomUpdateRequest {
+            community: Some(req.community.into()),
+            room: Some(req.room.into()),
+            last_received: req.last_received.map(Into::into),
+        }
+    }
+}
+
+impl TryFrom<proto::requests::active::RoomUpdateRequest> for RoomUpdateRequest {
+    type Error = DeserializeError;
+
+    fn try_from(req: proto::requests::active::RoomUpdateRequest) -> Result<Self, Self::Error> {
+        Ok(RoomUpdateRequest {
+            community: req.community?.try_into()?,
+            room: req.room?.try_into()?,
+            last_received: req.last_received.map(TryInto::try_into).transpose()?,
         })
     }
 }
@@ -73,6 +120,14 @@ impl TryFrom<proto::requests::active::ClientSentMessage> for ClientSentMessage {
 #[non_exhaustive]
 pub enum ClientRequest {
     LogOut,
+    ListDevices,
+    /// Set the presence status shown to other users. Setting `Offline` appears offline.
+    SetStatus(PresenceStatus),
+    /// Set what other users can see of this user, and whether strangers may message them
+    SetPrivacy(PrivacySettings),
+    /// Log out the given device of this user. Revoking the current device is the same as logging
+    /// out.
+    RevokeDevice(DeviceId),
     SendMessage(ClientSentMessage),
     EditMessage(Edit),
     GetRoomUpdate {
@@ -81,6 +136,12 @@ pub enum ClientRequest {
         last_received: Option<MessageId>,
         message_count: u64,
     },
+    /// Get updates for several rooms at once. The updates are returned in the same order as the
+    /// rooms.
+    GetRoomUpdates {
+        rooms: Vec<RoomUpdateRequest>,
+        message_count: u64,
+    },
     GetMessages {
         community: CommunityId,
         room: RoomId,
@@ -96,6 +157,8 @@ pub enum ClientRequest {
         community: CommunityId,
         room: RoomId,
     },
+    /// Mark every given room as read at once. Rooms which the user is not in are skipped.
+    SetManyAsRead(Vec<(CommunityId, RoomId)>),
     CreateCommunity {
         name: String,
     },
@@ -103,12 +166,77 @@ pub enum ClientRequest {
         name: String,
         community: CommunityId,
     },
+    DeleteRoom {
+        community: CommunityId,
+        room: RoomId,
+    },
     CreateInvite {
         community: CommunityId,
         expiration_datetime: Option<DateTime<Utc>>,
+        /// How many times the code can be used before it stops working, or `None` for no limit. It
+        /// must be at least 1.
+        max_uses: Option<u32>,
+        /// A memorable code to use instead of a randomly generated one. It must be valid according
+        /// to `InviteCode::is_valid_vanity`.
+        vanity: Option<String>,
     },
+    /// List the community's invite codes that are neither expired nor used up
+    GetInviteCodes(CommunityId),
+    /// Get how many members, rooms and messages the community has. Requires the
+    /// `MANAGE_COMMUNITY` permission. The counts may be up to a minute old.
+    GetCommunityStats(CommunityId),
     JoinCommunity(InviteCode),
-    Delete(Delete),
+    /// Get a page of the public communities, ordered by name. `after` is the last community of the
+    /// previous page, if any.
+    ListPublicCommunities {
+        after: Option<CommunityId>,
+        limit: usize,
+    },
+    /// Join a public community, which needs no invite code
+    JoinPublicCommunity(CommunityId),
+    /// Get the direct channel with the given user, creating it if it does not exist yet
+    OpenDirectChannel(UserId),
+    SendDirectMessage {
+        channel: DirectChannelId,
+        content: String,
+    },
+    /// Get messages in a direct channel. With no selector, the newest messages are returned.
+    GetDirectMessages {
+        channel: DirectChannelId,
+        selector: Option<MessageSelector>,
+        count: u64,
+    },
+    /// Hide the user's messages from this user, and stop them from opening a direct channel with
+    /// this user
+    BlockUser(UserId),
+    UnblockUser(UserId),
+    GetBlockedUsers,
+    LeaveCommunity(CommunityId),
+    /// Delete the community along with everything in it. Only members with every permission, such
+    /// as its creator, may do this. `name` must be the community's name, re-typed by the user to
+    /// confirm.
+    DeleteCommunity {
+        community: CommunityId,
+        name: String,
+    },
+    /// Get a page of the community's members, ordered by display name. `after` is the last member
+    /// of the previous page, if any.
+    GetCommunityMembers {
+        community: CommunityId,
+        after: Option<UserId>,
+        limit: usize,
+    },
+    CreateRole {
+        community: CommunityId,
+        name: String,
+        permissions: CommunityPermissionFlags,
+    },
+    AssignRole {
+        community: CommunityId,
+        role: RoleId,
+        user: UserId,
+    },
+    DeleteMessage(Delete),
     ChangeUsername {
         new_username: String,
     },
@@ -116,19 +244,176 @@ pub enum ClientRequest {
         new_display_name: String,
     },
     GetProfile(UserId),
-    ChangeCommunityName {
+    /// Change a community's name, description or icon. Fields that are `None` are left unchanged.
+    EditCommunity {
+        community: CommunityId,
+        name: Option<String>,
+        description: Option<String>,
+        icon: Option<AttachmentId>,
+        /// Days after which messages in the community are deleted. 0 keeps them forever.
+        retention_days: Option<u32>,
+        /// Whether anyone can find the community and join it without an invite code
+        public: Option<bool>,
+        /// Whether the server signs messages sent to the community from now on
+        sign_messages: Option<bool>,
+    },
+    /// Set a room's topic and slow mode. A slow mode of 0 disables it.
+    EditRoom {
+        community: CommunityId,
+        room: RoomId,
+        topic: Option<String>,
+        slow_mode_secs: u32,
+    },
+    /// Archive or unarchive a room. Archived rooms keep their history but cannot be sent to.
+    SetRoomArchived {
+        community: CommunityId,
+        room: RoomId,
+        archived: bool,
+    },
+    SetWatchLevel {
+        community: CommunityId,
+        room: RoomId,
+        level: WatchLevel,
+    },
+    PinMessage {
+        community: CommunityId,
+        room: RoomId,
+        message: MessageId,
+    },
+    UnpinMessage {
+        community: CommunityId,
+        room: RoomId,
+        message: MessageId,
+    },
+    GetPinnedMessages {
         community: CommunityId,
-        new: String,
+        room: RoomId,
     },
-    ChangeCommunityDescription {
+    /// Remove a member from the community. They can join again later.
+    KickUser {
         community: CommunityId,
-        new: String,
+        user: UserId,
     },
+    /// Stop a member from sending messages to the community, either permanently or until the
+    /// given time
+    MuteUser {
+        community: CommunityId,
+        user: UserId,
+        until: Option<DateTime<Utc>>,
+    },
+    UnmuteUser {
+        community: CommunityId,
+        user: UserId,
+    },
+    /// Get the newest message and unread state of every room the user is in, so that a client
+    /// which has reconnected can fetch any history it missed with `GetRoomUpdate`
+    Resync,
     AdminAction(AdminRequest),
     ReportUser {
         message: MessageId,
         short_desc: String,
         extended_desc: String,
+    },
+    GetEditHistory {
+        community: CommunityId,
+        room: RoomId,
+        message: MessageId,
+    },
+    SearchMessages {
+        community: CommunityId,
+        query: String,
+        limit: usize,
+    },
+    /// Searches users by their username and display name, for finding someone to message
+    SearchUsers {
+        query: String,
+        limit: usize,
+    },
+    AddReaction {
+        community: CommunityId,
+        room: RoomId,
+        message: MessageId,
+        emoji: String,
+    },
+    RemoveReaction {
+        community: CommunityId,
+        room: RoomId,
+        message: MessageId,
+        emoji: String,
+    },
+    StartTyping {
+        community: CommunityId,
+        room: RoomId,
+    },
+    StopTyping {
+        community: CommunityId,
+        room: RoomId,
+    },
+}
+
+impl ClientRequest {
+    /// The name of the request, without any of its contents, for use in logs
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ClientRequest::LogOut => "LogOut",
+            ClientRequest::ListDevices => "ListDevices",
+            ClientRequest::SetStatus(_) => "SetStatus",
+            ClientRequest::SetPrivacy(_) => "SetPrivacy",
+            ClientRequest::RevokeDevice(_) => "RevokeDevice",
+            ClientRequest::SendMessage(_) => "SendMessage",
+            ClientRequest::EditMessage(_) => "EditMessage",
+            ClientRequest::GetRoomUpdate { .. } => "GetRoomUpdate",
+            ClientRequest::GetRoomUpdates { .. } => "GetRoomUpdates",
+            ClientRequest::GetMessages { .. } => "GetMessages",
+            ClientRequest::SelectRoom { .. } => "SelectRoom",
+            ClientRequest::DeselectRoom => "DeselectRoom",
+            ClientRequest::SetAsRead { .. } => "SetAsRead",
+            ClientRequest::SetManyAsRead(_) => "SetManyAsRead",
+            ClientRequest::CreateCommunity { .. } => "CreateCommunity",
+            ClientRequest::CreateRoom { .. } => "CreateRoom",
+            ClientRequest::DeleteRoom { .. } => "DeleteRoom",
+            ClientRequest::CreateInvite { .. } => "CreateInvite",
+            ClientRequest::GetInviteCodes(_) => "GetInviteCodes",
+            ClientRequest::GetCommunityStats(_) => "GetCommunityStats",
+            ClientRequest::JoinCommunity(_) => "JoinCommunity",
+            ClientRequest::ListPublicCommunities { .. } => "ListPublicCommunities",
+            ClientRequest::JoinPublicCommunity(_) => "JoinPublicCommunity",
+            ClientRequest::OpenDirectChannel(_) => "OpenDirectChannel",
+            ClientRequest::SendDirectMessage { .. } => "SendDirectMessage",
+            ClientRequest::GetDirectMessages { .. } => "GetDirectMessages",
+            ClientRequest::BlockUser(_) => "BlockUser",
+            ClientRequest::UnblockUser(_) => "UnblockUser",
+            ClientRequest::GetBlockedUsers => "GetBlockedUsers",
+            ClientRequest::LeaveCommunity(_) => "LeaveCommunity",
+            ClientRequest::DeleteCommunity { .. } => "DeleteCommunity",
+            ClientRequest::GetCommunityMembers { .. } => "GetCommunityMembers",
+            ClientRequest::CreateRole { .. } => "CreateRole",
+            ClientRequest::AssignRole { .. } => "AssignRole",
+            ClientRequest::DeleteMessage(_) => "DeleteMessage",
+            ClientRequest::ChangeUsername { .. } => "ChangeUsername",
+            ClientRequest::ChangeDisplayName { .. } => "ChangeDisplayName",
+            ClientRequest::GetProfile(_) => "GetProfile",
+            ClientRequest::EditCommunity { .. } => "EditCommunity",
+            ClientRequest::EditRoom { .. } => "EditRoom",
+            ClientRequest::SetRoomArchived { .. } => "SetRoomArchived",
+            ClientRequest::SetWatchLevel { .. } => "SetWatchLevel",
+            ClientRequest::PinMessage { .. } => "PinMessage",
+            ClientRequest::UnpinMessage { .. } => "UnpinMessage",
+            ClientRequest::GetPinnedMessages { .. } => "GetPinnedMessages",
+            ClientRequest::KickUser { .. } => "KickUser",
+            ClientRequest::MuteUser { .. } => "MuteUser",
+            ClientRequest::UnmuteUser { .. } => "UnmuteUser",
+            ClientRequest::Resync => "Resync",
+            ClientRequest::AdminAction(_) => "AdminAction",
+            ClientRequest::ReportUser { .. } => "ReportUser",
+            ClientRequest::GetEditHistory { .. } => "GetEditHistory",
+            ClientRequest::SearchMessages { .. } => "SearchMessages",
+            ClientRequest::SearchUsers { .. } => "SearchUsers",
+            ClientRequest::AddReaction { .. } => "AddReaction",
+            ClientRequest::RemoveReaction { .. } => "RemoveReaction",
+            ClientRequest::StartTyping { .. } => "StartTyping",
+            ClientRequest::StopTyping { .. } => "StopTyping",
+        }
     }
 }
 
@@ -139,6 +424,12 @@ impl From<ClientRequest> for proto::requests::active::ClientRequest {
 
         let inner = match req {
             LogOut => Request::LogOut(proto::types::None {}),
+            ListDevices => Request::ListDevices(proto::types::None {}),
+            SetStatus(status) => {
+                Request::SetStatus(proto::structures::PresenceStatus::from(status) as i32)
+            }
+            SetPrivacy(privacy) => Request::SetPrivacy(privacy.into()),
+            RevokeDevice(device) => Request::RevokeDevice(device.into()),
             SendMessage(msg) => Request::SendMessage(msg.into()),
             EditMessage(edit) => Request::Edit(edit.into()),
             GetRoomUpdate {
@@ -154,6 +445,12 @@ impl From<ClientRequest> for proto::requests::active::ClientRequest {
                     message_count,
                 })
             }
+            GetRoomUpdates { rooms, message_count } => {
+                Request::GetRoomUpdates(request::GetRoomUpdates {
+                    rooms: rooms.into_iter().map(Into::into).collect(),
+                    message_count,
+                })
+            }
             GetMessages {
                 community,
                 room,
@@ -174,25 +471,106 @@ impl From<ClientRequest> for proto::requests::active::ClientRequest {
                 community: Some(community.into()),
                 room: Some(room.into()),
             }),
+            SetManyAsRead(rooms) => Request::SetManyAsRead(request::SetManyAsRead {
+                rooms: rooms
+                    .into_iter()
+                    .map(|(community, room)| request::SetAsRead {
+                        community: Some(community.into()),
+                        room: Some(room.into()),
+                    })
+                    .collect(),
+            }),
             CreateCommunity { name } => Request::CreateCommunity(request::CreateCommunity { name }),
             CreateRoom { name, community } => Request::CreateRoom(request::CreateRoom {
                 name,
                 community: Some(community.into()),
             }),
+            DeleteRoom { community, room } => Request::DeleteRoom(request::DeleteRoom {
+                community: Some(community.into()),
+                room: Some(room.into()),
+            }),
             CreateInvite {
                 community,
                 expiration_datetime: dt,
+                max_uses,
+                vanity,
             } => {
-                use request::create_invite::ExpirationDatetime::Present;
+                use request::create_invite::{
+                    ExpirationDatetime::Present, MaxUses::Limit, Vanity::Code,
+                };
                 Request::CreateInvite(request::CreateInvite {
                     community: Some(community.into()),
                     expiration_datetime: dt.map(|x| Present(x.timestamp())),
+                    max_uses: max_uses.map(Limit),
+                    vanity: vanity.map(Code),
                 })
             }
+            GetInviteCodes(community) => Request::GetInviteCodes(community.into()),
+            GetCommunityStats(community) => Request::GetCommunityStats(community.into()),
             JoinCommunity(code) => Request::JoinCommunity(request::JoinCommunity {
                 invite_code: code.0,
             }),
-            Delete(delete) => Request::Delete(delete.into()),
+            ListPublicCommunities { after, limit } => {
+                Request::ListPublicCommunities(request::ListPublicCommunities {
+                    after: after.map(Into::into),
+                    limit: limit as u64,
+                })
+            }
+            JoinPublicCommunity(community) => Request::JoinPublicCommunity(community.into()),
+            OpenDirectChannel(user) => Request::OpenDirectChannel(user.into()),
+            SendDirectMessage { channel, content } => {
+                Request::SendDirectMessage(request::SendDirectMessage {
+                    channel: Some(channel.into()),
+                    content,
+                })
+            }
+            GetDirectMessages {
+                channel,
+                selector,
+                count,
+            } => Request::GetDirectMessages(request::GetDirectMessages {
+                channel: Some(channel.into()),
+                selector: selector.map(Into::into),
+                message_count: count,
+            }),
+            BlockUser(user) => Request::BlockUser(user.into()),
+            UnblockUser(user) => Request::UnblockUser(user.into()),
+            GetBlockedUsers => Request::GetBlockedUsers(proto::types::None {}),
+            LeaveCommunity(community) => Request::LeaveCommunity(request::LeaveCommunity {
+                community: Some(community.into()),
+            }),
+            DeleteCommunity { community, name } => {
+                Request::DeleteCommunity(request::DeleteCommunity {
+                    community: Some(community.into()),
+                    name,
+                })
+            }
+            GetCommunityMembers { community, after, limit } => {
+                Request::GetCommunityMembers(request::GetCommunityMembers {
+                    community: Some(community.into()),
+                    after: after.map(Into::into),
+                    limit: limit as u64,
+                })
+            }
+            CreateRole {
+                community,
+                name,
+                permissions,
+            } => Request::CreateRole(request::CreateRole {
+                community: Some(community.into()),
+                name,
+                permission_flags: permissions.bits(),
+            }),
+            AssignRole {
+                community,
+                role,
+                user,
+            } => Request::AssignRole(request::AssignRole {
+                community: Some(community.into()),
+                role: Some(role.into()),
+                user: Some(user.into()),
+            }),
+            DeleteMessage(delete) => Request::Delete(delete.into()),
             ChangeUsername { new_username } => {
                 Request::ChangeUsername(request::ChangeUsername { new_username })
             }
@@ -202,18 +580,88 @@ impl From<ClientRequest> for proto::requests::active::ClientRequest {
             GetProfile(id) => Request::GetProfile(request::GetProfile {
                 user: Some(id.into()),
             }),
-            ChangeCommunityName { new, community } => {
-                Request::ChangeCommunityName(request::ChangeCommunityName {
-                    new,
+            EditCommunity {
+                community,
+                name,
+                description,
+                icon,
+                retention_days,
+                public,
+                sign_messages,
+            } => {
+                use request::edit_community::{
+                    Description::NewDescription, Name::NewName, Public::NewPublic,
+                    RetentionDays::NewRetentionDays, SignMessages::NewSignMessages,
+                };
+                Request::EditCommunity(request::EditCommunity {
+                    community: Some(community.into()),
+                    name: name.map(NewName),
+                    description: description.map(NewDescription),
+                    icon: icon.map(Into::into),
+                    retention_days: retention_days.map(NewRetentionDays),
+                    public: public.map(NewPublic),
+                    sign_messages: sign_messages.map(NewSignMessages),
+                })
+            }
+            EditRoom {
+                community,
+                room,
+                topic,
+                slow_mode_secs,
+            } => Request::EditRoom(request::EditRoom {
+                community: Some(community.into()),
+                room: Some(room.into()),
+                topic: topic.map(request::edit_room::Topic::Text),
+                slow_mode_secs,
+            }),
+            SetRoomArchived { community, room, archived } => {
+                Request::SetRoomArchived(request::SetRoomArchived {
+                    community: Some(community.into()),
+                    room: Some(room.into()),
+                    archived,
+                })
+            }
+            SetWatchLevel { community, room, level } => {
+                Request::SetWatchLevel(request::SetWatchLevel {
+                    community: Some(community.into()),
+                    room: Some(room.into()),
+                    level: proto::structures::WatchLevel::from(level) as i32,
+                })
+            }
+            PinMessage { community, room, message } => {
+                Request::PinMessage(request::PinMessage {
+                    community: Some(community.into()),
+                    room: Some(room.into()),
+                    message: Some(message.into()),
+                })
+            }
+            UnpinMessage { community, room, message } => {
+                Request::UnpinMessage(request::UnpinMessage {
                     community: Some(community.into()),
+                    room: Some(room.into()),
+                    message: Some(message.into()),
                 })
             }
-            ChangeCommunityDescription { new, community } => {
-                Request::ChangeCommunityDescription(request::ChangeCommunityDescription {
-                    new,
+            GetPinnedMessages { community, room } => {
+                Request::GetPinnedMessages(request::GetPinnedMessages {
                     community: Some(community.into()),
+                    room: Some(room.into()),
                 })
             }
+            KickUser { community, user } => Request::KickUser(request::KickUser {
+                community: Some(community.into()),
+                user: Some(user.into()),
+            }),
+            MuteUser { community, user, until } => Request::MuteUser(request::MuteUser {
+                community: Some(community.into()),
+                user: Some(user.into()),
+                until: until.map(|t| request::mute_user::Until::UntilTimestamp(t.timestamp())),
+            }),
+            UnmuteUser { community, user } => Request::UnmuteUser(request::UnmuteUser {
+                community: Some(community.into()),
+                user: Some(user.into()),
+            }),
+            Resync => Request::Resync(proto::types::None {}),
             AdminAction(req) => Request::AdminAction(req.into()),
             ReportUser { message, short_desc, extended_desc } => {
                 Request::ReportUser(request::ReportUser {
@@ -222,6 +670,48 @@ impl From<ClientRequest> for proto::requests::active::ClientRequest {
                     extended_desc,
                 })
             }
+            GetEditHistory { community, room, message } => {
+                Request::GetEditHistory(request::GetEditHistory {
+                    community: Some(community.into()),
+                    room: Some(room.into()),
+                    message: Some(message.into()),
+                })
+            }
+            SearchMessages { community, query, limit } => {
+                Request::SearchMessages(request::SearchMessages {
+                    community: Some(community.into()),
+                    query,
+                    limit: limit as u64,
+                })
+            }
+            SearchUsers { query, limit } => Request::SearchUsers(request::SearchUsers {
+                query,
+                limit: limit as u64,
+            }),
+            AddReaction { community, room, message, emoji } => {
+                Request::AddReaction(request::AddReaction {
+                    community: Some(community.into()),
+                    room: Some(room.into()),
+                    message: Some(message.into()),
+                    emoji,
+                })
+            }
+            RemoveReaction { community, room, message, emoji } => {
+                Request::RemoveReaction(request::RemoveReaction {
+                    community: Some(community.into()),
+                    room: Some(room.into()),
+                    message: Some(message.into()),
+                    emoji,
+                })
+            }
+            StartTyping { community, room } => Request::StartTyping(request::StartTyping {
+                community: Some(community.into()),
+                room: Some(room.into()),
+            }),
+            StopTyping { community, room } => Request::StopTyping(request::StopTyping {
+                community: Some(community.into()),
+                room: Some(room.into()),
+            }),
         };
 
         request::ClientRequest {
@@ -238,6 +728,13 @@ impl TryFrom<proto::requests::active::ClientRequest> for ClientRequest {
 
         let val = match req.request? {
             LogOut(_) => ClientRequest::LogOut,
+            ListDevices(_) => ClientRequest::ListDevices,
+            SetStatus(status) => {
+                let status = proto::structures::PresenceStatus::from_i32(status);
+                ClientRequest::SetStatus(status.ok_or(DeserializeError::InvalidEnumVariant)?.into())
+            }
+            SetPrivacy(privacy) => ClientRequest::SetPrivacy(privacy.into()),
+            RevokeDevice(device) => ClientRequest::RevokeDevice(device.try_into()?),
             SendMessage(msg) => ClientRequest::SendMessage(msg.try_into()?),
             Edit(edit) => ClientRequest::EditMessage(edit.try_into()?),
             GetRoomUpdate(get) => {
@@ -248,6 +745,14 @@ impl TryFrom<proto::requests::active::ClientRequest> for ClientRequest {
                     message_count: get.message_count,
                 }
             }
+            GetRoomUpdates(get) => ClientRequest::GetRoomUpdates {
+                rooms: get
+                    .rooms
+                    .into_iter()
+                    .map(TryInto::try_into)
+                    .collect::<Result<_, _>>()?,
+                message_count: get.message_count,
+            },
             GetMessages(get) => ClientRequest::GetMessages {
                 community: get.community?.try_into()?,
                 room: get.room?.try_into()?,
@@ -263,13 +768,27 @@ impl TryFrom<proto::requests::active::ClientRequest> for ClientRequest {
                 community: set.community?.try_into()?,
                 room: set.room?.try_into()?,
             },
+            SetManyAsRead(set) => {
+                let rooms = set
+                    .rooms
+                    .into_iter()
+                    .map(|set| Ok((set.community?.try_into()?, set.room?.try_into()?)))
+                    .collect::<Result<_, DeserializeError>>()?;
+                ClientRequest::SetManyAsRead(rooms)
+            }
             CreateCommunity(create) => ClientRequest::CreateCommunity { name: create.name },
             CreateRoom(create) => ClientRequest::CreateRoom {
                 name: create.name,
                 community: create.community?.try_into()?,
             },
+            DeleteRoom(delete) => ClientRequest::DeleteRoom {
+                community: delete.community?.try_into()?,
+                room: delete.room?.try_into()?,
+            },
             CreateInvite(create) => {
-                use request::create_invite::ExpirationDatetime::Present;
+                use request::create_invite::{
+                    ExpirationDatetime::Present, MaxUses::Limit, Vanity::Code,
+                };
                 ClientRequest::CreateInvite {
                     community: create.community?.try_into()?,
                     expiration_datetime: create
@@ -277,10 +796,56 @@ impl TryFrom<proto::requests::active::ClientRequest> for ClientRequest {
                         .map(|Present(x)| x)
                         .map(|ts| NaiveDateTime::from_timestamp(ts, 0))
                         .map(|dt| Utc.from_utc_datetime(&dt)),
+                    max_uses: create.max_uses.map(|Limit(x)| x),
+                    vanity: create.vanity.map(|Code(x)| x),
                 }
             }
+            GetInviteCodes(community) => ClientRequest::GetInviteCodes(community.try_into()?),
+            GetCommunityStats(community) => {
+                ClientRequest::GetCommunityStats(community.try_into()?)
+            }
             JoinCommunity(join) => ClientRequest::JoinCommunity(InviteCode(join.invite_code)),
-            Delete(delete) => ClientRequest::Delete(delete.try_into()?),
+            ListPublicCommunities(list) => ClientRequest::ListPublicCommunities {
+                after: list.after.map(|x| x.try_into()).transpose()?,
+                limit: list.limit as usize,
+            },
+            JoinPublicCommunity(community) => {
+                ClientRequest::JoinPublicCommunity(community.try_into()?)
+            }
+            OpenDirectChannel(user) => ClientRequest::OpenDirectChannel(user.try_into()?),
+            SendDirectMessage(send) => ClientRequest::SendDirectMessage {
+                channel: send.channel?.try_into()?,
+                content: send.content,
+            },
+            GetDirectMessages(get) => ClientRequest::GetDirectMessages {
+                channel: get.channel?.try_into()?,
+                selector: get.selector.map(|x| x.try_into()).transpose()?,
+                count: get.message_count,
+            },
+            BlockUser(user) => ClientRequest::BlockUser(user.try_into()?),
+            UnblockUser(user) => ClientRequest::UnblockUser(user.try_into()?),
+            GetBlockedUsers(_) => ClientRequest::GetBlockedUsers,
+            LeaveCommunity(leave) => ClientRequest::LeaveCommunity(leave.community?.try_into()?),
+            DeleteCommunity(delete) => ClientRequest::DeleteCommunity {
+                community: delete.community?.try_into()?,
+                name: delete.name,
+            },
+            GetCommunityMembers(get) => ClientRequest::GetCommunityMembers {
+                community: get.community?.try_into()?,
+                after: get.after.map(|x| x.try_into()).transpose()?,
+                limit: get.limit as usize,
+            },
+            CreateRole(create) => ClientRequest::CreateRole {
+                community: create.community?.try_into()?,
+                name: create.name,
+                permissions: CommunityPermissionFlags::from_bits_truncate(create.permission_flags),
+            },
+            AssignRole(assign) => ClientRequest::AssignRole {
+                community: assign.community?.try_into()?,
+                role: assign.role?.try_into()?,
+                user: assign.user?.try_into()?,
+            },
+            Delete(delete) => ClientRequest::DeleteMessage(delete.try_into()?),
             ChangeUsername(change) => ClientRequest::ChangeUsername {
                 new_username: change.new_username,
             },
@@ -288,20 +853,113 @@ impl TryFrom<proto::requests::active::ClientRequest> for ClientRequest {
                 new_display_name: change.new_display_name,
             },
             GetProfile(get) => ClientRequest::GetProfile(get.user?.try_into()?),
-            ChangeCommunityName(change) => ClientRequest::ChangeCommunityName {
-                new: change.new,
-                community: change.community?.try_into()?,
+            EditCommunity(edit) => {
+                use request::edit_community::{
+                    Description::NewDescription, Name::NewName, Public::NewPublic,
+                    RetentionDays::NewRetentionDays, SignMessages::NewSignMessages,
+                };
+                ClientRequest::EditCommunity {
+                    community: edit.community?.try_into()?,
+                    name: edit.name.map(|NewName(name)| name),
+                    description: edit.description.map(|NewDescription(desc)| desc),
+                    icon: edit.icon.map(TryInto::try_into).transpose()?,
+                    retention_days: edit.retention_days.map(|NewRetentionDays(days)| days),
+                    public: edit.public.map(|NewPublic(public)| public),
+                    sign_messages: edit.sign_messages.map(|NewSignMessages(sign)| sign),
+                }
+            }
+            EditRoom(edit) => ClientRequest::EditRoom {
+                community: edit.community?.try_into()?,
+                room: edit.room?.try_into()?,
+                topic: edit.topic.map(|request::edit_room::Topic::Text(topic)| topic),
+                slow_mode_secs: edit.slow_mode_secs,
+            },
+            SetRoomArchived(set) => ClientRequest::SetRoomArchived {
+                community: set.community?.try_into()?,
+                room: set.room?.try_into()?,
+                archived: set.archived,
+            },
+            SetWatchLevel(set) => {
+                let level = proto::structures::WatchLevel::from_i32(set.level)
+                    .ok_or(DeserializeError::InvalidEnumVariant)?;
+
+                ClientRequest::SetWatchLevel {
+                    community: set.community?.try_into()?,
+                    room: set.room?.try_into()?,
+                    level: level.into(),
+                }
+            }
+            PinMessage(pin) => ClientRequest::PinMessage {
+                community: pin.community?.try_into()?,
+                room: pin.room?.try_into()?,
+                message: pin.message?.try_into()?,
+            },
+            UnpinMessage(unpin) => ClientRequest::UnpinMessage {
+                community: unpin.community?.try_into()?,
+                room: unpin.room?.try_into()?,
+                message: unpin.message?.try_into()?,
+            },
+            GetPinnedMessages(get) => ClientRequest::GetPinnedMessages {
+                community: get.community?.try_into()?,
+                room: get.room?.try_into()?,
+            },
+            KickUser(kick) => ClientRequest::KickUser {
+                community: kick.community?.try_into()?,
+                user: kick.user?.try_into()?,
             },
-            ChangeCommunityDescription(change) => ClientRequest::ChangeCommunityDescription {
-                new: change.new,
-                community: change.community?.try_into()?,
+            MuteUser(mute) => ClientRequest::MuteUser {
+                community: mute.community?.try_into()?,
+                user: mute.user?.try_into()?,
+                until: mute.until.map(|request::mute_user::Until::UntilTimestamp(x)| {
+                    let dt = &NaiveDateTime::from_timestamp(x, 0);
+                    Utc.from_utc_datetime(dt)
+                }),
             },
+            UnmuteUser(unmute) => ClientRequest::UnmuteUser {
+                community: unmute.community?.try_into()?,
+                user: unmute.user?.try_into()?,
+            },
+            Resync(_) => ClientRequest::Resync,
             AdminAction(action) => ClientRequest::AdminAction(action.try_into()?),
             ReportUser(report) => ClientRequest::ReportUser {
                 message: report.message?.try_into()?,
                 short_desc: report.short_desc,
                 extended_desc: report.extended_desc,
             },
+            GetEditHistory(get) => ClientRequest::GetEditHistory {
+                community: get.community?.try_into()?,
+                room: get.room?.try_into()?,
+                message: get.message?.try_into()?,
+            },
+            SearchMessages(search) => ClientRequest::SearchMessages {
+                community: search.community?.try_into()?,
+                query: search.query,
+                limit: search.limit as usize,
+            },
+            SearchUsers(search) => ClientRequest::SearchUsers {
+                query: search.query,
+                limit: search.limit as usize,
+            },
+            AddReaction(add) => ClientRequest::AddReaction {
+                community: add.community?.try_into()?,
+                room: add.room?.try_into()?,
+                message: add.message?.try_into()?,
+                emoji: add.emoji,
+            },
+            RemoveReaction(remove) => ClientRequest::RemoveReaction {
+                community: remove.community?.try_into()?,
+                room: remove.room?.try_into()?,
+                message: remove.message?.try_into()?,
+                emoji: remove.emoji,
+            },
+            StartTyping(start) => ClientRequest::StartTyping {
+                community: start.community?.try_into()?,
+                room: start.room?.try_into()?,
+            },
+            StopTyping(stop) => ClientRequest::StopTyping {
+                community: stop.community?.try_into()?,
+                room: stop.room?.try_into()?,
+            },
         };
 
         Ok(val)
@@ -342,40 +1000,72 @@ impl<T> Bound<T> {
     }
 }
 
-impl From<Bound<MessageId>> for proto::requests::active::Bound {
-    fn from(bound: Bound<MessageId>) -> Self {
+/// A place in the history of a room or direct channel, either at a message or at a sequence
+/// number. A sequence number does not have to belong to a message which still exists.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MessagePosition {
+    Id(MessageId),
+    Seq(u64),
+}
+
+impl From<MessagePosition> for proto::requests::active::bound::Position {
+    fn from(position: MessagePosition) -> Self {
+        use proto::requests::active::bound::Position;
+
+        match position {
+            MessagePosition::Id(id) => Position::Message(id.into()),
+            MessagePosition::Seq(seq) => Position::Seq(seq),
+        }
+    }
+}
+
+impl TryFrom<proto::requests::active::bound::Position> for MessagePosition {
+    type Error = DeserializeError;
+
+    fn try_from(position: proto::requests::active::bound::Position) -> Result<Self, Self::Error> {
+        use proto::requests::active::bound::Position;
+
+        Ok(match position {
+            Position::Message(id) => MessagePosition::Id(id.try_into()?),
+            Position::Seq(seq) => MessagePosition::Seq(seq),
+        })
+    }
+}
+
+impl From<Bound<MessagePosition>> for proto::requests::active::Bound {
+    fn from(bound: Bound<MessagePosition>) -> Self {
         match bound {
             Bound::Inclusive(bound) => proto::requests::active::Bound {
                 exclusive: false,
-                message: Some(bound.into()),
+                position: Some(bound.into()),
             },
             Bound::Exclusive(bound) => proto::requests::active::Bound {
                 exclusive: true,
-                message: Some(bound.into()),
+                position: Some(bound.into()),
             },
         }
     }
 }
 
-impl TryFrom<proto::requests::active::Bound> for Bound<MessageId> {
+impl TryFrom<proto::requests::active::Bound> for Bound<MessagePosition> {
     type Error = DeserializeError;
 
     fn try_from(bound: proto::requests::active::Bound) -> Result<Self, Self::Error> {
-        let proto::requests::active::Bound { exclusive, message } = bound;
-        let message = message?.try_into()?;
+        let proto::requests::active::Bound { exclusive, position } = bound;
+        let position = position?.try_into()?;
 
         Ok(if exclusive {
-            Bound::Exclusive(message)
+            Bound::Exclusive(position)
         } else {
-            Bound::Inclusive(message)
+            Bound::Inclusive(position)
         })
     }
 }
 
 #[derive(Debug, Copy, Clone)]
 pub enum MessageSelector {
-    Before(Bound<MessageId>),
-    After(Bound<MessageId>),
+    Before(Bound<MessagePosition>),
+    After(Bound<MessagePosition>),
 }
 
 impl From<MessageSelector> for proto::requests::active::MessageSelector {
@@ -407,3 +1097,42 @@ impl TryFrom<proto::requests::active::MessageSelector> for MessageSelector {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn garbage_is_malformed() {
+        let garbage = [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01];
+        assert!(ClientMessage::from_protobuf_bytes(&garbage).is_err());
+    }
+
+    #[test]
+    fn text_is_malformed() {
+        let text = "{\"id\": 1, \"request\": \"LogOut\"}";
+        assert!(ClientMessage::from_protobuf_bytes(text.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn empty_message_is_malformed() {
+        assert!(ClientMessage::from_protobuf_bytes(&[]).is_err());
+    }
+
+    #[test]
+    fn truncated_message_is_malformed() {
+        let message = ClientMessage::new(ClientRequest::GetBlockedUsers, RequestId::new(7));
+        let bytes: Vec<u8> = message.into();
+        assert!(ClientMessage::from_protobuf_bytes(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn valid_message_round_trips() {
+        let message = ClientMessage::new(ClientRequest::GetBlockedUsers, RequestId::new(7));
+        let bytes: Vec<u8> = message.into();
+
+        let decoded = ClientMessage::from_protobuf_bytes(&bytes).unwrap();
+        assert_eq!(decoded.id, RequestId::new(7));
+        assert_eq!(decoded.request.kind(), "GetBlockedUsers");
+    }
+}
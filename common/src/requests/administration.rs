@@ -20,6 +20,8 @@ bitflags! {
         const IS_ADMIN = 1 << 3;
         /// Whether the user can set accounts compromised
         const SET_ACCOUNTS_COMPROMISED = 1 << 4;
+        /// Send system messages to the rooms of any community
+        const BROADCAST = 1 << 5;
     }
 }
 
@@ -31,7 +33,12 @@ pub enum AdminRequest {
         permissions: AdminPermissionFlags,
     },
     Demote(UserId),
-    Ban(UserId),
+    /// Ban a user, either permanently or until the given time, revoking all of their logins
+    Ban {
+        user: UserId,
+        reason: String,
+        until: Option<DateTime<Utc>>,
+    },
     Unban(UserId),
     Unlock(UserId),
     SearchUser {
@@ -45,6 +52,24 @@ pub enum AdminRequest {
         status: ReportStatus,
     },
     SetAccountsCompromised(SetCompromisedType),
+    /// Close a report, taking the given action against the reported user
+    ResolveReport {
+        id: i32,
+        action: ReportAction,
+    },
+    /// Get a page of the audit log, newest first. `after` is the id of the last event of the
+    /// previous page, if any.
+    GetAuditLog {
+        after: Option<i64>,
+        limit: u32,
+    },
+    /// Send a system message to a room, which is shown to its members as coming from the server.
+    /// A message confirmation is returned.
+    BroadcastSystemMessage {
+        community: CommunityId,
+        room: RoomId,
+        text: String,
+    },
 }
 
 impl From<AdminRequest> for proto::requests::administration::AdminRequest {
@@ -61,8 +86,10 @@ impl From<AdminRequest> for proto::requests::administration::AdminRequest {
             Demote(user) => Request::DemoteUser(request::Demote {
                 user: Some(user.into()),
             }),
-            Ban(user) => Request::BanUser(request::Ban {
+            Ban { user, reason, until } => Request::BanUser(request::Ban {
                 user: Some(user.into()),
+                reason,
+                until: until.map(|t| request::ban::Until::UntilTimestamp(t.timestamp())),
             }),
             Unban(user) => Request::UnbanUser(request::Unban {
                 user: Some(user.into()),
@@ -81,6 +108,21 @@ impl From<AdminRequest> for proto::requests::administration::AdminRequest {
             SetAccountsCompromised(typ) => Request::SetAccountsCompromised(
                 request::SetCompromisedType::from(typ) as i32
             ),
+            ResolveReport { id, action } => Request::ResolveReport(request::ResolveReport {
+                id,
+                action: Some(action.into()),
+            }),
+            GetAuditLog { after, limit } => Request::GetAuditLog(request::GetAuditLog {
+                after: after.map(request::get_audit_log::After::AfterId),
+                limit,
+            }),
+            BroadcastSystemMessage { community, room, text } => {
+                Request::BroadcastSystemMessage(request::BroadcastSystemMessage {
+                    community: Some(community.into()),
+                    room: Some(room.into()),
+                    text,
+                })
+            }
         };
 
         proto::requests::administration::AdminRequest {
@@ -96,6 +138,8 @@ impl TryFrom<proto::requests::administration::AdminRequest> for AdminRequest {
         req: proto::requests::administration::AdminRequest,
     ) -> Result<Self, DeserializeError> {
         use proto::requests::administration::admin_request::Request::*;
+        use proto::requests::administration::ban::Until;
+        use proto::requests::administration::get_audit_log;
 
         let req = match req.request? {
             PromoteUser(promote) => AdminRequest::Promote {
@@ -103,7 +147,14 @@ impl TryFrom<proto::requests::administration::AdminRequest> for AdminRequest {
                 permissions: AdminPermissionFlags::from_bits_truncate(promote.permissions_flags),
             },
             DemoteUser(demote) => AdminRequest::Demote(demote.user?.try_into()?),
-            BanUser(ban) => AdminRequest::Ban(ban.user?.try_into()?),
+            BanUser(ban) => AdminRequest::Ban {
+                user: ban.user?.try_into()?,
+                reason: ban.reason,
+                until: ban.until.map(|Until::UntilTimestamp(x)| {
+                    let dt = &NaiveDateTime::from_timestamp(x, 0);
+                    Utc.from_utc_datetime(dt)
+                }),
+            },
             UnbanUser(unban) => AdminRequest::Unban(unban.user?.try_into()?),
             UnlockUser(unlock) => AdminRequest::Unlock(unlock.user?.try_into()?),
             SearchUser(search) => AdminRequest::SearchUser { name: search.name },
@@ -119,6 +170,19 @@ impl TryFrom<proto::requests::administration::AdminRequest> for AdminRequest {
                     .ok_or(DeserializeError::InvalidEnumVariant)?;
                 AdminRequest::SetAccountsCompromised(typ.try_into()?)
             },
+            ResolveReport(resolve) => AdminRequest::ResolveReport {
+                id: resolve.id,
+                action: resolve.action?.into(),
+            },
+            GetAuditLog(get) => AdminRequest::GetAuditLog {
+                after: get.after.map(|get_audit_log::After::AfterId(id)| id),
+                limit: get.limit,
+            },
+            BroadcastSystemMessage(broadcast) => AdminRequest::BroadcastSystemMessage {
+                community: broadcast.community?.try_into()?,
+                room: broadcast.room?.try_into()?,
+                text: broadcast.text,
+            },
         };
 
         Ok(req)
@@ -131,6 +195,8 @@ pub enum AdminResponse {
     SearchedUsers(Vec<ServerUser>),
     Admins(Vec<Admin>),
     Reports(Vec<Report>),
+    /// Events from the audit log, newest first
+    AuditLog(Vec<AuditEvent>),
 }
 
 impl From<AdminResponse> for proto::requests::administration::AdminResponse {
@@ -152,6 +218,10 @@ impl From<AdminResponse> for proto::requests::administration::AdminResponse {
                 let reports = reports.into_iter().map(Into::into).collect();
                 Response::Reports(request::Reports { reports })
             }
+            AuditLog(events) => {
+                let events = events.into_iter().map(Into::into).collect();
+                Response::AuditLog(request::AuditLog { events })
+            }
         };
 
         proto::requests::administration::AdminResponse {
@@ -184,6 +254,11 @@ impl TryFrom<proto::requests::administration::AdminResponse> for AdminResponse {
                 let admins: Vec<Report> = res?;
                 AdminResponse::Reports(admins)
             }
+            AuditLog(log) => {
+                let res: Result<_, _> = log.events.into_iter().map(TryInto::try_into).collect();
+                let events: Vec<AuditEvent> = res?;
+                AdminResponse::AuditLog(events)
+            }
         };
 
         Ok(res)
@@ -305,6 +380,172 @@ impl fmt::Display for ReportStatus {
     }
 }
 
+/// What to do about a reported user when resolving a report
+#[derive(Debug, Clone)]
+pub enum ReportAction {
+    /// Take no action, denying the report
+    Dismiss,
+    /// Ban the reported user, accepting the report
+    Ban {
+        reason: String,
+        until: Option<DateTime<Utc>>,
+    },
+}
+
+impl From<ReportAction> for proto::requests::administration::resolve_report::Action {
+    fn from(action: ReportAction) -> Self {
+        use proto::requests::administration::{ban_reported::Until, resolve_report::Action};
+        use proto::requests::administration::BanReported;
+
+        match action {
+            ReportAction::Dismiss => Action::Dismiss(proto::types::None {}),
+            ReportAction::Ban { reason, until } => Action::Ban(BanReported {
+                reason,
+                until: until.map(|t| Until::UntilTimestamp(t.timestamp())),
+            }),
+        }
+    }
+}
+
+impl From<proto::requests::administration::resolve_report::Action> for ReportAction {
+    fn from(action: proto::requests::administration::resolve_report::Action) -> Self {
+        use proto::requests::administration::{ban_reported::Until, resolve_report::Action};
+
+        match action {
+            Action::Dismiss(_) => ReportAction::Dismiss,
+            Action::Ban(ban) => ReportAction::Ban {
+                reason: ban.reason,
+                until: ban.until.map(|Until::UntilTimestamp(x)| {
+                    let dt = &NaiveDateTime::from_timestamp(x, 0);
+                    Utc.from_utc_datetime(dt)
+                }),
+            },
+        }
+    }
+}
+
+/// An administrative action recorded in the audit log
+#[derive(Debug, Clone)]
+pub struct AuditEvent {
+    pub id: i64,
+    pub time: DateTime<Utc>,
+    /// The admin who took the action, or `None` if the server operator took it from the command
+    /// line
+    pub actor: Option<UserId>,
+    pub action: AuditAction,
+}
+
+#[derive(Debug, Clone)]
+pub enum AuditAction {
+    Ban {
+        user: UserId,
+        reason: String,
+        until: Option<DateTime<Utc>>,
+    },
+    Unban(UserId),
+    Unlock(UserId),
+    Promote {
+        user: UserId,
+        permissions: AdminPermissionFlags,
+    },
+    Demote(UserId),
+    SetReportStatus {
+        report: i32,
+        status: ReportStatus,
+    },
+    SetAccountsCompromised(SetCompromisedType),
+}
+
+impl From<AuditEvent> for proto::requests::administration::AuditEvent {
+    fn from(event: AuditEvent) -> Self {
+        use proto::requests::administration as request;
+        use proto::requests::administration::audit_event::Action;
+
+        let action = match event.action {
+            AuditAction::Ban { user, reason, until } => Action::Ban(request::Ban {
+                user: Some(user.into()),
+                reason,
+                until: until.map(|t| request::ban::Until::UntilTimestamp(t.timestamp())),
+            }),
+            AuditAction::Unban(user) => Action::Unban(request::Unban {
+                user: Some(user.into()),
+            }),
+            AuditAction::Unlock(user) => Action::Unlock(request::Unlock {
+                user: Some(user.into()),
+            }),
+            AuditAction::Promote { user, permissions } => Action::Promote(request::Promote {
+                user: Some(user.into()),
+                permissions_flags: permissions.bits,
+            }),
+            AuditAction::Demote(user) => Action::Demote(request::Demote {
+                user: Some(user.into()),
+            }),
+            AuditAction::SetReportStatus { report, status } => {
+                Action::SetReportStatus(request::SetReportStatus {
+                    id: report,
+                    status: status as i8 as u32,
+                })
+            }
+            AuditAction::SetAccountsCompromised(typ) => {
+                Action::SetAccountsCompromised(request::SetCompromisedType::from(typ) as i32)
+            }
+        };
+
+        request::AuditEvent {
+            id: event.id,
+            timestamp: event.time.timestamp(),
+            actor: event.actor.map(Into::into),
+            action: Some(action),
+        }
+    }
+}
+
+impl TryFrom<proto::requests::administration::AuditEvent> for AuditEvent {
+    type Error = DeserializeError;
+
+    fn try_from(
+        event: proto::requests::administration::AuditEvent
+    ) -> Result<Self, DeserializeError> {
+        use proto::requests::administration::audit_event::Action;
+        use proto::requests::administration::ban::Until;
+
+        let action = match event.action? {
+            Action::Ban(ban) => AuditAction::Ban {
+                user: ban.user?.try_into()?,
+                reason: ban.reason,
+                until: ban.until.map(|Until::UntilTimestamp(x)| {
+                    let dt = &NaiveDateTime::from_timestamp(x, 0);
+                    Utc.from_utc_datetime(dt)
+                }),
+            },
+            Action::Unban(unban) => AuditAction::Unban(unban.user?.try_into()?),
+            Action::Unlock(unlock) => AuditAction::Unlock(unlock.user?.try_into()?),
+            Action::Promote(promote) => AuditAction::Promote {
+                user: promote.user?.try_into()?,
+                permissions: AdminPermissionFlags::from_bits_truncate(promote.permissions_flags),
+            },
+            Action::Demote(demote) => AuditAction::Demote(demote.user?.try_into()?),
+            Action::SetReportStatus(set) => AuditAction::SetReportStatus {
+                report: set.id,
+                status: i8::try_from(set.status)?.try_into()?,
+            },
+            Action::SetAccountsCompromised(typ) => {
+                let typ = proto::requests::administration::SetCompromisedType::from_i32(typ)
+                    .ok_or(DeserializeError::InvalidEnumVariant)?;
+                AuditAction::SetAccountsCompromised(typ.try_into()?)
+            }
+        };
+
+        let dt = &NaiveDateTime::from_timestamp(event.timestamp, 0);
+        Ok(AuditEvent {
+            id: event.id,
+            time: Utc.from_utc_datetime(dt),
+            actor: event.actor.map(TryInto::try_into).transpose()?,
+            action,
+        })
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ReportUser {
     pub id: UserId,
@@ -1,6 +1,6 @@
 use crate::proto;
 use crate::proto::DeserializeError;
-use crate::structures::{Credentials, TokenCreationOptions};
+use crate::structures::{AccountRules, Credentials, TokenCreationOptions};
 use crate::types::*;
 use serde::{Deserialize, Serialize};
 use std::convert::{TryFrom, TryInto};
@@ -12,6 +12,14 @@ use std::ops::Try;
 pub struct Login {
     pub device: DeviceId,
     pub token: AuthToken,
+    /// Protocol version of the client. This is only needed to authenticate a websocket
+    /// connection, which is refused if it is missing or incompatible.
+    #[serde(default)]
+    pub protocol: Option<ProtocolVersion>,
+    /// Whether the client can receive compressed frames over the websocket connection. If so,
+    /// every message sent to it is wrapped in a frame as described in [`crate::frame`].
+    #[serde(default)]
+    pub compression: bool,
 }
 
 #[non_exhaustive]
@@ -291,16 +299,19 @@ impl From<AuthResponse> for proto::requests::auth::AuthResponse {
     fn from(result: AuthResponse) -> Self {
         use proto::requests::auth::auth_response::Response;
 
+        let server_protocol = match &result {
+            AuthResponse::Err(AuthError::IncompatibleProtocol { server }) => Some(*server),
+            _ => None,
+        };
+
         let inner = match result {
             AuthResponse::Ok(ok) => Response::Ok(ok.into()),
-            AuthResponse::Err(err) => {
-                let error: proto::requests::auth::AuthError = err.into();
-                Response::Error(error as i32)
-            }
+            AuthResponse::Err(err) => Response::Error(err.error_code() as i32),
         };
 
         proto::requests::auth::AuthResponse {
             response: Some(inner),
+            server_protocol: server_protocol.map(Into::into),
         }
     }
 }
@@ -314,9 +325,16 @@ impl TryFrom<proto::requests::auth::AuthResponse> for AuthResponse {
         Ok(match response.response? {
             Response::Ok(ok) => AuthResponse::Ok(ok.try_into()?),
             Response::Error(err) => {
-                let error = proto::requests::auth::AuthError::from_i32(err)
-                    .ok_or(DeserializeError::InvalidEnumVariant)?;
-                AuthResponse::Err(error.try_into()?)
+                use proto::requests::auth::AuthError as ProtoAuthError;
+
+                match ProtoAuthError::from_i32(err) {
+                    Some(ProtoAuthError::IncompatibleProtocol) => {
+                        let server = response.server_protocol?.into();
+                        AuthResponse::Err(AuthError::IncompatibleProtocol { server })
+                    }
+                    Some(error) => AuthResponse::Err(error.try_into()?),
+                    None => AuthResponse::Err(AuthError::Unknown { code: err as u32 }),
+                }
             }
         })
     }
@@ -327,6 +345,8 @@ pub enum AuthOk {
     User(UserId),
     Token(NewToken),
     NoData,
+    Attachment(AttachmentId),
+    AccountRules(AccountRules),
 }
 
 impl From<AuthOk> for proto::requests::auth::AuthOk {
@@ -338,6 +358,8 @@ impl From<AuthOk> for proto::requests::auth::AuthOk {
             User(user) => Ok::User(user.into()),
             Token(token) => Ok::Token(token.into()),
             NoData => Ok::NoData(proto::types::None {}),
+            Attachment(id) => Ok::Attachment(id.into()),
+            AccountRules(rules) => Ok::AccountRules(rules.into()),
         };
 
         proto::requests::auth::AuthOk { ok: Some(inner) }
@@ -354,6 +376,8 @@ impl TryFrom<proto::requests::auth::AuthOk> for AuthOk {
             User(user) => AuthOk::User(user.try_into()?),
             Token(token) => AuthOk::Token(token.try_into()?),
             NoData(_) => AuthOk::NoData,
+            Attachment(id) => AuthOk::Attachment(id.try_into()?),
+            AccountRules(rules) => AuthOk::AccountRules(rules.into()),
         })
     }
 }
@@ -376,6 +400,33 @@ pub enum AuthError {
     InvalidPassword,
     InvalidDisplayName,
     InvalidMessage,
+    /// The upload would take the user over their attachment storage quota
+    StorageQuotaExceeded,
+    /// The uploaded avatar is not an image
+    InvalidAvatar,
+    /// The client's protocol version is missing or cannot be understood by the server, which
+    /// speaks the given version
+    IncompatibleProtocol { server: ProtocolVersion },
+    /// The user's account has been marked as compromised, so the password must be changed before
+    /// they can log in again
+    PasswordResetRequired,
+    /// The requested token expiration date is in the past or further away than the server allows
+    InvalidTokenLifetime,
+    InvalidDeviceName,
+    /// An error with a code which this version does not know of, such as one added in a newer
+    /// version of the server
+    Unknown { code: u32 },
+}
+
+impl AuthError {
+    /// A stable code for the error, which is its number in the protocol. Codes are never reused,
+    /// so they can be matched on across versions.
+    pub fn error_code(&self) -> u32 {
+        match self {
+            AuthError::Unknown { code } => *code,
+            err => proto::requests::auth::AuthError::from(err.clone()) as u32,
+        }
+    }
 }
 
 impl fmt::Display for AuthError {
@@ -397,22 +448,37 @@ impl fmt::Display for AuthError {
             InvalidPassword => write!(f, "Invalid password"),
             InvalidDisplayName => write!(f, "Invalid display name"),
             InvalidMessage => write!(f, "Invalid message"),
+            StorageQuotaExceeded => write!(f, "Storage quota exceeded"),
+            InvalidAvatar => write!(f, "Invalid avatar"),
+            PasswordResetRequired => write!(f, "Password must be changed"),
+            InvalidTokenLifetime => write!(f, "Invalid token lifetime"),
+            InvalidDeviceName => write!(f, "Invalid device name"),
+            IncompatibleProtocol { server } => {
+                write!(f, "Incompatible protocol: the server speaks version {}", server)
+            }
+            Unknown { code } => write!(f, "Unknown auth error (code {})", code),
         }
     }
 }
 
 macro_rules! convert_to_proto {
-    ($err:ident: { $($variant:ident$(,)?)* }) => {
+    ($err:ident: { $($variant:ident$(,)?)* } with data { $($data:ident$(,)?)* }) => {
         match $err {
             $(AuthError::$variant => proto::requests::auth::AuthError::$variant,)*
+            $(AuthError::$data { .. } => proto::requests::auth::AuthError::$data,)*
+            // Only sent as its code, which this version of the enum does not have
+            AuthError::Unknown { .. } => proto::requests::auth::AuthError::Internal,
         }
     };
 }
 
+/// Errors which carry data cannot be converted from the enum alone, since the data is sent
+/// alongside it in the `AuthResponse`
 macro_rules! convert_from_proto {
-    ($err:ident: { $($variant:ident$(,)?)* }) => {
+    ($err:ident: { $($variant:ident$(,)?)* } with data { $($data:ident$(,)?)* }) => {
         match $err {
             $(proto::requests::auth::AuthError::$variant => Ok(AuthError::$variant),)*
+            $(proto::requests::auth::AuthError::$data => Err(DeserializeError::NullField),)*
         }
     };
 }
@@ -435,7 +501,14 @@ impl From<AuthError> for proto::requests::auth::AuthError {
                 InvalidUsername,
                 InvalidPassword,
                 InvalidDisplayName,
-                InvalidMessage
+                InvalidMessage,
+                StorageQuotaExceeded,
+                InvalidAvatar,
+                PasswordResetRequired,
+                InvalidTokenLifetime,
+                InvalidDeviceName,
+            } with data {
+                IncompatibleProtocol,
             }
         }
     }
@@ -461,7 +534,14 @@ impl TryFrom<proto::requests::auth::AuthError> for AuthError {
                 InvalidUsername,
                 InvalidPassword,
                 InvalidDisplayName,
-                InvalidMessage
+                InvalidMessage,
+                StorageQuotaExceeded,
+                InvalidAvatar,
+                PasswordResetRequired,
+                InvalidTokenLifetime,
+                InvalidDeviceName,
+            } with data {
+                IncompatibleProtocol,
             }
         }
     }
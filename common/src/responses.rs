@@ -1,5 +1,6 @@
 use std::convert::{TryFrom, TryInto};
 use std::fmt;
+use std::time::Duration;
 
 use crate::proto;
 use crate::proto::DeserializeError;
@@ -23,8 +24,29 @@ pub enum OkResponse {
     Profile(Profile),
     NewInvite(InviteCode),
     RoomUpdate(RoomUpdate),
+    /// Updates for several rooms, in the order that they were asked for
+    RoomUpdates(Vec<RoomUpdate>),
     MessageHistory(MessageHistory),
     Admin(AdminResponse),
+    CommunityMembers(Vec<MemberInfo>),
+    NewRole(RoleId),
+    Devices(Vec<DeviceInfo>),
+    /// Previous versions of a message, oldest first
+    EditHistory(Vec<EditRecord>),
+    /// Messages matching a search, newest first
+    SearchResults(Vec<SearchResult>),
+    /// Users matching a search, best match first
+    UserSearchResults(Vec<UserSearchResult>),
+    /// A page of the public communities, ordered by name
+    PublicCommunities(Vec<PublicCommunity>),
+    DirectChannel(DirectChannelId),
+    BlockedUsers(Vec<UserId>),
+    InviteCodes(Vec<InviteCodeInfo>),
+    /// Messages pinned in a room, most recently pinned first
+    PinnedMessages(Vec<Message>),
+    /// The state of every room the user is in
+    Resync(Vec<RoomSyncState>),
+    CommunityStats(CommunityStats),
 }
 
 impl From<OkResponse> for proto::responses::Ok {
@@ -47,8 +69,44 @@ impl From<OkResponse> for proto::responses::Ok {
                 Response::NewInvite(responses::NewInvite { code: code.0 })
             }
             RoomUpdate(update) => Response::RoomUpdate(update.into()),
+            RoomUpdates(updates) => Response::RoomUpdates(responses::RoomUpdates {
+                updates: updates.into_iter().map(Into::into).collect(),
+            }),
             MessageHistory(history) => Response::MessageHistory(history.into()),
             Admin(admin) => Response::Admin(admin.into()),
+            NewRole(id) => Response::NewRole(id.into()),
+            DirectChannel(id) => Response::DirectChannel(id.into()),
+            InviteCodes(codes) => Response::InviteCodes(responses::InviteCodes {
+                codes: codes.into_iter().map(Into::into).collect(),
+            }),
+            PinnedMessages(messages) => Response::PinnedMessages(responses::PinnedMessages {
+                messages: messages.into_iter().map(Into::into).collect(),
+            }),
+            Resync(rooms) => Response::Resync(responses::Resync {
+                rooms: rooms.into_iter().map(Into::into).collect(),
+            }),
+            BlockedUsers(users) => Response::BlockedUsers(responses::BlockedUsers {
+                users: users.into_iter().map(Into::into).collect(),
+            }),
+            SearchResults(results) => Response::SearchResults(responses::SearchResults {
+                results: results.into_iter().map(Into::into).collect(),
+            }),
+            UserSearchResults(users) => Response::UserSearchResults(responses::UserSearchResults {
+                users: users.into_iter().map(Into::into).collect(),
+            }),
+            PublicCommunities(list) => Response::PublicCommunities(responses::PublicCommunities {
+                communities: list.into_iter().map(Into::into).collect(),
+            }),
+            CommunityStats(stats) => Response::CommunityStats(stats.into()),
+            EditHistory(edits) => Response::EditHistory(responses::EditHistory {
+                edits: edits.into_iter().map(Into::into).collect(),
+            }),
+            Devices(devices) => Response::Devices(responses::Devices {
+                devices: devices.into_iter().map(Into::into).collect(),
+            }),
+            CommunityMembers(members) => Response::CommunityMembers(responses::CommunityMembers {
+                members: members.into_iter().map(Into::into).collect(),
+            }),
         };
 
         proto::responses::Ok {
@@ -75,8 +133,72 @@ impl TryFrom<proto::responses::Ok> for OkResponse {
             Profile(profile) => OkResponse::Profile(profile.try_into()?),
             NewInvite(new_invite) => OkResponse::NewInvite(InviteCode(new_invite.code)),
             RoomUpdate(update) => OkResponse::RoomUpdate(update.try_into()?),
+            RoomUpdates(list) => OkResponse::RoomUpdates(
+                list.updates
+                    .into_iter()
+                    .map(TryInto::try_into)
+                    .collect::<Result<_, _>>()?,
+            ),
             MessageHistory(history) => OkResponse::MessageHistory(history.try_into()?),
             Admin(admin) => OkResponse::Admin(admin.try_into()?),
+            NewRole(id) => OkResponse::NewRole(id.try_into()?),
+            DirectChannel(id) => OkResponse::DirectChannel(id.try_into()?),
+            InviteCodes(list) => {
+                OkResponse::InviteCodes(list.codes.into_iter().map(Into::into).collect())
+            }
+            PinnedMessages(list) => OkResponse::PinnedMessages(
+                list.messages
+                    .into_iter()
+                    .map(TryInto::try_into)
+                    .collect::<Result<_, _>>()?,
+            ),
+            Resync(resync) => OkResponse::Resync(
+                resync
+                    .rooms
+                    .into_iter()
+                    .map(TryInto::try_into)
+                    .collect::<Result<_, _>>()?,
+            ),
+            BlockedUsers(list) => OkResponse::BlockedUsers(
+                list.users
+                    .into_iter()
+                    .map(TryInto::try_into)
+                    .collect::<Result<_, _>>()?,
+            ),
+            SearchResults(list) => OkResponse::SearchResults(
+                list.results
+                    .into_iter()
+                    .map(TryInto::try_into)
+                    .collect::<Result<_, _>>()?,
+            ),
+            UserSearchResults(list) => OkResponse::UserSearchResults(
+                list.users
+                    .into_iter()
+                    .map(TryInto::try_into)
+                    .collect::<Result<_, _>>()?,
+            ),
+            PublicCommunities(list) => OkResponse::PublicCommunities(
+                list.communities
+                    .into_iter()
+                    .map(TryInto::try_into)
+                    .collect::<Result<_, _>>()?,
+            ),
+            CommunityStats(stats) => OkResponse::CommunityStats(stats.into()),
+            EditHistory(history) => {
+                OkResponse::EditHistory(history.edits.into_iter().map(Into::into).collect())
+            }
+            Devices(list) => OkResponse::Devices(
+                list.devices
+                    .into_iter()
+                    .map(TryInto::try_into)
+                    .collect::<Result<_, _>>()?,
+            ),
+            CommunityMembers(list) => OkResponse::CommunityMembers(
+                list.members
+                    .into_iter()
+                    .map(TryInto::try_into)
+                    .collect::<Result<_, _>>()?,
+            ),
         })
     }
 }
@@ -102,13 +224,71 @@ pub enum Error {
     InvalidInviteCode,
     InvalidUser,
     InvalidMessage,
+    /// The given reaction was not a valid emoji
+    InvalidReaction,
+    /// The role does not exist in the given community
+    InvalidRole,
+    /// The search query was too short
+    InvalidSearchQuery,
+    /// The request's quota has been used up. It can be retried after `retry_after_secs`.
+    RateLimited { retry_after_secs: u32 },
+    /// An attachment does not exist, was not uploaded by the user, or was already sent
+    InvalidAttachment,
+    /// The user has already reported this message
+    AlreadyReported,
+    /// A report does not exist
+    InvalidReport,
     /// The given string field value was too long.
     TooLong,
     AlreadyInCommunity,
     TooManyInviteCodes,
+    /// The vanity invite code asked for is already used by another invite
+    InviteCodeTaken,
+    /// The room already has as many pinned messages as the server allows
+    TooManyPins,
+    /// More rooms were asked for in one request than the server allows
+    TooManyRooms,
+    /// The user changed their username too recently. A `ServerMessage::RateLimited` saying when it
+    /// can be changed again is sent before the response.
+    UsernameChangeCooldown,
+    /// The user changed their display name too recently. A `ServerMessage::RateLimited` saying
+    /// when it can be changed again is sent before the response.
+    DisplayNameChangeCooldown,
     InvalidMessageSelector,
+    /// The message was longer than the `max_message_len` given in `ClientReady`
     MessageTooLong,
+    /// The message had no text other than whitespace, and no attachments
+    EmptyMessage,
+    /// The user has been muted in the community, so cannot send messages to it
+    Muted,
+    /// The room has been archived, so can be read but not sent to
+    RoomArchived,
+    /// The text re-typed to confirm a destructive action, such as a community's name, was wrong
+    IncorrectConfirmation,
     Unimplemented,
+    /// An error with a code which this version does not know of, such as one added in a newer
+    /// version of the server
+    Unknown { code: u32 },
+}
+
+impl Error {
+    /// A stable code for the error, which is its number in the protocol. Codes are never reused,
+    /// so they can be matched on across versions.
+    pub fn error_code(&self) -> u32 {
+        match self {
+            Error::Unknown { code } => *code,
+            err => proto::responses::Error::from(err.clone()) as u32,
+        }
+    }
+
+    /// A `RateLimited` error for a request which can be retried after the given time, rounded up
+    /// to the second
+    pub fn rate_limited(ready_in: Duration) -> Error {
+        let secs = ready_in.as_secs() + (ready_in.subsec_nanos() > 0) as u64;
+        Error::RateLimited {
+            retry_after_secs: secs.try_into().unwrap_or(std::u32::MAX),
+        }
+    }
 }
 
 impl fmt::Display for Error {
@@ -130,27 +310,52 @@ impl fmt::Display for Error {
             InvalidUser => write!(f, "Invalid user"),
             AlreadyInCommunity => write!(f, "Already in community"),
             TooManyInviteCodes => write!(f, "Too many invite codes"),
+            InviteCodeTaken => write!(f, "Invite code already taken"),
+            TooManyPins => write!(f, "Too many pinned messages in room"),
+            TooManyRooms => write!(f, "Too many rooms in request"),
+            UsernameChangeCooldown => write!(f, "Username changed too recently"),
+            DisplayNameChangeCooldown => write!(f, "Display name changed too recently"),
             InvalidMessageSelector => write!(f, "Invalid message selector"),
             MessageTooLong => write!(f, "Message too long"),
+            EmptyMessage => write!(f, "Message empty"),
+            Muted => write!(f, "Muted in this community"),
+            RoomArchived => write!(f, "Room is archived"),
+            IncorrectConfirmation => write!(f, "Incorrect confirmation"),
             TooLong => write!(f, "Text field too long"),
             Unimplemented => write!(f, "Unimplemented API"),
             InvalidMessage => write!(f, "Invalid message (deleted?)"),
+            InvalidReaction => write!(f, "Invalid reaction"),
+            InvalidRole => write!(f, "Invalid role"),
+            InvalidSearchQuery => write!(f, "Invalid search query"),
+            RateLimited { retry_after_secs } => {
+                write!(f, "Rate limited, retry in {} seconds", retry_after_secs)
+            }
+            InvalidAttachment => write!(f, "Invalid attachment"),
+            AlreadyReported => write!(f, "Message already reported"),
+            InvalidReport => write!(f, "Invalid report"),
+            Unknown { code } => write!(f, "Unknown error (code {})", code),
         }
     }
 }
 
 macro_rules! convert_to_proto {
-    ($err:ident: { $($variant:ident$(,)?)* }) => {
+    ($err:ident: { $($variant:ident$(,)?)* } with data { $($data:ident$(,)?)* }) => {
         match $err {
             $(Error::$variant => proto::responses::Error::$variant,)*
+            $(Error::$data { .. } => proto::responses::Error::$data,)*
+            // Only sent as its code, which this version of the enum does not have
+            Error::Unknown { .. } => proto::responses::Error::Internal,
         }
     };
 }
 
+/// Errors which carry data cannot be converted from the enum alone, since the data is sent
+/// alongside it in the `Response`
 macro_rules! convert_from_proto {
-    ($err:ident: { $($variant:ident$(,)?)* }) => {
+    ($err:ident: { $($variant:ident$(,)?)* } with data { $($data:ident$(,)?)* }) => {
         match $err {
             $(proto::responses::Error::$variant => Ok(Error::$variant),)*
+            $(proto::responses::Error::$data => Err(DeserializeError::NullField),)*
         }
     };
 }
@@ -173,12 +378,29 @@ impl From<Error> for proto::responses::Error {
                 InvalidInviteCode,
                 InvalidUser,
                 InvalidMessage,
+                InvalidReaction,
+                InvalidRole,
+                InvalidSearchQuery,
+                InvalidAttachment,
+                AlreadyReported,
+                InvalidReport,
                 AlreadyInCommunity,
                 TooManyInviteCodes,
+                TooManyPins,
+                TooManyRooms,
+                UsernameChangeCooldown,
+                DisplayNameChangeCooldown,
                 InvalidMessageSelector,
                 MessageTooLong,
+                EmptyMessage,
+                Muted,
+                RoomArchived,
+                IncorrectConfirmation,
+                InviteCodeTaken,
                 Unimplemented,
                 TooLong,
+            } with data {
+                RateLimited,
             }
         }
     }
@@ -204,12 +426,29 @@ impl TryFrom<proto::responses::Error> for Error {
                 InvalidInviteCode,
                 InvalidUser,
                 InvalidMessage,
+                InvalidReaction,
+                InvalidRole,
+                InvalidSearchQuery,
+                InvalidAttachment,
+                AlreadyReported,
+                InvalidReport,
                 AlreadyInCommunity,
                 TooManyInviteCodes,
+                TooManyPins,
+                TooManyRooms,
+                UsernameChangeCooldown,
+                DisplayNameChangeCooldown,
                 InvalidMessageSelector,
                 MessageTooLong,
+                EmptyMessage,
+                Muted,
+                RoomArchived,
+                IncorrectConfirmation,
+                InviteCodeTaken,
                 Unimplemented,
                 TooLong,
+            } with data {
+                RateLimited,
             }
         }
     }
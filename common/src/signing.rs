@@ -0,0 +1,51 @@
+//! Signatures of messages in communities which have message signing turned on. The server signs
+//! the bytes given by [`message_payload`] with its Ed25519 key, which can be fetched from
+//! `/vertex/signing_key`. Anyone holding the key can then check that a message was stored by the
+//! server and has not been changed since.
+//!
+//! A signature covers the message as it was sent, so edited and deleted messages are not signed.
+
+use crate::structures::Message;
+use crate::types::{CommunityId, RoomId};
+
+/// Written at the start of every payload, so that signatures cannot be reused for anything else
+const PAYLOAD_PREFIX: &[u8] = b"vertex-message-v1\0";
+
+/// The bytes which are signed for a message sent to the given room. Fields are written in a fixed
+/// order, with lengths before anything which varies in length, so that no two messages share a
+/// payload. Reactions and the author's profile version are not covered, since they change.
+pub fn message_payload(community: CommunityId, room: RoomId, message: &Message) -> Vec<u8> {
+    let mut payload = PAYLOAD_PREFIX.to_vec();
+
+    payload.extend_from_slice(community.0.as_bytes());
+    payload.extend_from_slice(room.0.as_bytes());
+    payload.extend_from_slice(message.id.0.as_bytes());
+    payload.extend_from_slice(message.author.0.as_bytes());
+    payload.extend_from_slice(&message.seq.to_be_bytes());
+    payload.extend_from_slice(&message.time_sent.timestamp().to_be_bytes());
+    payload.push(message.kind as u8);
+
+    match &message.reply_to {
+        Some(reply_to) => {
+            payload.push(1);
+            payload.extend_from_slice(reply_to.message.0.as_bytes());
+        }
+        None => payload.push(0),
+    }
+
+    payload.extend_from_slice(&(message.attachments.len() as u32).to_be_bytes());
+    for attachment in &message.attachments {
+        payload.extend_from_slice(attachment.0.as_bytes());
+    }
+
+    match &message.content {
+        Some(content) => {
+            payload.push(1);
+            payload.extend_from_slice(&(content.len() as u32).to_be_bytes());
+            payload.extend_from_slice(content.as_bytes());
+        }
+        None => payload.push(0),
+    }
+
+    payload
+}
@@ -11,6 +11,8 @@ pub struct CommunityStructure {
     pub name: String,
     pub description: String,
     pub rooms: Vec<RoomStructure>,
+    /// The community's icon, downloadable like any other attachment
+    pub icon: Option<AttachmentId>,
 }
 
 impl From<CommunityStructure> for proto::structures::CommunityStructure {
@@ -20,6 +22,7 @@ impl From<CommunityStructure> for proto::structures::CommunityStructure {
             name: community.name,
             description: community.description,
             rooms: community.rooms.into_iter().map(Into::into).collect(),
+            icon: community.icon.map(Into::into),
         }
     }
 }
@@ -39,6 +42,7 @@ impl TryFrom<proto::structures::CommunityStructure> for CommunityStructure {
             name: community.name,
             description: community.description,
             rooms,
+            icon: community.icon.map(TryInto::try_into).transpose()?,
         })
     }
 }
@@ -48,14 +52,30 @@ pub struct RoomStructure {
     pub id: RoomId,
     pub name: String,
     pub unread: bool,
+    pub topic: Option<String>,
+    /// How long members must wait between sending messages in the room. Disabled if 0.
+    pub slow_mode_secs: u32,
+    /// The user's own watch level for the room
+    pub watch_level: WatchLevel,
+    /// Archived rooms can be read but not sent to
+    pub archived: bool,
+    /// How many messages have been sent in the room since the user last read it
+    pub unread_count: u32,
 }
 
 impl From<RoomStructure> for proto::structures::RoomStructure {
     fn from(room: RoomStructure) -> Self {
+        use proto::structures::room_structure::Topic;
+
         proto::structures::RoomStructure {
             id: Some(room.id.into()),
             name: room.name,
             unread: room.unread,
+            topic: room.topic.map(Topic::Text),
+            slow_mode_secs: room.slow_mode_secs,
+            watch_level: proto::structures::WatchLevel::from(room.watch_level) as i32,
+            archived: room.archived,
+            unread_count: room.unread_count,
         }
     }
 }
@@ -64,10 +84,20 @@ impl TryFrom<proto::structures::RoomStructure> for RoomStructure {
     type Error = DeserializeError;
 
     fn try_from(room: proto::structures::RoomStructure) -> Result<Self, Self::Error> {
+        use proto::structures::room_structure::Topic;
+
+        let watch_level = proto::structures::WatchLevel::from_i32(room.watch_level)
+            .ok_or(DeserializeError::InvalidEnumVariant)?;
+
         Ok(RoomStructure {
             id: room.id?.try_into()?,
             name: room.name,
             unread: room.unread,
+            topic: room.topic.map(|Topic::Text(topic)| topic),
+            slow_mode_secs: room.slow_mode_secs,
+            watch_level: watch_level.into(),
+            archived: room.archived,
+            unread_count: room.unread_count,
         })
     }
 }
@@ -76,6 +106,8 @@ impl TryFrom<proto::structures::RoomStructure> for RoomStructure {
 pub struct MessageConfirmation {
     pub id: MessageId,
     pub time_sent: DateTime<Utc>,
+    /// The sequence number the message was given in its room or channel
+    pub seq: u64,
 }
 
 impl From<MessageConfirmation> for proto::structures::MessageConfirmation {
@@ -83,6 +115,7 @@ impl From<MessageConfirmation> for proto::structures::MessageConfirmation {
         proto::structures::MessageConfirmation {
             id: Some(confirmation.id.into()),
             time_sent: confirmation.time_sent.timestamp(),
+            seq: confirmation.seq,
         }
     }
 }
@@ -95,21 +128,44 @@ impl TryFrom<proto::structures::MessageConfirmation> for MessageConfirmation {
         Ok(MessageConfirmation {
             id: confirmation.id?.try_into()?,
             time_sent: Utc.from_utc_datetime(dt),
+            seq: confirmation.seq,
         })
     }
 }
 
-#[derive(Debug, Clone)]
+/// A page of message history, ordered from oldest to newest
+#[derive(Debug, Clone, Default)]
 pub struct MessageHistory {
     pub buffer: Vec<Message>,
+    pub cursor: HistoryCursor,
+}
+
+/// Where a page of message history starts and ends. The pages next to it should be fetched with
+/// exclusive bounds on these messages, so that none are skipped if more are sent while paging.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub struct HistoryCursor {
+    pub oldest: Option<MessageId>,
+    pub newest: Option<MessageId>,
+    /// Whether more messages matched the selector than fit in the page. These are older than the
+    /// page for `Before` selectors, and newer than it for `After` selectors.
+    pub has_more: bool,
 }
 
 impl MessageHistory {
-    pub fn from_newest_to_oldest(messages: Vec<Message>) -> Self {
+    pub fn from_newest_to_oldest(messages: Vec<Message>, has_more: bool) -> Self {
         let mut messages = messages;
         messages.reverse();
 
-        MessageHistory { buffer: messages }
+        let cursor = HistoryCursor {
+            oldest: messages.first().map(|message| message.id),
+            newest: messages.last().map(|message| message.id),
+            has_more,
+        };
+
+        MessageHistory {
+            buffer: messages,
+            cursor,
+        }
     }
 }
 
@@ -117,6 +173,9 @@ impl From<MessageHistory> for proto::structures::MessageHistory {
     fn from(history: MessageHistory) -> Self {
         proto::structures::MessageHistory {
             messages: history.buffer.into_iter().map(Into::into).collect(),
+            oldest: history.cursor.oldest.map(Into::into),
+            newest: history.cursor.newest.map(Into::into),
+            has_more: history.cursor.has_more,
         }
     }
 }
@@ -131,7 +190,13 @@ impl TryFrom<proto::structures::MessageHistory> for MessageHistory {
             .map(TryInto::try_into)
             .collect::<Result<Vec<Message>, DeserializeError>>()?;
 
-        Ok(MessageHistory { buffer })
+        let cursor = HistoryCursor {
+            oldest: history.oldest.map(TryInto::try_into).transpose()?,
+            newest: history.newest.map(TryInto::try_into).transpose()?,
+            has_more: history.has_more,
+        };
+
+        Ok(MessageHistory { buffer, cursor })
     }
 }
 
@@ -162,7 +227,7 @@ impl TryFrom<proto::structures::RoomUpdate> for RoomUpdate {
             new_messages: if let Some(new_messages) = update.new_messages {
                 new_messages.try_into()?
             } else {
-                MessageHistory { buffer: vec![] }
+                MessageHistory::default()
             },
             continuous: update.continuous,
         })
@@ -176,11 +241,21 @@ pub struct Message {
     pub author_profile_version: ProfileVersion,
     pub time_sent: DateTime<Utc>,
     pub content: Option<String>,
+    pub reactions: Vec<ReactionSummary>,
+    pub attachments: Vec<AttachmentId>,
+    pub reply_to: Option<ReplyPreview>,
+    pub kind: MessageKind,
+    /// The message's place in its room or direct channel. Each message is numbered one higher than
+    /// the message sent before it, so a jump in numbers means messages are missing.
+    pub seq: u64,
+    /// The server's Ed25519 signature of the message, if its community signs messages. See
+    /// [`crate::signing`].
+    pub signature: Option<Vec<u8>>,
 }
 
 impl From<Message> for proto::structures::Message {
     fn from(msg: Message) -> Self {
-        use proto::structures::message::Content;
+        use proto::structures::message::{Content, Signature};
 
         proto::structures::Message {
             id: Some(msg.id.into()),
@@ -188,6 +263,12 @@ impl From<Message> for proto::structures::Message {
             author_profile_version: msg.author_profile_version.0 as u32,
             time_sent: msg.time_sent.timestamp(),
             content: msg.content.map(Content::Present),
+            reactions: msg.reactions.into_iter().map(Into::into).collect(),
+            attachments: msg.attachments.into_iter().map(Into::into).collect(),
+            reply_to: msg.reply_to.map(Into::into),
+            kind: proto::structures::MessageKind::from(msg.kind) as i32,
+            seq: msg.seq,
+            signature: msg.signature.map(Signature::SignaturePresent),
         }
     }
 }
@@ -196,8 +277,10 @@ impl TryFrom<proto::structures::Message> for Message {
     type Error = DeserializeError;
 
     fn try_from(message: proto::structures::Message) -> Result<Self, Self::Error> {
-        use proto::structures::message::Content;
+        use proto::structures::message::{Content, Signature};
         let dt = &NaiveDateTime::from_timestamp(message.time_sent, 0);
+        let kind = proto::structures::MessageKind::from_i32(message.kind)
+            .ok_or(DeserializeError::InvalidEnumVariant)?;
 
         Ok(Message {
             id: message.id?.try_into()?,
@@ -208,10 +291,283 @@ impl TryFrom<proto::structures::Message> for Message {
                 let Content::Present(content) = c;
                 content
             }),
+            reactions: message.reactions.into_iter().map(Into::into).collect(),
+            attachments: message
+                .attachments
+                .into_iter()
+                .map(TryInto::try_into)
+                .collect::<Result<_, _>>()?,
+            reply_to: message.reply_to.map(TryInto::try_into).transpose()?,
+            kind: kind.into(),
+            seq: message.seq,
+            signature: message.signature.map(|Signature::SignaturePresent(sig)| sig),
+        })
+    }
+}
+
+/// Who a message was sent by
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[repr(u8)]
+pub enum MessageKind {
+    /// Written by its author
+    Normal = 0,
+    /// An announcement from the administrators of the server. Its author is the administrator who
+    /// sent it, but it should be shown as coming from the server.
+    System = 1,
+}
+
+impl Default for MessageKind {
+    fn default() -> Self {
+        MessageKind::Normal
+    }
+}
+
+impl From<MessageKind> for proto::structures::MessageKind {
+    fn from(kind: MessageKind) -> Self {
+        match kind {
+            MessageKind::Normal => proto::structures::MessageKind::Normal,
+            MessageKind::System => proto::structures::MessageKind::System,
+        }
+    }
+}
+
+impl From<proto::structures::MessageKind> for MessageKind {
+    fn from(kind: proto::structures::MessageKind) -> Self {
+        match kind {
+            proto::structures::MessageKind::Normal => MessageKind::Normal,
+            proto::structures::MessageKind::System => MessageKind::System,
+        }
+    }
+}
+
+/// The message that a reply is to, with its content cut short. The content is `None` if the
+/// message has been deleted.
+#[derive(Debug, Clone)]
+pub struct ReplyPreview {
+    pub message: MessageId,
+    pub author: UserId,
+    pub content: Option<String>,
+}
+
+impl From<ReplyPreview> for proto::structures::ReplyPreview {
+    fn from(preview: ReplyPreview) -> Self {
+        use proto::structures::reply_preview::Content;
+
+        proto::structures::ReplyPreview {
+            message: Some(preview.message.into()),
+            author: Some(preview.author.into()),
+            content: preview.content.map(Content::Present),
+        }
+    }
+}
+
+impl TryFrom<proto::structures::ReplyPreview> for ReplyPreview {
+    type Error = DeserializeError;
+
+    fn try_from(preview: proto::structures::ReplyPreview) -> Result<Self, Self::Error> {
+        use proto::structures::reply_preview::Content;
+
+        Ok(ReplyPreview {
+            message: preview.message?.try_into()?,
+            author: preview.author?.try_into()?,
+            content: preview.content.map(|Content::Present(content)| content),
         })
     }
 }
 
+/// The state of one of the user's rooms, for a client to compare against what it has after
+/// reconnecting. The newest message is `None` if nothing has been sent in the room.
+#[derive(Debug, Clone)]
+pub struct RoomSyncState {
+    pub community: CommunityId,
+    pub room: RoomId,
+    pub newest_message: Option<MessageId>,
+    pub unread: bool,
+}
+
+impl From<RoomSyncState> for proto::structures::RoomSyncState {
+    fn from(state: RoomSyncState) -> Self {
+        proto::structures::RoomSyncState {
+            community: Some(state.community.into()),
+            room: Some(state.room.into()),
+            newest_message: state.newest_message.map(Into::into),
+            unread: state.unread,
+        }
+    }
+}
+
+impl TryFrom<proto::structures::RoomSyncState> for RoomSyncState {
+    type Error = DeserializeError;
+
+    fn try_from(state: proto::structures::RoomSyncState) -> Result<Self, Self::Error> {
+        Ok(RoomSyncState {
+            community: state.community?.try_into()?,
+            room: state.room?.try_into()?,
+            newest_message: state.newest_message.map(TryInto::try_into).transpose()?,
+            unread: state.unread,
+        })
+    }
+}
+
+/// Finds the usernames mentioned in message content as `@username`. A mention must start a word,
+/// and runs until the next whitespace, not counting any trailing punctuation.
+pub fn mentioned_usernames(content: &str) -> impl Iterator<Item = &str> {
+    content
+        .split(char::is_whitespace)
+        .filter_map(|word| word.strip_prefix('@'))
+        .map(|name| name.trim_end_matches(|c: char| c.is_ascii_punctuation()))
+        .filter(|name| !name.is_empty())
+}
+
+/// A previous version of an edited message
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct EditRecord {
+    pub old_content: String,
+    /// When this version was replaced
+    pub edited_at: DateTime<Utc>,
+}
+
+impl From<EditRecord> for proto::structures::EditRecord {
+    fn from(record: EditRecord) -> Self {
+        proto::structures::EditRecord {
+            old_content: record.old_content,
+            edited_at: record.edited_at.timestamp(),
+        }
+    }
+}
+
+impl From<proto::structures::EditRecord> for EditRecord {
+    fn from(record: proto::structures::EditRecord) -> Self {
+        let dt = NaiveDateTime::from_timestamp(record.edited_at, 0);
+        EditRecord {
+            old_content: record.old_content,
+            edited_at: Utc.from_utc_datetime(&dt),
+        }
+    }
+}
+
+/// A message matching a search query
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct SearchResult {
+    pub message: MessageId,
+    pub room: RoomId,
+    /// The part of the message content that matched the query
+    pub snippet: String,
+}
+
+impl From<SearchResult> for proto::structures::SearchResult {
+    fn from(result: SearchResult) -> Self {
+        proto::structures::SearchResult {
+            message: Some(result.message.into()),
+            room: Some(result.room.into()),
+            snippet: result.snippet,
+        }
+    }
+}
+
+impl TryFrom<proto::structures::SearchResult> for SearchResult {
+    type Error = DeserializeError;
+
+    fn try_from(result: proto::structures::SearchResult) -> Result<Self, Self::Error> {
+        Ok(SearchResult {
+            message: result.message?.try_into()?,
+            room: result.room?.try_into()?,
+            snippet: result.snippet,
+        })
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PresenceStatus {
+    Offline,
+    Online,
+    Away,
+}
+
+impl From<PresenceStatus> for proto::structures::PresenceStatus {
+    fn from(status: PresenceStatus) -> Self {
+        match status {
+            PresenceStatus::Offline => proto::structures::PresenceStatus::Offline,
+            PresenceStatus::Online => proto::structures::PresenceStatus::Online,
+            PresenceStatus::Away => proto::structures::PresenceStatus::Away,
+        }
+    }
+}
+
+impl From<proto::structures::PresenceStatus> for PresenceStatus {
+    fn from(status: proto::structures::PresenceStatus) -> Self {
+        match status {
+            proto::structures::PresenceStatus::Offline => PresenceStatus::Offline,
+            proto::structures::PresenceStatus::Online => PresenceStatus::Online,
+            proto::structures::PresenceStatus::Away => PresenceStatus::Away,
+        }
+    }
+}
+
+/// How closely a user follows a room: which of its messages they are sent as they arrive, and
+/// which they are notified of
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[repr(u8)]
+pub enum WatchLevel {
+    /// Every message is sent and notified
+    All = 0,
+    /// Only mentions are notified. Other messages are just marked unread.
+    MentionsOnly = 1,
+    /// Nothing is notified, not even mentions
+    Nothing = 2,
+}
+
+impl Default for WatchLevel {
+    fn default() -> Self {
+        WatchLevel::MentionsOnly
+    }
+}
+
+impl From<WatchLevel> for proto::structures::WatchLevel {
+    fn from(level: WatchLevel) -> Self {
+        match level {
+            WatchLevel::All => proto::structures::WatchLevel::All,
+            WatchLevel::MentionsOnly => proto::structures::WatchLevel::MentionsOnly,
+            WatchLevel::Nothing => proto::structures::WatchLevel::Nothing,
+        }
+    }
+}
+
+impl From<proto::structures::WatchLevel> for WatchLevel {
+    fn from(level: proto::structures::WatchLevel) -> Self {
+        match level {
+            proto::structures::WatchLevel::All => WatchLevel::All,
+            proto::structures::WatchLevel::MentionsOnly => WatchLevel::MentionsOnly,
+            proto::structures::WatchLevel::Nothing => WatchLevel::Nothing,
+        }
+    }
+}
+
+/// The number of users who have reacted to a message with a given emoji
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ReactionSummary {
+    pub emoji: String,
+    pub count: u32,
+}
+
+impl From<ReactionSummary> for proto::structures::ReactionSummary {
+    fn from(summary: ReactionSummary) -> Self {
+        proto::structures::ReactionSummary {
+            emoji: summary.emoji,
+            count: summary.count,
+        }
+    }
+}
+
+impl From<proto::structures::ReactionSummary> for ReactionSummary {
+    fn from(summary: proto::structures::ReactionSummary) -> Self {
+        ReactionSummary {
+            emoji: summary.emoji,
+            count: summary.count,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Edit {
     pub message: MessageId,
@@ -280,6 +636,11 @@ pub struct ClientReady {
     pub communities: Vec<CommunityStructure>,
     pub permissions: TokenPermissionFlags,
     pub admin_permissions: AdminPermissionFlags,
+    /// Longest message the server accepts, in bytes
+    pub max_message_len: u32,
+    /// The room this device had selected when it was last connected, if the user is still in it
+    pub selected_room: Option<(CommunityId, RoomId)>,
+    pub privacy: PrivacySettings,
 }
 
 impl From<ClientReady> for proto::structures::ClientReady {
@@ -290,6 +651,14 @@ impl From<ClientReady> for proto::structures::ClientReady {
             communities: ready.communities.into_iter().map(Into::into).collect(),
             permission_flags: ready.permissions.bits(),
             admin_permission_flags: ready.admin_permissions.bits(),
+            max_message_len: ready.max_message_len,
+            selected_room: ready.selected_room.map(|(community, room)| {
+                proto::structures::SelectedRoom {
+                    community: Some(community.into()),
+                    room: Some(room.into()),
+                }
+            }),
+            privacy: Some(ready.privacy.into()),
         }
     }
 }
@@ -312,6 +681,14 @@ impl TryFrom<proto::structures::ClientReady> for ClientReady {
             admin_permissions: AdminPermissionFlags::from_bits_truncate(
                 ready.admin_permission_flags,
             ),
+            max_message_len: ready.max_message_len,
+            selected_room: ready
+                .selected_room
+                .map(|selected| -> Result<_, DeserializeError> {
+                    Ok((selected.community?.try_into()?, selected.room?.try_into()?))
+                })
+                .transpose()?,
+            privacy: ready.privacy?.into(),
         })
     }
 }
@@ -321,14 +698,23 @@ pub struct Profile {
     pub version: ProfileVersion,
     pub username: String,
     pub display_name: String,
+    /// The user's avatar, downloadable like any other attachment
+    pub avatar: Option<AttachmentId>,
+    /// When the user's last session disconnected. Absent while they are online, if they have
+    /// never been seen, or if they have chosen to hide it.
+    pub last_seen: Option<DateTime<Utc>>,
 }
 
 impl From<Profile> for proto::structures::Profile {
     fn from(profile: Profile) -> Self {
+        use proto::structures::profile::LastSeen;
+
         proto::structures::Profile {
             version: profile.version.0,
             username: profile.username,
             display_name: profile.display_name,
+            avatar: profile.avatar.map(Into::into),
+            last_seen: profile.last_seen.map(|dt| LastSeen::LastSeenAt(dt.timestamp())),
         }
     }
 }
@@ -337,14 +723,260 @@ impl TryFrom<proto::structures::Profile> for Profile {
     type Error = DeserializeError;
 
     fn try_from(profile: proto::structures::Profile) -> Result<Self, Self::Error> {
+        use proto::structures::profile::LastSeen;
+
         Ok(Profile {
             version: ProfileVersion(profile.version),
             username: profile.username,
             display_name: profile.display_name,
+            avatar: profile.avatar.map(TryInto::try_into).transpose()?,
+            last_seen: profile.last_seen.map(|LastSeen::LastSeenAt(ts)| {
+                Utc.from_utc_datetime(&NaiveDateTime::from_timestamp(ts, 0))
+            }),
+        })
+    }
+}
+
+/// What a user lets other users see of them, and who may contact them
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct PrivacySettings {
+    /// Whether others can see the user's presence status. If not, they always appear offline.
+    pub show_online_status: bool,
+    /// Whether others can see when the user was last online
+    pub show_last_seen: bool,
+    /// Whether users who share no community with the user may open a direct channel with them
+    pub allow_stranger_dms: bool,
+}
+
+impl Default for PrivacySettings {
+    fn default() -> Self {
+        PrivacySettings {
+            show_online_status: true,
+            show_last_seen: false,
+            allow_stranger_dms: false,
+        }
+    }
+}
+
+impl From<PrivacySettings> for proto::structures::PrivacySettings {
+    fn from(privacy: PrivacySettings) -> Self {
+        proto::structures::PrivacySettings {
+            show_online_status: privacy.show_online_status,
+            show_last_seen: privacy.show_last_seen,
+            allow_stranger_dms: privacy.allow_stranger_dms,
+        }
+    }
+}
+
+impl From<proto::structures::PrivacySettings> for PrivacySettings {
+    fn from(privacy: proto::structures::PrivacySettings) -> Self {
+        PrivacySettings {
+            show_online_status: privacy.show_online_status,
+            show_last_seen: privacy.show_last_seen,
+            allow_stranger_dms: privacy.allow_stranger_dms,
+        }
+    }
+}
+
+/// A community which anyone can find and join without an invite code
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct PublicCommunity {
+    pub id: CommunityId,
+    pub name: String,
+    pub description: String,
+    pub member_count: u64,
+}
+
+impl From<PublicCommunity> for proto::structures::PublicCommunity {
+    fn from(community: PublicCommunity) -> Self {
+        proto::structures::PublicCommunity {
+            id: Some(community.id.into()),
+            name: community.name,
+            description: community.description,
+            member_count: community.member_count,
+        }
+    }
+}
+
+impl TryFrom<proto::structures::PublicCommunity> for PublicCommunity {
+    type Error = DeserializeError;
+
+    fn try_from(community: proto::structures::PublicCommunity) -> Result<Self, Self::Error> {
+        Ok(PublicCommunity {
+            id: community.id?.try_into()?,
+            name: community.name,
+            description: community.description,
+            member_count: community.member_count,
+        })
+    }
+}
+
+/// A user matching a search query
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct UserSearchResult {
+    pub user: UserId,
+    pub profile: Profile,
+}
+
+impl From<UserSearchResult> for proto::structures::UserSearchResult {
+    fn from(result: UserSearchResult) -> Self {
+        proto::structures::UserSearchResult {
+            user: Some(result.user.into()),
+            profile: Some(result.profile.into()),
+        }
+    }
+}
+
+impl TryFrom<proto::structures::UserSearchResult> for UserSearchResult {
+    type Error = DeserializeError;
+
+    fn try_from(result: proto::structures::UserSearchResult) -> Result<Self, Self::Error> {
+        Ok(UserSearchResult {
+            user: result.user?.try_into()?,
+            profile: result.profile?.try_into()?,
         })
     }
 }
 
+/// An entry in a community's member list
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct MemberInfo {
+    pub user: UserId,
+    pub display_name: String,
+    pub online: bool,
+}
+
+impl From<MemberInfo> for proto::structures::MemberInfo {
+    fn from(member: MemberInfo) -> Self {
+        proto::structures::MemberInfo {
+            user: Some(member.user.into()),
+            display_name: member.display_name,
+            online: member.online,
+        }
+    }
+}
+
+impl TryFrom<proto::structures::MemberInfo> for MemberInfo {
+    type Error = DeserializeError;
+
+    fn try_from(member: proto::structures::MemberInfo) -> Result<Self, Self::Error> {
+        Ok(MemberInfo {
+            user: member.user?.try_into()?,
+            display_name: member.display_name,
+            online: member.online,
+        })
+    }
+}
+
+/// A device that the user is logged in on
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct DeviceInfo {
+    pub device: DeviceId,
+    pub device_name: Option<String>,
+    pub last_used: DateTime<Utc>,
+}
+
+impl From<DeviceInfo> for proto::structures::DeviceInfo {
+    fn from(info: DeviceInfo) -> Self {
+        use proto::structures::device_info::DeviceName;
+
+        proto::structures::DeviceInfo {
+            device: Some(info.device.into()),
+            device_name: info.device_name.map(DeviceName::Present),
+            last_used: info.last_used.timestamp(),
+        }
+    }
+}
+
+impl TryFrom<proto::structures::DeviceInfo> for DeviceInfo {
+    type Error = DeserializeError;
+
+    fn try_from(info: proto::structures::DeviceInfo) -> Result<Self, Self::Error> {
+        use proto::structures::device_info::DeviceName;
+
+        let dt = NaiveDateTime::from_timestamp(info.last_used, 0);
+        Ok(DeviceInfo {
+            device: info.device?.try_into()?,
+            device_name: info.device_name.map(|name| {
+                let DeviceName::Present(name) = name;
+                name
+            }),
+            last_used: Utc.from_utc_datetime(&dt),
+        })
+    }
+}
+
+/// Counts describing a community, for its owners and administrators
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct CommunityStats {
+    pub members: u64,
+    pub rooms: u64,
+    /// Every message ever sent to the community which has not been removed by its retention
+    /// period, including deleted messages
+    pub messages: u64,
+    /// Messages sent in the last 24 hours
+    pub messages_last_day: u64,
+}
+
+impl From<CommunityStats> for proto::structures::CommunityStats {
+    fn from(stats: CommunityStats) -> Self {
+        proto::structures::CommunityStats {
+            members: stats.members,
+            rooms: stats.rooms,
+            messages: stats.messages,
+            messages_last_day: stats.messages_last_day,
+        }
+    }
+}
+
+impl From<proto::structures::CommunityStats> for CommunityStats {
+    fn from(stats: proto::structures::CommunityStats) -> Self {
+        CommunityStats {
+            members: stats.members,
+            rooms: stats.rooms,
+            messages: stats.messages,
+            messages_last_day: stats.messages_last_day,
+        }
+    }
+}
+
+/// An invite code that can still be used
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct InviteCodeInfo {
+    pub code: InviteCode,
+    pub expiration_date: Option<DateTime<Utc>>,
+    /// How many more times the code can be used, or `None` if it has no limit
+    pub remaining_uses: Option<u32>,
+}
+
+impl From<InviteCodeInfo> for proto::structures::InviteCodeInfo {
+    fn from(info: InviteCodeInfo) -> Self {
+        use proto::structures::invite_code_info::{ExpirationDate, RemainingUses};
+
+        proto::structures::InviteCodeInfo {
+            code: info.code.0,
+            expiration_date: info
+                .expiration_date
+                .map(|dt| ExpirationDate::ExpiresAt(dt.timestamp())),
+            remaining_uses: info.remaining_uses.map(RemainingUses::Remaining),
+        }
+    }
+}
+
+impl From<proto::structures::InviteCodeInfo> for InviteCodeInfo {
+    fn from(info: proto::structures::InviteCodeInfo) -> Self {
+        use proto::structures::invite_code_info::{ExpirationDate, RemainingUses};
+
+        InviteCodeInfo {
+            code: InviteCode(info.code),
+            expiration_date: info.expiration_date.map(|ExpirationDate::ExpiresAt(ts)| {
+                Utc.from_utc_datetime(&NaiveDateTime::from_timestamp(ts, 0))
+            }),
+            remaining_uses: info.remaining_uses.map(|RemainingUses::Remaining(n)| n),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Credentials {
     pub username: String,
@@ -375,9 +1007,72 @@ impl From<proto::structures::Credentials> for Credentials {
     }
 }
 
+/// The limits that the server puts on account details, so that clients can check them before
+/// sending anything. Lengths are in bytes of UTF-8. Any characters are allowed, but usernames are
+/// compared after NFKC normalization and lowercasing, so `Alice` and `alice` are the same user.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct AccountRules {
+    pub min_username_len: u32,
+    pub max_username_len: u32,
+    pub max_display_name_len: u32,
+    pub min_password_len: u32,
+    pub max_password_len: u32,
+    pub max_device_name_len: u32,
+}
+
+impl AccountRules {
+    pub fn valid_username(&self, username: &str) -> bool {
+        let len = username.len() as u32;
+        len >= self.min_username_len && len <= self.max_username_len
+    }
+
+    pub fn valid_display_name(&self, display_name: &str) -> bool {
+        !display_name.is_empty() && display_name.len() as u32 <= self.max_display_name_len
+    }
+
+    pub fn valid_password(&self, password: &str) -> bool {
+        let len = password.len() as u32;
+        len >= self.min_password_len && len <= self.max_password_len
+    }
+
+    /// Device names must contain something other than whitespace
+    pub fn valid_device_name(&self, device_name: &str) -> bool {
+        !device_name.trim().is_empty() && device_name.len() as u32 <= self.max_device_name_len
+    }
+}
+
+impl From<AccountRules> for proto::structures::AccountRules {
+    fn from(rules: AccountRules) -> Self {
+        proto::structures::AccountRules {
+            min_username_len: rules.min_username_len,
+            max_username_len: rules.max_username_len,
+            max_display_name_len: rules.max_display_name_len,
+            min_password_len: rules.min_password_len,
+            max_password_len: rules.max_password_len,
+            max_device_name_len: rules.max_device_name_len,
+        }
+    }
+}
+
+impl From<proto::structures::AccountRules> for AccountRules {
+    fn from(rules: proto::structures::AccountRules) -> Self {
+        AccountRules {
+            min_username_len: rules.min_username_len,
+            max_username_len: rules.max_username_len,
+            max_display_name_len: rules.max_display_name_len,
+            min_password_len: rules.min_password_len,
+            max_password_len: rules.max_password_len,
+            max_device_name_len: rules.max_device_name_len,
+        }
+    }
+}
+
 #[derive(Default, Debug, Clone)]
 pub struct TokenCreationOptions {
+    /// Name to tell the device apart from the user's others by, such as "Laptop"
     pub device_name: Option<String>,
+    /// When the token stops being valid. Tokens without one expire after the longest lifetime
+    /// that the server allows, if it has a limit.
     pub expiration_datetime: Option<DateTime<Utc>>,
     pub permission_flags: TokenPermissionFlags,
 }
@@ -461,6 +1156,38 @@ impl TokenPermissionFlags {
     }
 }
 
+bitflags! {
+    /// Permissions granted to members of a community through their roles
+    pub struct CommunityPermissionFlags: i64 {
+        /// All permissions. Given to the creator of a community.
+        const ALL = 1;
+        /// Send messages
+        const SEND_MESSAGES = 1 << 1;
+        /// Create rooms
+        const CREATE_ROOMS = 1 << 2;
+        /// Create invites to the community
+        const CREATE_INVITES = 1 << 3;
+        /// Create roles and assign them to members
+        const MANAGE_ROLES = 1 << 4;
+        /// Delete rooms, along with all of their messages
+        const DELETE_ROOMS = 1 << 5;
+        /// Change the community's name, description and icon
+        const MANAGE_COMMUNITY = 1 << 6;
+        /// Change rooms' topics and slow mode
+        const MANAGE_ROOMS = 1 << 7;
+        /// Pin and unpin messages in rooms
+        const PIN_MESSAGES = 1 << 8;
+        /// Kick and mute members, other than those with every permission
+        const MODERATE_MEMBERS = 1 << 9;
+    }
+}
+
+impl CommunityPermissionFlags {
+    pub fn has_perms(self, perms: CommunityPermissionFlags) -> bool {
+        self.contains(CommunityPermissionFlags::ALL) || self.contains(perms)
+    }
+}
+
 impl Default for TokenPermissionFlags {
     fn default() -> Self {
         TokenPermissionFlags::ALL
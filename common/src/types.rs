@@ -1,7 +1,8 @@
 use crate::proto;
-use serde::{Deserialize, Serialize};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
 use std::convert::TryFrom;
 use std::fmt;
+use std::str::FromStr;
 use uuid::Uuid;
 
 macro_rules! impl_protobuf_conversions {
@@ -38,11 +39,29 @@ pub struct RoomId(pub Uuid);
 #[derive(Hash, Eq, PartialEq, Ord, PartialOrd, Debug, Copy, Clone, Default)]
 pub struct MessageId(pub Uuid);
 
+#[derive(Hash, Eq, PartialEq, Ord, PartialOrd, Debug, Copy, Clone, Default)]
+pub struct RoleId(pub Uuid);
+
+/// A private channel between two users, outside of any community
+#[derive(Hash, Eq, PartialEq, Ord, PartialOrd, Debug, Copy, Clone, Default)]
+pub struct DirectChannelId(pub Uuid);
+
+/// A file uploaded to be attached to a message
+#[derive(Hash, Eq, PartialEq, Ord, PartialOrd, Debug, Copy, Clone, Default)]
+pub struct AttachmentId(pub Uuid);
+
+/// Chosen by the client to identify a message it sends, so that resending it is harmless
+#[derive(Hash, Eq, PartialEq, Ord, PartialOrd, Debug, Copy, Clone, Default)]
+pub struct MessageNonce(pub Uuid);
+
 #[serde(transparent)]
 #[derive(Hash, Eq, PartialEq, Ord, PartialOrd, Debug, Copy, Clone, Serialize, Deserialize)]
 pub struct DeviceId(pub Uuid);
 
-impl_protobuf_conversions! { DeviceId, MessageId, RoomId, CommunityId, UserId }
+impl_protobuf_conversions! {
+    DeviceId, MessageId, RoomId, CommunityId, UserId, RoleId, DirectChannelId, AttachmentId,
+    MessageNonce,
+}
 
 /// Does not need to be sequential; just unique within a desired time-span (or not, if you're a fan
 /// of trying to handle two responses with the same id attached). This exists for the client-side
@@ -56,6 +75,12 @@ impl RequestId {
     }
 }
 
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
 impl From<RequestId> for proto::types::RequestId {
     fn from(id: RequestId) -> Self {
         proto::types::RequestId { value: id.0 }
@@ -71,6 +96,69 @@ impl From<proto::types::RequestId> for RequestId {
 #[derive(Hash, Eq, PartialEq, Ord, PartialOrd, Debug, Copy, Clone)]
 pub struct ProfileVersion(pub u32);
 
+/// Version of the protocol spoken between clients and the server. The minor version is bumped for
+/// backwards compatible changes, so clients and servers can talk as long as the major versions
+/// match. This is written as `major.minor` in URLs.
+#[derive(Hash, Eq, PartialEq, Ord, PartialOrd, Debug, Copy, Clone)]
+pub struct ProtocolVersion {
+    pub major: u32,
+    pub minor: u32,
+}
+
+impl ProtocolVersion {
+    pub fn is_compatible_with(&self, other: ProtocolVersion) -> bool {
+        self.major == other.major
+    }
+}
+
+impl fmt::Display for ProtocolVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+impl FromStr for ProtocolVersion {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, '.');
+        let major = parts.next().and_then(|major| major.parse().ok()).ok_or(())?;
+        let minor = parts.next().and_then(|minor| minor.parse().ok()).ok_or(())?;
+        Ok(ProtocolVersion { major, minor })
+    }
+}
+
+impl Serialize for ProtocolVersion {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for ProtocolVersion {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let version = String::deserialize(deserializer)?;
+        version.parse().map_err(|_| de::Error::custom("invalid protocol version"))
+    }
+}
+
+impl From<ProtocolVersion> for proto::types::ProtocolVersion {
+    fn from(version: ProtocolVersion) -> Self {
+        proto::types::ProtocolVersion {
+            major: version.major,
+            minor: version.minor,
+        }
+    }
+}
+
+impl From<proto::types::ProtocolVersion> for ProtocolVersion {
+    fn from(version: proto::types::ProtocolVersion) -> Self {
+        ProtocolVersion {
+            major: version.major,
+            minor: version.minor,
+        }
+    }
+}
+
 impl fmt::Display for DeviceId {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}", self.0)
@@ -89,3 +177,21 @@ impl fmt::Display for AuthToken {
 
 #[derive(Hash, Eq, PartialEq, Debug, Clone)]
 pub struct InviteCode(pub String);
+
+/// Shortest vanity invite code that can be asked for
+pub const MIN_VANITY_INVITE_CODE_LEN: usize = 3;
+/// Longest vanity invite code that can be asked for. Generated codes are always 11 characters, so
+/// vanity codes are kept shorter than them to never be mistaken for one.
+pub const MAX_VANITY_INVITE_CODE_LEN: usize = 10;
+
+impl InviteCode {
+    /// Whether the code can be used as a vanity invite code. These are made of lowercase letters,
+    /// digits and dashes, like a community's slug.
+    pub fn is_valid_vanity(code: &str) -> bool {
+        let len = code.len();
+        (MIN_VANITY_INVITE_CODE_LEN..=MAX_VANITY_INVITE_CODE_LEN).contains(&len)
+            && code
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+    }
+}
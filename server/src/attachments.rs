@@ -0,0 +1,164 @@
+//! Uploading and downloading message attachments and avatars over HTTP. Attachments are stored as
+//! files named by their id in the configured attachments directory, with their metadata in the
+//! database. Avatars and community icons are stored as attachments which are referenced by the
+//! user or community rather than by a message.
+
+use std::convert::Infallible;
+use std::path::PathBuf;
+
+use futures::TryStreamExt;
+use http::header::{self, HeaderValue};
+use log::warn;
+use tokio_util::codec::{BytesCodec, FramedRead};
+use uuid::Uuid;
+use warp::hyper::Body;
+
+use vertex::prelude::*;
+
+use crate::client::Authenticator;
+use crate::config::Config;
+use crate::database::{DbResult, NonexistentUser, StorageQuotaExceeded};
+use crate::Global;
+
+const DEFAULT_CONTENT_TYPE: &str = "application/octet-stream";
+
+pub(crate) fn path_of(config: &Config, id: AttachmentId) -> PathBuf {
+    config.attachments_dir.join(id.0.to_string())
+}
+
+pub async fn upload(
+    global: Global,
+    login: Login,
+    content_type: Option<String>,
+    bytes: bytes::Bytes,
+) -> AuthResponse {
+    let user = authenticate(&global, login).await?;
+    let content_type = content_type.unwrap_or_else(|| DEFAULT_CONTENT_TYPE.to_string());
+    let id = store(&global, user, content_type, bytes).await?;
+
+    AuthResponse::Ok(AuthOk::Attachment(id))
+}
+
+/// Uploads a new avatar for the user, replacing and deleting their previous one
+pub async fn upload_avatar(
+    global: Global,
+    login: Login,
+    content_type: Option<String>,
+    bytes: bytes::Bytes,
+) -> AuthResponse {
+    let user = authenticate(&global, login).await?;
+    let content_type = match content_type {
+        Some(content_type) if content_type.starts_with("image/") => content_type,
+        _ => return AuthResponse::Err(AuthError::InvalidAvatar),
+    };
+
+    let id = store(&global, user, content_type, bytes).await?;
+    let old = match global.database.set_avatar(user, id).await? {
+        Ok(old) => old,
+        Err(NonexistentUser) => {
+            delete(&global, id).await?;
+            return AuthResponse::Err(AuthError::InvalidUser);
+        }
+    };
+
+    if let Some(old) = old {
+        delete(&global, old).await?;
+    }
+
+    AuthResponse::Ok(AuthOk::Attachment(id))
+}
+
+async fn authenticate(global: &Global, login: Login) -> Result<UserId, AuthError> {
+    let authenticator = Authenticator {
+        global: global.clone(),
+    };
+    let (user, ..) = authenticator.login(login.device, login.token).await?;
+    Ok(user)
+}
+
+/// Records an attachment and writes it to disk
+async fn store(
+    global: &Global,
+    uploader: UserId,
+    content_type: String,
+    bytes: bytes::Bytes,
+) -> Result<AttachmentId, AuthError> {
+    let id = AttachmentId(Uuid::new_v4());
+    let quota = global.config.attachment_quota_per_user;
+    let db = &global.database;
+
+    let res = db
+        .create_attachment(id, uploader, content_type, bytes.len() as u64, quota)
+        .await?;
+    if let Err(StorageQuotaExceeded) = res {
+        return Err(AuthError::StorageQuotaExceeded);
+    }
+
+    if let Err(e) = tokio::fs::write(path_of(&global.config, id), &bytes).await {
+        warn!("Error writing attachment {}: {:?}", id.0, e);
+        db.delete_attachment(id).await?;
+        return Err(AuthError::Internal);
+    }
+
+    Ok(id)
+}
+
+/// Removes the files of attachments whose records have already been deleted
+pub async fn remove_files(config: &Config, ids: Vec<AttachmentId>) {
+    for id in ids {
+        if let Err(e) = tokio::fs::remove_file(path_of(config, id)).await {
+            warn!("Error deleting attachment {}: {:?}", id.0, e);
+        }
+    }
+}
+
+/// Deletes an attachment's record along with its file
+pub async fn delete(global: &Global, id: AttachmentId) -> DbResult<()> {
+    global.database.delete_attachment(id).await?;
+
+    if let Err(e) = tokio::fs::remove_file(path_of(&global.config, id)).await {
+        warn!("Error deleting attachment {}: {:?}", id.0, e);
+    }
+
+    Ok(())
+}
+
+pub async fn download(
+    global: Global,
+    id: AttachmentId,
+) -> Result<Box<dyn warp::Reply>, Infallible> {
+    let status = |code| {
+        let response = http::Response::builder().status(code).body("").unwrap();
+        Ok(Box::new(response) as Box<dyn warp::Reply>)
+    };
+
+    let record = match global.database.get_attachment(id).await {
+        Ok(Some(record)) => record,
+        Ok(None) => return status(404), // Not found
+        Err(_) => return status(500),   // Internal server error
+    };
+
+    let file = match tokio::fs::File::open(path_of(&global.config, id)).await {
+        Ok(file) => file,
+        Err(e) => {
+            warn!("Error opening attachment {}: {:?}", id.0, e);
+            return status(500);
+        }
+    };
+
+    let stream = FramedRead::new(file, BytesCodec::new()).map_ok(|bytes| bytes.freeze());
+    let content_type = HeaderValue::from_str(&record.content_type)
+        .unwrap_or_else(|_| HeaderValue::from_static(DEFAULT_CONTENT_TYPE));
+
+    // Served as a download and never sniffed, so that uploaded HTML or scripts are not run in the
+    // context of the server
+    let response = http::Response::builder()
+        .header(header::CONTENT_TYPE, content_type)
+        .header(header::CONTENT_LENGTH, record.size)
+        .header(header::CONTENT_DISPOSITION, "attachment")
+        .header(header::X_CONTENT_TYPE_OPTIONS, "nosniff")
+        .body(Body::wrap_stream(stream))
+        .unwrap();
+
+    Ok(Box::new(response))
+}
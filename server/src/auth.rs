@@ -11,34 +11,42 @@ pub const MAX_TOKEN_LENGTH: usize = 45;
 #[derive(Debug, Eq, PartialEq, Ord, PartialOrd)]
 #[repr(i16)]
 pub enum HashSchemeVersion {
+    /// Argon2 with the library's default parameters
     Argon2V1 = 1,
+    /// Argon2 with the parameters from the config, which are kept in the encoded hash
+    Argon2V2 = 2,
 }
 
 impl HashSchemeVersion {
-    pub const LATEST: HashSchemeVersion = HashSchemeVersion::Argon2V1;
+    pub const LATEST: HashSchemeVersion = HashSchemeVersion::Argon2V2;
 }
 
 impl From<i16> for HashSchemeVersion {
     fn from(v: i16) -> Self {
         match v {
             1 => HashSchemeVersion::Argon2V1,
+            2 => HashSchemeVersion::Argon2V2,
             invalid_version => panic!("Invalid hash scheme version {}", invalid_version),
         }
     }
 }
 
+// These check the same rules that clients are given, so that they can check them too
+
 pub fn valid_password(password: &str, config: &Config) -> bool {
-    password.len() <= config.max_password_len as usize
-        && password.len() >= config.min_password_len as usize
+    config.account_rules().valid_password(password)
 }
 
 pub fn valid_display_name(display_name: &str, config: &Config) -> bool {
-    display_name.len() <= config.max_display_name_len as usize && !display_name.is_empty()
+    config.account_rules().valid_display_name(display_name)
+}
+
+pub fn valid_device_name(device_name: &str, config: &Config) -> bool {
+    config.account_rules().valid_device_name(device_name)
 }
 
 fn valid_username(username: &str, config: &Config) -> bool {
-    username.len() <= config.max_username_len as usize
-        && username.len() >= config.min_username_len as usize
+    config.account_rules().valid_username(username)
 }
 
 pub struct TooShort;
@@ -58,16 +66,26 @@ pub fn prepare_username(username: &str, config: &Config) -> Result<String, TooSh
 // The `<E: Send + 'static>`s here are to allow the caller to specify an error type for easier use,
 // since this will never return an error
 
-pub fn hash(pass: String) -> impl Future<Output = (String, HashSchemeVersion)> {
+pub fn hash(pass: String, config: &Config) -> impl Future<Output = (String, HashSchemeVersion)> {
+    let mem_cost = config.password_hash_memory_kib;
+    let time_cost = config.password_hash_iterations;
+    let lanes = config.password_hash_lanes;
+
     tokio::task::spawn_blocking(move || {
         let mut salt: [u8; 32] = [0; 32]; // 256 bits
         rand::thread_rng().fill_bytes(&mut salt);
-        let config = Default::default();
+        let config = argon2::Config {
+            mem_cost,
+            time_cost,
+            lanes,
+            thread_mode: argon2::ThreadMode::from_threads(lanes),
+            ..Default::default()
+        };
 
         let hash = argon2::hash_encoded(pass.as_bytes(), &salt, &config)
             .expect("Error generating password hash");
 
-        (hash, HashSchemeVersion::Argon2V1)
+        (hash, HashSchemeVersion::Argon2V2)
     })
     .map(|r| r.expect("Error in tokio password hashing task"))
 }
@@ -81,13 +99,90 @@ pub fn verify(
         use HashSchemeVersion::*;
 
         match scheme_version {
-            Argon2V1 => argon2::verify_encoded(&hash, pass.as_bytes())
+            Argon2V1 | Argon2V2 => argon2::verify_encoded(&hash, pass.as_bytes())
                 .expect("Error verifying password hash"),
         }
     })
     .map(|r| r.expect("Error in tokio password verifying task"))
 }
 
+/// Whether the user's password hash should be replaced by one made with the latest scheme and the
+/// currently configured parameters
+pub fn needs_rehash(user: &UserRecord, config: &Config) -> bool {
+    let params = format!(
+        "$m={},t={},p={}$",
+        config.password_hash_memory_kib,
+        config.password_hash_iterations,
+        config.password_hash_lanes,
+    );
+
+    user.hash_scheme_version < HashSchemeVersion::LATEST || !user.password_hash.contains(&params)
+}
+
 pub async fn verify_user(user: UserRecord, password: String) -> bool {
     verify(password, user.password_hash, user.hash_scheme_version).await
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> Config {
+        toml::from_str("").unwrap()
+    }
+
+    fn user(password_hash: &str, scheme: HashSchemeVersion) -> UserRecord {
+        UserRecord::new("a".to_string(), "a".to_string(), password_hash.to_string(), scheme)
+    }
+
+    #[test]
+    fn old_scheme_needs_rehash() {
+        let config = config();
+        let hash = format!(
+            "$argon2i$v=19$m={},t={},p={}$c2FsdA$aGFzaA",
+            config.password_hash_memory_kib,
+            config.password_hash_iterations,
+            config.password_hash_lanes,
+        );
+
+        assert!(needs_rehash(&user(&hash, HashSchemeVersion::Argon2V1), &config));
+    }
+
+    #[test]
+    fn latest_scheme_with_current_params_does_not_need_rehash() {
+        let config = config();
+        let argon2_config = argon2::Config {
+            mem_cost: config.password_hash_memory_kib,
+            time_cost: config.password_hash_iterations,
+            lanes: config.password_hash_lanes,
+            ..Default::default()
+        };
+        let hash = argon2::hash_encoded(b"password", &[0; 32], &argon2_config).unwrap();
+
+        assert!(!needs_rehash(&user(&hash, HashSchemeVersion::LATEST), &config));
+    }
+
+    #[test]
+    fn changed_params_need_rehash() {
+        let mut config = config();
+        let hash = format!(
+            "$argon2i$v=19$m={},t={},p={}$c2FsdA$aGFzaA",
+            config.password_hash_memory_kib,
+            config.password_hash_iterations,
+            config.password_hash_lanes,
+        );
+        let user = user(&hash, HashSchemeVersion::LATEST);
+        assert!(!needs_rehash(&user, &config));
+
+        config.password_hash_iterations += 1;
+        assert!(needs_rehash(&user, &config));
+
+        let mut config = self::config();
+        config.password_hash_memory_kib *= 2;
+        assert!(needs_rehash(&user, &config));
+
+        let mut config = self::config();
+        config.password_hash_lanes += 1;
+        assert!(needs_rehash(&user, &config));
+    }
+}
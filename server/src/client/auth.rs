@@ -1,4 +1,4 @@
-use chrono::Utc;
+use chrono::{DateTime, Duration, Utc};
 use rand::RngCore;
 use uuid::Uuid;
 
@@ -37,6 +37,7 @@ impl Authenticator {
             return Err(AuthError::UserCompromised);
         } else if (Utc::now() - token.last_used).num_days()
             > self.global.config.token_stale_days as i64
+            || token.expiration_date.map_or(false, |date| date < Utc::now())
         {
             return Err(AuthError::StaleToken);
         }
@@ -82,7 +83,7 @@ impl Authenticator {
             return AuthResponse::Err(AuthError::InvalidDisplayName);
         }
 
-        let (hash, hash_version) = auth::hash(credentials.password).await;
+        let (hash, hash_version) = auth::hash(credentials.password, &self.global.config).await;
 
         let user = database::UserRecord::new(username, display_name, hash, hash_version);
         let user_id = user.id;
@@ -103,13 +104,27 @@ impl Authenticator {
             _ => return AuthResponse::Err(AuthError::InvalidMessage),
         };
 
+        let expiration_date = self.token_expiration_date(options.expiration_datetime)?;
+
+        if let Some(name) = &options.device_name {
+            if !auth::valid_device_name(name, &self.global.config) {
+                return AuthResponse::Err(AuthError::InvalidDeviceName);
+            }
+        }
+
+        // Compromised users may only change their password, which clears the flag
+        let compromised = self.global.database.get_user_by_id(user).await?.map(|u| u.compromised);
+        if compromised.unwrap_or(false) {
+            return AuthResponse::Err(AuthError::PasswordResetRequired);
+        }
+
         let mut token_bytes: [u8; 32] = [0; 32]; // 256 bits
         rand::thread_rng().fill_bytes(&mut token_bytes);
 
         let token = base64::encode(&token_bytes);
 
         let auth_token = AuthToken(token.clone());
-        let (token_hash, hash_scheme_version) = auth::hash(token).await;
+        let (token_hash, hash_scheme_version) = auth::hash(token, &self.global.config).await;
 
         let device = DeviceId(Uuid::new_v4());
         let db_token = database::Token {
@@ -119,7 +134,7 @@ impl Authenticator {
             device,
             device_name: options.device_name,
             last_used: Utc::now(),
-            expiration_date: options.expiration_datetime,
+            expiration_date,
             permission_flags: options.permission_flags,
         };
 
@@ -180,7 +195,7 @@ impl Authenticator {
             return AuthResponse::Err(AuthError::IncorrectCredentials);
         }
 
-        let (new_password_hash, hash_version) = auth::hash(new_password).await;
+        let (new_password_hash, hash_version) = auth::hash(new_password, &self.global.config).await;
 
         let database = &self.global.database;
         database
@@ -192,6 +207,26 @@ impl Authenticator {
         AuthResponse::Ok(AuthOk::NoData)
     }
 
+    /// Checks the expiration date requested for a new token against the longest lifetime allowed,
+    /// filling in that lifetime if none was requested
+    fn token_expiration_date(
+        &self,
+        requested: Option<DateTime<Utc>>,
+    ) -> Result<Option<DateTime<Utc>>, AuthError> {
+        let now = Utc::now();
+        let latest = match self.global.config.max_token_lifetime_days {
+            0 => None,
+            days => Some(now + Duration::days(days as i64)),
+        };
+
+        match (requested, latest) {
+            (Some(date), _) if date <= now => Err(AuthError::InvalidTokenLifetime),
+            (Some(date), Some(latest)) if date > latest => Err(AuthError::InvalidTokenLifetime),
+            (Some(date), _) => Ok(Some(date)),
+            (None, latest) => Ok(latest),
+        }
+    }
+
     async fn verify_credentials(&self, credentials: Credentials) -> AuthResponse {
         let username = auth::normalize_username(&credentials.username, &self.global.config);
         let password = credentials.password;
@@ -202,10 +237,23 @@ impl Authenticator {
         };
 
         let id = user.id;
-        if auth::verify_user(user, password).await {
-            AuthResponse::Ok(AuthOk::User(id))
+        let outdated = if auth::needs_rehash(&user, &self.global.config) {
+            Some(user.password_hash.clone())
         } else {
-            AuthResponse::Err(AuthError::IncorrectCredentials)
+            None
+        };
+
+        if !auth::verify_user(user, password.clone()).await {
+            return AuthResponse::Err(AuthError::IncorrectCredentials);
+        }
+
+        // Move the user over to the latest hash scheme and parameters while we have their password
+        if let Some(old_hash) = outdated {
+            let (hash, hash_version) = auth::hash(password, &self.global.config).await;
+            let db = &self.global.database;
+            db.upgrade_password_hash(id, old_hash, hash, hash_version).await?;
         }
+
+        AuthResponse::Ok(AuthOk::User(id))
     }
 }
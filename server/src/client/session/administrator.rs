@@ -2,8 +2,11 @@ use super::manager;
 use crate::auth::HashSchemeVersion;
 use crate::client::session::LogoutThisSession;
 use crate::client::Session;
+use crate::community::{self, SendSystemMessage};
 use crate::handle_disconnected;
+use chrono::{DateTime, Utc};
 use futures::TryStreamExt;
+use vertex::formatting;
 use vertex::prelude::*;
 use xtra::prelude::*;
 
@@ -24,7 +27,7 @@ impl super::ActiveSession {
         request: AdminRequest,
     ) -> Result<OkResponse, Error> {
         match request {
-            AdminRequest::Ban(user) => self.ban(user).await,
+            AdminRequest::Ban { user, reason, until } => self.ban(user, reason, until).await,
             AdminRequest::Unban(user) => self.unban(user).await,
             AdminRequest::Unlock(user) => self.unlock(user).await,
             AdminRequest::Promote { user, permissions } => self.promote(user, permissions).await,
@@ -37,6 +40,11 @@ impl super::ActiveSession {
                 self.set_report_status(id, status).await
             }
             AdminRequest::SetAccountsCompromised(typ) => self.set_accounts_compromised(typ).await,
+            AdminRequest::ResolveReport { id, action } => self.resolve_report(id, action).await,
+            AdminRequest::GetAuditLog { after, limit } => self.get_audit_log(after, limit).await,
+            AdminRequest::BroadcastSystemMessage { community, room, text } => {
+                self.broadcast_system_message(community, room, text).await
+            }
             _ => Err(Error::Unimplemented),
         }
     }
@@ -50,7 +58,12 @@ impl super::ActiveSession {
         Ok(perms.contains(AdminPermissionFlags::ALL) || perms.contains(check))
     }
 
-    async fn ban(&mut self, user: UserId) -> Result<OkResponse, Error> {
+    async fn ban(
+        &mut self,
+        user: UserId,
+        reason: String,
+        until: Option<DateTime<Utc>>,
+    ) -> Result<OkResponse, Error> {
         if !self.has_admin_perms(AdminPermissionFlags::BAN)? {
             return Err(Error::AccessDenied);
         }
@@ -66,10 +79,24 @@ impl super::ActiveSession {
             return Err(Error::AccessDenied);
         }
 
-        db.set_banned(user, true)
+        if reason.len() > self.global.config.max_ban_reason_len as usize {
+            return Err(Error::TooLong);
+        }
+
+        let action = AuditAction::Ban {
+            user,
+            reason: reason.clone(),
+            until,
+        };
+        db.ban_user(user, self.user, reason, until)
             .await?
-            .map_err(|_| Error::InvalidUser)
-            .map(|_| OkResponse::NoData)
+            .map_err(|_| Error::InvalidUser)?;
+        self.audit(action).await?;
+
+        // Their tokens have been revoked, so they are logged out everywhere
+        manager::remove_and_notify_user(user);
+
+        Ok(OkResponse::NoData)
     }
 
     async fn unban(&mut self, user: UserId) -> Result<OkResponse, Error> {
@@ -79,10 +106,12 @@ impl super::ActiveSession {
 
         let db = &self.global.database;
 
-        db.set_banned(user, false)
+        db.unban_user(user)
             .await?
-            .map_err(|_| Error::InvalidUser)
-            .map(|_| OkResponse::NoData)
+            .map_err(|_| Error::InvalidUser)?;
+        self.audit(AuditAction::Unban(user)).await?;
+
+        Ok(OkResponse::NoData)
     }
 
     async fn unlock(&mut self, user: UserId) -> Result<OkResponse, Error> {
@@ -94,8 +123,10 @@ impl super::ActiveSession {
 
         db.set_locked(user, false)
             .await?
-            .map_err(|_| Error::InvalidUser)
-            .map(|_| OkResponse::NoData)
+            .map_err(|_| Error::InvalidUser)?;
+        self.audit(AuditAction::Unlock(user)).await?;
+
+        Ok(OkResponse::NoData)
     }
 
     async fn promote(
@@ -126,6 +157,7 @@ impl super::ActiveSession {
         db.set_admin_permissions(user, perms)
             .await?
             .map_err(|_| Error::InvalidUser)?;
+        self.audit(AuditAction::Promote { user, permissions: perms }).await?;
 
         notify_of_admin_perm_change(user, perms);
 
@@ -155,6 +187,7 @@ impl super::ActiveSession {
         db.set_admin_permissions(user, no_perms)
             .await?
             .map_err(|_| Error::InvalidUser)?;
+        self.audit(AuditAction::Demote(user)).await?;
 
         notify_of_admin_perm_change(user, no_perms);
 
@@ -179,7 +212,31 @@ impl super::ActiveSession {
         Ok(OkResponse::Admin(AdminResponse::Admins(admins)))
     }
 
+    async fn get_audit_log(&mut self, after: Option<i64>, limit: u32) -> Result<OkResponse, Error> {
+        if !self.is_admin()? {
+            return Err(Error::AccessDenied);
+        }
+
+        let events = self.global.database.get_audit_log(after, limit).await?;
+        Ok(OkResponse::Admin(AdminResponse::AuditLog(events)))
+    }
+
+    /// Records an action taken by this admin in the audit log
+    async fn audit(&mut self, action: AuditAction) -> Result<(), Error> {
+        self.global.database.record_audit_event(Some(self.user), &action).await?;
+        Ok(())
+    }
+
+    /// Whether the user has any admin permissions at all
+    fn is_admin(&self) -> Result<bool, Error> {
+        Ok(!self.admin_perms()?.is_empty())
+    }
+
     async fn search_reports(&mut self, criteria: SearchCriteria) -> Result<OkResponse, Error> {
+        if !self.is_admin()? {
+            return Err(Error::AccessDenied);
+        }
+
         let stream = self.global.database.search_reports(criteria).await?;
         let reports: Vec<Report> = stream.try_collect().await?;
         Ok(OkResponse::Admin(AdminResponse::Reports(reports)))
@@ -190,7 +247,35 @@ impl super::ActiveSession {
         id: i32,
         status: ReportStatus,
     ) -> Result<OkResponse, Error> {
+        if !self.is_admin()? {
+            return Err(Error::AccessDenied);
+        }
+
+        self.global.database.set_report_status(id, status).await?;
+        self.audit(AuditAction::SetReportStatus { report: id, status }).await?;
+        Ok(OkResponse::NoData)
+    }
+
+    async fn resolve_report(&mut self, id: i32, action: ReportAction) -> Result<OkResponse, Error> {
+        if !self.is_admin()? {
+            return Err(Error::AccessDenied);
+        }
+
+        let reported = match self.global.database.get_reported_user(id).await? {
+            Some(user) => user,
+            None => return Err(Error::InvalidReport),
+        };
+
+        let status = match action {
+            ReportAction::Dismiss => ReportStatus::Denied,
+            ReportAction::Ban { reason, until } => {
+                self.ban(reported, reason, until).await?;
+                ReportStatus::Accepted
+            }
+        };
+
         self.global.database.set_report_status(id, status).await?;
+        self.audit(AuditAction::SetReportStatus { report: id, status }).await?;
         Ok(OkResponse::NoData)
     }
 
@@ -208,12 +293,12 @@ impl super::ActiveSession {
             SetCompromisedType::All => db.set_all_accounts_compromised().await?,
             SetCompromisedType::OldHashes => db.set_accounts_with_old_hashes_compromised().await?,
         }
+        self.audit(AuditAction::SetAccountsCompromised(typ)).await?;
 
         // Log out logged-in users
         super::manager::USERS.retain(|_, user| {
             if user.hash_scheme_version < HashSchemeVersion::LATEST || all {
-                let sessions = &mut user.sessions;
-                for (_, session) in sessions {
+                for session in user.sessions.values_mut() {
                     if let Session::Active { actor, .. } = session {
                         let _ = actor
                             .address()
@@ -230,6 +315,41 @@ impl super::ActiveSession {
 
         Ok(OkResponse::NoData)
     }
+
+    async fn broadcast_system_message(
+        &mut self,
+        community: CommunityId,
+        room: RoomId,
+        text: String,
+    ) -> Result<OkResponse, Error> {
+        if !self.has_admin_perms(AdminPermissionFlags::BROADCAST)? {
+            return Err(Error::AccessDenied);
+        }
+
+        if text.len() > self.global.config.max_message_len as usize {
+            return Err(Error::MessageTooLong);
+        }
+
+        let content = formatting::sanitize(&text);
+        if content.trim().is_empty() {
+            return Err(Error::EmptyMessage);
+        }
+
+        let send = SendSystemMessage {
+            author: self.user,
+            room,
+            content,
+        };
+        let confirmation = community::address_of(community)?
+            .send(send)
+            .await
+            .map_err(handle_disconnected("Community"))??;
+
+        match confirmation {
+            Some(confirmation) => Ok(OkResponse::ConfirmMessage(confirmation)),
+            None => Err(Error::InvalidRoom),
+        }
+    }
 }
 
 fn notify_of_admin_perm_change(user: UserId, new: AdminPermissionFlags) {
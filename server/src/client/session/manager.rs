@@ -4,6 +4,7 @@ use lazy_static::lazy_static;
 use vertex::prelude::*;
 
 use super::*;
+use super::presence;
 use crate::auth::HashSchemeVersion;
 use futures::TryStreamExt;
 use std::collections::HashMap;
@@ -17,6 +18,10 @@ pub struct ActiveUser {
     pub communities: HashMap<CommunityId, UserCommunity>,
     pub sessions: HashMap<DeviceId, Session>,
     pub admin_perms: AdminPermissionFlags,
+    /// Status set by the user, shown while they have at least one active session
+    pub status: PresenceStatus,
+    /// Whether the user lets others see their status. If not, they always appear offline.
+    pub show_online_status: bool,
     /// Stored here so, in case of set to compromised, we can check if to log this user out
     pub hash_scheme_version: HashSchemeVersion,
 }
@@ -31,6 +36,7 @@ impl ActiveUser {
     ) -> DbResult<Self> {
         let communities = db.get_communities_for_user(user).await?;
         let admin_perms = db.get_admin_permissions(user).await?;
+        let privacy = db.get_privacy_settings(user).await?.unwrap_or_default();
         let db = &db; // To prevent move
 
         let communities = communities
@@ -49,6 +55,8 @@ impl ActiveUser {
             communities,
             sessions,
             admin_perms,
+            status: PresenceStatus::Online,
+            show_online_status: privacy.show_online_status,
             hash_scheme_version,
         })
     }
@@ -95,6 +103,8 @@ impl Session {
 #[derive(Debug)]
 pub struct UserCommunity {
     pub rooms: HashMap<RoomId, UserRoom>,
+    /// Combined permissions of the user's roles, or `None` if they have no roles in the community
+    pub role_permissions: Option<CommunityPermissionFlags>,
 }
 
 impl UserCommunity {
@@ -113,8 +123,12 @@ impl UserCommunity {
             });
 
         let rooms = stream.try_collect().await?;
+        let role_permissions = db.get_community_permissions(community, user).await?;
 
-        Ok(UserCommunity { rooms })
+        Ok(UserCommunity {
+            rooms,
+            role_permissions,
+        })
     }
 }
 
@@ -164,6 +178,10 @@ pub fn upgrade(user: UserId, device: DeviceId, addr: ActiveSession) -> Result<()
                 actor: addr,
                 looking_at: None,
             };
+
+            let communities = user.communities.keys().copied().collect();
+            presence::schedule_update(*user.key(), communities);
+
             Ok(())
         }
         None => Err(()),
@@ -186,9 +204,12 @@ pub fn remove_and_notify_user(user: UserId) {
         });
 
         if sessions.is_empty() {
+            let communities = active_user.communities.keys().copied().collect();
+
             // Drop the lock so that we can remove it without deadlocking
             drop(lock);
             USERS.remove(&user);
+            presence::schedule_update(user, communities);
         }
     }
 }
@@ -209,9 +230,12 @@ pub fn remove_device(user: UserId, device: DeviceId) -> Option<Session> {
         let sessions = &mut active_user.sessions;
         if let Some(session) = sessions.remove(&device) {
             if sessions.is_empty() {
+                let communities = active_user.communities.keys().copied().collect();
+
                 // Drop the lock so that we can remove it without deadlocking
                 drop(lock);
                 USERS.remove(&user);
+                presence::schedule_update(user, communities);
             }
 
             return Some(session);
@@ -221,6 +245,28 @@ pub fn remove_device(user: UserId, device: DeviceId) -> Option<Session> {
     None
 }
 
+/// Send a message to every fully connected session of every user
+pub fn send_to_all_sessions(msg: ServerMessage) {
+    for user in USERS.iter() {
+        user.sessions
+            .values()
+            .filter_map(|session| session.as_active_actor())
+            .for_each(|session| {
+                let _ = session.send(msg.clone());
+            });
+    }
+}
+
+/// The number of sessions across all users, including those still connecting
+pub fn session_count() -> usize {
+    USERS.iter().map(|user| user.sessions.len()).sum()
+}
+
+/// Whether the user has at least one fully connected session and does not appear offline
+pub fn is_online(user: UserId) -> bool {
+    presence::current_status(user) != PresenceStatus::Offline
+}
+
 pub fn get_active_user<'a>(user: UserId) -> Result<ActiveUserRef<'a>, Error> {
     USERS.get(&user).ok_or(Error::LoggedOut)
 }
@@ -1,8 +1,10 @@
 use std::fmt::Debug;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
+use futures::channel::mpsc;
+use futures::future::{AbortHandle, AbortRegistration, Abortable};
 use futures::stream::SplitSink;
-use futures::SinkExt;
+use futures::{SinkExt, StreamExt};
 use log::{debug, error, warn};
 use warp::filters::ws;
 use warp::filters::ws::WebSocket;
@@ -10,6 +12,7 @@ use xtra::prelude::*;
 use async_trait::async_trait;
 
 pub use manager::*;
+use vertex::frame;
 use vertex::prelude::*;
 
 use crate::community::{self, Connect, CreateRoom, GetRoomInfo, Join, COMMUNITIES};
@@ -21,6 +24,7 @@ use xtra::KeepRunning;
 
 mod administrator;
 mod manager;
+mod presence;
 mod regular_user;
 
 #[derive(Debug)]
@@ -30,6 +34,10 @@ impl xtra::Message for LogoutThisSession {
     type Result = ();
 }
 
+/// How long to keep writing what was queued for a session after it stops, such as it being told
+/// that it was logged out, before its connection is dropped
+const FINAL_SEND_TIMEOUT: Duration = Duration::from_secs(5);
+
 struct CheckHeartbeat;
 
 impl xtra::Message for CheckHeartbeat {
@@ -54,6 +62,18 @@ pub struct ForwardMessage {
     pub community: CommunityId,
     pub room: RoomId,
     pub message: vertex::structures::Message,
+    /// Users mentioned by the message, who are sent it even if they only watch for mentions
+    pub mentioned: Vec<UserId>,
+}
+
+/// What a session is sent when a message is sent to a room that its user is in
+enum MessageDelivery {
+    /// The message itself
+    Message,
+    /// A notice that there are new messages to fetch
+    Ready,
+    /// Nothing, since the room was already unread or the user does not watch it
+    Nothing,
 }
 
 #[derive(Debug, Clone)]
@@ -62,6 +82,39 @@ pub struct AddRoom {
     pub structure: RoomStructure,
 }
 
+#[derive(Debug, Clone)]
+pub struct RemoveRoom {
+    pub community: CommunityId,
+    pub room: RoomId,
+}
+
+/// Why a message could not be queued to be sent to the client
+#[derive(Debug)]
+pub enum SendError {
+    /// The client has stopped reading messages, and too many are waiting to be sent to it
+    Backpressure,
+    /// The websocket has closed
+    Closed,
+}
+
+#[derive(Debug)]
+enum WsError {
+    Receive(warp::Error),
+    Send(SendError),
+}
+
+impl From<warp::Error> for WsError {
+    fn from(e: warp::Error) -> Self {
+        WsError::Receive(e)
+    }
+}
+
+impl From<SendError> for WsError {
+    fn from(e: SendError) -> Self {
+        WsError::Send(e)
+    }
+}
+
 #[derive(Debug)]
 pub struct WsMessage(pub Result<ws::Message, warp::Error>);
 
@@ -77,8 +130,9 @@ impl Handler<WsMessage> for ActiveSession {
             Ok(_) => KeepRunning::Yes,
             Err(e) => {
                 debug!(
-                    "Error handling websocket message. Error: {:?}\nClient: {:#?}",
-                    e, self
+                    "[{}] Error handling websocket message. Error: {:?}",
+                    self.log_context(),
+                    e
                 );
                 ctx.stop();
                 KeepRunning::No
@@ -87,21 +141,44 @@ impl Handler<WsMessage> for ActiveSession {
     }
 }
 
+/// Identifies the session which a log line is about, so that everything one device did can be
+/// found by searching the logs for its id. Written as `key=value` pairs.
+#[derive(Debug, Copy, Clone)]
+pub struct LogContext {
+    pub user: UserId,
+    pub device: DeviceId,
+}
+
+impl fmt::Display for LogContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "user={} device={}", self.user.0, self.device)
+    }
+}
+
 #[spaad::entangled]
 pub struct ActiveSession {
-    pub ws: SplitSink<WebSocket, ws::Message>,
+    /// Messages waiting to be written to the websocket. This is bounded so that a client which
+    /// stops reading cannot make the server buffer messages for it without limit.
+    pub outbound: mpsc::Sender<ws::Message>,
+    /// Stops the task writing `outbound` to the websocket, closing the connection
+    pub writer: AbortHandle,
     pub global: crate::Global,
-    pub heartbeat: Instant,
+    /// When the client last sent anything, including pings
+    pub last_activity: Instant,
+    /// When the client last sent a `StartTyping` request that was not ignored
+    pub last_typing: Option<Instant>,
     pub user: UserId,
     pub device: DeviceId,
     pub perms: TokenPermissionFlags,
+    /// Whether the client asked for messages to be sent in compressed frames
+    pub compression: bool,
 }
 
 #[spaad::entangled]
 impl fmt::Debug for ActiveSession {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("ActiveSession")
-            .field("heartbeat", &self.heartbeat)
+            .field("last_activity", &self.last_activity)
             .field("user", &self.user)
             .field("device", &self.device)
             .field("perms", &self.perms)
@@ -113,20 +190,36 @@ impl fmt::Debug for ActiveSession {
 impl Actor for ActiveSession {
     fn started(&mut self, ctx: &mut Context<Self>) {
         ctx.notify_immediately(NotifyClientReady);
-        ctx.notify_interval(HEARTBEAT_TIMEOUT, || CheckHeartbeat);
+        let sweep_interval = Duration::from_secs(self.global.config.session_sweep_interval_secs);
+        ctx.notify_interval(sweep_interval, || CheckHeartbeat);
     }
 
     fn stopped(&mut self, _ctx: &mut Context<Self>) {
         self.log_out();
+
+        let writer = self.writer.clone();
+        tokio::spawn(async move {
+            tokio::time::delay_for(FINAL_SEND_TIMEOUT).await;
+            writer.abort();
+        });
     }
 }
 
 #[spaad::entangled]
-impl SyncHandler<CheckHeartbeat> for ActiveSession {
-    fn handle(&mut self, _: CheckHeartbeat, ctx: &mut Context<Self>) {
-        if Instant::now().duration_since(self.heartbeat) > HEARTBEAT_TIMEOUT {
-            ctx.stop();
+#[async_trait]
+impl Handler<CheckHeartbeat> for ActiveSession {
+    async fn handle(&mut self, _: CheckHeartbeat, ctx: &mut Context<Self>) {
+        let timeout = Duration::from_secs(self.global.config.session_timeout_secs);
+        if Instant::now().duration_since(self.last_activity) <= timeout {
+            return;
         }
+
+        debug!("[{}] Session timed out", self.log_context());
+
+        // The connection is likely half-open, so the writer is only given a short while to tell
+        // the client once the session stops
+        let _ = self.try_send(ServerMessage::Event(ServerEvent::SessionLoggedOut));
+        ctx.stop();
     }
 }
 
@@ -136,10 +229,8 @@ impl Handler<NotifyClientReady> for ActiveSession {
     async fn handle(&mut self, _: NotifyClientReady, ctx: &mut Context<Self>) {
         if let Err(e) = self.ready(ctx).await {
             // Probably non-recoverable
-            let _ = self
-                .try_send(ServerMessage::Event(ServerEvent::InternalError))
-                .await;
-            error!("Error in client ready. Error: {:?}\nClient: {:#?}", e, self);
+            let _ = self.try_send(ServerMessage::Event(ServerEvent::InternalError));
+            error!("[{}] Error in client ready. Error: {:?}", self.log_context(), e);
             ctx.stop();
         }
     }
@@ -151,7 +242,7 @@ impl Handler<LogoutThisSession> for ActiveSession {
     async fn handle(&mut self, _: LogoutThisSession, ctx: &mut Context<Self>) {
         self.send(ServerMessage::Event(ServerEvent::SessionLoggedOut), ctx)
             .await;
-        self.log_out();
+        ctx.stop(); // Logs out the device once stopped
     }
 }
 
@@ -164,37 +255,86 @@ impl ActiveSession {
         user: UserId,
         device: DeviceId,
         perms: TokenPermissionFlags,
+        compression: bool,
     ) -> Self {
+        let (outbound, receiver) = mpsc::channel(global.config.session_send_buffer);
+        let (writer, registration) = AbortHandle::new_pair();
+        tokio::spawn(write_outbound(ws, receiver, registration));
+
         ActiveSession {
-            ws,
+            outbound,
+            writer,
             global,
-            heartbeat: Instant::now(),
+            last_activity: Instant::now(),
+            last_typing: None,
             user,
             device,
             perms,
+            compression,
         }
     }
 
-    async fn try_send<M: Into<Vec<u8>>>(&mut self, msg: M) -> Result<(), warp::Error> {
-        self.ws.send(ws::Message::binary(msg)).await
+    fn try_send<M: Into<Vec<u8>>>(&mut self, msg: M) -> Result<(), SendError> {
+        let mut msg = msg.into();
+        if self.compression {
+            msg = frame::encode(msg);
+        }
+
+        self.queue(ws::Message::binary(msg))
+    }
+
+    /// Queues a message to be written to the websocket. If the queue is full, the client is not
+    /// keeping up, so its connection is closed straight away rather than after the queue drains.
+    fn queue(&mut self, msg: ws::Message) -> Result<(), SendError> {
+        match self.outbound.try_send(msg) {
+            Ok(()) => Ok(()),
+            Err(e) if e.is_full() => {
+                warn!(
+                    "[{}] Dropping session which is not reading its messages",
+                    self.log_context()
+                );
+                self.writer.abort();
+                Err(SendError::Backpressure)
+            }
+            Err(_) => Err(SendError::Closed),
+        }
     }
 
     #[spaad::handler]
     pub async fn send<M>(&mut self, msg: M, ctx: &mut Context<Self>)
         where M: Into<Vec<u8>> + Send + 'static
     {
-        if let Err(e) = self.try_send(msg).await {
+        if let Err(e) = self.try_send(msg) {
             error!(
-                "Error sending websocket message. Error: {:?}\nClient: {:#?}",
-                e, self
+                "[{}] Error sending websocket message. Error: {:?}",
+                self.log_context(),
+                e
             );
             ctx.stop()
         }
     }
 
+    fn log_context(&self) -> LogContext {
+        LogContext {
+            user: self.user,
+            device: self.device,
+        }
+    }
+
     /// Remove the device from wherever it is referenced
     fn log_out(&mut self) {
-        manager::remove_device(self.user, self.device);
+        let removed = manager::remove_device(self.user, self.device).is_some();
+
+        // Once the user's last session is gone, remember when they were last seen
+        if removed && manager::get_active_user(self.user).is_err() {
+            let db = self.global.database.clone();
+            let user = self.user;
+            tokio::spawn(async move {
+                if let Err(e) = db.set_last_seen(user).await {
+                    error!("Error setting last seen time of {:?}: {:?}", user, e);
+                }
+            });
+        }
     }
 
     fn in_community(&self, id: &CommunityId) -> Result<bool, Error> {
@@ -203,6 +343,18 @@ impl ActiveSession {
             .contains_key(&id))
     }
 
+    /// The user's permissions in the community, falling back to the default role if they have no
+    /// roles in it
+    fn community_perms(&self, id: &CommunityId) -> Result<CommunityPermissionFlags, Error> {
+        let user = manager::get_active_user(self.user)?;
+        let community = user.communities.get(id).ok_or(Error::InvalidCommunity)?;
+        let default = self.global.config.default_role_permissions;
+
+        Ok(community
+            .role_permissions
+            .unwrap_or_else(|| CommunityPermissionFlags::from_bits_truncate(default)))
+    }
+
     // in future, this will change with permissioning
     fn in_room(&self, community: &CommunityId, room: &RoomId) -> Result<bool, Error> {
         let user = manager::get_active_user(self.user)?;
@@ -213,24 +365,36 @@ impl ActiveSession {
         })
     }
 
-    /// Returns whether the client should be notified and whether the room had unread messages. It
-    /// also sets the room to unread.
-    fn should_notify_client(
+    /// Returns what the client should be sent for a new message in the room, according to the
+    /// room's watch level and whether the client is looking at it. It also sets the room to unread.
+    fn message_delivery(
         &self,
         community: CommunityId,
         room: RoomId,
-    ) -> Result<(bool, bool), Error> {
+        mentioned: bool,
+    ) -> Result<MessageDelivery, Error> {
         let mut active_user = manager::get_active_user_mut(self.user)?;
         let session = &active_user.sessions[&self.device];
         let looking_at = session.as_active_looking_at().unwrap();
 
         if let Some(user_community) = active_user.communities.get_mut(&community) {
             if let Some(user_room) = user_community.rooms.get_mut(&room) {
-                let notify = looking_at == Some((community, room))
-                    || user_room.watch_level == WatchLevel::Watching;
                 let was_unread = user_room.unread;
                 user_room.unread = true;
-                Ok((notify, was_unread))
+
+                let watched = match user_room.watch_level {
+                    WatchLevel::All => true,
+                    WatchLevel::MentionsOnly => mentioned,
+                    WatchLevel::Nothing => false,
+                };
+
+                Ok(if looking_at == Some((community, room)) || watched {
+                    MessageDelivery::Message
+                } else if was_unread || user_room.watch_level == WatchLevel::Nothing {
+                    MessageDelivery::Nothing
+                } else {
+                    MessageDelivery::Ready
+                })
             } else {
                 Err(Error::InvalidRoom)
             }
@@ -249,6 +413,7 @@ impl ActiveSession {
 
         let active = manager::get_active_user(self.user)?;
         let mut communities = Vec::with_capacity(active.communities.len());
+        let unread_counts = self.global.database.get_unread_counts(self.user).await?;
 
         for (id, user_community) in active.communities.iter() {
             let addr = community::address_of(*id)?;
@@ -264,6 +429,11 @@ impl ActiveSession {
                         id: info.id,
                         name: info.name,
                         unread: room.unread,
+                        topic: info.topic,
+                        slow_mode_secs: info.slow_mode_secs,
+                        watch_level: room.watch_level,
+                        archived: info.archived,
+                        unread_count: unread_counts.get(&info.id).copied().unwrap_or(0),
                     })
                 })
                 .collect::<Result<Vec<RoomStructure>, Error>>()?;
@@ -280,21 +450,40 @@ impl ActiveSession {
                 name: info.name.clone(),
                 description: info.description(),
                 rooms,
+                icon: info.icon,
             };
 
             communities.push(structure);
         }
 
+        // The room may have been left or deleted since it was selected
+        let selected_room = self
+            .global
+            .database
+            .get_selected_room(self.device)
+            .await?
+            .filter(|(community, room)| {
+                active
+                    .communities
+                    .get(community)
+                    .map_or(false, |community| community.rooms.contains_key(room))
+            });
+
         let ready = ClientReady {
             user: self.user,
             profile: Profile {
                 version: user.profile_version,
                 username: user.username,
                 display_name: user.display_name,
+                avatar: user.avatar,
+                last_seen: None,
             },
             communities,
             permissions: self.perms,
             admin_permissions: active.admin_perms,
+            max_message_len: self.global.config.max_message_len,
+            selected_room,
+            privacy: user.privacy,
         };
 
         let msg = ServerMessage::Event(ServerEvent::ClientReady(ready));
@@ -307,58 +496,62 @@ impl ActiveSession {
         &mut self,
         message: Result<ws::Message, warp::Error>,
         ctx: &mut Context<Self>,
-    ) -> Result<(), warp::Error> {
+    ) -> Result<(), WsError> {
         let message = message?;
+        self.last_activity = Instant::now();
+
         {
             let ratelimiter = self.global.ratelimiter.load();
 
-            if let Err(not_until) = ratelimiter.check_key(&self.device) {
+            if let Err(not_until) = ratelimiter.global.check_key(&self.device) {
                 self.try_send(ServerMessage::RateLimited {
-                    ready_in: not_until.wait_time_from(Instant::now()),
-                })
-                .await?;
+                    ready_in: crate::ready_in(not_until),
+                })?;
 
                 return Ok(());
             }
         }
 
-        if message.is_ping() || message.is_pong() {
-            self.heartbeat = Instant::now();
-
-            if message.is_ping() {
-                self.ws.send(ws::Message::ping(vec![])).await?; // Doesn't let us send pong :(
-            }
+        if message.is_ping() {
+            self.queue(ws::Message::ping(vec![]))?; // Doesn't let us send pong :(
+        } else if message.is_pong() {
+            // Only keeps the session alive
         } else if message.is_binary() {
             let msg = match ClientMessage::from_protobuf_bytes(message.as_bytes()) {
                 Ok(m) => m,
                 Err(e) => {
-                    log::debug!("Malformed message: {:#?}", e);
-                    self.try_send(ServerMessage::MalformedMessage).await?;
+                    log::debug!("[{}] Malformed message: {:#?}", self.log_context(), e);
+                    self.try_send(ServerMessage::MalformedMessage)?;
                     return Ok(());
                 }
             };
 
             let (user, device, perms) = (self.user, self.device, self.perms);
+            let context = self.log_context();
+            let kind = msg.request.kind();
             let handler = RequestHandler {
                 session: self,
                 ctx,
+                context,
                 user,
                 device,
                 perms,
             };
+
+            let started = Instant::now();
             let result = handler.handle_request(msg.request).await;
+            log_request(context, msg.id, kind, &result, started.elapsed());
 
             if let Err(Error::LoggedOut) = result {
-                own_user_nonexistent(self, ctx);
+                own_user_nonexistent(context, ctx);
             }
 
-            self.try_send(ServerMessage::Response { id: msg.id, result })
-                .await?;
+            self.try_send(ServerMessage::Response { id: msg.id, result })?;
         } else if message.is_close() {
             ctx.stop();
         } else {
-            log::debug!("Malformed message: {:#?}", message);
-            self.try_send(ServerMessage::MalformedMessage).await?;
+            log::debug!("[{}] Malformed message: {:#?}", self.log_context(), message);
+            self.try_send(ServerMessage::MalformedMessage)?;
         }
 
         Ok(())
@@ -366,22 +559,20 @@ impl ActiveSession {
 
     #[spaad::handler]
     pub async fn forward_message(&mut self, fwd: ForwardMessage, ctx: &mut Context<Self>) {
-        // Ok path is (notify, unread messages)
-        let msg = match self.should_notify_client(fwd.community, fwd.room) {
-            // If the user is watching the room, always forward the message
-            Ok((true, _)) => ServerEvent::AddMessage {
+        let mentioned = fwd.mentioned.contains(&self.user);
+        let msg = match self.message_delivery(fwd.community, fwd.room, mentioned) {
+            Ok(MessageDelivery::Message) => ServerEvent::AddMessage {
                 community: fwd.community,
                 room: fwd.room,
                 message: fwd.message,
             },
-            // If the user is not watching but it wasn't unread, tell the client that there are new msgs
-            Ok((false, false)) => ServerEvent::NotifyMessageReady {
+            Ok(MessageDelivery::Ready) => ServerEvent::NotifyMessageReady {
                 room: fwd.room,
                 community: fwd.community,
             },
-            // It was unread, so we don't need to tell the client about the new messages.
-            Ok((false, true)) => return,
-            Err(Error::InvalidUser) => own_user_nonexistent(self, ctx),
+            // Messages can still be fetched when the client asks for them
+            Ok(MessageDelivery::Nothing) => return,
+            Err(Error::InvalidUser) => own_user_nonexistent(self.log_context(), ctx),
             Err(_) => return, // It's *probably* a timing anomaly.
         };
 
@@ -394,7 +585,7 @@ impl ActiveSession {
             Ok(user) => user,
             Err(_) => {
                 let _ = self.send(ServerMessage::Event(ServerEvent::SessionLoggedOut), ctx);
-                own_user_nonexistent(self, ctx);
+                own_user_nonexistent(self.log_context(), ctx);
                 return;
             }
         };
@@ -417,13 +608,80 @@ impl ActiveSession {
             self.send(msg, ctx).await;
         } // Else case is *probably* a timing anomaly
     }
+
+    #[spaad::handler]
+    pub async fn remove_room(&mut self, remove: RemoveRoom, ctx: &mut Context<Self>) {
+        let mut user = match manager::get_active_user_mut(self.user) {
+            Ok(user) => user,
+            Err(_) => {
+                let _ = self.send(ServerMessage::Event(ServerEvent::SessionLoggedOut), ctx);
+                own_user_nonexistent(self.log_context(), ctx);
+                return;
+            }
+        };
+
+        // Another session of this user may have already removed the room
+        if let Some(community) = user.communities.get_mut(&remove.community) {
+            community.rooms.remove(&remove.room);
+        }
+
+        if let Some(session) = user.sessions.get_mut(&self.device) {
+            if session.as_active_looking_at() == Some(Some((remove.community, remove.room))) {
+                session.set_looking_at(None);
+            }
+        }
+
+        let msg = ServerMessage::Event(ServerEvent::RemoveRoom {
+            community: remove.community,
+            room: remove.room,
+        });
+
+        drop(user); // Drop lock
+        self.send(msg, ctx).await;
+    }
 }
 
-fn own_user_nonexistent<T: Debug, S: xtra::Actor>(client: &T, ctx: &mut Context<S>) -> ServerEvent {
-    warn!(
-        "Nonexistent user! Is this a timing anomaly? Client: {:#?}",
-        client
-    );
+fn own_user_nonexistent<S: xtra::Actor>(context: LogContext, ctx: &mut Context<S>) -> ServerEvent {
+    warn!("[{}] Nonexistent user! Is this a timing anomaly?", context);
     ctx.stop(); // The user did not exist at the time of request
     ServerEvent::InternalError
 }
+
+/// Logs the outcome of a request along with the session which made it. Internal errors are always
+/// logged, since they mean something went wrong on the server; everything else only when debugging.
+fn log_request(
+    context: LogContext,
+    id: RequestId,
+    kind: &str,
+    result: &Result<OkResponse, Error>,
+    took: Duration,
+) {
+    let took = took.as_millis();
+    match result {
+        Ok(_) => debug!("[{}] request={} kind={} result=ok took={}ms", context, id, kind, took),
+        Err(Error::Internal) => error!(
+            "[{}] request={} kind={} result=Internal took={}ms",
+            context, id, kind, took
+        ),
+        Err(e) => debug!(
+            "[{}] request={} kind={} result={:?} took={}ms",
+            context, id, kind, e, took
+        ),
+    }
+}
+
+/// Writes the messages queued for a session to its websocket, until the session is dropped or the
+/// writer is aborted. The connection is closed either way.
+async fn write_outbound(
+    mut ws: SplitSink<WebSocket, ws::Message>,
+    outbound: mpsc::Receiver<ws::Message>,
+    abort: AbortRegistration,
+) {
+    let forward = outbound.map(Ok).forward(&mut ws);
+    if let Ok(Err(e)) = Abortable::new(forward, abort).await {
+        debug!("Error writing to websocket: {:?}", e);
+        return;
+    }
+
+    let _ = tokio::time::timeout(FINAL_SEND_TIMEOUT, ws.close()).await;
+}
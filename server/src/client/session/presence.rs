@@ -0,0 +1,92 @@
+//! Tells other users when a user comes online, goes away, or goes offline.
+//!
+//! Changes are not broadcast immediately: they are settled after `PRESENCE_DEBOUNCE`, and only
+//! broadcast if the status differs from the last one that was broadcast. This way, a flaky
+//! connection that drops and reconnects quickly does not cause presence to flip back and forth.
+
+use std::time::Duration;
+
+use dashmap::mapref::entry::Entry;
+use dashmap::DashMap;
+use lazy_static::lazy_static;
+use vertex::prelude::*;
+use xtra::prelude::*;
+
+use super::manager::USERS;
+use crate::community::{self, BroadcastPresence};
+use crate::handle_disconnected;
+
+/// How long a presence change must last before it is broadcast
+const PRESENCE_DEBOUNCE: Duration = Duration::from_secs(3);
+
+lazy_static! {
+    /// Users with a pending presence change, and the communities to tell about it
+    static ref PENDING: DashMap<UserId, Vec<CommunityId>> = DashMap::new();
+    /// The last status broadcast for each user who is not offline
+    static ref BROADCAST: DashMap<UserId, PresenceStatus> = DashMap::new();
+}
+
+/// The status of the user as others currently see it, which may not have been broadcast yet
+pub fn current_status(user: UserId) -> PresenceStatus {
+    match USERS.get(&user) {
+        Some(user) if !user.show_online_status => PresenceStatus::Offline,
+        Some(user) if user.sessions.values().any(|s| s.as_active_actor().is_some()) => user.status,
+        _ => PresenceStatus::Offline,
+    }
+}
+
+/// Schedule the user's presence to be broadcast to the given communities, if it has changed by
+/// the time the debounce period is over
+pub fn schedule_update(user: UserId, communities: Vec<CommunityId>) {
+    match PENDING.entry(user) {
+        Entry::Occupied(mut pending) => {
+            let pending = pending.get_mut();
+            for community in communities {
+                if !pending.contains(&community) {
+                    pending.push(community);
+                }
+            }
+
+            return;
+        }
+        Entry::Vacant(vacant) => {
+            vacant.insert(communities);
+        }
+    }
+
+    tokio::spawn(async move {
+        tokio::time::delay_for(PRESENCE_DEBOUNCE).await;
+        settle(user);
+    });
+}
+
+fn settle(user: UserId) {
+    let communities = match PENDING.remove(&user) {
+        Some((_, communities)) => communities,
+        None => return,
+    };
+
+    let status = current_status(user);
+    let last = BROADCAST
+        .get(&user)
+        .map(|s| *s)
+        .unwrap_or(PresenceStatus::Offline);
+
+    if status == last {
+        return;
+    }
+
+    if status == PresenceStatus::Offline {
+        BROADCAST.remove(&user);
+    } else {
+        BROADCAST.insert(user, status);
+    }
+
+    for id in communities {
+        if let Ok(community) = community::address_of(id) {
+            let _ = community
+                .do_send(BroadcastPresence { user, status })
+                .map_err(handle_disconnected("Community"));
+        }
+    }
+}
@@ -1,19 +1,36 @@
 //! Methods that can be executed by regular users
 
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
 use chrono::{DateTime, Utc};
 use futures::TryStreamExt;
+use uuid::Uuid;
+use vertex::formatting;
 use xtra::Context;
 
-use crate::client::session::{manager, UserCommunity, UserRoom};
-use crate::community::CommunityActor;
+use crate::client::session::{manager, presence, UserCommunity, UserRoom};
 use crate::community::COMMUNITIES;
-use crate::{auth, community, handle_disconnected, IdentifiedMessage};
+use crate::community::{
+    BroadcastCommunityUpdate, BroadcastPinsUpdated, Community, CommunityActor, DeleteCommunity,
+    DeleteRoom, EditRoom, FilterMembers, IdentifiedMessage, IsRoomArchived,
+    Leave, RoomRateLimited, SentMessage, SetRoomArchived, StartTyping, StopTyping, UpdateReactions,
+};
+use crate::database::{CommunityEdit, EditCommunityError};
+use crate::{attachments, auth, community, handle_disconnected};
 
 use super::*;
 
+/// Minimum time between two `StartTyping` requests from a session being forwarded to the community
+const TYPING_THROTTLE: Duration = Duration::from_secs(1);
+
+/// Rooms that new communities are created with
+const DEFAULT_ROOMS: &[&str] = &["General", "Off Topic"];
+
 pub struct RequestHandler<'a> {
     pub session: &'a mut __ActiveSessionActor::ActiveSession,
     pub ctx: &'a mut Context<__ActiveSessionActor::ActiveSession>,
+    pub context: LogContext,
     pub user: UserId,
     pub device: DeviceId,
     pub perms: TokenPermissionFlags,
@@ -21,12 +38,79 @@ pub struct RequestHandler<'a> {
 
 impl<'a> RequestHandler<'a> {
     pub async fn handle_request(self, request: ClientRequest) -> Result<OkResponse, Error> {
+        let ratelimited = self.check_ratelimit(&request);
+        if let Err(ready_in) = ratelimited {
+            let msg = ServerMessage::RateLimited { ready_in };
+            self.session.send(msg, self.ctx).await;
+            return Err(Error::rate_limited(ready_in));
+        }
+
         match request {
             ClientRequest::SendMessage(message) => self.send_message(message).await,
             ClientRequest::EditMessage(edit) => self.edit_message(edit).await,
+            ClientRequest::DeleteMessage(delete) => self.delete_message(delete).await,
+            ClientRequest::SearchMessages {
+                community,
+                query,
+                limit,
+            } => self.search_messages(community, query, limit).await,
+            ClientRequest::SearchUsers { query, limit } => self.search_users(query, limit).await,
+            ClientRequest::GetEditHistory {
+                community,
+                room,
+                message,
+            } => self.get_edit_history(community, room, message).await,
             ClientRequest::JoinCommunity(code) => self.join_community(code).await,
+            ClientRequest::ListPublicCommunities { after, limit } => {
+                self.list_public_communities(after, limit).await
+            }
+            ClientRequest::JoinPublicCommunity(id) => self.join_public_community(id).await,
+            ClientRequest::KickUser { community, user } => self.kick_user(community, user).await,
+            ClientRequest::MuteUser {
+                community,
+                user,
+                until,
+            } => self.set_muted(community, user, true, until).await,
+            ClientRequest::UnmuteUser { community, user } => {
+                self.set_muted(community, user, false, None).await
+            }
+            ClientRequest::LeaveCommunity(id) => self.leave_community(id).await,
+            ClientRequest::DeleteCommunity { community, name } => {
+                self.delete_community(community, name).await
+            }
+            ClientRequest::OpenDirectChannel(user) => self.open_direct_channel(user).await,
+            ClientRequest::SendDirectMessage { channel, content } => {
+                self.send_direct_message(channel, content).await
+            }
+            ClientRequest::GetDirectMessages {
+                channel,
+                selector,
+                count,
+            } => self.get_direct_messages(channel, selector, count).await,
+            ClientRequest::BlockUser(user) => self.block_user(user).await,
+            ClientRequest::UnblockUser(user) => self.unblock_user(user).await,
+            ClientRequest::GetBlockedUsers => self.get_blocked_users().await,
+            ClientRequest::CreateRole {
+                community,
+                name,
+                permissions,
+            } => self.create_role(community, name, permissions).await,
+            ClientRequest::AssignRole {
+                community,
+                role,
+                user,
+            } => self.assign_role(community, role, user).await,
+            ClientRequest::GetCommunityMembers {
+                community,
+                after,
+                limit,
+            } => self.get_community_members(community, after, limit).await,
             ClientRequest::CreateCommunity { name } => self.create_community(name).await,
             ClientRequest::LogOut => self.log_out().await,
+            ClientRequest::ListDevices => self.list_devices().await,
+            ClientRequest::SetStatus(status) => self.set_status(status).await,
+            ClientRequest::SetPrivacy(privacy) => self.set_privacy(privacy).await,
+            ClientRequest::RevokeDevice(device) => self.revoke_device(device).await,
             ClientRequest::GetProfile(id) => self.get_user_profile(id).await,
             ClientRequest::ChangeUsername { new_username } => {
                 self.change_username(new_username).await
@@ -37,10 +121,22 @@ impl<'a> RequestHandler<'a> {
             ClientRequest::CreateRoom { name, community } => {
                 self.create_room(name, community).await
             }
+            ClientRequest::DeleteRoom { community, room } => {
+                self.delete_room(community, room).await
+            }
             ClientRequest::CreateInvite {
                 community,
                 expiration_datetime,
-            } => self.create_invite(community, expiration_datetime).await,
+                max_uses,
+                vanity,
+            } => {
+                self.create_invite(community, expiration_datetime, max_uses, vanity)
+                    .await
+            }
+            ClientRequest::GetInviteCodes(community) => self.get_invite_codes(community).await,
+            ClientRequest::GetCommunityStats(community) => {
+                self.get_community_stats(community).await
+            }
             ClientRequest::GetRoomUpdate {
                 community,
                 room,
@@ -50,6 +146,10 @@ impl<'a> RequestHandler<'a> {
                 self.get_room_update(community, room, last_received, message_count)
                     .await
             }
+            ClientRequest::GetRoomUpdates {
+                rooms,
+                message_count,
+            } => self.get_room_updates(rooms, message_count).await,
             ClientRequest::SelectRoom { community, room } => {
                 self.select_room(community, room).await
             }
@@ -61,12 +161,56 @@ impl<'a> RequestHandler<'a> {
                 count,
             } => self.get_messages(community, room, selector, count).await,
             ClientRequest::SetAsRead { community, room } => self.set_as_read(community, room).await,
-            ClientRequest::ChangeCommunityName { new, community } => {
-                self.change_community_name(new, community).await
+            ClientRequest::SetManyAsRead(rooms) => self.set_many_as_read(rooms).await,
+            ClientRequest::EditCommunity {
+                community,
+                name,
+                description,
+                icon,
+                retention_days,
+                public,
+                sign_messages,
+            } => {
+                let edit = CommunityEdit {
+                    name,
+                    description,
+                    icon,
+                    retention_days,
+                    public,
+                    sign_messages,
+                };
+                self.edit_community(community, edit).await
             }
-            ClientRequest::ChangeCommunityDescription { new, community } => {
-                self.change_community_description(new, community).await
+            ClientRequest::EditRoom {
+                community,
+                room,
+                topic,
+                slow_mode_secs,
+            } => self.edit_room(community, room, topic, slow_mode_secs).await,
+            ClientRequest::SetRoomArchived {
+                community,
+                room,
+                archived,
+            } => self.set_room_archived(community, room, archived).await,
+            ClientRequest::SetWatchLevel {
+                community,
+                room,
+                level,
+            } => self.set_watch_level(community, room, level).await,
+            ClientRequest::PinMessage {
+                community,
+                room,
+                message,
+            } => self.pin_message(community, room, message).await,
+            ClientRequest::UnpinMessage {
+                community,
+                room,
+                message,
+            } => self.unpin_message(community, room, message).await,
+            ClientRequest::GetPinnedMessages { community, room } => {
+                self.get_pinned_messages(community, room).await
             }
+            ClientRequest::Resync => self.resync().await,
             ClientRequest::AdminAction(req) => {
                 if !self.perms.has_perms(TokenPermissionFlags::ADMINISTER) {
                     return Err(Error::AccessDenied);
@@ -79,11 +223,29 @@ impl<'a> RequestHandler<'a> {
                 short_desc,
                 extended_desc,
             } => self.report_user(message, short_desc, extended_desc).await,
+            ClientRequest::AddReaction {
+                community,
+                room,
+                message,
+                emoji,
+            } => self.add_reaction(community, room, message, emoji).await,
+            ClientRequest::RemoveReaction {
+                community,
+                room,
+                message,
+                emoji,
+            } => self.remove_reaction(community, room, message, emoji).await,
+            ClientRequest::StartTyping { community, room } => {
+                self.start_typing(community, room).await
+            }
+            ClientRequest::StopTyping { community, room } => {
+                self.stop_typing(community, room).await
+            }
             _ => Err(Error::Unimplemented),
         }
     }
 
-    async fn send_message(self, message: ClientSentMessage) -> Result<OkResponse, Error> {
+    async fn send_message(mut self, mut message: ClientSentMessage) -> Result<OkResponse, Error> {
         if !self.perms.has_perms(TokenPermissionFlags::SEND_MESSAGES) {
             return Err(Error::AccessDenied);
         }
@@ -92,231 +254,1042 @@ impl<'a> RequestHandler<'a> {
             return Err(Error::InvalidCommunity);
         }
 
+        let community_perms = self.session.community_perms(&message.to_community)?;
+        if !community_perms.has_perms(CommunityPermissionFlags::SEND_MESSAGES) {
+            return Err(Error::AccessDenied);
+        }
+
+        let db = &self.session.global.database;
+        if db.is_muted(message.to_community, self.user).await? {
+            return Err(Error::Muted);
+        }
+
         if message.content.len() > self.session.global.config.max_message_len as usize {
             return Err(Error::MessageTooLong);
         }
 
+        let names = self.mentioned_names(&message.content);
+        let mentioned = self.resolve_mentions(message.to_community, names).await?;
+        message.content = formatting::sanitize(&message.content);
+        if message.content.trim().is_empty() && message.attachments.is_empty() {
+            return Err(Error::EmptyMessage);
+        }
+
+        let (community_id, room) = (message.to_community, message.to_room);
+
         let community = community::address_of(message.to_community)?;
         let message = IdentifiedMessage {
             user: self.user,
             device: self.device,
-            message,
+            message: SentMessage {
+                message,
+                mentioned: mentioned.clone(),
+            },
         };
-        let confirmation = community
+        let res = community
             .send(message)
             .await
             .map_err(handle_disconnected("Community"))??;
 
-        Ok(OkResponse::ConfirmMessage(confirmation))
+        match res {
+            Ok(confirmation) => {
+                self.notify_mentions(community_id, room, confirmation.id, mentioned)
+                    .await?;
+                Ok(OkResponse::ConfirmMessage(confirmation))
+            }
+            Err(RoomRateLimited { ready_in }) => {
+                let msg = ServerMessage::RateLimited { ready_in };
+                self.session.send(msg, self.ctx).await;
+                Err(Error::rate_limited(ready_in))
+            }
+        }
+    }
+
+    /// The normalized names mentioned in a message, without repeats. Each name has to be looked
+    /// up, so only the first `max_mentions_per_message` are kept.
+    fn mentioned_names(&self, content: &str) -> Vec<String> {
+        let config = &self.session.global.config;
+        let max = config.max_mentions_per_message as usize;
+        let mut seen = HashSet::new();
+
+        mentioned_usernames(content)
+            .map(|name| auth::normalize_username(name, config))
+            .filter(|name| seen.insert(name.clone()))
+            .take(max)
+            .collect()
     }
 
-    async fn edit_message(self, edit: Edit) -> Result<OkResponse, Error> {
-        if !self.perms.has_perms(TokenPermissionFlags::SEND_MESSAGES) {
-            return Err(Error::AccessDenied);
-        }
+    /// Finds the users mentioned by name in a message. Names which are not those of another member
+    /// of the community are ignored, as are users who have blocked the author.
+    async fn resolve_mentions(
+        &mut self,
+        community: CommunityId,
+        names: Vec<String>,
+    ) -> Result<Vec<UserId>, Error> {
+        let db = &self.session.global.database;
+        let mut users = Vec::new();
 
-        if !self.session.in_community(&edit.community)? {
-            return Err(Error::InvalidCommunity);
+        for name in names {
+            if let Some(record) = db.get_user_by_name(name).await? {
+                if record.id != self.user && !users.contains(&record.id) {
+                    users.push(record.id);
+                }
+            }
         }
 
-        if edit.new_content.len() > self.session.global.config.max_message_len as usize {
-            return Err(Error::MessageTooLong);
+        if users.is_empty() {
+            return Ok(users);
         }
 
-        let community = community::address_of(edit.community)?;
-        let message = IdentifiedMessage {
-            user: self.user,
-            device: self.device,
-            message: edit,
-        };
-        community
-            .send(message)
+        let members = community::address_of(community)?
+            .send(FilterMembers(users))
             .await
-            .map_err(handle_disconnected("Community"))??;
-        Ok(OkResponse::NoData)
-    }
+            .map_err(handle_disconnected("Community"))?;
 
-    async fn log_out(self) -> Result<OkResponse, Error> {
-        if let Err(NonexistentDevice) = self
-            .session
-            .global
-            .database
-            .revoke_token(self.device)
-            .await?
-        {
-            return Err(Error::DeviceDoesNotExist);
+        let mut mentioned = Vec::with_capacity(members.len());
+        for user in members {
+            if !db.is_blocked(user, self.user).await? {
+                mentioned.push(user);
+            }
         }
 
-        self.ctx.notify_immediately(LogoutThisSession);
-
-        Ok(OkResponse::NoData)
+        Ok(mentioned)
     }
 
-    async fn get_user_profile(self, id: UserId) -> Result<OkResponse, Error> {
-        match self.session.global.database.get_user_profile(id).await? {
-            Some(profile) => Ok(OkResponse::Profile(profile)),
-            None => Err(Error::InvalidUser),
-        }
-    }
+    /// Records and notifies the users mentioned in a sent message
+    async fn notify_mentions(
+        &mut self,
+        community: CommunityId,
+        room: RoomId,
+        message: MessageId,
+        users: Vec<UserId>,
+    ) -> Result<(), Error> {
+        let db = &self.session.global.database;
 
-    async fn change_username(self, new_username: String) -> Result<OkResponse, Error> {
-        if !self.perms.has_perms(TokenPermissionFlags::CHANGE_USERNAME) {
-            return Err(Error::AccessDenied);
+        for user in users {
+            // Only notify once, however many times they are mentioned
+            if !db.add_mention(message, user).await? {
+                continue;
+            }
+
+            let event = ServerMessage::Event(ServerEvent::Mentioned {
+                community,
+                room,
+                message,
+                author: self.user,
+            });
+
+            if let Ok(active) = manager::get_active_user(user) {
+                active
+                    .sessions
+                    .values()
+                    .filter_map(|session| session.as_active_actor())
+                    .for_each(|session| {
+                        let _ = session.send(event.clone());
+                    });
+            }
         }
 
-        let new_username = match auth::prepare_username(&new_username, &self.session.global.config)
-        {
-            Ok(name) => name,
-            Err(auth::TooShort) => return Err(Error::InvalidUsername),
-        };
+        Ok(())
+    }
 
-        let database = &self.session.global.database;
-        match database.change_username(self.user, new_username).await? {
-            Ok(()) => Ok(OkResponse::NoData),
-            Err(ChangeUsernameError::UsernameConflict) => Err(Error::UsernameAlreadyExists),
-            Err(ChangeUsernameError::NonexistentUser) => {
-                self.ctx.stop(); // The user did not exist at the time of request
-                Err(Error::LoggedOut)
+    /// Checks the device against the quota for this kind of request, if it has one. Returns how
+    /// long until it may be retried if the quota has been used up.
+    fn check_ratelimit(&self, request: &ClientRequest) -> Result<(), Duration> {
+        let ratelimiters = self.session.global.ratelimiter.load();
+        let ratelimiter = match request {
+            ClientRequest::SendMessage(_) | ClientRequest::SendDirectMessage { .. } => {
+                &ratelimiters.send_message
             }
-        }
+            ClientRequest::CreateCommunity { .. } => &ratelimiters.create_community,
+            ClientRequest::CreateInvite { .. } => &ratelimiters.create_invite,
+            _ => return Ok(()),
+        };
+
+        ratelimiter
+            .check_key(&self.device)
+            .map_err(crate::ready_in)
     }
 
-    async fn change_display_name(self, new_display_name: String) -> Result<OkResponse, Error> {
-        if !self
-            .perms
-            .has_perms(TokenPermissionFlags::CHANGE_DISPLAY_NAME)
-        {
+    async fn open_direct_channel(self, other: UserId) -> Result<OkResponse, Error> {
+        if !self.perms.has_perms(TokenPermissionFlags::SEND_MESSAGES) {
             return Err(Error::AccessDenied);
         }
 
-        if !auth::valid_display_name(&new_display_name, &self.session.global.config) {
-            return Err(Error::InvalidDisplayName);
+        if other == self.user {
+            return Err(Error::InvalidUser);
         }
 
-        let database = &self.session.global.database;
-        match database
-            .change_display_name(self.user, new_display_name)
-            .await?
-        {
-            Ok(()) => Ok(OkResponse::NoData),
-            Err(_) => {
-                self.ctx.stop(); // The user did not exist at the time of request
-                Err(Error::LoggedOut)
-            }
+        let db = &self.session.global.database;
+        if db.is_blocked(other, self.user).await? {
+            return Err(Error::AccessDenied);
         }
-    }
 
-    async fn create_community(self, name: String) -> Result<OkResponse, Error> {
-        if !self
-            .perms
-            .has_perms(TokenPermissionFlags::CREATE_COMMUNITIES)
-        {
+        let privacy = db.get_privacy_settings(other).await?.ok_or(Error::InvalidUser)?;
+        if !privacy.allow_stranger_dms && !db.share_community(self.user, other).await? {
             return Err(Error::AccessDenied);
         }
 
-        let max = self.session.global.config.max_community_name_len as usize;
-        if name.is_empty() || name.len() > max {
-            return Err(Error::TooLong);
+        match db.open_direct_channel(self.user, other).await? {
+            Ok(id) => Ok(OkResponse::DirectChannel(id)),
+            Err(InvalidUser) => Err(Error::InvalidUser),
+        }
+    }
+
+    async fn block_user(self, user: UserId) -> Result<OkResponse, Error> {
+        if user == self.user {
+            return Err(Error::InvalidUser);
         }
 
         let db = &self.session.global.database;
-        let id = db.create_community(name.clone()).await?;
-        let res = db
-            .create_default_user_room_states_for_user(id, self.user)
-            .await?;
+        match db.block_user(self.user, user).await? {
+            Ok(()) => Ok(OkResponse::NoData),
+            Err(InvalidUser) => Err(Error::InvalidUser),
+        }
+    }
 
-        match res {
-            Ok(_) => {
-                CommunityActor::create_and_spawn(name, id, db.clone(), self.user);
-                self.join_community_by_id(id).await
-            }
-            Err(_) => {
-                self.ctx.stop(); // The user did not exist at the time of request
-                Err(Error::LoggedOut)
-            }
+    async fn unblock_user(self, user: UserId) -> Result<OkResponse, Error> {
+        let db = &self.session.global.database;
+        if db.unblock_user(self.user, user).await? {
+            Ok(OkResponse::NoData)
+        } else {
+            Err(Error::InvalidUser)
         }
     }
 
-    async fn join_community(self, code: InviteCode) -> Result<OkResponse, Error> {
-        if !self.perms.has_perms(TokenPermissionFlags::JOIN_COMMUNITIES) {
+    async fn get_blocked_users(self) -> Result<OkResponse, Error> {
+        let db = &self.session.global.database;
+        let blocked = db.get_blocked_users(self.user).await?;
+        Ok(OkResponse::BlockedUsers(blocked))
+    }
+
+    async fn send_direct_message(
+        self,
+        channel: DirectChannelId,
+        content: String,
+    ) -> Result<OkResponse, Error> {
+        if !self.perms.has_perms(TokenPermissionFlags::SEND_MESSAGES) {
             return Err(Error::AccessDenied);
         }
 
-        if code.0.len() > 11 {
-            return Err(Error::InvalidInviteCode);
+        if content.len() > self.session.global.config.max_message_len as usize {
+            return Err(Error::MessageTooLong);
         }
 
-        let database = &self.session.global.database;
-        let id = match database.get_community_from_invite_code(code).await? {
-            Ok(Some(id)) => id,
-            Ok(None) | Err(_) => return Err(Error::InvalidInviteCode),
+        let content = formatting::sanitize(&content);
+        if content.trim().is_empty() {
+            return Err(Error::EmptyMessage);
+        }
+
+        let db = &self.session.global.database;
+        let record = match db.get_direct_channel(channel).await? {
+            Some(record) if record.has_member(self.user) => record,
+            _ => return Err(Error::InvalidRoom),
         };
 
-        self.join_community_by_id(id).await
-    }
+        let [user_a, user_b] = record.members();
+        let other = if user_a == self.user { user_b } else { user_a };
+        if db.is_blocked(other, self.user).await? {
+            return Err(Error::AccessDenied);
+        }
 
-    async fn join_community_by_id(self, id: CommunityId) -> Result<OkResponse, Error> {
-        let community = community::address_of(id)?;
+        let id = MessageId(Uuid::new_v4());
+        let (seq, time_sent, profile_version) = db
+            .create_direct_message(id, self.user, channel, Utc::now(), content.clone())
+            .await?;
 
-        let join = Join {
-            user: self.user,
-            device_id: self.device,
-            session: self.ctx.address().unwrap().into(),
-        };
+        let send = ServerMessage::Event(ServerEvent::AddDirectMessage {
+            channel,
+            message: vertex::structures::Message {
+                id,
+                author: self.user,
+                author_profile_version: profile_version,
+                time_sent,
+                content: Some(content),
+                reactions: Vec::new(),
+                attachments: Vec::new(),
+                reply_to: None,
+                kind: MessageKind::Normal,
+                seq,
+                signature: None,
+            },
+        });
+
+        // Send to both members, so that the sender's other devices see the message too
+        for member in record.members().iter() {
+            if let Ok(user) = manager::get_active_user(*member) {
+                user.sessions
+                    .iter()
+                    .filter(|(device, _)| **device != self.device)
+                    .filter_map(|(_, session)| session.as_active_actor())
+                    .for_each(|session| {
+                        let _ = session.send(send.clone());
+                    });
+            }
+        }
 
-        let res = community
-            .send(join)
-            .await
-            .map_err(handle_disconnected("Community"))??;
+        Ok(OkResponse::ConfirmMessage(MessageConfirmation { id, time_sent, seq }))
+    }
 
-        match res {
-            Ok(community) => {
-                let db = &self.session.global.database;
-                let user_community = UserCommunity::load(db, self.user, id).await?;
+    async fn get_direct_messages(
+        self,
+        channel: DirectChannelId,
+        selector: Option<MessageSelector>,
+        count: u64,
+    ) -> Result<OkResponse, Error> {
+        let db = &self.session.global.database;
+        match db.get_direct_channel(channel).await? {
+            Some(record) if record.has_member(self.user) => {}
+            _ => return Err(Error::InvalidRoom),
+        }
 
-                if let Ok(mut user) = manager::get_active_user_mut(self.user) {
-                    user.communities.insert(community.id, user_community);
+        let history = db
+            .get_direct_messages(channel, selector, count as usize)
+            .await?
+            .map_err(|_| Error::InvalidMessageSelector)?;
 
-                    let community = community.clone();
-                    let send = ServerMessage::Event(ServerEvent::AddCommunity(community));
-                    let sessions = user.sessions.iter();
+        Ok(OkResponse::MessageHistory(history))
+    }
 
-                    sessions
-                        .filter(|(id, _)| **id != self.device)
-                        .filter_map(|(_, session)| session.as_active_actor())
-                        .for_each(|session| {
-                            let _ = session.send(send.clone());
-                        });
-                }
+    async fn edit_message(self, mut edit: Edit) -> Result<OkResponse, Error> {
+        if !self.perms.has_perms(TokenPermissionFlags::SEND_MESSAGES) {
+            return Err(Error::AccessDenied);
+        }
 
-                Ok(OkResponse::AddCommunity(community))
-            }
-            Err(AddToCommunityError::AlreadyInCommunity) => Err(Error::AlreadyInCommunity),
-            Err(AddToCommunityError::InvalidCommunity) => Err(Error::InvalidCommunity),
-            Err(AddToCommunityError::InvalidUser) => Err(Error::InvalidUser),
+        if !self.session.in_room(&edit.community, &edit.room)? {
+            return Err(Error::InvalidRoom);
         }
-    }
 
-    async fn create_room(self, name: String, community: CommunityId) -> Result<OkResponse, Error> {
-        if !self.perms.has_perms(TokenPermissionFlags::CREATE_ROOMS) {
+        let community_perms = self.session.community_perms(&edit.community)?;
+        if !community_perms.has_perms(CommunityPermissionFlags::SEND_MESSAGES) {
             return Err(Error::AccessDenied);
         }
 
-        if !self.session.in_community(&community)? {
-            return Err(Error::InvalidCommunity);
+        let db = &self.session.global.database;
+        if db.is_muted(edit.community, self.user).await? {
+            return Err(Error::Muted);
         }
 
-        let max = self.session.global.config.max_channel_name_len as usize;
-        if name.is_empty() || name.len() > max {
-            return Err(Error::TooLong);
+        if edit.new_content.len() > self.session.global.config.max_message_len as usize {
+            return Err(Error::MessageTooLong);
         }
 
-        let community_id = community;
-        let community = community::address_of(community)?;
+        edit.new_content = formatting::sanitize(&edit.new_content);
+        if edit.new_content.trim().is_empty() {
+            return Err(Error::EmptyMessage);
+        }
 
-        let create = CreateRoom {
-            creator: self.device,
-            name: name.clone(),
+        let record = match db.get_message_by_id(edit.message).await? {
+            Some(record) => record,
+            None => return Err(Error::InvalidMessage),
+        };
+
+        if record.community != edit.community || record.room != edit.room {
+            return Err(Error::InvalidMessage);
+        }
+
+        if record.author != self.user {
+            return Err(Error::AccessDenied);
+        }
+
+        if record.content.is_none() {
+            return Err(Error::InvalidMessage); // Deleted messages cannot be edited
+        }
+
+        let community = community::address_of(edit.community)?;
+        let message = IdentifiedMessage {
+            user: self.user,
+            device: self.device,
+            message: edit,
+        };
+        community
+            .send(message)
+            .await
+            .map_err(handle_disconnected("Community"))??;
+        Ok(OkResponse::NoData)
+    }
+
+    async fn search_messages(
+        self,
+        community: CommunityId,
+        query: String,
+        limit: usize,
+    ) -> Result<OkResponse, Error> {
+        if !self.session.in_community(&community)? {
+            return Err(Error::InvalidCommunity);
+        }
+
+        let query = query.trim();
+        if query.chars().count() < MIN_SEARCH_QUERY_LEN {
+            return Err(Error::InvalidSearchQuery);
+        }
+
+        if query.len() > self.session.global.config.max_message_len as usize {
+            return Err(Error::TooLong);
+        }
+
+        let db = &self.session.global.database;
+        let results = db.search_messages(community, self.user, query, limit).await?;
+        Ok(OkResponse::SearchResults(results))
+    }
+
+    async fn search_users(self, query: String, limit: usize) -> Result<OkResponse, Error> {
+        let query = query.trim();
+        if query.chars().count() < MIN_SEARCH_QUERY_LEN {
+            return Err(Error::InvalidSearchQuery);
+        }
+
+        let config = &self.session.global.config;
+        if query.len() > config.max_username_len.max(config.max_display_name_len) as usize {
+            return Err(Error::TooLong);
+        }
+
+        // Admins can see users who have been banned or locked, so as to find them to undo it
+        let admin = !manager::get_active_user(self.user)?.admin_perms.is_empty();
+
+        let db = &self.session.global.database;
+        let results = db.search_users(query, limit, admin).await?;
+        Ok(OkResponse::UserSearchResults(results))
+    }
+
+    async fn get_edit_history(
+        self,
+        community: CommunityId,
+        room: RoomId,
+        message: MessageId,
+    ) -> Result<OkResponse, Error> {
+        if !self.session.in_room(&community, &room)? {
+            return Err(Error::InvalidRoom);
+        }
+
+        let db = &self.session.global.database;
+        match db.get_message_by_id(message).await? {
+            Some(record) if record.community == community && record.room == room => {}
+            _ => return Err(Error::InvalidMessage),
+        }
+
+        let history = db.get_edit_history(message).await?;
+        Ok(OkResponse::EditHistory(history))
+    }
+
+    async fn delete_message(self, delete: Delete) -> Result<OkResponse, Error> {
+        let own = self.perms.has_perms(TokenPermissionFlags::DELETE_OWN_MESSAGES);
+        let any = self.perms.has_perms(TokenPermissionFlags::DELETE_ANY_MESSAGES);
+        if !own && !any {
+            return Err(Error::AccessDenied);
+        }
+
+        if !self.session.in_room(&delete.community, &delete.room)? {
+            return Err(Error::InvalidRoom);
+        }
+
+        let db = &self.session.global.database;
+        let record = match db.get_message_by_id(delete.message).await? {
+            Some(record) => record,
+            None => return Err(Error::InvalidMessage),
+        };
+
+        if record.community != delete.community || record.room != delete.room {
+            return Err(Error::InvalidMessage);
+        }
+
+        // Other users' messages can only be deleted by moderators
+        if record.author != self.user {
+            let community_perms = self.session.community_perms(&delete.community)?;
+            let moderator = community_perms.has_perms(CommunityPermissionFlags::MODERATE_MEMBERS);
+            if !any || !moderator {
+                return Err(Error::AccessDenied);
+            }
+        }
+
+        // Deleting an already deleted message is a no-op
+        if record.content.is_none() {
+            return Ok(OkResponse::NoData);
+        }
+
+        let community = community::address_of(delete.community)?;
+        let message = IdentifiedMessage {
+            user: self.user,
+            device: self.device,
+            message: delete,
+        };
+        let attachments = community
+            .send(message)
+            .await
+            .map_err(handle_disconnected("Community"))??;
+
+        attachments::remove_files(&self.session.global.config, attachments).await;
+        Ok(OkResponse::NoData)
+    }
+
+    async fn log_out(self) -> Result<OkResponse, Error> {
+        if let Err(NonexistentDevice) = self
+            .session
+            .global
+            .database
+            .revoke_token(self.device)
+            .await?
+        {
+            return Err(Error::DeviceDoesNotExist);
+        }
+
+        self.ctx.notify_immediately(LogoutThisSession);
+
+        Ok(OkResponse::NoData)
+    }
+
+    async fn set_status(self, status: PresenceStatus) -> Result<OkResponse, Error> {
+        let mut user = manager::get_active_user_mut(self.user)?;
+        user.status = status;
+
+        let communities = user.communities.keys().copied().collect();
+        drop(user); // Drop lock
+
+        presence::schedule_update(self.user, communities);
+        Ok(OkResponse::NoData)
+    }
+
+    async fn set_privacy(self, privacy: PrivacySettings) -> Result<OkResponse, Error> {
+        let db = &self.session.global.database;
+        if let Err(NonexistentUser) = db.set_privacy_settings(self.user, privacy).await? {
+            self.ctx.stop(); // The user did not exist at the time of request
+            return Err(Error::LoggedOut);
+        }
+
+        let mut user = manager::get_active_user_mut(self.user)?;
+        user.show_online_status = privacy.show_online_status;
+
+        let communities = user.communities.keys().copied().collect();
+        drop(user); // Drop lock
+
+        // Appear offline or come back online, if this changed
+        presence::schedule_update(self.user, communities);
+        Ok(OkResponse::NoData)
+    }
+
+    async fn list_devices(self) -> Result<OkResponse, Error> {
+        let db = &self.session.global.database;
+        let devices = db
+            .get_tokens_for_user(self.user)
+            .await?
+            .map_ok(|token| DeviceInfo {
+                device: token.device,
+                device_name: token.device_name,
+                last_used: token.last_used,
+            })
+            .try_collect()
+            .await?;
+
+        Ok(OkResponse::Devices(devices))
+    }
+
+    async fn revoke_device(self, device: DeviceId) -> Result<OkResponse, Error> {
+        if device == self.device {
+            return self.log_out().await;
+        }
+
+        // Limited tokens (e.g. for bots) should not be able to log out the user's other devices
+        if !self.perms.has_perms(TokenPermissionFlags::ALL) {
+            return Err(Error::AccessDenied);
+        }
+
+        let db = &self.session.global.database;
+        match db.get_token(device).await? {
+            Some(token) if token.user == self.user => {}
+            _ => return Err(Error::DeviceDoesNotExist),
+        }
+
+        if let Err(NonexistentDevice) = db.revoke_token(device).await? {
+            return Err(Error::DeviceDoesNotExist);
+        }
+
+        let _ = manager::remove_and_notify_device(self.user, device); // Token is gone either way
+
+        Ok(OkResponse::NoData)
+    }
+
+    async fn get_user_profile(self, id: UserId) -> Result<OkResponse, Error> {
+        match self.session.global.database.get_user_profile(id).await? {
+            Some(mut profile) => {
+                // Users who are online are being seen right now
+                if manager::is_online(id) {
+                    profile.last_seen = None;
+                }
+                Ok(OkResponse::Profile(profile))
+            }
+            None => Err(Error::InvalidUser),
+        }
+    }
+
+    async fn change_username(self, new_username: String) -> Result<OkResponse, Error> {
+        if !self.perms.has_perms(TokenPermissionFlags::CHANGE_USERNAME) {
+            return Err(Error::AccessDenied);
+        }
+
+        let new_username = match auth::prepare_username(&new_username, &self.session.global.config)
+        {
+            Ok(name) => name,
+            Err(auth::TooShort) => return Err(Error::InvalidUsername),
+        };
+
+        let days = self.session.global.config.username_change_cooldown_days;
+        let cooldown = chrono::Duration::days(days as i64);
+
+        let database = &self.session.global.database;
+        match database
+            .change_username(self.user, new_username, cooldown)
+            .await?
+        {
+            Ok(()) => Ok(OkResponse::NoData),
+            Err(ChangeUsernameError::UsernameConflict) => Err(Error::UsernameAlreadyExists),
+            Err(ChangeUsernameError::Cooldown { ready_in }) => {
+                let msg = ServerMessage::RateLimited { ready_in };
+                self.session.send(msg, self.ctx).await;
+                Err(Error::UsernameChangeCooldown)
+            }
+            Err(ChangeUsernameError::NonexistentUser) => {
+                self.ctx.stop(); // The user did not exist at the time of request
+                Err(Error::LoggedOut)
+            }
+        }
+    }
+
+    async fn change_display_name(self, new_display_name: String) -> Result<OkResponse, Error> {
+        if !self
+            .perms
+            .has_perms(TokenPermissionFlags::CHANGE_DISPLAY_NAME)
+        {
+            return Err(Error::AccessDenied);
+        }
+
+        if !auth::valid_display_name(&new_display_name, &self.session.global.config) {
+            return Err(Error::InvalidDisplayName);
+        }
+
+        let secs = self.session.global.config.display_name_change_cooldown_secs;
+        let cooldown = chrono::Duration::seconds(secs as i64);
+
+        let database = &self.session.global.database;
+        match database
+            .change_display_name(self.user, new_display_name, cooldown)
+            .await?
+        {
+            Ok(()) => Ok(OkResponse::NoData),
+            Err(ChangeDisplayNameError::Cooldown { ready_in }) => {
+                let msg = ServerMessage::RateLimited { ready_in };
+                self.session.send(msg, self.ctx).await;
+                Err(Error::DisplayNameChangeCooldown)
+            }
+            Err(ChangeDisplayNameError::NonexistentUser) => {
+                self.ctx.stop(); // The user did not exist at the time of request
+                Err(Error::LoggedOut)
+            }
+        }
+    }
+
+    async fn create_community(self, name: String) -> Result<OkResponse, Error> {
+        if !self
+            .perms
+            .has_perms(TokenPermissionFlags::CREATE_COMMUNITIES)
+        {
+            return Err(Error::AccessDenied);
+        }
+
+        let max = self.session.global.config.max_community_name_len as usize;
+        if name.is_empty() || name.len() > max {
+            return Err(Error::TooLong);
+        }
+
+        // The creator of a community is given an owner role with every permission
+        let db = &self.session.global.database;
+        let res = db.create_community(name.clone(), self.user, DEFAULT_ROOMS).await?;
+        let (id, rooms) = match res {
+            Ok(created) => created,
+            Err(_) => {
+                self.ctx.stop(); // The user did not exist at the time of request
+                return Err(Error::LoggedOut);
+            }
+        };
+
+        let global = &self.session.global;
+        let room_quota = community::room_message_quota(&global.config);
+        CommunityActor::create_and_spawn(
+            name.clone(),
+            id,
+            &rooms,
+            db.clone(),
+            self.user,
+            room_quota,
+            global.signing_key.clone(),
+        );
+
+        let community = CommunityStructure {
+            id,
+            name,
+            description: Community::desc_or_default(&None),
+            rooms: rooms
+                .into_iter()
+                .map(|(id, name)| RoomStructure {
+                    id,
+                    name,
+                    unread: true,
+                    topic: None,
+                    slow_mode_secs: 0,
+                    watch_level: WatchLevel::default(),
+                    archived: false,
+                    unread_count: 0,
+                })
+                .collect(),
+            icon: None,
+        };
+
+        self.add_community(community).await
+    }
+
+    async fn join_community(self, code: InviteCode) -> Result<OkResponse, Error> {
+        if !self.perms.has_perms(TokenPermissionFlags::JOIN_COMMUNITIES) {
+            return Err(Error::AccessDenied);
+        }
+
+        if code.0.len() > 11 {
+            return Err(Error::InvalidInviteCode);
+        }
+
+        let database = self.session.global.database.clone();
+        let id = match database.use_invite_code(&code).await? {
+            Ok(Some(id)) => id,
+            Ok(None) | Err(_) => return Err(Error::InvalidInviteCode),
+        };
+
+        let res = self.join_community_by_id(id).await;
+        if res.is_err() {
+            database.refund_invite_code_use(&code).await?;
+        }
+
+        res
+    }
+
+    async fn list_public_communities(
+        self,
+        after: Option<CommunityId>,
+        limit: usize,
+    ) -> Result<OkResponse, Error> {
+        let db = &self.session.global.database;
+        let communities = db.get_public_communities(after, limit).await?;
+        Ok(OkResponse::PublicCommunities(communities))
+    }
+
+    async fn join_public_community(self, id: CommunityId) -> Result<OkResponse, Error> {
+        if !self.perms.has_perms(TokenPermissionFlags::JOIN_COMMUNITIES) {
+            return Err(Error::AccessDenied);
+        }
+
+        // Private communities can only be joined with an invite code, so they are treated as if
+        // they do not exist
+        if !self.session.global.database.is_community_public(id).await? {
+            return Err(Error::InvalidCommunity);
+        }
+
+        self.join_community_by_id(id).await
+    }
+
+    async fn join_community_by_id(self, id: CommunityId) -> Result<OkResponse, Error> {
+        let community = community::address_of(id)?;
+
+        let join = Join {
+            user: self.user,
+            device_id: self.device,
+            session: self.ctx.address().unwrap().into(),
+        };
+
+        let res = community
+            .send(join)
+            .await
+            .map_err(handle_disconnected("Community"))??;
+
+        match res {
+            Ok(community) => self.add_community(community).await,
+            Err(AddToCommunityError::AlreadyInCommunity) => Err(Error::AlreadyInCommunity),
+            Err(AddToCommunityError::InvalidCommunity) => Err(Error::InvalidCommunity),
+            Err(AddToCommunityError::InvalidUser) => Err(Error::InvalidUser),
+        }
+    }
+
+    /// Adds a community which the user has just become a member of to their active user, and tells
+    /// their other sessions about it
+    async fn add_community(self, community: CommunityStructure) -> Result<OkResponse, Error> {
+        let db = &self.session.global.database;
+        let user_community = UserCommunity::load(db, self.user, community.id).await?;
+
+        if let Ok(mut user) = manager::get_active_user_mut(self.user) {
+            user.communities.insert(community.id, user_community);
+
+            let community = community.clone();
+            let send = ServerMessage::Event(ServerEvent::AddCommunity(community));
+            let sessions = user.sessions.iter();
+
+            sessions
+                .filter(|(id, _)| **id != self.device)
+                .filter_map(|(_, session)| session.as_active_actor())
+                .for_each(|session| {
+                    let _ = session.send(send.clone());
+                });
+        }
+
+        Ok(OkResponse::AddCommunity(community))
+    }
+
+    async fn leave_community(self, id: CommunityId) -> Result<OkResponse, Error> {
+        if !self.session.in_community(&id)? {
+            return Err(Error::InvalidCommunity);
+        }
+
+        let community = community::address_of(id)?;
+        let left = community
+            .send(Leave { user: self.user })
+            .await
+            .map_err(handle_disconnected("Community"))??;
+
+        if !left {
+            return Err(Error::InvalidCommunity);
+        }
+
+        let reason = RemoveCommunityReason::Left;
+        remove_community_from_user(self.user, id, reason, Some(self.device));
+        Ok(OkResponse::NoData)
+    }
+
+    async fn delete_community(self, id: CommunityId, name: String) -> Result<OkResponse, Error> {
+        // Limited tokens (e.g. for bots) should not be able to delete whole communities
+        if !self.perms.has_perms(TokenPermissionFlags::ALL) {
+            return Err(Error::AccessDenied);
+        }
+
+        if !self.session.in_community(&id)? {
+            return Err(Error::InvalidCommunity);
+        }
+
+        let community_perms = self.session.community_perms(&id)?;
+        if !community_perms.contains(CommunityPermissionFlags::ALL) {
+            return Err(Error::AccessDenied);
+        }
+
+        match COMMUNITIES.get(&id) {
+            Some(community) if community.name == name => {}
+            Some(_) => return Err(Error::IncorrectConfirmation),
+            None => return Err(Error::InvalidCommunity),
+        }
+
+        let deleted = community::address_of(id)?
+            .send(DeleteCommunity)
+            .await
+            .map_err(handle_disconnected("Community"))??
+            .ok_or(Error::InvalidCommunity)?;
+
+        let reason = RemoveCommunityReason::Deleted;
+        for member in deleted.members {
+            remove_community_from_user(member, id, reason.clone(), Some(self.device));
+        }
+
+        for attachment in deleted.attachments {
+            let path = attachments::path_of(&self.session.global.config, attachment);
+            if let Err(e) = tokio::fs::remove_file(path).await {
+                warn!(
+                    "[{}] Error deleting attachment {}: {:?}",
+                    self.context, attachment.0, e
+                );
+            }
+        }
+
+        Ok(OkResponse::NoData)
+    }
+
+    /// Checks that the user may kick or mute the given member of the community. Members with every
+    /// permission, such as the community's creator, cannot be moderated.
+    async fn check_can_moderate(
+        &mut self,
+        community: CommunityId,
+        user: UserId,
+    ) -> Result<(), Error> {
+        let own_perms = self.session.community_perms(&community)?;
+        if !own_perms.has_perms(CommunityPermissionFlags::MODERATE_MEMBERS) {
+            return Err(Error::AccessDenied);
+        }
+
+        let db = &self.session.global.database;
+        if user == self.user || db.get_community_membership(community, user).await?.is_none() {
+            return Err(Error::InvalidUser);
+        }
+
+        let their_perms = db.get_community_permissions(community, user).await?;
+        if their_perms.map_or(false, |perms| perms.contains(CommunityPermissionFlags::ALL)) {
+            return Err(Error::AccessDenied);
+        }
+
+        Ok(())
+    }
+
+    async fn kick_user(
+        mut self,
+        community: CommunityId,
+        user: UserId,
+    ) -> Result<OkResponse, Error> {
+        self.check_can_moderate(community, user).await?;
+
+        let left = community::address_of(community)?
+            .send(Leave { user })
+            .await
+            .map_err(handle_disconnected("Community"))??;
+
+        if !left {
+            return Err(Error::InvalidUser);
+        }
+
+        remove_community_from_user(user, community, RemoveCommunityReason::Kicked, None);
+        Ok(OkResponse::NoData)
+    }
+
+    async fn set_muted(
+        mut self,
+        community: CommunityId,
+        user: UserId,
+        muted: bool,
+        until: Option<DateTime<Utc>>,
+    ) -> Result<OkResponse, Error> {
+        self.check_can_moderate(community, user).await?;
+
+        let db = &self.session.global.database;
+        if !db.set_muted(community, user, muted, until).await? {
+            return Err(Error::InvalidUser);
+        }
+
+        Ok(OkResponse::NoData)
+    }
+
+    async fn get_community_members(
+        self,
+        community: CommunityId,
+        after: Option<UserId>,
+        limit: usize,
+    ) -> Result<OkResponse, Error> {
+        if !self.session.in_community(&community)? {
+            return Err(Error::InvalidCommunity);
+        }
+
+        let max = self.session.global.config.max_members_per_request as usize;
+        let limit = limit.min(max);
+
+        let db = &self.session.global.database;
+        let members = db
+            .get_members_paginated(community, after, limit)
+            .await?
+            .map_ok(|member| MemberInfo {
+                user: member.user,
+                display_name: member.display_name,
+                online: manager::is_online(member.user),
+            })
+            .try_collect()
+            .await?;
+
+        Ok(OkResponse::CommunityMembers(members))
+    }
+
+    async fn create_role(
+        self,
+        community: CommunityId,
+        name: String,
+        permissions: CommunityPermissionFlags,
+    ) -> Result<OkResponse, Error> {
+        let own_perms = self.session.community_perms(&community)?;
+        if !own_perms.has_perms(CommunityPermissionFlags::MANAGE_ROLES) {
+            return Err(Error::AccessDenied);
+        }
+
+        // Users cannot create roles more powerful than themselves
+        if !own_perms.has_perms(permissions) {
+            return Err(Error::AccessDenied);
+        }
+
+        let max = self.session.global.config.max_role_name_len as usize;
+        if name.is_empty() || name.len() > max {
+            return Err(Error::TooLong);
+        }
+
+        let db = &self.session.global.database;
+        match db.create_role(community, name, permissions).await? {
+            Ok(id) => Ok(OkResponse::NewRole(id)),
+            Err(InvalidCommunity) => Err(Error::InvalidCommunity),
+        }
+    }
+
+    async fn assign_role(
+        self,
+        community: CommunityId,
+        role: RoleId,
+        user: UserId,
+    ) -> Result<OkResponse, Error> {
+        let own_perms = self.session.community_perms(&community)?;
+        if !own_perms.has_perms(CommunityPermissionFlags::MANAGE_ROLES) {
+            return Err(Error::AccessDenied);
+        }
+
+        let db = &self.session.global.database;
+        let role_perms = db
+            .get_role_permissions(community, role)
+            .await?
+            .ok_or(Error::InvalidRole)?;
+
+        // Users cannot give out roles more powerful than themselves
+        if !own_perms.has_perms(role_perms) {
+            return Err(Error::AccessDenied);
+        }
+
+        if db.get_community_membership(community, user).await?.is_none() {
+            return Err(Error::InvalidUser);
+        }
+
+        match db.assign_role(community, role, user).await? {
+            Ok(()) => {}
+            Err(AssignRoleError::InvalidRole) => return Err(Error::InvalidRole),
+            Err(AssignRoleError::InvalidUser) => return Err(Error::InvalidUser),
+        }
+
+        // Refresh the cached permissions if the user is online
+        let perms = db.get_community_permissions(community, user).await?;
+        if let Ok(mut active_user) = manager::get_active_user_mut(user) {
+            if let Some(user_community) = active_user.communities.get_mut(&community) {
+                user_community.role_permissions = perms;
+            }
+        }
+
+        Ok(OkResponse::NoData)
+    }
+
+    async fn create_room(self, name: String, community: CommunityId) -> Result<OkResponse, Error> {
+        if !self.perms.has_perms(TokenPermissionFlags::CREATE_ROOMS) {
+            return Err(Error::AccessDenied);
+        }
+
+        if !self.session.in_community(&community)? {
+            return Err(Error::InvalidCommunity);
+        }
+
+        let community_perms = self.session.community_perms(&community)?;
+        if !community_perms.has_perms(CommunityPermissionFlags::CREATE_ROOMS) {
+            return Err(Error::AccessDenied);
+        }
+
+        let max = self.session.global.config.max_channel_name_len as usize;
+        if name.is_empty() || name.len() > max {
+            return Err(Error::TooLong);
+        }
+
+        let community_id = community;
+        let community = community::address_of(community)?;
+
+        let create = CreateRoom {
+            creator: self.device,
+            name: name.clone(),
         };
         let id = community
             .send(create)
@@ -333,6 +1306,11 @@ impl<'a> RequestHandler<'a> {
             id,
             name,
             unread: true,
+            topic: None,
+            slow_mode_secs: 0,
+            watch_level: WatchLevel::default(),
+            archived: false,
+            unread_count: 0,
         };
         community.rooms.insert(
             room.id,
@@ -348,33 +1326,203 @@ impl<'a> RequestHandler<'a> {
         })
     }
 
+    async fn edit_room(
+        self,
+        community: CommunityId,
+        room: RoomId,
+        topic: Option<String>,
+        slow_mode_secs: u32,
+    ) -> Result<OkResponse, Error> {
+        if !self.session.in_room(&community, &room)? {
+            return Err(Error::InvalidRoom);
+        }
+
+        let community_perms = self.session.community_perms(&community)?;
+        if !community_perms.has_perms(CommunityPermissionFlags::MANAGE_ROOMS) {
+            return Err(Error::AccessDenied);
+        }
+
+        let config = &self.session.global.config;
+        if let Some(topic) = &topic {
+            if topic.len() > config.max_room_topic_len as usize {
+                return Err(Error::TooLong);
+            }
+        }
+
+        if slow_mode_secs > config.max_slow_mode_secs {
+            return Err(Error::TooLong);
+        }
+
+        let edit = EditRoom {
+            editor: self.device,
+            room,
+            topic,
+            slow_mode_secs,
+        };
+        let edited = community::address_of(community)?
+            .send(edit)
+            .await
+            .map_err(handle_disconnected("Community"))??;
+
+        if edited {
+            Ok(OkResponse::NoData)
+        } else {
+            Err(Error::InvalidRoom)
+        }
+    }
+
+    async fn set_room_archived(
+        self,
+        community: CommunityId,
+        room: RoomId,
+        archived: bool,
+    ) -> Result<OkResponse, Error> {
+        if !self.session.in_room(&community, &room)? {
+            return Err(Error::InvalidRoom);
+        }
+
+        let community_perms = self.session.community_perms(&community)?;
+        if !community_perms.has_perms(CommunityPermissionFlags::MANAGE_ROOMS) {
+            return Err(Error::AccessDenied);
+        }
+
+        let set = SetRoomArchived {
+            editor: self.device,
+            room,
+            archived,
+        };
+        let edited = community::address_of(community)?
+            .send(set)
+            .await
+            .map_err(handle_disconnected("Community"))??;
+
+        if edited {
+            Ok(OkResponse::NoData)
+        } else {
+            Err(Error::InvalidRoom)
+        }
+    }
+
+    async fn delete_room(self, community: CommunityId, room: RoomId) -> Result<OkResponse, Error> {
+        // Deleting a room cannot be undone, so limited tokens (e.g. for bots) may not do it
+        if !self.perms.has_perms(TokenPermissionFlags::ALL) {
+            return Err(Error::AccessDenied);
+        }
+
+        if !self.session.in_room(&community, &room)? {
+            return Err(Error::InvalidRoom);
+        }
+
+        let community_perms = self.session.community_perms(&community)?;
+        if !community_perms.has_perms(CommunityPermissionFlags::DELETE_ROOMS) {
+            return Err(Error::AccessDenied);
+        }
+
+        let delete = DeleteRoom {
+            deleter: self.device,
+            room,
+        };
+        let attachments = community::address_of(community)?
+            .send(delete)
+            .await
+            .map_err(handle_disconnected("Community"))??
+            .ok_or(Error::InvalidRoom)?;
+
+        attachments::remove_files(&self.session.global.config, attachments).await;
+
+        // The community actor notifies every other session, which also deselects the room there
+        let mut user = manager::get_active_user_mut(self.user)?;
+        if let Some(user_community) = user.communities.get_mut(&community) {
+            user_community.rooms.remove(&room);
+        }
+
+        // The device may have been revoked in the meantime
+        if let Some(session) = user.sessions.get_mut(&self.device) {
+            if session.as_active_looking_at() == Some(Some((community, room))) {
+                session.set_looking_at(None);
+            }
+        }
+
+        Ok(OkResponse::NoData)
+    }
+
     async fn create_invite(
         self,
         id: CommunityId,
         expiration_date: Option<DateTime<Utc>>,
+        max_uses: Option<u32>,
+        vanity: Option<String>,
     ) -> Result<OkResponse, Error> {
         if !self.perms.has_perms(TokenPermissionFlags::CREATE_INVITES) {
             return Err(Error::AccessDenied);
         }
 
+        if let Some(vanity) = &vanity {
+            if !InviteCode::is_valid_vanity(vanity) {
+                return Err(Error::InvalidInviteCode);
+            }
+        }
+
+        // A code with no uses could never be used to join
+        if max_uses == Some(0) {
+            return Err(Error::InvalidInviteCode);
+        }
+
         if !self.session.in_community(&id)? {
             return Err(Error::InvalidCommunity);
         }
 
+        let community_perms = self.session.community_perms(&id)?;
+        if !community_perms.has_perms(CommunityPermissionFlags::CREATE_INVITES) {
+            return Err(Error::AccessDenied);
+        }
+
         if COMMUNITIES.contains_key(&id) {
             let db = &self.session.global.database;
             let max = self.session.global.config.max_invite_codes_per_community as i64;
-            let res = db.create_invite_code(id, expiration_date, max).await?;
+            let res = db
+                .create_invite_code(id, expiration_date, max_uses, vanity, max)
+                .await?;
 
             match res {
                 Ok(code) => Ok(OkResponse::NewInvite(code)),
-                Err(_) => Err(Error::TooManyInviteCodes),
+                Err(CreateInviteCodeError::TooManyInviteCodes) => Err(Error::TooManyInviteCodes),
+                Err(CreateInviteCodeError::VanityTaken) => Err(Error::InviteCodeTaken),
             }
         } else {
             Err(Error::InvalidCommunity)
         }
     }
 
+    async fn get_invite_codes(self, id: CommunityId) -> Result<OkResponse, Error> {
+        if !self.session.in_community(&id)? {
+            return Err(Error::InvalidCommunity);
+        }
+
+        let community_perms = self.session.community_perms(&id)?;
+        if !community_perms.has_perms(CommunityPermissionFlags::CREATE_INVITES) {
+            return Err(Error::AccessDenied);
+        }
+
+        let db = &self.session.global.database;
+        let codes = db.get_invite_codes(id).await?;
+        Ok(OkResponse::InviteCodes(codes))
+    }
+
+    async fn get_community_stats(self, id: CommunityId) -> Result<OkResponse, Error> {
+        if !self.session.in_community(&id)? {
+            return Err(Error::InvalidCommunity);
+        }
+
+        let community_perms = self.session.community_perms(&id)?;
+        if !community_perms.has_perms(CommunityPermissionFlags::MANAGE_COMMUNITY) {
+            return Err(Error::AccessDenied);
+        }
+
+        let stats = community::stats(&self.session.global.database, id).await?;
+        Ok(OkResponse::CommunityStats(stats))
+    }
+
     async fn get_room_update(
         self,
         community: CommunityId,
@@ -387,40 +1535,60 @@ impl<'a> RequestHandler<'a> {
         }
 
         let db = &self.session.global.database;
-
         let newest_message = db.get_newest_message(community, room).await?;
         let last_read = db.get_last_read(self.user, room).await?;
+        let head = RoomHead {
+            newest_message,
+            last_read,
+        };
 
-        let selector = match (last_received, newest_message) {
-            (Some(last_received), _) => {
-                Some(MessageSelector::After(Bound::Exclusive(last_received)))
-            }
-            (_, Some(newest_message)) => {
-                Some(MessageSelector::Before(Bound::Inclusive(newest_message)))
-            }
-            _ => None,
+        let update = RoomUpdateQuery {
+            user: self.user,
+            community,
+            room,
+            last_received,
+            message_count,
         };
+        let update = room_update(db, update, head).await?;
+
+        Ok(OkResponse::RoomUpdate(update))
+    }
+
+    async fn get_room_updates(
+        self,
+        rooms: Vec<RoomUpdateRequest>,
+        message_count: u64,
+    ) -> Result<OkResponse, Error> {
+        let max = self.session.global.config.max_room_updates_per_request as usize;
+        if rooms.len() > max {
+            return Err(Error::TooManyRooms);
+        }
 
-        let new_messages = match selector {
-            Some(selector) => {
-                let messages = db
-                    .get_messages(community, room, selector, message_count as usize)
-                    .await?
-                    .map_err(|_| Error::InvalidMessageSelector)?;
-                messages.map_messages().try_collect().await?
+        for request in &rooms {
+            if !self.session.in_room(&request.community, &request.room)? {
+                return Err(Error::InvalidRoom);
             }
-            None => Vec::new(),
-        };
+        }
 
-        let continuous = new_messages.len() < (message_count as usize);
+        let db = &self.session.global.database;
+        let ids: Vec<RoomId> = rooms.iter().map(|request| request.room).collect();
+        let heads = db.get_room_heads(self.user, &ids).await?;
+
+        let mut updates = Vec::with_capacity(rooms.len());
+        for request in rooms {
+            let head = heads.get(&request.room).copied().unwrap_or_default();
+            let update = RoomUpdateQuery {
+                user: self.user,
+                community: request.community,
+                room: request.room,
+                last_received: request.last_received,
+                message_count,
+            };
 
-        let new_messages = MessageHistory::from_newest_to_oldest(new_messages);
+            updates.push(room_update(db, update, head).await?);
+        }
 
-        Ok(OkResponse::RoomUpdate(RoomUpdate {
-            last_read,
-            continuous,
-            new_messages,
-        }))
+        Ok(OkResponse::RoomUpdates(updates))
     }
 
     async fn select_room(self, community: CommunityId, room: RoomId) -> Result<OkResponse, Error> {
@@ -428,11 +1596,17 @@ impl<'a> RequestHandler<'a> {
             return Err(Error::InvalidRoom);
         }
 
+        let db = &self.session.global.database;
+        db.set_selected_room(self.device, Some((community, room))).await?;
+
         self.set_looking_at(Some((community, room))).await;
         Ok(OkResponse::NoData)
     }
 
     async fn deselect_room(self) -> Result<OkResponse, Error> {
+        let db = &self.session.global.database;
+        db.set_selected_room(self.device, None).await?;
+
         self.set_looking_at(None).await;
         Ok(OkResponse::NoData)
     }
@@ -455,24 +1629,141 @@ impl<'a> RequestHandler<'a> {
         }
 
         let db = &self.session.global.database;
-        let stream = db
-            .get_messages(community, room, selector, count as usize)
+        let history = db
+            .get_messages(self.user, community, room, selector, count as usize)
             .await?
             .map_err(|_| Error::InvalidMessageSelector)?;
 
+        Ok(OkResponse::MessageHistory(history))
+    }
+
+    async fn pin_message(
+        self,
+        community: CommunityId,
+        room: RoomId,
+        message: MessageId,
+    ) -> Result<OkResponse, Error> {
+        self.check_can_pin(community, room)?;
+        check_not_archived(community, room).await?;
+
+        let db = &self.session.global.database;
+        check_live_message(db, community, room, message).await?;
+
+        let max = self.session.global.config.max_pins_per_room as i64;
+        let pinned = db
+            .pin_message(room, message, self.user, max)
+            .await?
+            .map_err(|TooManyPins| Error::TooManyPins)?;
+
+        if pinned {
+            self.broadcast_pins(community, room)?;
+        }
+
+        Ok(OkResponse::NoData)
+    }
+
+    async fn unpin_message(
+        self,
+        community: CommunityId,
+        room: RoomId,
+        message: MessageId,
+    ) -> Result<OkResponse, Error> {
+        self.check_can_pin(community, room)?;
+        check_not_archived(community, room).await?;
+
+        // Deleting a message already unpins it
+        let db = &self.session.global.database;
+        check_live_message(db, community, room, message).await?;
+
+        if db.unpin_message(message).await? {
+            self.broadcast_pins(community, room)?;
+        }
+
+        Ok(OkResponse::NoData)
+    }
+
+    fn check_can_pin(&self, community: CommunityId, room: RoomId) -> Result<(), Error> {
+        if !self.session.in_room(&community, &room)? {
+            return Err(Error::InvalidRoom);
+        }
+
+        let community_perms = self.session.community_perms(&community)?;
+        if !community_perms.has_perms(CommunityPermissionFlags::PIN_MESSAGES) {
+            return Err(Error::AccessDenied);
+        }
+
+        Ok(())
+    }
+
+    fn broadcast_pins(&self, community: CommunityId, room: RoomId) -> Result<(), Error> {
+        community::address_of(community)?
+            .do_send(BroadcastPinsUpdated { room })
+            .map_err(handle_disconnected("Community"))?;
+
+        Ok(())
+    }
+
+    async fn get_pinned_messages(
+        self,
+        community: CommunityId,
+        room: RoomId,
+    ) -> Result<OkResponse, Error> {
+        if !self.session.in_room(&community, &room)? {
+            return Err(Error::InvalidRoom);
+        }
+
+        let db = &self.session.global.database;
+        let stream = db.get_pinned_messages(room, self.user).await?;
         let messages = stream.map_messages().try_collect().await?;
-        Ok(OkResponse::MessageHistory(
-            MessageHistory::from_newest_to_oldest(messages),
-        ))
+
+        Ok(OkResponse::PinnedMessages(messages))
     }
 
-    async fn set_as_read(self, community: CommunityId, room: RoomId) -> Result<OkResponse, Error> {
+    async fn resync(self) -> Result<OkResponse, Error> {
+        let db = &self.session.global.database;
+        let rooms = db.get_room_sync_states(self.user).await?;
+        Ok(OkResponse::Resync(rooms))
+    }
+
+    async fn set_watch_level(
+        self,
+        community: CommunityId,
+        room: RoomId,
+        level: WatchLevel,
+    ) -> Result<OkResponse, Error> {
         let mut active_user = manager::get_active_user_mut(self.user).unwrap();
         let community = active_user
             .communities
             .get_mut(&community)
             .ok_or(Error::InvalidCommunity)?;
         let user_room = community.rooms.get_mut(&room).ok_or(Error::InvalidRoom)?;
+        user_room.watch_level = level;
+
+        drop(active_user); // Drop lock
+
+        let db = &self.session.global.database;
+        let res = db.set_watch_level(room, self.user, level).await?;
+
+        match res {
+            Ok(_) => Ok(OkResponse::NoData),
+            Err(SetUserRoomStateError::InvalidRoom) => Err(Error::InvalidRoom),
+            Err(SetUserRoomStateError::InvalidUser) => {
+                self.ctx.stop(); // The user did not exist at the time of request
+                Err(Error::LoggedOut)
+            }
+        }
+    }
+
+    async fn set_as_read(self, community: CommunityId, room: RoomId) -> Result<OkResponse, Error> {
+        let mut active_user = manager::get_active_user_mut(self.user).unwrap();
+        let user_community = active_user
+            .communities
+            .get_mut(&community)
+            .ok_or(Error::InvalidCommunity)?;
+        let user_room = user_community
+            .rooms
+            .get_mut(&room)
+            .ok_or(Error::InvalidRoom)?;
         user_room.unread = false;
 
         drop(active_user); // Drop lock
@@ -481,7 +1772,26 @@ impl<'a> RequestHandler<'a> {
         let res = db.set_room_read(room, self.user).await?;
 
         match res {
-            Ok(_) => Ok(OkResponse::NoData),
+            Ok(_) => {
+                let last_read = db.get_last_read(self.user, room).await?;
+                let send = ServerMessage::Event(ServerEvent::RoomReadStateChanged {
+                    community,
+                    room,
+                    last_read,
+                });
+
+                if let Ok(user) = manager::get_active_user(self.user) {
+                    user.sessions
+                        .iter()
+                        .filter(|(id, _)| **id != self.device)
+                        .filter_map(|(_, session)| session.as_active_actor())
+                        .for_each(|session| {
+                            let _ = session.send(send.clone());
+                        });
+                }
+
+                Ok(OkResponse::NoData)
+            }
             Err(SetUserRoomStateError::InvalidRoom) => Err(Error::InvalidRoom),
             Err(SetUserRoomStateError::InvalidUser) => {
                 self.ctx.stop(); // The user did not exist at the time of request
@@ -490,44 +1800,123 @@ impl<'a> RequestHandler<'a> {
         }
     }
 
-    async fn change_community_name(
+    async fn set_many_as_read(
         self,
-        new: String,
-        id: CommunityId,
+        rooms: Vec<(CommunityId, RoomId)>,
     ) -> Result<OkResponse, Error> {
-        if !self.session.in_community(&id)? {
-            return Err(Error::InvalidCommunity);
+        let mut active_user = manager::get_active_user_mut(self.user)?;
+
+        // Rooms which the user is not in are skipped rather than failing the whole batch
+        let mut valid = HashMap::new();
+        for (community, room) in rooms {
+            let user_room = active_user
+                .communities
+                .get_mut(&community)
+                .and_then(|community| community.rooms.get_mut(&room));
+
+            if let Some(user_room) = user_room {
+                user_room.unread = false;
+                valid.insert(room, community);
+            }
         }
 
-        if let Some(mut community) = COMMUNITIES.get_mut(&id) {
-            community.name = new.clone();
-            drop(community); // Drop lock
-            let db = &self.session.global.database;
-            db.change_community_name(id, new).await?;
-            Ok(OkResponse::NoData)
-        } else {
-            Err(Error::InvalidCommunity)
+        drop(active_user); // Drop lock
+
+        if valid.is_empty() {
+            return Ok(OkResponse::NoData);
+        }
+
+        let db = &self.session.global.database;
+        let room_ids: Vec<RoomId> = valid.keys().copied().collect();
+        let read = db.set_rooms_read(&room_ids, self.user).await?;
+
+        let states = read
+            .into_iter()
+            .map(|(room, last_read)| RoomReadState {
+                community: valid[&room],
+                room,
+                last_read,
+            })
+            .collect();
+        let send = ServerMessage::Event(ServerEvent::RoomsReadStateChanged(states));
+
+        if let Ok(user) = manager::get_active_user(self.user) {
+            user.sessions
+                .iter()
+                .filter(|(id, _)| **id != self.device)
+                .filter_map(|(_, session)| session.as_active_actor())
+                .for_each(|session| {
+                    let _ = session.send(send.clone());
+                });
         }
+
+        Ok(OkResponse::NoData)
     }
 
-    async fn change_community_description(
+    async fn edit_community(
         self,
-        new: String,
         id: CommunityId,
+        edit: CommunityEdit,
     ) -> Result<OkResponse, Error> {
         if !self.session.in_community(&id)? {
             return Err(Error::InvalidCommunity);
         }
 
+        let community_perms = self.session.community_perms(&id)?;
+        if !community_perms.has_perms(CommunityPermissionFlags::MANAGE_COMMUNITY) {
+            return Err(Error::AccessDenied);
+        }
+
+        let config = &self.session.global.config;
+        if let Some(name) = &edit.name {
+            if name.is_empty() || name.len() > config.max_community_name_len as usize {
+                return Err(Error::TooLong);
+            }
+        }
+
+        if let Some(description) = &edit.description {
+            if description.len() > config.max_community_description_len as usize {
+                return Err(Error::TooLong);
+            }
+        }
+
+        if edit.retention_days.map_or(false, |days| days > i32::MAX as u32) {
+            return Err(Error::TooLong);
+        }
+
+        let global = &self.session.global;
+        let res = global
+            .database
+            .edit_community(id, self.user, edit)
+            .await?;
+        let (record, replaced_icon) = match res {
+            Ok(edited) => edited,
+            Err(EditCommunityError::NonexistentCommunity) => return Err(Error::InvalidCommunity),
+            Err(EditCommunityError::InvalidIcon) => return Err(Error::InvalidAttachment),
+        };
+
+        if let Some(replaced_icon) = replaced_icon {
+            attachments::delete(global, replaced_icon).await?;
+        }
+
+        let update = BroadcastCommunityUpdate {
+            name: record.name.clone(),
+            description: Community::desc_or_default(&record.description),
+            icon: record.icon,
+            sign_messages: record.sign_messages,
+        };
+
         if let Some(mut community) = COMMUNITIES.get_mut(&id) {
-            community.description = Some(new.clone());
-            drop(community); // Drop lock
-            let db = &self.session.global.database;
-            db.change_community_description(id, new).await?;
-            Ok(OkResponse::NoData)
-        } else {
-            Err(Error::InvalidCommunity)
+            community.name = record.name;
+            community.description = record.description;
+            community.icon = record.icon;
         }
+
+        community::address_of(id)?
+            .do_send(update)
+            .map_err(handle_disconnected("Community"))?;
+
+        Ok(OkResponse::NoData)
     }
 
     async fn report_user(
@@ -563,6 +1952,262 @@ impl<'a> RequestHandler<'a> {
             Ok(_) => Ok(OkResponse::NoData),
             Err(ReportUserError::InvalidReporter) => Err(Error::LoggedOut),
             Err(ReportUserError::InvalidMessage) => Err(Error::InvalidMessage),
+            Err(ReportUserError::AlreadyReported) => Err(Error::AlreadyReported),
+        }
+    }
+
+    async fn start_typing(self, community: CommunityId, room: RoomId) -> Result<OkResponse, Error> {
+        if !self.perms.has_perms(TokenPermissionFlags::SEND_MESSAGES) {
+            return Err(Error::AccessDenied);
+        }
+
+        if !self.session.in_room(&community, &room)? {
+            return Err(Error::InvalidRoom);
+        }
+
+        // Clients may send this on every keystroke, so ignore it if it is too soon after the last
+        let now = Instant::now();
+        if let Some(last) = self.session.last_typing {
+            if now.duration_since(last) < TYPING_THROTTLE {
+                return Ok(OkResponse::NoData);
+            }
+        }
+        self.session.last_typing = Some(now);
+
+        let start = StartTyping {
+            user: self.user,
+            room,
+        };
+        community::address_of(community)?
+            .do_send(start)
+            .map_err(handle_disconnected("Community"))?;
+
+        Ok(OkResponse::NoData)
+    }
+
+    async fn stop_typing(self, community: CommunityId, room: RoomId) -> Result<OkResponse, Error> {
+        if !self.session.in_room(&community, &room)? {
+            return Err(Error::InvalidRoom);
+        }
+
+        self.session.last_typing = None;
+
+        let stop = StopTyping {
+            user: self.user,
+            room,
+        };
+        community::address_of(community)?
+            .do_send(stop)
+            .map_err(handle_disconnected("Community"))?;
+
+        Ok(OkResponse::NoData)
+    }
+
+    async fn add_reaction(
+        self,
+        community: CommunityId,
+        room: RoomId,
+        message: MessageId,
+        emoji: String,
+    ) -> Result<OkResponse, Error> {
+        if !self.perms.has_perms(TokenPermissionFlags::SEND_MESSAGES) {
+            return Err(Error::AccessDenied);
+        }
+
+        if !valid_reaction(&emoji) {
+            return Err(Error::InvalidReaction);
+        }
+
+        if !self.session.in_room(&community, &room)? {
+            return Err(Error::InvalidRoom);
+        }
+
+        check_not_archived(community, room).await?;
+
+        let db = &self.session.global.database;
+        check_live_message(db, community, room, message).await?;
+
+        match db.add_reaction(message, self.user, &emoji).await? {
+            Ok(()) => {}
+            Err(AddReactionError::InvalidMessage) => return Err(Error::InvalidMessage),
+            Err(AddReactionError::InvalidUser) => {
+                self.ctx.stop(); // The user did not exist at the time of request
+                return Err(Error::LoggedOut);
+            }
+        }
+
+        self.broadcast_reactions(community, room, message).await
+    }
+
+    async fn remove_reaction(
+        self,
+        community: CommunityId,
+        room: RoomId,
+        message: MessageId,
+        emoji: String,
+    ) -> Result<OkResponse, Error> {
+        if !self.perms.has_perms(TokenPermissionFlags::SEND_MESSAGES) {
+            return Err(Error::AccessDenied);
+        }
+
+        if !self.session.in_room(&community, &room)? {
+            return Err(Error::InvalidRoom);
+        }
+
+        check_not_archived(community, room).await?;
+
+        let db = &self.session.global.database;
+        check_live_message(db, community, room, message).await?;
+
+        db.remove_reaction(message, self.user, &emoji).await?;
+
+        self.broadcast_reactions(community, room, message).await
+    }
+
+    async fn broadcast_reactions(
+        self,
+        community: CommunityId,
+        room: RoomId,
+        message: MessageId,
+    ) -> Result<OkResponse, Error> {
+        let db = &self.session.global.database;
+        let reactions = db.get_reactions(message).await?;
+
+        let update = UpdateReactions {
+            room,
+            message,
+            reactions,
+        };
+        community::address_of(community)?
+            .do_send(update)
+            .map_err(handle_disconnected("Community"))?;
+
+        Ok(OkResponse::NoData)
+    }
+}
+
+/// Forgets the community for all of the user's sessions if they are online, telling every session
+/// but `except` why it was removed
+fn remove_community_from_user(
+    user: UserId,
+    id: CommunityId,
+    reason: RemoveCommunityReason,
+    except: Option<DeviceId>,
+) {
+    let mut user = match manager::get_active_user_mut(user) {
+        Ok(user) => user,
+        Err(_) => return,
+    };
+
+    user.communities.remove(&id);
+
+    // Deselect the community's rooms in every session so that they do not keep looking at a
+    // room they can no longer see
+    for session in user.sessions.values_mut() {
+        if let Some(Some((looking_at, _))) = session.as_active_looking_at() {
+            if looking_at == id {
+                session.set_looking_at(None);
+            }
+        }
+    }
+
+    let send = ServerMessage::Event(ServerEvent::RemoveCommunity { id, reason });
+
+    user.sessions
+        .iter()
+        .filter(|(device, _)| Some(**device) != except)
+        .filter_map(|(_, session)| session.as_active_actor())
+        .for_each(|session| {
+            let _ = session.send(send.clone());
+        });
+}
+
+/// A reaction must be a short string of non-alphabetic, non-whitespace characters (i.e an emoji).
+fn valid_reaction(emoji: &str) -> bool {
+    let len_ok = !emoji.is_empty() && emoji.len() <= vertex::MAX_REACTION_EMOJI_LEN;
+    len_ok
+        && !emoji
+            .chars()
+            .any(|c| c.is_alphabetic() || c.is_whitespace() || c.is_control())
+}
+
+struct RoomUpdateQuery {
+    user: UserId,
+    community: CommunityId,
+    room: RoomId,
+    last_received: Option<MessageId>,
+    message_count: u64,
+}
+
+/// Gets the messages in a room since the last one the client received, or the newest ones if it
+/// has none. If more have been sent since then than fit in a page, the newest are given instead and
+/// the update is not continuous.
+async fn room_update(
+    db: &Database,
+    query: RoomUpdateQuery,
+    head: RoomHead,
+) -> Result<RoomUpdate, Error> {
+    let count = query.message_count as usize;
+    let get_page = |selector| {
+        db.get_messages(query.user, query.community, query.room, selector, count)
+    };
+
+    let mut new_messages = MessageHistory::default();
+    let mut continuous = true;
+
+    if let Some(last_received) = query.last_received {
+        let selector = MessageSelector::After(Bound::Exclusive(MessagePosition::Id(last_received)));
+        new_messages = get_page(selector)
+            .await?
+            .map_err(|_| Error::InvalidMessageSelector)?;
+        continuous = !new_messages.cursor.has_more;
+    }
+
+    if query.last_received.is_none() || !continuous {
+        if let Some(newest_message) = head.newest_message {
+            let bound = Bound::Inclusive(MessagePosition::Id(newest_message));
+            let selector = MessageSelector::Before(bound);
+            new_messages = get_page(selector)
+                .await?
+                .map_err(|_| Error::InvalidMessageSelector)?;
+            continuous = continuous && !new_messages.cursor.has_more;
+        }
+    }
+
+    Ok(RoomUpdate {
+        last_read: head.last_read,
+        continuous,
+        new_messages,
+    })
+}
+
+/// Checks that the message exists, is not deleted, and is in the given room
+async fn check_live_message(
+    db: &Database,
+    community: CommunityId,
+    room: RoomId,
+    message: MessageId,
+) -> Result<(), Error> {
+    match db.get_message_by_id(message).await? {
+        Some(record)
+            if record.community == community && record.room == room && record.content.is_some() =>
+        {
+            Ok(())
         }
+        _ => Err(Error::InvalidMessage),
+    }
+}
+
+/// Checks that the room is not archived, since archived rooms are read-only
+async fn check_not_archived(community: CommunityId, room: RoomId) -> Result<(), Error> {
+    let archived = community::address_of(community)?
+        .send(IsRoomArchived(room))
+        .await
+        .map_err(handle_disconnected("Community"))?;
+
+    if archived {
+        Err(Error::RoomArchived)
+    } else {
+        Ok(())
     }
 }
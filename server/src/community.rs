@@ -1,19 +1,49 @@
-use crate::client::session::{AddRoom, ForwardMessage};
+use crate::client::session::{AddRoom, ForwardMessage, RemoveRoom};
 use crate::client::{self, ActiveSession, Session};
+use crate::config::Config;
 use crate::database::{AddToCommunityError, CommunityRecord, Database, DbResult};
-use crate::{handle_disconnected, IdentifiedMessage};
+use crate::handle_disconnected;
+use crate::signing::SigningKey;
 use chrono::Utc;
 use dashmap::mapref::one::{Ref, RefMut};
 use dashmap::DashMap;
 use futures::TryStreamExt;
+use governor::clock::DefaultClock;
+use governor::state::keyed::DashMapStateStore;
+use governor::{Quota, RateLimiter};
 use lazy_static::lazy_static;
-use std::collections::{BTreeSet, HashMap};
+use log::info;
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::num::NonZeroU32;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use uuid::Uuid;
 use vertex::prelude::*;
 use xtra::prelude::*;
 use xtra::Disconnected;
 use async_trait::async_trait;
 
+/// How often expired typing indicators are cleared
+const TYPING_SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+/// How long a message nonce is remembered for, so that a resent message is not sent twice
+const NONCE_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+/// Most message nonces remembered by a community at once. The oldest are forgotten first.
+const MAX_REMEMBERED_NONCES: usize = 4096;
+/// How long a community's stats are reused for before they are counted again
+const STATS_CACHE_TIME: Duration = Duration::from_secs(60);
+/// How often the per-room message limits of users who have not sent anything lately are forgotten
+const ROOM_LIMITER_PRUNE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+type RoomRateLimiter =
+    RateLimiter<(UserId, RoomId), DashMapStateStore<(UserId, RoomId)>, DefaultClock>;
+
+/// How many messages a user may send in one room in a burst, and how quickly that refills
+pub fn room_message_quota(config: &Config) -> Quota {
+    let per_min = NonZeroU32::new(config.room_message_per_min).unwrap();
+    let burst = NonZeroU32::new(config.room_message_burst).unwrap();
+    Quota::per_minute(per_min).allow_burst(burst)
+}
+
 lazy_static! {
     pub static ref COMMUNITIES: DashMap<CommunityId, Community> = DashMap::new();
 }
@@ -30,12 +60,33 @@ pub fn address_of(id: CommunityId) -> Result<Address<CommunityActor>, Error> {
     get(id).map(|c| c.actor.clone())
 }
 
+/// Gets the stats of a community. Counting them is expensive, so they are only counted again once
+/// they are older than `STATS_CACHE_TIME`.
+pub async fn stats(database: &Database, id: CommunityId) -> Result<CommunityStats, Error> {
+    let cached = get(id)?.stats;
+    if let Some((counted_at, stats)) = cached {
+        if counted_at.elapsed() < STATS_CACHE_TIME {
+            return Ok(stats);
+        }
+    }
+
+    let stats = database.get_community_stats(id).await?;
+    if let Ok(mut community) = get_mut(id) {
+        community.stats = Some((Instant::now(), stats));
+    }
+
+    Ok(stats)
+}
+
 /// Community info that is just read/updated very quickly (no logic like in the actor). Used to avoid
 /// calls back and forth to the actor for simple things like getting the community name.
 pub struct Community {
     pub actor: Address<CommunityActor>,
     pub name: String,
     pub description: Option<String>,
+    pub icon: Option<AttachmentId>,
+    /// The community's stats and when they were counted, if they have been asked for
+    pub stats: Option<(Instant, CommunityStats)>,
 }
 
 impl Community {
@@ -49,6 +100,34 @@ impl Community {
     }
 }
 
+/// Marker trait for structs that are actor messages when sent along with who sent them
+pub trait VertexActorMessage: Send + 'static {
+    type Result: Send;
+}
+
+impl VertexActorMessage for Edit {
+    type Result = ();
+}
+
+/// Returns the attachments of the deleted message, whose files should be removed
+impl VertexActorMessage for Delete {
+    type Result = Vec<AttachmentId>;
+}
+
+pub struct IdentifiedMessage<T: VertexActorMessage> {
+    pub user: UserId,
+    pub device: DeviceId,
+    pub message: T,
+}
+
+impl<T> xtra::Message for IdentifiedMessage<T>
+where
+    T: VertexActorMessage,
+    T::Result: 'static,
+{
+    type Result = Result<T::Result, Error>;
+}
+
 pub struct Connect {
     pub user: UserId,
     pub device: DeviceId,
@@ -73,6 +152,15 @@ impl xtra::Message for Join {
     type Result = DbResult<Result<CommunityStructure, AddToCommunityError>>;
 }
 
+/// Remove a user from the community. Returns whether they were a member of it.
+pub struct Leave {
+    pub user: UserId,
+}
+
+impl xtra::Message for Leave {
+    type Result = DbResult<bool>;
+}
+
 pub struct CreateRoom {
     pub creator: DeviceId,
     pub name: String,
@@ -82,6 +170,180 @@ impl xtra::Message for CreateRoom {
     type Result = DbResult<RoomId>;
 }
 
+/// Set a room's topic and slow mode. Returns whether the room existed.
+pub struct EditRoom {
+    pub editor: DeviceId,
+    pub room: RoomId,
+    pub topic: Option<String>,
+    pub slow_mode_secs: u32,
+}
+
+impl xtra::Message for EditRoom {
+    type Result = DbResult<bool>;
+}
+
+/// Archive or unarchive a room. Returns whether the room existed.
+pub struct SetRoomArchived {
+    pub editor: DeviceId,
+    pub room: RoomId,
+    pub archived: bool,
+}
+
+impl xtra::Message for SetRoomArchived {
+    type Result = DbResult<bool>;
+}
+
+/// A message sent by a user, along with the other members of the community that it mentions
+pub struct SentMessage {
+    pub message: ClientSentMessage,
+    pub mentioned: Vec<UserId>,
+}
+
+impl VertexActorMessage for SentMessage {
+    type Result = Result<MessageConfirmation, RoomRateLimited>;
+}
+
+/// Send a system message to a room on behalf of an administrator. Returns the confirmation of the
+/// message, or `None` if the room does not exist.
+pub struct SendSystemMessage {
+    pub author: UserId,
+    pub room: RoomId,
+    pub content: String,
+}
+
+impl xtra::Message for SendSystemMessage {
+    type Result = DbResult<Option<MessageConfirmation>>;
+}
+
+/// Returned instead of sending a message when the author is still waiting out a room's slow mode,
+/// or has sent too many messages to the room in a short time
+#[derive(Debug, Copy, Clone)]
+pub struct RoomRateLimited {
+    pub ready_in: Duration,
+}
+
+/// Delete a room and its history. Returns whether the room existed.
+pub struct DeleteRoom {
+    pub deleter: DeviceId,
+    pub room: RoomId,
+}
+
+/// Returns the attachments of the room's messages, whose files should be removed, or `None` if the
+/// room did not exist
+impl xtra::Message for DeleteRoom {
+    type Result = DbResult<Option<Vec<AttachmentId>>>;
+}
+
+/// Delete the community and stop its actor. Returns `None` if the community did not exist.
+pub struct DeleteCommunity;
+
+impl xtra::Message for DeleteCommunity {
+    type Result = DbResult<Option<DeletedCommunity>>;
+}
+
+/// What is left to clean up after a community is deleted
+pub struct DeletedCommunity {
+    /// Members of the community, who need to be told that it is gone if they are online
+    pub members: Vec<UserId>,
+    /// Attachments of the community's messages, whose files should be removed
+    pub attachments: Vec<AttachmentId>,
+}
+
+/// Broadcast the new reaction counts of a message to every online member
+pub struct UpdateReactions {
+    pub room: RoomId,
+    pub message: MessageId,
+    pub reactions: Vec<ReactionSummary>,
+}
+
+impl xtra::Message for UpdateReactions {
+    type Result = ();
+}
+
+/// Tell every online member that the pinned messages of a room changed
+pub struct BroadcastPinsUpdated {
+    pub room: RoomId,
+}
+
+impl xtra::Message for BroadcastPinsUpdated {
+    type Result = ();
+}
+
+/// Tell every online member about the community's new name, description and icon. Whether
+/// messages are signed is only kept by the actor.
+pub struct BroadcastCommunityUpdate {
+    pub name: String,
+    pub description: String,
+    pub icon: Option<AttachmentId>,
+    pub sign_messages: bool,
+}
+
+impl xtra::Message for BroadcastCommunityUpdate {
+    type Result = ();
+}
+
+/// Sent while the server is shutting down. Messages are handled in order, so the reply only comes
+/// once everything sent to the actor before it, including any database writes, has been handled.
+pub struct Drain;
+
+impl xtra::Message for Drain {
+    type Result = ();
+}
+
+/// Tell every online member except the user about their new presence status
+pub struct BroadcastPresence {
+    pub user: UserId,
+    pub status: PresenceStatus,
+}
+
+impl xtra::Message for BroadcastPresence {
+    type Result = ();
+}
+
+pub struct StartTyping {
+    pub user: UserId,
+    pub room: RoomId,
+}
+
+impl xtra::Message for StartTyping {
+    type Result = ();
+}
+
+pub struct StopTyping {
+    pub user: UserId,
+    pub room: RoomId,
+}
+
+impl xtra::Message for StopTyping {
+    type Result = ();
+}
+
+struct SweepTyping;
+
+impl xtra::Message for SweepTyping {
+    type Result = ();
+}
+
+struct PruneRoomLimiter;
+
+impl xtra::Message for PruneRoomLimiter {
+    type Result = ();
+}
+
+/// Whether the room is archived, and so read-only. Rooms which do not exist are not archived.
+pub struct IsRoomArchived(pub RoomId);
+
+impl xtra::Message for IsRoomArchived {
+    type Result = bool;
+}
+
+/// Keeps only the given users who are members of the community
+pub struct FilterMembers(pub Vec<UserId>);
+
+impl xtra::Message for FilterMembers {
+    type Result = Vec<UserId>;
+}
+
 pub struct GetRoomInfo;
 
 impl xtra::Message for GetRoomInfo {
@@ -91,6 +353,9 @@ impl xtra::Message for GetRoomInfo {
 pub struct RoomInfo {
     pub id: RoomId,
     pub name: String,
+    pub topic: Option<String>,
+    pub slow_mode_secs: u32,
+    pub archived: bool,
 }
 
 /// A community is a collection (or "house", if you will) of rooms, as well as some metadata.
@@ -99,40 +364,121 @@ pub struct CommunityActor {
     id: CommunityId,
     database: Database,
     rooms: HashMap<RoomId, Room>,
-    /// BTreeSet gives us efficient iteration and checking, compared to HashSet which has O(capacity)
-    /// iteration.
-    online_members: BTreeSet<UserId>,
+    roster: Roster,
+    /// The time at which each user last sent a message in a room with slow mode
+    last_sent: HashMap<(UserId, RoomId), Instant>,
+    /// Limits how quickly each user can send messages to each room, apart from slow mode
+    room_limiter: RoomRateLimiter,
+    signing_key: Arc<SigningKey>,
+    /// Whether messages sent to the community are signed
+    sign_messages: bool,
+    /// Recently sent messages which came with a nonce, by the device which sent them
+    nonces: NonceCache,
 }
 
-impl Actor for CommunityActor {}
+impl Actor for CommunityActor {
+    fn started(&mut self, ctx: &mut Context<Self>) {
+        ctx.notify_interval(TYPING_SWEEP_INTERVAL, || SweepTyping);
+        ctx.notify_interval(ROOM_LIMITER_PRUNE_INTERVAL, || PruneRoomLimiter);
+    }
+}
 
 impl CommunityActor {
-    pub fn new(id: CommunityId, database: Database, creator: UserId) -> CommunityActor {
-        let mut online_members = BTreeSet::new();
-        online_members.insert(creator);
+    /// Signs a message which has just been sent to the room and stores its signature, if the
+    /// community signs messages
+    async fn sign(&self, room: RoomId, message: &mut vertex::structures::Message) -> DbResult<()> {
+        if !self.sign_messages {
+            return Ok(());
+        }
+
+        let payload = vertex::signing::message_payload(self.id, room, message);
+        let signature = self.signing_key.sign(&payload);
+        self.database.set_message_signature(message.id, &signature).await?;
+        message.signature = Some(signature);
+
+        Ok(())
+    }
+
+    pub fn new(
+        id: CommunityId,
+        database: Database,
+        creator: UserId,
+        room_quota: Quota,
+        signing_key: Arc<SigningKey>,
+    ) -> CommunityActor {
+        let mut roster = Roster::default();
+        roster.join(creator);
 
         CommunityActor {
             id,
             database,
             rooms: HashMap::new(),
-            online_members,
+            roster,
+            last_sent: HashMap::new(),
+            room_limiter: RateLimiter::dashmap(room_quota),
+            signing_key,
+            sign_messages: false,
+            nonces: NonceCache::default(),
         }
     }
 
-    pub fn create_and_spawn(name: String, id: CommunityId, database: Database, creator: UserId) {
-        let addr = CommunityActor::new(id, database, creator).spawn();
+    pub fn create_and_spawn(
+        name: String,
+        id: CommunityId,
+        rooms: &[(RoomId, String)],
+        database: Database,
+        creator: UserId,
+        room_quota: Quota,
+        signing_key: Arc<SigningKey>,
+    ) {
+        let mut actor = CommunityActor::new(id, database, creator, room_quota, signing_key);
+        actor.rooms = rooms
+            .iter()
+            .map(|(id, name)| {
+                let room = Room {
+                    name: name.clone(),
+                    topic: None,
+                    slow_mode_secs: 0,
+                    archived: false,
+                };
+                (*id, room)
+            })
+            .collect();
+
+        let addr = actor.spawn();
         let community = Community {
             actor: addr,
             name,
             description: None,
+            icon: None,
+            stats: None,
         };
         COMMUNITIES.insert(id, community);
     }
 
-    pub async fn load_and_spawn(record: CommunityRecord, database: Database) -> DbResult<()> {
+    pub async fn load_and_spawn(
+        record: CommunityRecord,
+        database: Database,
+        room_quota: Quota,
+        signing_key: Arc<SigningKey>,
+    ) -> DbResult<()> {
         let rooms = database.get_rooms_in_community(record.id).await?;
         let rooms = rooms
-            .map_ok(|record| (record.id, Room { name: record.name }))
+            .map_ok(|record| {
+                let room = Room {
+                    name: record.name,
+                    topic: record.topic,
+                    slow_mode_secs: record.slow_mode_secs,
+                    archived: record.archived,
+                };
+                (record.id, room)
+            })
+            .try_collect()
+            .await?;
+
+        let members = database
+            .get_community_member_ids(record.id)
+            .await?
             .try_collect()
             .await?;
 
@@ -140,7 +486,12 @@ impl CommunityActor {
             id: record.id,
             database,
             rooms,
-            online_members: BTreeSet::new(),
+            roster: Roster::new(members),
+            last_sent: HashMap::new(),
+            room_limiter: RateLimiter::dashmap(room_quota),
+            signing_key,
+            sign_messages: record.sign_messages,
+            nonces: NonceCache::default(),
         }
         .spawn();
 
@@ -148,6 +499,8 @@ impl CommunityActor {
             actor: addr,
             name: record.name,
             description: record.description,
+            icon: record.icon,
+            stats: None,
         };
 
         COMMUNITIES.insert(record.id, community);
@@ -155,11 +508,15 @@ impl CommunityActor {
         Ok(())
     }
 
+    fn is_archived(&self, room: RoomId) -> bool {
+        self.rooms.get(&room).map_or(false, |room| room.archived)
+    }
+
     fn for_each_online_device_except<F>(&mut self, mut f: F, except: Option<DeviceId>)
     where
         F: FnMut(&ActiveSession) -> Result<(), Disconnected>,
     {
-        for member in self.online_members.iter() {
+        for member in self.roster.members.iter() {
             let user = match client::session::get_active_user(*member) {
                 Ok(user) => user,
                 Err(_) => continue, // Assume that this is a timing anomaly which will be corrected soon
@@ -181,6 +538,33 @@ impl CommunityActor {
             }
         }
     }
+
+    /// Send the message to every device of every online member, except for the given user
+    fn broadcast_except_user(&mut self, send: ServerMessage, except: UserId) {
+        for member in self.roster.members.iter().filter(|member| **member != except) {
+            let user = match client::session::get_active_user(*member) {
+                Ok(user) => user,
+                Err(_) => continue, // Assume that this is a timing anomaly which will be corrected soon
+            };
+
+            for session in user.sessions.values() {
+                if let Session::Active { actor, .. } = session {
+                    let _ = actor.send(send.clone());
+                }
+            }
+        }
+    }
+
+    fn broadcast_typing(&mut self, user: UserId, room: RoomId, typing: bool) {
+        let community = self.id;
+        let event = if typing {
+            ServerEvent::Typing { community, room, user }
+        } else {
+            ServerEvent::StoppedTyping { community, room, user }
+        };
+
+        self.broadcast_except_user(ServerMessage::Event(event), user);
+    }
 }
 
 #[async_trait]
@@ -190,13 +574,8 @@ impl Handler<Connect> for CommunityActor {
         connect: Connect,
         _: &mut Context<Self>
     ) -> DbResult<Result<(), ConnectError>> {
-        let membership = self
-            .database
-            .get_community_membership(self.id, connect.user)
-            .await?;
-        if membership.is_some() {
+        if self.roster.members.contains(&connect.user) {
             // TODO(banning): check if user is not banned
-            self.online_members.insert(connect.user);
             Ok(Ok(()))
         } else {
             Ok(Err(ConnectError::NotInCommunity))
@@ -205,41 +584,106 @@ impl Handler<Connect> for CommunityActor {
 }
 
 #[async_trait]
-impl Handler<IdentifiedMessage<ClientSentMessage>> for CommunityActor {
+impl Handler<IdentifiedMessage<SentMessage>> for CommunityActor {
     async fn handle(
         &mut self,
-        identified: IdentifiedMessage<ClientSentMessage>,
+        identified: IdentifiedMessage<SentMessage>,
         _: &mut Context<Self>,
-    ) -> Result<MessageConfirmation, Error> {
+    ) -> Result<Result<MessageConfirmation, RoomRateLimited>, Error> {
         let id = MessageId(Uuid::new_v4());
 
-        let message = identified.message;
+        let SentMessage { message, mentioned } = identified.message;
         let author = identified.user;
-        let time_sent = Utc::now();
+        let from_device = identified.device;
 
-        let (_ord, profile_version) = self
+        // The client resent a message which was already sent, so confirm it again
+        let nonce = message.nonce.map(|nonce| (from_device, nonce));
+        if let Some(nonce) = nonce {
+            if let Some(confirmation) = self.nonces.get(&nonce, Instant::now()) {
+                return Ok(Ok(confirmation.clone()));
+            }
+        }
+
+        if self.is_archived(message.to_room) {
+            return Err(Error::RoomArchived);
+        }
+
+        let slow_mode = self
+            .rooms
+            .get(&message.to_room)
+            .map(|room| Duration::from_secs(room.slow_mode_secs as u64))
+            .filter(|slow_mode| *slow_mode > Duration::from_secs(0));
+        let key = (author, message.to_room);
+
+        if let (Some(slow_mode), Some(last)) = (slow_mode, self.last_sent.get(&key)) {
+            let elapsed = last.elapsed();
+            if elapsed < slow_mode {
+                return Ok(Err(RoomRateLimited {
+                    ready_in: slow_mode - elapsed,
+                }));
+            }
+        }
+
+        if let Err(not_until) = self.room_limiter.check_key(&key) {
+            return Ok(Err(RoomRateLimited {
+                ready_in: crate::ready_in(not_until),
+            }));
+        }
+
+        // Replies must be to a message in the same room, though it may have been deleted since
+        let reply_to = match message.reply_to {
+            Some(parent) => match self.database.get_message_by_id(parent).await? {
+                Some(parent)
+                    if parent.community == message.to_community
+                        && parent.room == message.to_room =>
+                {
+                    Some(parent.reply_preview())
+                }
+                _ => return Err(Error::InvalidMessage),
+            },
+            None => None,
+        };
+
+        let res = self
             .database
             .create_message(
                 id,
                 author,
                 message.to_community,
                 message.to_room,
-                time_sent,
+                Utc::now(),
                 message.content.clone(),
+                &message.attachments,
+                message.reply_to,
+                MessageKind::Normal,
             )
             .await?;
+        let (seq, time_sent, profile_version) = res.map_err(|_| Error::InvalidAttachment)?;
+
+        if slow_mode.is_some() {
+            self.last_sent.insert(key, Instant::now());
+        }
+
+        let mut sent = vertex::structures::Message {
+            id,
+            author,
+            author_profile_version: profile_version,
+            time_sent,
+            content: Some(message.content),
+            reactions: Vec::new(),
+            attachments: message.attachments,
+            reply_to,
+            kind: MessageKind::Normal,
+            seq,
+            signature: None,
+        };
+        self.sign(message.to_room, &mut sent).await?;
 
-        let from_device = identified.device;
         let send = ForwardMessage {
             community: message.to_community,
             room: message.to_room,
-            message: vertex::structures::Message {
-                id,
-                author,
-                author_profile_version: profile_version,
-                time_sent,
-                content: Some(message.content),
-            },
+            message: sent,
+            mentioned,
         };
 
         self.for_each_online_device_except(
@@ -250,14 +694,35 @@ impl Handler<IdentifiedMessage<ClientSentMessage>> for CommunityActor {
             Some(from_device),
         );
 
-        Ok(MessageConfirmation { id, time_sent })
+        let confirmation = MessageConfirmation { id, time_sent, seq };
+        if let Some(nonce) = nonce {
+            self.nonces.remember(nonce, confirmation.clone(), Instant::now());
+        }
+
+        Ok(Ok(confirmation))
     }
 }
 
-impl SyncHandler<IdentifiedMessage<Edit>> for CommunityActor {
-    fn handle(&mut self, m: IdentifiedMessage<Edit>, _: &mut Context<Self>) -> Result<(), Error> {
+#[async_trait]
+impl Handler<IdentifiedMessage<Edit>> for CommunityActor {
+    async fn handle(
+        &mut self,
+        m: IdentifiedMessage<Edit>,
+        _: &mut Context<Self>,
+    ) -> Result<(), Error> {
+        let edit = m.message;
+        if self.is_archived(edit.room) {
+            return Err(Error::RoomArchived);
+        }
+
+        let edit_count = self
+            .database
+            .edit_message(edit.message, edit.new_content.clone())
+            .await?
+            .ok_or(Error::InvalidMessage)?;
+
         let from_device = m.device;
-        let send = ServerMessage::Event(ServerEvent::Edit(m.message));
+        let send = ServerMessage::Event(ServerEvent::Edit { edit, edit_count });
 
         self.for_each_online_device_except(
             |session| {
@@ -271,6 +736,70 @@ impl SyncHandler<IdentifiedMessage<Edit>> for CommunityActor {
     }
 }
 
+#[async_trait]
+impl Handler<IdentifiedMessage<Delete>> for CommunityActor {
+    async fn handle(
+        &mut self,
+        m: IdentifiedMessage<Delete>,
+        _: &mut Context<Self>,
+    ) -> Result<Vec<AttachmentId>, Error> {
+        // Only tell clients about the deletion if the message was not already deleted
+        let attachments = match self.database.delete_message(m.message.message).await? {
+            Some(attachments) => attachments,
+            None => return Ok(Vec::new()),
+        };
+
+        let from_device = m.device;
+        let send = ServerMessage::Event(ServerEvent::Delete(m.message));
+
+        self.for_each_online_device_except(
+            |session| {
+                let _ = session.send(send.clone());
+                Ok(())
+            },
+            Some(from_device),
+        );
+
+        Ok(attachments)
+    }
+}
+
+impl SyncHandler<UpdateReactions> for CommunityActor {
+    fn handle(&mut self, update: UpdateReactions, _: &mut Context<Self>) {
+        let send = ServerMessage::Event(ServerEvent::ReactionUpdate {
+            community: self.id,
+            room: update.room,
+            message: update.message,
+            reactions: update.reactions,
+        });
+
+        self.for_each_online_device_except(
+            |session| {
+                let _ = session.send(send.clone());
+                Ok(())
+            },
+            None,
+        );
+    }
+}
+
+impl SyncHandler<BroadcastPinsUpdated> for CommunityActor {
+    fn handle(&mut self, update: BroadcastPinsUpdated, _: &mut Context<Self>) {
+        let send = ServerMessage::Event(ServerEvent::PinsUpdated {
+            community: self.id,
+            room: update.room,
+        });
+
+        self.for_each_online_device_except(
+            |session| {
+                let _ = session.send(send.clone());
+                Ok(())
+            },
+            None,
+        );
+    }
+}
+
 #[async_trait]
 impl Handler<Join> for CommunityActor {
     async fn handle(
@@ -282,7 +811,7 @@ impl Handler<Join> for CommunityActor {
             return Ok(Err(e)); // TODO(banning): check if user is not banned
         }
 
-        self.online_members.insert(join.user);
+        self.roster.join(join.user);
 
         let info = match get_mut(self.id) {
             Ok(i) => i,
@@ -301,12 +830,33 @@ impl Handler<Join> for CommunityActor {
                     id: *id,
                     name: room.name.clone(),
                     unread: true,
+                    topic: room.topic.clone(),
+                    slow_mode_secs: room.slow_mode_secs,
+                    watch_level: WatchLevel::default(),
+                    archived: room.archived,
+                    unread_count: 0,
                 })
                 .collect(),
+            icon: info.icon,
         }))
     }
 }
 
+#[async_trait]
+impl Handler<Leave> for CommunityActor {
+    async fn handle(&mut self, leave: Leave, _: &mut Context<Self>) -> DbResult<bool> {
+        if !self.database.remove_from_community(self.id, leave.user).await? {
+            return Ok(false);
+        }
+
+        for room in self.roster.leave(leave.user) {
+            self.broadcast_typing(leave.user, room, false);
+        }
+
+        Ok(true)
+    }
+}
+
 #[async_trait]
 impl Handler<CreateRoom> for CommunityActor {
     async fn handle(&mut self, create: CreateRoom, _: &mut Context<Self>) -> DbResult<RoomId> {
@@ -321,6 +871,9 @@ impl Handler<CreateRoom> for CommunityActor {
             id,
             Room {
                 name: create.name.clone(),
+                topic: None,
+                slow_mode_secs: 0,
+                archived: false,
             },
         );
 
@@ -330,6 +883,11 @@ impl Handler<CreateRoom> for CommunityActor {
                 id,
                 name: create.name.clone(),
                 unread: false,
+                topic: None,
+                slow_mode_secs: 0,
+                watch_level: WatchLevel::default(),
+                archived: false,
+                unread_count: 0,
             },
         };
 
@@ -345,6 +903,308 @@ impl Handler<CreateRoom> for CommunityActor {
     }
 }
 
+#[async_trait]
+impl Handler<DeleteRoom> for CommunityActor {
+    async fn handle(
+        &mut self,
+        delete: DeleteRoom,
+        _: &mut Context<Self>,
+    ) -> DbResult<Option<Vec<AttachmentId>>> {
+        if !self.rooms.contains_key(&delete.room) {
+            return Ok(None);
+        }
+
+        let attachments = match self.database.delete_room(self.id, delete.room).await? {
+            Some(attachments) => attachments,
+            None => return Ok(None),
+        };
+
+        self.rooms.remove(&delete.room);
+        self.roster.typing.retain(|(_, room), _| *room != delete.room);
+        self.last_sent.retain(|(_, room), _| *room != delete.room);
+
+        if self.rooms.is_empty() {
+            info!("Last room in community {} was deleted", self.id.0);
+        }
+
+        let send = RemoveRoom {
+            community: self.id,
+            room: delete.room,
+        };
+
+        self.for_each_online_device_except(
+            |addr| {
+                let _ = addr.remove_room(send.clone());
+                Ok(())
+            },
+            Some(delete.deleter),
+        );
+
+        Ok(Some(attachments))
+    }
+}
+
+#[async_trait]
+impl Handler<DeleteCommunity> for CommunityActor {
+    async fn handle(
+        &mut self,
+        _: DeleteCommunity,
+        ctx: &mut Context<Self>,
+    ) -> DbResult<Option<DeletedCommunity>> {
+        let attachments = match self.database.delete_community(self.id).await? {
+            Some(attachments) => attachments,
+            None => return Ok(None),
+        };
+
+        info!("Community {} was deleted", self.id.0);
+
+        COMMUNITIES.remove(&self.id);
+        ctx.stop();
+
+        Ok(Some(DeletedCommunity {
+            members: self.roster.members.iter().copied().collect(),
+            attachments,
+        }))
+    }
+}
+
+impl SyncHandler<BroadcastCommunityUpdate> for CommunityActor {
+    fn handle(&mut self, update: BroadcastCommunityUpdate, _: &mut Context<Self>) {
+        self.sign_messages = update.sign_messages;
+
+        let send = ServerMessage::Event(ServerEvent::CommunityUpdated {
+            id: self.id,
+            name: update.name,
+            description: update.description,
+            icon: update.icon,
+        });
+
+        self.for_each_online_device_except(
+            |session| {
+                let _ = session.send(send.clone());
+                Ok(())
+            },
+            None,
+        );
+    }
+}
+
+#[async_trait]
+impl Handler<SendSystemMessage> for CommunityActor {
+    async fn handle(
+        &mut self,
+        send: SendSystemMessage,
+        _: &mut Context<Self>,
+    ) -> DbResult<Option<MessageConfirmation>> {
+        if !self.rooms.contains_key(&send.room) {
+            return Ok(None);
+        }
+
+        let id = MessageId(Uuid::new_v4());
+        let res = self
+            .database
+            .create_message(
+                id,
+                send.author,
+                self.id,
+                send.room,
+                Utc::now(),
+                send.content.clone(),
+                &[],
+                None,
+                MessageKind::System,
+            )
+            .await?;
+        let (seq, time_sent, profile_version) = match res {
+            Ok(created) => created,
+            Err(_) => unreachable!("System messages have no attachments to be invalid"),
+        };
+
+        let mut message = vertex::structures::Message {
+            id,
+            author: send.author,
+            author_profile_version: profile_version,
+            time_sent,
+            content: Some(send.content),
+            reactions: Vec::new(),
+            attachments: Vec::new(),
+            reply_to: None,
+            kind: MessageKind::System,
+            seq,
+            signature: None,
+        };
+        self.sign(send.room, &mut message).await?;
+
+        let forward = ForwardMessage {
+            community: self.id,
+            room: send.room,
+            message,
+            mentioned: Vec::new(),
+        };
+
+        self.for_each_online_device_except(
+            |session| {
+                let _ = session.forward_message(forward.clone());
+                Ok(())
+            },
+            None,
+        );
+
+        Ok(Some(MessageConfirmation { id, time_sent, seq }))
+    }
+}
+
+#[async_trait]
+impl Handler<EditRoom> for CommunityActor {
+    async fn handle(&mut self, edit: EditRoom, _: &mut Context<Self>) -> DbResult<bool> {
+        if !self.rooms.contains_key(&edit.room) {
+            return Ok(false);
+        }
+
+        let db = &self.database;
+        let topic = edit.topic.clone();
+        if !db.edit_room(self.id, edit.room, topic, edit.slow_mode_secs).await? {
+            return Ok(false);
+        }
+
+        if let Some(room) = self.rooms.get_mut(&edit.room) {
+            room.topic = edit.topic.clone();
+            room.slow_mode_secs = edit.slow_mode_secs;
+        }
+
+        // Nobody still needs to wait if slow mode was shortened or disabled
+        let slow_mode = Duration::from_secs(edit.slow_mode_secs as u64);
+        self.last_sent
+            .retain(|(_, room), last| *room != edit.room || last.elapsed() < slow_mode);
+
+        let archived = self.rooms.get(&edit.room).map_or(false, |room| room.archived);
+        let send = ServerMessage::Event(ServerEvent::RoomUpdated {
+            community: self.id,
+            room: edit.room,
+            topic: edit.topic,
+            slow_mode_secs: edit.slow_mode_secs,
+            archived,
+        });
+
+        self.for_each_online_device_except(
+            |session| {
+                let _ = session.send(send.clone());
+                Ok(())
+            },
+            Some(edit.editor),
+        );
+
+        Ok(true)
+    }
+}
+
+impl SyncHandler<Drain> for CommunityActor {
+    fn handle(&mut self, _: Drain, _: &mut Context<Self>) {}
+}
+
+impl SyncHandler<BroadcastPresence> for CommunityActor {
+    fn handle(&mut self, presence: BroadcastPresence, _: &mut Context<Self>) {
+        let send = ServerMessage::Event(ServerEvent::PresenceUpdate {
+            user: presence.user,
+            status: presence.status,
+        });
+        self.broadcast_except_user(send, presence.user);
+    }
+}
+
+impl SyncHandler<StartTyping> for CommunityActor {
+    fn handle(&mut self, start: StartTyping, _: &mut Context<Self>) {
+        // Only broadcast if they were not already typing, so that repeated requests do not flood
+        // the room
+        let key = (start.user, start.room);
+        if self.roster.typing.insert(key, Instant::now()).is_none() {
+            self.broadcast_typing(start.user, start.room, true);
+        }
+    }
+}
+
+impl SyncHandler<StopTyping> for CommunityActor {
+    fn handle(&mut self, stop: StopTyping, _: &mut Context<Self>) {
+        if self.roster.typing.remove(&(stop.user, stop.room)).is_some() {
+            self.broadcast_typing(stop.user, stop.room, false);
+        }
+    }
+}
+
+impl SyncHandler<PruneRoomLimiter> for CommunityActor {
+    fn handle(&mut self, _: PruneRoomLimiter, _: &mut Context<Self>) {
+        self.room_limiter.retain_recent();
+    }
+}
+
+impl SyncHandler<SweepTyping> for CommunityActor {
+    fn handle(&mut self, _: SweepTyping, _: &mut Context<Self>) {
+        let expired: Vec<(UserId, RoomId)> = self
+            .roster
+            .typing
+            .iter()
+            .filter(|(_, last)| last.elapsed() > TYPING_TIMEOUT)
+            .map(|(key, _)| *key)
+            .collect();
+
+        for (user, room) in expired {
+            self.roster.typing.remove(&(user, room));
+            self.broadcast_typing(user, room, false);
+        }
+    }
+}
+
+#[async_trait]
+impl Handler<SetRoomArchived> for CommunityActor {
+    async fn handle(&mut self, set: SetRoomArchived, _: &mut Context<Self>) -> DbResult<bool> {
+        if !self.rooms.contains_key(&set.room) {
+            return Ok(false);
+        }
+
+        if !self.database.set_room_archived(self.id, set.room, set.archived).await? {
+            return Ok(false);
+        }
+
+        let room = match self.rooms.get_mut(&set.room) {
+            Some(room) => room,
+            None => return Ok(false),
+        };
+        room.archived = set.archived;
+
+        let send = ServerMessage::Event(ServerEvent::RoomUpdated {
+            community: self.id,
+            room: set.room,
+            topic: room.topic.clone(),
+            slow_mode_secs: room.slow_mode_secs,
+            archived: set.archived,
+        });
+
+        self.for_each_online_device_except(
+            |session| {
+                let _ = session.send(send.clone());
+                Ok(())
+            },
+            Some(set.editor),
+        );
+
+        Ok(true)
+    }
+}
+
+impl SyncHandler<IsRoomArchived> for CommunityActor {
+    fn handle(&mut self, is_archived: IsRoomArchived, _: &mut Context<Self>) -> bool {
+        self.is_archived(is_archived.0)
+    }
+}
+
+impl SyncHandler<FilterMembers> for CommunityActor {
+    fn handle(&mut self, filter: FilterMembers, _: &mut Context<Self>) -> Vec<UserId> {
+        let mut users = filter.0;
+        users.retain(|user| self.roster.members.contains(user));
+        users
+    }
+}
+
 impl SyncHandler<GetRoomInfo> for CommunityActor {
     fn handle(&mut self, _get: GetRoomInfo, _: &mut Context<Self>) -> Vec<RoomInfo> {
         self.rooms
@@ -352,13 +1212,234 @@ impl SyncHandler<GetRoomInfo> for CommunityActor {
             .map(move |(id, room)| RoomInfo {
                 id: *id,
                 name: room.name.clone(),
+                topic: room.topic.clone(),
+                slow_mode_secs: room.slow_mode_secs,
+                archived: room.archived,
             })
             .collect()
     }
 }
 
+type NonceKey = (DeviceId, MessageNonce);
+
+/// Confirmations of messages which were sent with a nonce, so that a message which is sent again
+/// is confirmed rather than stored twice. Nonces are forgotten after `NONCE_TIMEOUT`, or once
+/// `MAX_REMEMBERED_NONCES` newer ones have been seen.
+#[derive(Default)]
+struct NonceCache {
+    confirmations: HashMap<NonceKey, MessageConfirmation>,
+    /// The remembered nonces, oldest first, along with when they were first seen
+    order: VecDeque<(Instant, NonceKey)>,
+}
+
+impl NonceCache {
+    fn get(&mut self, key: &NonceKey, now: Instant) -> Option<&MessageConfirmation> {
+        self.forget_expired(now);
+        self.confirmations.get(key)
+    }
+
+    fn remember(&mut self, key: NonceKey, confirmation: MessageConfirmation, now: Instant) {
+        self.forget_expired(now);
+
+        if self.order.len() >= MAX_REMEMBERED_NONCES {
+            if let Some((_, oldest)) = self.order.pop_front() {
+                self.confirmations.remove(&oldest);
+            }
+        }
+
+        self.confirmations.insert(key, confirmation);
+        self.order.push_back((now, key));
+    }
+
+    fn forget_expired(&mut self, now: Instant) {
+        while let Some((seen, key)) = self.order.front() {
+            if now.duration_since(*seen) < NONCE_TIMEOUT {
+                break;
+            }
+
+            self.confirmations.remove(key);
+            self.order.pop_front();
+        }
+    }
+}
+
+/// Who is in a community, and which of them are typing where
+#[derive(Default)]
+struct Roster {
+    /// Every member of the community. This is loaded from the database when the actor is spawned
+    /// and kept up to date as users join and leave, so it does not depend on who has connected.
+    /// BTreeSet gives us efficient iteration and checking, compared to HashSet which has O(capacity)
+    /// iteration.
+    members: BTreeSet<UserId>,
+    /// The time at which each user last said that they were typing in a room
+    typing: HashMap<(UserId, RoomId), Instant>,
+}
+
+impl Roster {
+    fn new(members: BTreeSet<UserId>) -> Self {
+        Roster {
+            members,
+            typing: HashMap::new(),
+        }
+    }
+
+    /// Adds a member, returning whether they were not already one
+    fn join(&mut self, user: UserId) -> bool {
+        self.members.insert(user)
+    }
+
+    /// Removes a member, returning the rooms which they were typing in and so should be told
+    /// that they have stopped
+    fn leave(&mut self, user: UserId) -> Vec<RoomId> {
+        self.members.remove(&user);
+
+        let typing_in: Vec<RoomId> = self
+            .typing
+            .keys()
+            .filter(|(typing, _)| *typing == user)
+            .map(|(_, room)| *room)
+            .collect();
+
+        for room in &typing_in {
+            self.typing.remove(&(user, *room));
+        }
+
+        typing_in
+    }
+}
+
 /// A room, loaded into memory
 #[derive(Debug)]
 struct Room {
     name: String,
+    topic: Option<String>,
+    slow_mode_secs: u32,
+    archived: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(n: u128) -> NonceKey {
+        (DeviceId(Uuid::nil()), MessageNonce(Uuid::from_u128(n)))
+    }
+
+    fn confirmation(seq: u64) -> MessageConfirmation {
+        MessageConfirmation {
+            id: MessageId(Uuid::from_u128(seq as u128)),
+            time_sent: Utc::now(),
+            seq,
+        }
+    }
+
+    #[test]
+    fn repeated_nonce_is_confirmed_again() {
+        let mut nonces = NonceCache::default();
+        let now = Instant::now();
+
+        assert!(nonces.get(&key(1), now).is_none());
+        nonces.remember(key(1), confirmation(1), now);
+
+        let again = nonces.get(&key(1), now + Duration::from_secs(1)).unwrap();
+        assert_eq!(again.seq, 1);
+        assert!(nonces.get(&key(2), now).is_none());
+    }
+
+    #[test]
+    fn same_nonce_from_another_device_is_not_a_repeat() {
+        let mut nonces = NonceCache::default();
+        let now = Instant::now();
+
+        nonces.remember(key(1), confirmation(1), now);
+        let other_device = (DeviceId(Uuid::from_u128(1)), key(1).1);
+        assert!(nonces.get(&other_device, now).is_none());
+    }
+
+    #[test]
+    fn oldest_nonce_is_forgotten_at_capacity() {
+        let mut nonces = NonceCache::default();
+        let now = Instant::now();
+
+        for n in 0..MAX_REMEMBERED_NONCES as u64 {
+            nonces.remember(key(n as u128), confirmation(n), now);
+        }
+        assert!(nonces.get(&key(0), now).is_some());
+
+        let newest = MAX_REMEMBERED_NONCES as u64;
+        nonces.remember(key(newest as u128), confirmation(newest), now);
+
+        assert!(nonces.get(&key(0), now).is_none());
+        assert!(nonces.get(&key(1), now).is_some());
+        assert!(nonces.get(&key(newest as u128), now).is_some());
+        assert_eq!(nonces.confirmations.len(), MAX_REMEMBERED_NONCES);
+    }
+
+    #[test]
+    fn nonces_expire() {
+        let mut nonces = NonceCache::default();
+        let now = Instant::now();
+
+        nonces.remember(key(1), confirmation(1), now);
+        nonces.remember(key(2), confirmation(2), now + Duration::from_secs(1));
+
+        let expired = now + NONCE_TIMEOUT;
+        assert!(nonces.get(&key(1), expired).is_none());
+        assert!(nonces.get(&key(2), expired).is_some());
+        assert_eq!(nonces.order.len(), 1);
+    }
+
+    fn user(n: u128) -> UserId {
+        UserId(Uuid::from_u128(n))
+    }
+
+    #[test]
+    fn joining_adds_a_member() {
+        let mut roster = Roster::default();
+
+        assert!(!roster.members.contains(&user(1)));
+        assert!(roster.join(user(1)));
+        assert!(roster.members.contains(&user(1)));
+    }
+
+    #[test]
+    fn rejoining_is_a_no_op() {
+        let mut roster = Roster::new(vec![user(1)].into_iter().collect());
+
+        assert!(!roster.join(user(1)));
+        assert_eq!(roster.members.len(), 1);
+    }
+
+    #[test]
+    fn leaving_stops_typing() {
+        let mut roster = Roster::default();
+        roster.join(user(1));
+        roster.join(user(2));
+
+        let (a, b) = (RoomId(Uuid::from_u128(1)), RoomId(Uuid::from_u128(2)));
+        let now = Instant::now();
+        roster.typing.insert((user(1), a), now);
+        roster.typing.insert((user(1), b), now);
+        roster.typing.insert((user(2), a), now);
+
+        let mut stopped = roster.leave(user(1));
+        stopped.sort_by_key(|room| room.0);
+
+        assert_eq!(stopped, vec![a, b]);
+        assert!(!roster.members.contains(&user(1)));
+        assert!(roster.members.contains(&user(2)));
+        assert_eq!(roster.typing.len(), 1);
+        assert!(roster.typing.contains_key(&(user(2), a)));
+    }
+
+    #[test]
+    fn non_member_leaving_changes_nothing() {
+        let mut roster = Roster::default();
+        roster.join(user(1));
+        roster.typing.insert((user(1), RoomId(Uuid::nil())), Instant::now());
+
+        assert!(roster.leave(user(2)).is_empty());
+        assert!(roster.members.contains(&user(1)));
+        assert_eq!(roster.typing.len(), 1);
+    }
 }
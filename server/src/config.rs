@@ -1,13 +1,19 @@
 // configuration framework rewrite time. very epic
 
 use directories_next::ProjectDirs;
+use http::header::HeaderName;
+use http::Method;
+use lazy_static::lazy_static;
 use log::Level;
 use serde::{Deserialize, Serialize};
+use std::env;
 use std::fs::{self, File};
 use std::io::ErrorKind;
 use std::net::SocketAddr;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use url::Url;
+use vertex::prelude::{AccountRules, CommunityPermissionFlags, HEARTBEAT_TIMEOUT};
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Config {
@@ -19,34 +25,163 @@ pub struct Config {
     pub max_community_description_len: u16,
     #[serde(default = "max_channel_name_len")]
     pub max_channel_name_len: u16,
+    #[serde(default = "max_room_topic_len")]
+    pub max_room_topic_len: u16,
+    #[serde(default = "max_ban_reason_len")]
+    pub max_ban_reason_len: u16,
+    /// Longest slow mode that can be set on a room, in seconds
+    #[serde(default = "max_slow_mode_secs")]
+    pub max_slow_mode_secs: u32,
     #[serde(default = "max_password_len")]
     pub max_password_len: u16,
     #[serde(default = "min_password_len")]
     pub min_password_len: u16,
+    /// Memory used to hash each password and token, in KiB. More memory makes hashes more costly
+    /// to crack on GPUs, but every login holds this much memory for the length of the hash.
+    #[serde(default = "password_hash_memory_kib")]
+    pub password_hash_memory_kib: u32,
+    /// Passes made over the memory when hashing. Hashing time grows linearly with this, and logins
+    /// are slowed down by it just as much as attackers are.
+    #[serde(default = "password_hash_iterations")]
+    pub password_hash_iterations: u32,
+    /// Lanes which are hashed in parallel, each on its own thread. More lanes hash faster on
+    /// machines with cores to spare, but take up more threads from the blocking pool at once.
+    #[serde(default = "password_hash_lanes")]
+    pub password_hash_lanes: u32,
     #[serde(default = "max_username_len")]
     pub max_username_len: u16,
     #[serde(default = "min_username_len")]
     pub min_username_len: u16,
     #[serde(default = "max_display_name_len")]
     pub max_display_name_len: u16,
+    #[serde(default = "max_device_name_len")]
+    pub max_device_name_len: u16,
+    /// How long a user must wait between changing their username, in days. 0 disables the cooldown
+    #[serde(default = "username_change_cooldown_days")]
+    pub username_change_cooldown_days: u16,
+    /// How long a user must wait between changing their display name, in seconds
+    #[serde(default = "display_name_change_cooldown_secs")]
+    pub display_name_change_cooldown_secs: u64,
     #[serde(default = "tokens_sweep_interval_secs")]
     pub tokens_sweep_interval_secs: u64,
     #[serde(default = "token_stale_days")]
     pub token_stale_days: u16,
     #[serde(default = "token_expiry_days")]
     pub token_expiry_days: u16,
+    /// Longest a token can be created to last, in days. Tokens created without an expiration date
+    /// expire after this long. 0 lets tokens last for as long as they keep being used.
+    #[serde(default = "max_token_lifetime_days")]
+    pub max_token_lifetime_days: u16,
     #[serde(default = "max_invite_codes_per_community")]
     pub max_invite_codes_per_community: u32,
+    #[serde(default = "max_pins_per_room")]
+    pub max_pins_per_room: u32,
+    #[serde(default = "max_role_name_len")]
+    pub max_role_name_len: u16,
+    /// Permissions of community members who have not been given any roles
+    #[serde(default = "default_role_permissions")]
+    pub default_role_permissions: i64,
+    #[serde(default = "max_members_per_request")]
+    pub max_members_per_request: u32,
+    /// Most rooms that updates can be asked for in one request
+    #[serde(default = "max_room_updates_per_request")]
+    pub max_room_updates_per_request: u32,
+    /// Most different users that one message can mention. Names past this are not looked up.
+    #[serde(default = "max_mentions_per_message")]
+    pub max_mentions_per_message: u32,
+    /// Messages (including direct messages) a device may send per minute
+    #[serde(default = "send_message_burst_per_min")]
+    pub send_message_burst_per_min: u32,
+    /// Messages a user may send to one room in a quick burst, on top of the per-device limit
+    #[serde(default = "room_message_burst")]
+    pub room_message_burst: u32,
+    /// Messages per minute that a user's burst allowance in a room refills at
+    #[serde(default = "room_message_per_min")]
+    pub room_message_per_min: u32,
+    /// Communities a device may create per minute
+    #[serde(default = "create_community_burst_per_min")]
+    pub create_community_burst_per_min: u32,
+    /// Invite codes a device may create per minute
+    #[serde(default = "create_invite_burst_per_min")]
+    pub create_invite_burst_per_min: u32,
+    /// Where uploaded attachments are stored
+    #[serde(default = "attachments_dir")]
+    pub attachments_dir: PathBuf,
+    /// Where the server's Ed25519 key for signing messages is kept. It is generated if missing.
+    #[serde(default = "signing_key_path")]
+    pub signing_key_path: PathBuf,
+    /// Largest file that can be uploaded, in bytes
+    #[serde(default = "max_upload_size")]
+    pub max_upload_size: u64,
+    /// Total size of attachments each user may upload, in bytes
+    #[serde(default = "attachment_quota_per_user")]
+    pub attachment_quota_per_user: u64,
+    /// How long to wait for communities and connections to finish up when shutting down
+    #[serde(default = "shutdown_drain_timeout_secs")]
+    pub shutdown_drain_timeout_secs: u64,
     #[serde(default = "invite_codes_sweep_interval_secs")]
     pub invite_codes_sweep_interval_secs: u64,
+    #[serde(default = "bans_sweep_interval_secs")]
+    pub bans_sweep_interval_secs: u64,
+    /// How often messages past their community's retention period are deleted, in seconds
+    #[serde(default = "messages_sweep_interval_secs")]
+    pub messages_sweep_interval_secs: u64,
+    /// How long a session may go without sending anything before it is logged out, in seconds
+    #[serde(default = "session_timeout_secs")]
+    pub session_timeout_secs: u64,
+    /// How often sessions are checked for having timed out, in seconds
+    #[serde(default = "session_sweep_interval_secs")]
+    pub session_sweep_interval_secs: u64,
+    /// Most messages that can be waiting to be sent to a session. A session which falls this far
+    /// behind is dropped, since its client has likely stopped reading.
+    #[serde(default = "session_send_buffer")]
+    pub session_send_buffer: usize,
+    /// Postgres connection string. The `VERTEX_DB` environment variable takes precedence over it,
+    /// and `db.conf` in the config directory is read if neither is given.
+    #[serde(default)]
+    pub db_connection: Option<String>,
+    /// Connect to the database over TLS, verifying it against `db_tls_ca_cert`
+    #[serde(default)]
+    pub db_tls: bool,
+    /// PEM file of the CA certificates to trust when connecting to the database over TLS
+    #[serde(default)]
+    pub db_tls_ca_cert: Option<PathBuf>,
     #[serde(default = "log_level")]
     pub log_level: String,
     #[serde(default = "https")]
     pub https: bool,
+    /// Host (and port, if not the default) that users reach the server on, such as
+    /// `chat.example.com`. Invite pages link to it. If unset, they work it out from their own URL.
+    #[serde(default)]
+    pub public_host: Option<String>,
+    /// Origins (e.g. `https://chat.example.com`) that web pages may call the server's HTTP routes
+    /// from. With none, only pages served from the server's own origin can use them.
+    #[serde(default)]
+    pub cors_allowed_origins: Vec<String>,
+    /// Methods that pages from the allowed origins may use
+    #[serde(default = "cors_allowed_methods")]
+    pub cors_allowed_methods: Vec<String>,
+    /// Request headers that pages from the allowed origins may send
+    #[serde(default = "cors_allowed_headers")]
+    pub cors_allowed_headers: Vec<String>,
     #[serde(default = "ip")]
     pub ip: SocketAddr,
 }
 
+impl Config {
+    /// The limits on account details, as told to clients
+    pub fn account_rules(&self) -> AccountRules {
+        AccountRules {
+            min_username_len: self.min_username_len.into(),
+            max_username_len: self.max_username_len.into(),
+            max_display_name_len: self.max_display_name_len.into(),
+            min_password_len: self.min_password_len.into(),
+            max_password_len: self.max_password_len.into(),
+            max_device_name_len: self.max_device_name_len.into(),
+        }
+    }
+}
+
 fn max_message_len() -> u32 {
     2500
 }
@@ -63,6 +198,18 @@ fn max_channel_name_len() -> u16 {
     50
 }
 
+fn max_room_topic_len() -> u16 {
+    250
+}
+
+fn max_ban_reason_len() -> u16 {
+    500
+}
+
+fn max_slow_mode_secs() -> u32 {
+    6 * 60 * 60 // 6 hours
+}
+
 fn max_password_len() -> u16 {
     1000
 }
@@ -71,6 +218,18 @@ fn min_password_len() -> u16 {
     12
 }
 
+fn password_hash_memory_kib() -> u32 {
+    4096 // 4 MiB
+}
+
+fn password_hash_iterations() -> u32 {
+    3
+}
+
+fn password_hash_lanes() -> u32 {
+    1
+}
+
 fn max_username_len() -> u16 {
     64
 }
@@ -91,6 +250,26 @@ fn ip() -> SocketAddr {
     "127.0.0.1:8443".parse().unwrap()
 }
 
+fn cors_allowed_methods() -> Vec<String> {
+    vec!["GET".to_string(), "POST".to_string()]
+}
+
+fn cors_allowed_headers() -> Vec<String> {
+    vec!["content-type".to_string()]
+}
+
+fn max_device_name_len() -> u16 {
+    64
+}
+
+fn username_change_cooldown_days() -> u16 {
+    7 // 1 week
+}
+
+fn display_name_change_cooldown_secs() -> u64 {
+    60
+}
+
 fn tokens_sweep_interval_secs() -> u64 {
     1800 // 30min
 }
@@ -103,6 +282,10 @@ fn token_expiry_days() -> u16 {
     90 // ~3 months
 }
 
+fn max_token_lifetime_days() -> u16 {
+    365 // 1 year
+}
+
 fn log_level() -> String {
     "info".to_string()
 }
@@ -111,27 +294,176 @@ fn invite_codes_sweep_interval_secs() -> u64 {
     1800 // 30min
 }
 
+fn bans_sweep_interval_secs() -> u64 {
+    60 // 1min
+}
+
+fn messages_sweep_interval_secs() -> u64 {
+    3600 // 1hr
+}
+
+fn session_timeout_secs() -> u64 {
+    HEARTBEAT_TIMEOUT.as_secs()
+}
+
+fn session_sweep_interval_secs() -> u64 {
+    5
+}
+
+fn session_send_buffer() -> usize {
+    256
+}
+
+fn shutdown_drain_timeout_secs() -> u64 {
+    10
+}
+
+fn attachments_dir() -> PathBuf {
+    DIRS.data.join("attachments")
+}
+
+fn signing_key_path() -> PathBuf {
+    DIRS.data.join("signing_key.pk8")
+}
+
+fn max_upload_size() -> u64 {
+    8 * 1024 * 1024 // 8 MiB
+}
+
+fn attachment_quota_per_user() -> u64 {
+    256 * 1024 * 1024 // 256 MiB
+}
+
 fn max_invite_codes_per_community() -> u32 {
     100
 }
 
-pub fn db_config() -> tokio_postgres::Config {
+fn max_pins_per_room() -> u32 {
+    50
+}
+
+fn max_role_name_len() -> u16 {
+    32
+}
+
+fn default_role_permissions() -> i64 {
+    let perms = CommunityPermissionFlags::SEND_MESSAGES
+        | CommunityPermissionFlags::CREATE_ROOMS
+        | CommunityPermissionFlags::CREATE_INVITES;
+    perms.bits()
+}
+
+fn max_members_per_request() -> u32 {
+    200
+}
+
+fn max_room_updates_per_request() -> u32 {
+    50
+}
+
+fn max_mentions_per_message() -> u32 {
+    20
+}
+
+fn send_message_burst_per_min() -> u32 {
+    60
+}
+
+fn room_message_burst() -> u32 {
+    10
+}
+
+fn room_message_per_min() -> u32 {
+    30
+}
+
+fn create_community_burst_per_min() -> u32 {
+    5
+}
+
+fn create_invite_burst_per_min() -> u32 {
+    10
+}
+
+/// Environment variable which overrides the database connection string in the config
+const DB_CONNECTION_VAR: &str = "VERTEX_DB";
+/// Environment variable which overrides the directory that the server keeps its files in
+const DATA_DIR_VAR: &str = "VERTEX_DATA_DIR";
+/// Environment variable which overrides the directory that config files are read from. Defaults to
+/// the data directory if only that is overridden.
+const CONFIG_DIR_VAR: &str = "VERTEX_CONFIG_DIR";
+
+lazy_static! {
+    /// Resolved once, when the server starts
+    pub static ref DIRS: Dirs = Dirs::resolve();
+}
+
+/// Where the server's files are kept on disk. Without any overrides, these are the platform's
+/// usual config and data directories for `vertex_server`.
+pub struct Dirs {
+    /// Holds `config.toml`, `db.conf`, and the TLS certificate and key
+    pub config: PathBuf,
+    /// Holds logs, attachments and the message signing key, unless the config says otherwise
+    pub data: PathBuf,
+}
+
+impl Dirs {
+    fn resolve() -> Dirs {
+        let data_override = dir_override(DATA_DIR_VAR);
+        let config_override = dir_override(CONFIG_DIR_VAR).or_else(|| data_override.clone());
+
+        let project_dirs = || {
+            ProjectDirs::from("", "vertex_chat", "vertex_server")
+                .expect("Error getting project directories")
+        };
+
+        Dirs {
+            config: config_override.unwrap_or_else(|| project_dirs().config_dir().to_path_buf()),
+            data: data_override.unwrap_or_else(|| project_dirs().data_dir().to_path_buf()),
+        }
+    }
+}
+
+/// Reads a directory from the environment variable, if it is set. The directory must exist, so
+/// that a mistyped path or missing volume is caught before anything is written.
+fn dir_override(var: &str) -> Option<PathBuf> {
+    let dir = PathBuf::from(env::var_os(var)?);
+    if !dir.is_dir() {
+        panic!(
+            "{} is set to {}, which is not an existing directory",
+            var,
+            dir.to_string_lossy(),
+        );
+    }
+
+    Some(dir)
+}
+
+/// Panics with a message naming the path and what it is for if it does not exist
+pub fn require_path(path: &Path, what: &str) {
+    if !path.exists() {
+        panic!("{} is missing. It is expected to be here: {}", what, path.to_string_lossy());
+    }
+}
+
+pub fn db_config(config: &Config) -> Result<tokio_postgres::Config, tokio_postgres::Error> {
     const DEFAULT: &str = "host=localhost user=postgres password=postgres dbname=vertex";
-    let path = ProjectDirs::from("", "vertex_chat", "vertex_server")
-        .expect("Error getting project directories")
-        .config_dir()
-        .join("db.conf");
 
-    fs::read_to_string(path)
+    if let Ok(connection) = env::var(DB_CONNECTION_VAR) {
+        return connection.parse();
+    }
+
+    if let Some(connection) = &config.db_connection {
+        return connection.parse();
+    }
+
+    fs::read_to_string(DIRS.config.join("db.conf"))
         .unwrap_or_else(|_| DEFAULT.to_string())
         .parse()
-        .expect("Invalid db.conf!")
 }
 
 pub fn load_config() -> Config {
-    let dirs = ProjectDirs::from("", "vertex_chat", "vertex_server")
-        .expect("Error getting project directories");
-    let config_dir = dirs.config_dir();
+    let config_dir = &DIRS.config;
     let config_file = config_dir.join("config.toml");
     let res = fs::read_to_string(&config_file);
 
@@ -177,6 +509,18 @@ pub fn load_config() -> Config {
         panic!("Maximum password length must be greater or equal to than minimum password length");
     }
 
+    if config.password_hash_iterations < 1 {
+        panic!("Password hash iterations must be greater than or equal to 1");
+    }
+
+    if config.password_hash_lanes < 1 || config.password_hash_lanes > 0x00FF_FFFF {
+        panic!("Password hash lanes must be between 1 and 16777215");
+    }
+
+    if (config.password_hash_memory_kib as u64) < 8 * config.password_hash_lanes as u64 {
+        panic!("Password hash memory must be at least 8 KiB for every password hash lane");
+    }
+
     if config.min_username_len < 1 {
         panic!("Minimum username length must be greater than or equal to 1");
     }
@@ -189,10 +533,30 @@ pub fn load_config() -> Config {
         panic!("Maximum display name length must be greater than or equal to minimum display name length");
     }
 
+    if config.max_device_name_len < 1 {
+        panic!("Maximum device name length must be greater than or equal to 1");
+    }
+
     if config.tokens_sweep_interval_secs < 60 {
         panic!("Tokens sweep interval must be greater than 1 minute!");
     }
 
+    if config.messages_sweep_interval_secs < 60 {
+        panic!("Messages sweep interval must be greater than or equal to 1 minute");
+    }
+
+    if config.session_sweep_interval_secs < 1 {
+        panic!("Session sweep interval must be greater than or equal to 1 second");
+    }
+
+    if config.session_timeout_secs < config.session_sweep_interval_secs {
+        panic!("Session timeout must be greater than or equal to the session sweep interval");
+    }
+
+    if config.session_send_buffer < 1 {
+        panic!("Session send buffer must be greater than or equal to 1");
+    }
+
     if config.max_message_len < 1 {
         panic!("Maximum message length must be greater than or equal to 1");
     }
@@ -209,21 +573,86 @@ pub fn load_config() -> Config {
         panic!("Maximum channel length must be greater than or equal to 1");
     }
 
+    if config.max_room_topic_len < 1 {
+        panic!("Maximum room topic length must be greater than or equal to 1");
+    }
+
+    if config.max_role_name_len < 1 {
+        panic!("Maximum role name length must be greater than or equal to 1");
+    }
+
+    if config.send_message_burst_per_min < 1 {
+        panic!("Send message burst per minute must be greater than or equal to 1");
+    }
+
+    if config.room_message_burst < 1 {
+        panic!("Room message burst must be greater than or equal to 1");
+    }
+
+    if config.room_message_per_min < 1 {
+        panic!("Room messages per minute must be greater than or equal to 1");
+    }
+
+    if config.create_community_burst_per_min < 1 {
+        panic!("Create community burst per minute must be greater than or equal to 1");
+    }
+
+    if config.create_invite_burst_per_min < 1 {
+        panic!("Create invite burst per minute must be greater than or equal to 1");
+    }
+
+    if config.max_upload_size > config.attachment_quota_per_user {
+        panic!("Maximum upload size must be less than or equal to the attachment quota per user");
+    }
+
+    if config.db_tls && config.db_tls_ca_cert.is_none() {
+        panic!("A database CA certificate must be given to connect to the database over TLS");
+    }
+
     if Level::from_str(&config.log_level).is_err() {
         panic!("Invalid log level! It should be 'trace', 'debug', 'info', 'warn', or 'error'")
     }
 
+    if let Some(host) = &config.public_host {
+        let valid = Url::parse(&format!("https://{}", host)).map_or(false, |url| {
+            url.has_host()
+                && url.username().is_empty()
+                && url.path() == "/"
+                && url.query().is_none()
+        });
+        if !valid || host.ends_with('/') {
+            panic!("Invalid public host {}. It should look like 'chat.example.com'", host);
+        }
+    }
+
+    for origin in &config.cors_allowed_origins {
+        let valid = Url::parse(origin).map_or(false, |url| {
+            url.has_host() && url.origin().ascii_serialization() == *origin
+        });
+        if !valid {
+            panic!("Invalid CORS origin {}. It should look like 'https://example.com'", origin);
+        }
+    }
+
+    for method in &config.cors_allowed_methods {
+        if Method::from_bytes(method.as_bytes()).is_err() {
+            panic!("Invalid CORS method {}", method);
+        }
+    }
+
+    for header in &config.cors_allowed_headers {
+        if HeaderName::from_bytes(header.as_bytes()).is_err() {
+            panic!("Invalid CORS header {}", header);
+        }
+    }
+
     config
 }
 
 /// Returns (cert path, key path)
 pub fn ssl_config() -> (PathBuf, PathBuf) {
-    let dirs = ProjectDirs::from("", "vertex_chat", "vertex_server")
-        .expect("Error getting project directories");
-    let dir = dirs.config_dir();
-
-    let cert_path = dir.join("cert.pem");
-    let key_path = dir.join("key.pem");
+    let cert_path = DIRS.config.join("cert.pem");
+    let key_path = DIRS.config.join("key.pem");
 
     (cert_path, key_path)
 }
@@ -29,7 +29,8 @@ impl Database {
         const DELETE: &str = "DELETE FROM administrators WHERE user_id = $1";
 
         let conn = self.pool.connection().await?;
-        let res = if permissions == AdminPermissionFlags::from_bits_truncate(0) {
+        let removing = permissions == AdminPermissionFlags::from_bits_truncate(0);
+        let res = if removing {
             conn.client.execute(DELETE, &[&user.0]).await
         } else {
             let args: &[&(dyn ToSql + Sync)] = &[&user.0, &permissions.bits()];
@@ -38,6 +39,7 @@ impl Database {
 
         match res {
             Ok(1) => Ok(Ok(())), // 1 row modified = successfully added
+            Ok(0) if removing => Ok(Ok(())), // The user was not an admin to begin with
             Ok(_n) => {
                 panic!("db error: create admin query returned != 1 row modified!");
             }
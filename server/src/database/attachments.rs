@@ -0,0 +1,105 @@
+use std::convert::TryFrom;
+
+use chrono::Utc;
+use tokio_postgres::types::ToSql;
+use tokio_postgres::Row;
+use vertex::prelude::*;
+
+use super::*;
+
+pub(super) const CREATE_ATTACHMENTS_TABLE: &str = r#"
+    CREATE TABLE IF NOT EXISTS attachments (
+        id               UUID PRIMARY KEY,
+        uploader         UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+        message          UUID REFERENCES messages(id) ON DELETE CASCADE,
+        content_type     VARCHAR NOT NULL,
+        size             BIGINT NOT NULL,
+        uploaded_at      TIMESTAMP WITH TIME ZONE NOT NULL
+    )"#;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct StorageQuotaExceeded;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct InvalidAttachment;
+
+#[derive(Debug)]
+pub struct AttachmentRecord {
+    pub id: AttachmentId,
+    pub uploader: UserId,
+    pub message: Option<MessageId>,
+    pub content_type: String,
+    pub size: u64,
+}
+
+impl TryFrom<Row> for AttachmentRecord {
+    type Error = tokio_postgres::Error;
+
+    fn try_from(row: Row) -> Result<AttachmentRecord, tokio_postgres::Error> {
+        Ok(AttachmentRecord {
+            id: AttachmentId(row.try_get("id")?),
+            uploader: UserId(row.try_get("uploader")?),
+            message: row.try_get::<&str, Option<_>>("message")?.map(MessageId),
+            content_type: row.try_get("content_type")?,
+            size: row.try_get::<&str, i64>("size")? as u64,
+        })
+    }
+}
+
+impl Database {
+    /// Records a new attachment, as long as it would not take the uploader over `quota` bytes
+    /// of attachments in total. The uploader's row is locked while checking, so that concurrent
+    /// uploads cannot both squeeze in under the quota.
+    pub async fn create_attachment(
+        &self,
+        id: AttachmentId,
+        uploader: UserId,
+        content_type: String,
+        size: u64,
+        quota: u64,
+    ) -> DbResult<Result<(), StorageQuotaExceeded>> {
+        const LOCK_USER: &str = "SELECT id FROM users WHERE id = $1 FOR UPDATE";
+        const USED: &str = "
+            SELECT COALESCE(SUM(size), 0)::BIGINT AS used FROM attachments WHERE uploader = $1
+        ";
+        const INSERT: &str = "
+            INSERT INTO attachments (id, uploader, content_type, size, uploaded_at)
+                VALUES ($1, $2, $3, $4, $5)
+        ";
+
+        let mut conn = self.pool.connection().await?;
+        let transaction = conn.client.transaction().await?;
+
+        transaction.execute(LOCK_USER, &[&uploader.0]).await?;
+        let used: i64 = transaction.query_one(USED, &[&uploader.0]).await?.try_get("used")?;
+
+        if used as u64 + size > quota {
+            return Ok(Err(StorageQuotaExceeded)); // Transaction is rolled back on drop
+        }
+
+        let args: &[&(dyn ToSql + Sync)] =
+            &[&id.0, &uploader.0, &content_type, &(size as i64), &Utc::now()];
+        transaction.execute(INSERT, args).await?;
+        transaction.commit().await?;
+
+        Ok(Ok(()))
+    }
+
+    pub async fn get_attachment(&self, id: AttachmentId) -> DbResult<Option<AttachmentRecord>> {
+        const QUERY: &str = "SELECT * FROM attachments WHERE id = $1";
+
+        match self.query_opt(QUERY, &[&id.0]).await? {
+            Some(row) => Ok(Some(AttachmentRecord::try_from(row)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn delete_attachment(&self, id: AttachmentId) -> DbResult<()> {
+        const STMT: &str = "DELETE FROM attachments WHERE id = $1";
+
+        let conn = self.pool.connection().await?;
+        conn.client.execute(STMT, &[&id.0]).await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,205 @@
+use std::convert::TryFrom;
+
+use chrono::{DateTime, Utc};
+use log::warn;
+use tokio_postgres::types::ToSql;
+use tokio_postgres::Row;
+use uuid::Uuid;
+use vertex::prelude::*;
+
+use super::*;
+
+/// Most audit events returned at one time
+const SERVER_MAX: u32 = 100;
+
+// The actor is not a foreign key so that the log is kept as it was when users are deleted. A null
+// actor is the server operator, acting from the command line.
+pub(super) const CREATE_AUDIT_LOG_TABLE: &str = r#"
+    CREATE TABLE IF NOT EXISTS audit_log (
+        id           BIGSERIAL PRIMARY KEY,
+        time         TIMESTAMP WITH TIME ZONE NOT NULL,
+        actor        UUID,
+        action       "char" NOT NULL,
+        target_user  UUID,
+        report       INTEGER,
+        reason       VARCHAR,
+        until        TIMESTAMP WITH TIME ZONE,
+        value        BIGINT
+    )"#;
+
+/// The columns of an audit log row which describe its action
+#[derive(Default)]
+struct ActionColumns {
+    code: i8,
+    target_user: Option<Uuid>,
+    report: Option<i32>,
+    reason: Option<String>,
+    until: Option<DateTime<Utc>>,
+    value: Option<i64>,
+}
+
+impl From<&AuditAction> for ActionColumns {
+    fn from(action: &AuditAction) -> ActionColumns {
+        match action {
+            AuditAction::Ban { user, reason, until } => ActionColumns {
+                code: 0,
+                target_user: Some(user.0),
+                reason: Some(reason.clone()),
+                until: *until,
+                ..Default::default()
+            },
+            AuditAction::Unban(user) => ActionColumns {
+                code: 1,
+                target_user: Some(user.0),
+                ..Default::default()
+            },
+            AuditAction::Unlock(user) => ActionColumns {
+                code: 2,
+                target_user: Some(user.0),
+                ..Default::default()
+            },
+            AuditAction::Promote { user, permissions } => ActionColumns {
+                code: 3,
+                target_user: Some(user.0),
+                value: Some(permissions.bits()),
+                ..Default::default()
+            },
+            AuditAction::Demote(user) => ActionColumns {
+                code: 4,
+                target_user: Some(user.0),
+                ..Default::default()
+            },
+            AuditAction::SetReportStatus { report, status } => ActionColumns {
+                code: 5,
+                report: Some(*report),
+                value: Some(*status as i8 as i64),
+                ..Default::default()
+            },
+            AuditAction::SetAccountsCompromised(typ) => ActionColumns {
+                code: 6,
+                value: Some(match typ {
+                    SetCompromisedType::All => 0,
+                    SetCompromisedType::OldHashes => 1,
+                }),
+                ..Default::default()
+            },
+        }
+    }
+}
+
+impl ActionColumns {
+    /// Rebuilds the action, or returns `None` if the columns do not describe a valid one
+    fn into_action(self) -> Option<AuditAction> {
+        let user = self.target_user.map(UserId);
+
+        let action = match self.code {
+            0 => AuditAction::Ban {
+                user: user?,
+                reason: self.reason.unwrap_or_default(),
+                until: self.until,
+            },
+            1 => AuditAction::Unban(user?),
+            2 => AuditAction::Unlock(user?),
+            3 => AuditAction::Promote {
+                user: user?,
+                permissions: AdminPermissionFlags::from_bits_truncate(self.value?),
+            },
+            4 => AuditAction::Demote(user?),
+            5 => AuditAction::SetReportStatus {
+                report: self.report?,
+                status: ReportStatus::try_from(i8::try_from(self.value?).ok()?).ok()?,
+            },
+            6 => AuditAction::SetAccountsCompromised(match self.value? {
+                0 => SetCompromisedType::All,
+                1 => SetCompromisedType::OldHashes,
+                _ => return None,
+            }),
+            _ => return None,
+        };
+
+        Some(action)
+    }
+}
+
+fn audit_event_from_row(row: &Row) -> DbResult<Option<AuditEvent>> {
+    let columns = ActionColumns {
+        code: row.try_get("action")?,
+        target_user: row.try_get("target_user")?,
+        report: row.try_get("report")?,
+        reason: row.try_get("reason")?,
+        until: row.try_get("until")?,
+        value: row.try_get("value")?,
+    };
+
+    let id = row.try_get("id")?;
+    let action = match columns.into_action() {
+        Some(action) => action,
+        None => {
+            warn!("Invalid audit log event {}", id);
+            return Ok(None);
+        }
+    };
+
+    Ok(Some(AuditEvent {
+        id,
+        time: row.try_get("time")?,
+        actor: row.try_get::<&str, Option<_>>("actor")?.map(UserId),
+        action,
+    }))
+}
+
+impl Database {
+    /// Records an administrative action. An actor of `None` is the server operator.
+    pub async fn record_audit_event(
+        &self,
+        actor: Option<UserId>,
+        action: &AuditAction,
+    ) -> DbResult<()> {
+        const STMT: &str = "
+            INSERT INTO audit_log (time, actor, action, target_user, report, reason, until, value)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        ";
+
+        let columns = ActionColumns::from(action);
+        let args: &[&(dyn ToSql + Sync)] = &[
+            &Utc::now(),
+            &actor.map(|user| user.0),
+            &columns.code,
+            &columns.target_user,
+            &columns.report,
+            &columns.reason,
+            &columns.until,
+            &columns.value,
+        ];
+
+        let conn = self.pool.connection().await?;
+        conn.client.execute(STMT, args).await?;
+
+        Ok(())
+    }
+
+    /// Gets a page of the audit log, newest first, starting after the event with the given id
+    pub async fn get_audit_log(
+        &self,
+        after: Option<i64>,
+        limit: u32,
+    ) -> DbResult<Vec<AuditEvent>> {
+        const QUERY: &str = "
+            SELECT * FROM audit_log
+                WHERE $1::BIGINT IS NULL OR id < $1
+                ORDER BY id DESC
+                LIMIT $2
+        ";
+
+        let limit = limit.min(SERVER_MAX) as i64;
+        let conn = self.pool.connection().await?;
+        let rows = conn.client.query(QUERY, &[&after, &limit]).await?;
+
+        let mut events = Vec::with_capacity(rows.len());
+        for row in rows.iter() {
+            events.extend(audit_event_from_row(row)?);
+        }
+
+        Ok(events)
+    }
+}
@@ -0,0 +1,83 @@
+use chrono::{DateTime, Utc};
+use tokio_postgres::types::ToSql;
+use vertex::prelude::*;
+
+use super::*;
+
+pub(super) const CREATE_BANS_TABLE: &str = r#"
+    CREATE TABLE IF NOT EXISTS bans (
+        user_id     UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+        banned_by   UUID REFERENCES users(id) ON DELETE SET NULL,
+        reason      VARCHAR NOT NULL,
+        banned_at   TIMESTAMP WITH TIME ZONE NOT NULL,
+        until       TIMESTAMP WITH TIME ZONE
+    )"#;
+
+impl Database {
+    /// Bans the user until the given time, or permanently if there is none, and revokes all of
+    /// their login tokens. Banning an already banned user replaces their ban.
+    pub async fn ban_user(
+        &self,
+        user: UserId,
+        banned_by: UserId,
+        reason: String,
+        until: Option<DateTime<Utc>>,
+    ) -> DbResult<Result<(), NonexistentUser>> {
+        const SET_BANNED: &str = "UPDATE users SET banned = TRUE WHERE id = $1";
+        const INSERT: &str = "
+            INSERT INTO bans (user_id, banned_by, reason, banned_at, until)
+                VALUES ($1, $2, $3, $4, $5)
+                ON CONFLICT (user_id) DO UPDATE
+                    SET banned_by = $2, reason = $3, banned_at = $4, until = $5
+        ";
+        const REVOKE_TOKENS: &str = "DELETE FROM login_tokens WHERE user_id = $1";
+
+        let mut conn = self.pool.connection().await?;
+        let transaction = conn.client.transaction().await?;
+
+        if transaction.execute(SET_BANNED, &[&user.0]).await? != 1 {
+            return Ok(Err(NonexistentUser)); // Transaction is rolled back on drop
+        }
+
+        let args: &[&(dyn ToSql + Sync)] = &[&user.0, &banned_by.0, &reason, &Utc::now(), &until];
+        transaction.execute(INSERT, args).await?;
+        transaction.execute(REVOKE_TOKENS, &[&user.0]).await?;
+        transaction.commit().await?;
+
+        Ok(Ok(()))
+    }
+
+    pub async fn unban_user(&self, user: UserId) -> DbResult<Result<(), NonexistentUser>> {
+        const SET_UNBANNED: &str = "UPDATE users SET banned = FALSE WHERE id = $1";
+        const DELETE: &str = "DELETE FROM bans WHERE user_id = $1";
+
+        let mut conn = self.pool.connection().await?;
+        let transaction = conn.client.transaction().await?;
+
+        if transaction.execute(SET_UNBANNED, &[&user.0]).await? != 1 {
+            return Ok(Err(NonexistentUser));
+        }
+
+        transaction.execute(DELETE, &[&user.0]).await?;
+        transaction.commit().await?;
+
+        Ok(Ok(()))
+    }
+
+    /// Lifts all bans which have expired, returning the users who were unbanned
+    pub(super) async fn unban_expired(&self) -> DbResult<Vec<UserId>> {
+        const QUERY: &str = "
+            WITH expired AS (DELETE FROM bans WHERE until < NOW() RETURNING user_id)
+            UPDATE users SET banned = FALSE FROM expired
+                WHERE users.id = expired.user_id
+                RETURNING users.id
+        ";
+
+        let conn = self.pool.connection().await?;
+        let rows = conn.client.query(QUERY, &[]).await?;
+
+        rows.into_iter()
+            .map(|row| Ok(UserId(row.try_get("id")?)))
+            .collect()
+    }
+}
@@ -0,0 +1,68 @@
+use tokio_postgres::error::SqlState;
+use vertex::prelude::*;
+
+use super::*;
+
+pub(super) const CREATE_BLOCKS_TABLE: &str = r#"
+    CREATE TABLE IF NOT EXISTS blocks (
+        blocker          UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+        blocked          UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+
+        UNIQUE(blocker, blocked)
+    )"#;
+
+impl Database {
+    /// Blocks a user. Blocking a user that is already blocked is a no-op.
+    pub async fn block_user(
+        &self,
+        blocker: UserId,
+        blocked: UserId,
+    ) -> DbResult<Result<(), InvalidUser>> {
+        const STMT: &str = "
+            INSERT INTO blocks (blocker, blocked) VALUES ($1, $2)
+                ON CONFLICT DO NOTHING
+        ";
+
+        let conn = self.pool.connection().await?;
+        let res = conn.client.execute(STMT, &[&blocker.0, &blocked.0]).await;
+
+        match res {
+            Ok(_) => Ok(Ok(())),
+            Err(err) if err.code() == Some(&SqlState::FOREIGN_KEY_VIOLATION) => {
+                Ok(Err(InvalidUser))
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Unblocks a user. Returns whether the user was blocked.
+    pub async fn unblock_user(&self, blocker: UserId, blocked: UserId) -> DbResult<bool> {
+        const STMT: &str = "DELETE FROM blocks WHERE blocker = $1 AND blocked = $2";
+
+        let conn = self.pool.connection().await?;
+        let modified = conn.client.execute(STMT, &[&blocker.0, &blocked.0]).await?;
+
+        Ok(modified > 0)
+    }
+
+    pub async fn get_blocked_users(&self, blocker: UserId) -> DbResult<Vec<UserId>> {
+        const QUERY: &str = "SELECT blocked FROM blocks WHERE blocker = $1";
+
+        let stream = self.query_stream(QUERY, &[&blocker.0]).await?;
+        let blocked = stream
+            .and_then(|row| async move { Ok(UserId(row.try_get("blocked")?)) })
+            .try_collect()
+            .await?;
+
+        Ok(blocked)
+    }
+
+    pub async fn is_blocked(&self, blocker: UserId, blocked: UserId) -> DbResult<bool> {
+        const QUERY: &str = "
+            SELECT EXISTS(SELECT 1 FROM blocks WHERE blocker = $1 AND blocked = $2) AS blocked
+        ";
+
+        let row = self.query_one(QUERY, &[&blocker.0, &blocked.0]).await?;
+        Ok(row.try_get("blocked")?)
+    }
+}
@@ -1,6 +1,8 @@
-use crate::database::{Database, DbResult};
+use crate::database::{Database, DbResult, InvalidUser};
 use futures::{Stream, TryStreamExt};
 use std::convert::TryFrom;
+use tokio_postgres::error::SqlState;
+use tokio_postgres::types::ToSql;
 use tokio_postgres::Row;
 use uuid::Uuid;
 use vertex::prelude::*;
@@ -9,14 +11,44 @@ pub(super) const CREATE_COMMUNITIES_TABLE: &str = "
     CREATE TABLE IF NOT EXISTS communities (
         id   UUID PRIMARY KEY,
         name VARCHAR NOT NULL,
-        description VARCHAR
+        description VARCHAR,
+        icon UUID,
+        retention_days INTEGER NOT NULL DEFAULT 0,
+        public BOOLEAN NOT NULL DEFAULT FALSE,
+        sign_messages BOOLEAN NOT NULL DEFAULT FALSE
     )";
 
+/// Most public communities the server will list at one time
+const MAX_PUBLIC_COMMUNITIES: usize = 50;
+
 #[derive(Debug, Clone)]
 pub struct CommunityRecord {
     pub id: CommunityId,
     pub name: String,
     pub description: Option<String>,
+    pub icon: Option<AttachmentId>,
+    /// Days after which messages are deleted, or 0 to keep them forever
+    pub retention_days: u32,
+    /// Whether anyone can find and join the community without an invite code
+    pub public: bool,
+    /// Whether messages sent to the community are signed with the server's key
+    pub sign_messages: bool,
+}
+
+/// Changes to make to a community. Fields which are `None` are left unchanged.
+#[derive(Debug, Clone, Default)]
+pub struct CommunityEdit {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub icon: Option<AttachmentId>,
+    pub retention_days: Option<u32>,
+    pub public: Option<bool>,
+    pub sign_messages: Option<bool>,
+}
+
+pub enum EditCommunityError {
+    NonexistentCommunity,
+    InvalidIcon,
 }
 
 impl TryFrom<Row> for CommunityRecord {
@@ -27,6 +59,10 @@ impl TryFrom<Row> for CommunityRecord {
             id: CommunityId(row.try_get("id")?),
             name: row.try_get("name")?,
             description: row.try_get("description")?,
+            icon: row.try_get::<&str, Option<_>>("icon")?.map(AttachmentId),
+            retention_days: row.try_get::<&str, i32>("retention_days")? as u32,
+            public: row.try_get("public")?,
+            sign_messages: row.try_get("sign_messages")?,
         })
     }
 }
@@ -46,13 +82,169 @@ impl Database {
         }
     }
 
-    pub async fn create_community(&self, name: String) -> DbResult<CommunityId> {
-        const STMT: &str = "INSERT INTO communities (id, name, description) VALUES ($1, $2, NULL)";
+    /// Creates a community with the given rooms, and makes its creator a member with an owner
+    /// role that has every permission. This is all done in one transaction, so a community is never
+    /// left half-created. Returns the community's id and its rooms, in the order they were given.
+    pub async fn create_community(
+        &self,
+        name: String,
+        creator: UserId,
+        rooms: &[&str],
+    ) -> DbResult<Result<(CommunityId, Vec<(RoomId, String)>), InvalidUser>> {
+        const CREATE_COMMUNITY: &str =
+            "INSERT INTO communities (id, name, description) VALUES ($1, $2, NULL)";
+        const CREATE_ROOM: &str = "INSERT INTO rooms (id, community, name) VALUES ($1, $2, $3)";
+        const ADD_MEMBER: &str =
+            "INSERT INTO community_membership (community, user_id) VALUES ($1, $2)";
+        const CREATE_ROOM_STATES: &str = "
+            INSERT INTO user_room_states (room, user_id, watch_level, last_read)
+                SELECT rooms.id, $1, $2, NULL::BIGINT
+                    FROM rooms
+                    WHERE rooms.community = $3
+        ";
+        const CREATE_OWNER_ROLE: &str = "
+            INSERT INTO roles (id, community, name, permission_flags) VALUES ($1, $2, $3, $4)
+        ";
+        const ASSIGN_OWNER_ROLE: &str = "INSERT INTO member_roles (role, user_id) VALUES ($1, $2)";
+
         let id = Uuid::new_v4();
-        let conn = self.pool.connection().await?;
-        let stmt = conn.client.prepare(STMT).await?;
-        conn.client.execute(&stmt, &[&id, &name]).await?;
-        Ok(CommunityId(id))
+        let mut conn = self.pool.connection().await?;
+        let transaction = conn.client.transaction().await?;
+
+        transaction.execute(CREATE_COMMUNITY, &[&id, &name]).await?;
+
+        let mut created = Vec::with_capacity(rooms.len());
+        for room in rooms {
+            let room_id = Uuid::new_v4();
+            transaction.execute(CREATE_ROOM, &[&room_id, &id, room]).await?;
+            created.push((RoomId(room_id), room.to_string()));
+        }
+
+        // The creator is the only user referenced, so a foreign key violation means they no longer
+        // exist. The transaction is rolled back on drop.
+        match transaction.execute(ADD_MEMBER, &[&id, &creator.0]).await {
+            Ok(_) => {}
+            Err(err) if err.code() == Some(&SqlState::FOREIGN_KEY_VIOLATION) => {
+                return Ok(Err(InvalidUser));
+            }
+            Err(err) => return Err(err.into()),
+        }
+
+        let watch_level = WatchLevel::default() as u8 as i8;
+        let args: &[&(dyn ToSql + Sync)] = &[&creator.0, &watch_level, &id];
+        transaction.execute(CREATE_ROOM_STATES, args).await?;
+
+        let role = Uuid::new_v4();
+        let permissions = CommunityPermissionFlags::ALL.bits();
+        let args: &[&(dyn ToSql + Sync)] = &[&role, &id, &"Owner", &permissions];
+        transaction.execute(CREATE_OWNER_ROLE, args).await?;
+        transaction.execute(ASSIGN_OWNER_ROLE, &[&role, &creator.0]).await?;
+
+        transaction.commit().await?;
+        Ok(Ok((CommunityId(id), created)))
+    }
+
+    /// Gets up to `limit` public communities ordered by name (then id, to break ties), starting
+    /// after the community `after` if given
+    pub async fn get_public_communities(
+        &self,
+        after: Option<CommunityId>,
+        limit: usize,
+    ) -> DbResult<Vec<PublicCommunity>> {
+        const QUERY: &str = "
+            SELECT communities.id, communities.name, communities.description,
+                (
+                    SELECT COUNT(*) FROM community_membership
+                        WHERE community_membership.community = communities.id
+                ) AS member_count
+            FROM communities
+                WHERE communities.public AND (
+                    $1::UUID IS NULL OR (LOWER(communities.name), communities.id) > (
+                        SELECT LOWER(name), id FROM communities WHERE id = $1
+                    )
+                )
+                ORDER BY LOWER(communities.name), communities.id
+                LIMIT $2
+        ";
+
+        let after = after.map(|community| community.0);
+        let limit = limit.min(MAX_PUBLIC_COMMUNITIES) as i64;
+        let stream = self.query_stream(QUERY, &[&after, &limit]).await?;
+        let communities = stream
+            .and_then(|row| async move {
+                Ok(PublicCommunity {
+                    id: CommunityId(row.try_get("id")?),
+                    name: row.try_get("name")?,
+                    description: row
+                        .try_get::<&str, Option<String>>("description")?
+                        .unwrap_or_default(),
+                    member_count: row.try_get::<&str, i64>("member_count")? as u64,
+                })
+            })
+            .try_collect()
+            .await?;
+
+        Ok(communities)
+    }
+
+    /// Deletes a community and, through cascading, its rooms, messages, memberships, roles and
+    /// invite codes. Returns the attachments of its messages, whose files should be removed, or
+    /// `None` if the community did not exist.
+    pub async fn delete_community(&self, id: CommunityId) -> DbResult<Option<Vec<AttachmentId>>> {
+        const DELETE_ATTACHMENTS: &str = "
+            DELETE FROM attachments
+                WHERE message IN (SELECT id FROM messages WHERE community = $1)
+                RETURNING id
+        ";
+        const DELETE_COMMUNITY: &str = "DELETE FROM communities WHERE id = $1";
+
+        let mut conn = self.pool.connection().await?;
+        let transaction = conn.client.transaction().await?;
+
+        let attachments = transaction
+            .query(DELETE_ATTACHMENTS, &[&id.0])
+            .await?
+            .iter()
+            .map(|row| row.try_get("id").map(AttachmentId))
+            .collect::<Result<_, _>>()?;
+
+        if transaction.execute(DELETE_COMMUNITY, &[&id.0]).await? == 0 {
+            return Ok(None); // Transaction is rolled back on drop
+        }
+
+        transaction.commit().await?;
+        Ok(Some(attachments))
+    }
+
+    /// Counts the members, rooms and messages of a community. This scans all of the community's
+    /// messages, so callers should not do it often.
+    pub async fn get_community_stats(&self, id: CommunityId) -> DbResult<CommunityStats> {
+        const QUERY: &str = "
+            SELECT
+                (SELECT COUNT(*) FROM community_membership WHERE community = $1) AS members,
+                (SELECT COUNT(*) FROM rooms WHERE community = $1) AS rooms,
+                (SELECT COUNT(*) FROM messages WHERE community = $1) AS messages,
+                (
+                    SELECT COUNT(*) FROM messages
+                        WHERE community = $1 AND date > NOW() - INTERVAL '1 day'
+                ) AS messages_last_day
+        ";
+
+        let row = self.query_one(QUERY, &[&id.0]).await?;
+        Ok(CommunityStats {
+            members: row.try_get::<&str, i64>("members")? as u64,
+            rooms: row.try_get::<&str, i64>("rooms")? as u64,
+            messages: row.try_get::<&str, i64>("messages")? as u64,
+            messages_last_day: row.try_get::<&str, i64>("messages_last_day")? as u64,
+        })
+    }
+
+    pub async fn is_community_public(&self, id: CommunityId) -> DbResult<bool> {
+        const QUERY: &str = "SELECT public FROM communities WHERE id = $1";
+        match self.query_opt(QUERY, &[&id.0]).await? {
+            Some(row) => Ok(row.try_get("public")?),
+            None => Ok(false),
+        }
     }
 
     pub async fn get_all_communities(
@@ -65,25 +257,64 @@ impl Database {
         Ok(stream)
     }
 
-    pub async fn change_community_description(
+    /// Changes the given fields of a community, leaving those which are `None` as they are. The
+    /// icon must be an image uploaded by `editor` which is not used anywhere else. Returns the
+    /// updated community along with its previous icon, if it was replaced.
+    pub async fn edit_community(
         &self,
         id: CommunityId,
-        new_description: String,
-    ) -> DbResult<()> {
-        const STMT: &str = "UPDATE communities SET description = $1 WHERE id = $2";
-        let conn = self.pool.connection().await?;
-        let stmt = conn.client.prepare(STMT).await?;
-        conn.client
-            .execute(&stmt, &[&new_description, &id.0])
-            .await?;
-        Ok(())
-    }
+        editor: UserId,
+        edit: CommunityEdit,
+    ) -> DbResult<Result<(CommunityRecord, Option<AttachmentId>), EditCommunityError>> {
+        const CHECK_ICON: &str = "
+            SELECT id FROM attachments
+                WHERE id = $1 AND uploader = $2 AND message IS NULL
+                AND content_type LIKE 'image/%'
+                AND NOT EXISTS(SELECT 1 FROM users WHERE users.avatar = attachments.id)
+                AND NOT EXISTS(SELECT 1 FROM communities WHERE communities.icon = attachments.id)
+                FOR UPDATE
+        ";
+        const QUERY: &str = "SELECT icon FROM communities WHERE id = $1 FOR UPDATE";
+        const STMT: &str = "
+            UPDATE communities
+                SET name = COALESCE($2, name),
+                    description = COALESCE($3, description),
+                    icon = COALESCE($4, icon),
+                    retention_days = COALESCE($5, retention_days),
+                    public = COALESCE($6, public),
+                    sign_messages = COALESCE($7, sign_messages)
+                WHERE id = $1
+                RETURNING *
+        ";
+
+        let mut conn = self.pool.connection().await?;
+        let transaction = conn.client.transaction().await?;
+
+        let old_icon = match transaction.query_opt(QUERY, &[&id.0]).await? {
+            Some(row) => row.try_get::<&str, Option<_>>("icon")?.map(AttachmentId),
+            None => return Ok(Err(EditCommunityError::NonexistentCommunity)),
+        };
+
+        let icon = edit.icon;
+        if let Some(icon) = icon {
+            if transaction.query_opt(CHECK_ICON, &[&icon.0, &editor.0]).await?.is_none() {
+                return Ok(Err(EditCommunityError::InvalidIcon)); // Rolled back on drop
+            }
+        }
+
+        let args: &[&(dyn ToSql + Sync)] = &[
+            &id.0,
+            &edit.name,
+            &edit.description,
+            &icon.map(|icon| icon.0),
+            &edit.retention_days.map(|days| days as i32),
+            &edit.public,
+            &edit.sign_messages,
+        ];
+        let row = transaction.query_one(STMT, args).await?;
+        transaction.commit().await?;
 
-    pub async fn change_community_name(&self, id: CommunityId, new_name: String) -> DbResult<()> {
-        const STMT: &str = "UPDATE communities SET name = $1 WHERE id = $2";
-        let conn = self.pool.connection().await?;
-        let stmt = conn.client.prepare(STMT).await?;
-        conn.client.execute(&stmt, &[&new_name, &id.0]).await?;
-        Ok(())
+        let replaced = old_icon.filter(|_| icon.is_some());
+        Ok(Ok((CommunityRecord::try_from(row)?, replaced)))
     }
 }
@@ -1,6 +1,8 @@
+use chrono::{DateTime, Utc};
 use std::convert::TryFrom;
 use std::error::Error;
 use tokio_postgres::error::{DbError, SqlState};
+use tokio_postgres::types::ToSql;
 use tokio_postgres::Row;
 use vertex::prelude::*;
 
@@ -10,6 +12,8 @@ pub(super) const CREATE_COMMUNITY_MEMBERSHIP_TABLE: &str = r#"
     CREATE TABLE IF NOT EXISTS community_membership (
         community        UUID NOT NULL REFERENCES communities(id) ON DELETE CASCADE,
         user_id          UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+        muted            BOOLEAN NOT NULL DEFAULT FALSE,
+        muted_until      TIMESTAMP WITH TIME ZONE,
 
         UNIQUE(user_id, community)
     )"#;
@@ -28,6 +32,22 @@ impl TryFrom<Row> for CommunityMember {
     }
 }
 
+pub struct MemberRecord {
+    pub user: UserId,
+    pub display_name: String,
+}
+
+impl TryFrom<Row> for MemberRecord {
+    type Error = tokio_postgres::Error;
+
+    fn try_from(row: Row) -> Result<MemberRecord, tokio_postgres::Error> {
+        Ok(MemberRecord {
+            user: UserId(row.try_get("id")?),
+            display_name: row.try_get("display_name")?,
+        })
+    }
+}
+
 pub enum AddToCommunityError {
     InvalidUser,
     InvalidCommunity,
@@ -49,6 +69,21 @@ impl Database {
         Ok(stream)
     }
 
+    /// Gets the ids of every member of a community
+    pub async fn get_community_member_ids(
+        &self,
+        community: CommunityId,
+    ) -> DbResult<impl Stream<Item = DbResult<UserId>>> {
+        const QUERY: &str = "SELECT user_id FROM community_membership WHERE community = $1";
+
+        let stream = self.query_stream(QUERY, &[&community.0]).await?;
+        let stream = stream
+            .and_then(|row| async move { Ok(UserId(row.try_get("user_id")?)) })
+            .map_err(|e| e.into());
+
+        Ok(stream)
+    }
+
     pub async fn get_community_membership(
         &self,
         community: CommunityId,
@@ -66,6 +101,78 @@ impl Database {
         }
     }
 
+    /// Gets up to `limit` members of a community ordered by display name (then id, to break ties),
+    /// starting after the member `after` if given.
+    pub async fn get_members_paginated(
+        &self,
+        community: CommunityId,
+        after: Option<UserId>,
+        limit: usize,
+    ) -> DbResult<impl Stream<Item = DbResult<MemberRecord>>> {
+        const QUERY: &str = "
+            SELECT users.id, users.display_name FROM community_membership
+                INNER JOIN users ON users.id = community_membership.user_id
+                WHERE community_membership.community = $1 AND (
+                    $2::UUID IS NULL OR (LOWER(users.display_name), users.id) > (
+                        SELECT LOWER(display_name), id FROM users WHERE id = $2
+                    )
+                )
+                ORDER BY LOWER(users.display_name), users.id
+                LIMIT $3
+        ";
+
+        let after = after.map(|user| user.0);
+        let args: &[&(dyn ToSql + Sync)] = &[&community.0, &after, &(limit as i64)];
+        let stream = self.query_stream(QUERY, args).await?;
+        let stream = stream
+            .and_then(|row| async move { Ok(MemberRecord::try_from(row)?) })
+            .map_err(|e| e.into());
+
+        Ok(stream)
+    }
+
+    /// Mutes or unmutes a member of a community. A mute without an end time lasts until the member
+    /// is unmuted. Returns whether the user was a member of the community.
+    pub async fn set_muted(
+        &self,
+        community: CommunityId,
+        user: UserId,
+        muted: bool,
+        until: Option<DateTime<Utc>>,
+    ) -> DbResult<bool> {
+        const STMT: &str = "
+            UPDATE community_membership SET muted = $3, muted_until = $4
+                WHERE community = $1 AND user_id = $2
+        ";
+
+        let conn = self.pool.connection().await?;
+        let stmt = conn.client.prepare(STMT).await?;
+        let args: &[&(dyn ToSql + Sync)] = &[&community.0, &user.0, &muted, &until];
+        Ok(conn.client.execute(&stmt, args).await? == 1)
+    }
+
+    pub async fn is_muted(&self, community: CommunityId, user: UserId) -> DbResult<bool> {
+        const QUERY: &str = "
+            SELECT 1 FROM community_membership
+                WHERE community = $1 AND user_id = $2
+                    AND muted AND (muted_until IS NULL OR muted_until > NOW())
+        ";
+
+        Ok(self.query_opt(QUERY, &[&community.0, &user.0]).await?.is_some())
+    }
+
+    /// Whether the two users are members of at least one community in common
+    pub async fn share_community(&self, user: UserId, other: UserId) -> DbResult<bool> {
+        const QUERY: &str = "
+            SELECT 1 FROM community_membership a
+                INNER JOIN community_membership b ON a.community = b.community
+                WHERE a.user_id = $1 AND b.user_id = $2
+                LIMIT 1
+        ";
+
+        Ok(self.query_opt(QUERY, &[&user.0, &other.0]).await?.is_some())
+    }
+
     pub async fn add_to_community(
         &self,
         community: CommunityId,
@@ -122,4 +229,46 @@ impl Database {
             }
         }
     }
+
+    /// Removes a user from a community, along with their room states and roles in it. Returns
+    /// whether the user was a member of the community in the first place.
+    pub async fn remove_from_community(
+        &self,
+        community: CommunityId,
+        user: UserId,
+    ) -> DbResult<bool> {
+        const DELETE_MEMBERSHIP: &str = "
+            DELETE FROM community_membership WHERE community = $1 AND user_id = $2
+        ";
+        const DELETE_ROOM_STATES: &str = "
+            DELETE FROM user_room_states
+                USING rooms
+                WHERE rooms.id = user_room_states.room
+                    AND rooms.community = $1
+                    AND user_room_states.user_id = $2
+        ";
+
+        const DELETE_ROLES: &str = "
+            DELETE FROM member_roles
+                USING roles
+                WHERE roles.id = member_roles.role
+                    AND roles.community = $1
+                    AND member_roles.user_id = $2
+        ";
+
+        let mut conn = self.pool.connection().await?;
+        let transaction = conn.client.transaction().await?;
+        let args: &[&(dyn ToSql + Sync)] = &[&community.0, &user.0];
+
+        let removed = transaction.execute(DELETE_MEMBERSHIP, args).await?;
+        if removed == 0 {
+            return Ok(false); // Transaction is rolled back on drop
+        }
+
+        transaction.execute(DELETE_ROOM_STATES, args).await?;
+        transaction.execute(DELETE_ROLES, args).await?;
+        transaction.commit().await?;
+
+        Ok(true)
+    }
 }
@@ -0,0 +1,224 @@
+use std::convert::TryFrom;
+
+use chrono::{DateTime, Utc};
+use tokio_postgres::error::SqlState;
+use tokio_postgres::types::ToSql;
+use tokio_postgres::Row;
+use uuid::Uuid;
+use vertex::prelude::*;
+
+use super::message::{history_page, selector_bound, selects_ascending, SERVER_MAX};
+use super::*;
+
+pub(super) const CREATE_DIRECT_CHANNELS_TABLE: &str = r#"
+    CREATE TABLE IF NOT EXISTS direct_channels (
+        id               UUID PRIMARY KEY,
+        user_a           UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+        user_b           UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+        last_seq         BIGINT NOT NULL DEFAULT 0,
+
+        UNIQUE(user_a, user_b),
+        CHECK(user_a < user_b)
+    )"#;
+
+pub(super) const CREATE_DIRECT_MESSAGES_TABLE: &str = r#"
+    CREATE TABLE IF NOT EXISTS direct_messages (
+        id          UUID PRIMARY KEY,
+        ord         BIGSERIAL,
+        seq         BIGINT NOT NULL,
+        channel     UUID NOT NULL REFERENCES direct_channels(id) ON DELETE CASCADE,
+        author      UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+        date        TIMESTAMP WITH TIME ZONE NOT NULL,
+        content     VARCHAR
+    )"#;
+
+/// Lets the newest date in a channel be found quickly when dating a new message
+pub(super) const CREATE_DIRECT_MESSAGES_DATE_INDEX: &str = r#"
+    CREATE INDEX IF NOT EXISTS direct_messages_channel_date ON direct_messages (channel, date)
+    "#;
+
+/// Direct messages in a channel are ordered by their sequence number, which is unique within the
+/// channel
+pub(super) const CREATE_DIRECT_MESSAGES_SEQ_INDEX: &str = r#"
+    CREATE UNIQUE INDEX IF NOT EXISTS direct_messages_channel_seq ON direct_messages (channel, seq)
+    "#;
+
+/// A direct channel. The two members are stored ordered, so that a pair of users can only have
+/// one channel between them.
+#[derive(Debug, Copy, Clone)]
+pub struct DirectChannelRecord {
+    pub id: DirectChannelId,
+    pub user_a: UserId,
+    pub user_b: UserId,
+}
+
+impl DirectChannelRecord {
+    pub fn has_member(&self, user: UserId) -> bool {
+        self.user_a == user || self.user_b == user
+    }
+
+    pub fn members(&self) -> [UserId; 2] {
+        [self.user_a, self.user_b]
+    }
+}
+
+impl TryFrom<Row> for DirectChannelRecord {
+    type Error = tokio_postgres::Error;
+
+    fn try_from(row: Row) -> Result<DirectChannelRecord, tokio_postgres::Error> {
+        Ok(DirectChannelRecord {
+            id: DirectChannelId(row.try_get("id")?),
+            user_a: UserId(row.try_get("user_a")?),
+            user_b: UserId(row.try_get("user_b")?),
+        })
+    }
+}
+
+impl Database {
+    /// Gets the direct channel between the two users, creating it if it does not exist yet
+    pub async fn open_direct_channel(
+        &self,
+        user: UserId,
+        other: UserId,
+    ) -> DbResult<Result<DirectChannelId, InvalidUser>> {
+        const INSERT: &str = "
+            INSERT INTO direct_channels (id, user_a, user_b) VALUES ($1, $2, $3)
+                ON CONFLICT DO NOTHING
+        ";
+        const QUERY: &str = "SELECT id FROM direct_channels WHERE user_a = $1 AND user_b = $2";
+
+        let (user_a, user_b) = if user < other {
+            (user, other)
+        } else {
+            (other, user)
+        };
+
+        let conn = self.pool.connection().await?;
+        let args: &[&(dyn ToSql + Sync)] = &[&Uuid::new_v4(), &user_a.0, &user_b.0];
+        let res = conn.client.execute(INSERT, args).await;
+
+        if let Err(err) = res {
+            return if err.code() == Some(&SqlState::FOREIGN_KEY_VIOLATION) {
+                Ok(Err(InvalidUser))
+            } else {
+                Err(err.into())
+            };
+        }
+
+        let row = conn.client.query_one(QUERY, &[&user_a.0, &user_b.0]).await?;
+        Ok(Ok(DirectChannelId(row.try_get("id")?)))
+    }
+
+    pub async fn get_direct_channel(
+        &self,
+        id: DirectChannelId,
+    ) -> DbResult<Option<DirectChannelRecord>> {
+        const QUERY: &str = "SELECT * FROM direct_channels WHERE id = $1";
+
+        match self.query_opt(QUERY, &[&id.0]).await? {
+            Some(row) => Ok(Some(DirectChannelRecord::try_from(row)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Creates a direct message, dated no earlier than the newest message in the channel and
+    /// numbered one past the last message sent in it. Returns the sequence number and date it was
+    /// given.
+    pub async fn create_direct_message(
+        &self,
+        id: MessageId,
+        author: UserId,
+        channel: DirectChannelId,
+        date: DateTime<Utc>,
+        content: String,
+    ) -> DbResult<(u64, DateTime<Utc>, ProfileVersion)> {
+        const QUERY: &str = "
+            WITH next(seq) AS
+                (UPDATE direct_channels SET last_seq = last_seq + 1 WHERE id = $2
+                    RETURNING last_seq),
+            inserted AS
+                (INSERT INTO direct_messages (id, seq, channel, author, date, content)
+                    VALUES (
+                        $1, (SELECT seq FROM next), $2, $3,
+                        GREATEST($4, (SELECT MAX(date) FROM direct_messages WHERE channel = $2)),
+                        $5
+                    )
+                    RETURNING seq, author, date
+                )
+            SELECT inserted.seq, inserted.date, users.profile_version FROM inserted
+            INNER JOIN users ON inserted.author = users.id
+        ";
+
+        let args: &[&(dyn ToSql + Sync)] = &[&id.0, &channel.0, &author.0, &date, &Some(content)];
+        let row = self.query_one(QUERY, args).await?;
+        let seq = row.try_get::<&str, i64>("seq")? as u64;
+        let date = row.try_get("date")?;
+        let profile_version = ProfileVersion(row.try_get::<&str, i32>("profile_version")? as u32);
+
+        Ok((seq, date, profile_version))
+    }
+
+    /// Gets messages in the channel, newest first. With no selector, the newest messages are
+    /// returned.
+    pub async fn get_direct_messages(
+        &self,
+        channel: DirectChannelId,
+        selector: Option<MessageSelector>,
+        count: usize,
+    ) -> DbResult<Result<MessageHistory, InvalidSelector>> {
+        const SEQ_QUERY: &str = "SELECT seq FROM direct_messages WHERE id = $1 AND channel = $2";
+
+        let ascending = selector.as_ref().map_or(false, selects_ascending);
+        let count = count.min(SERVER_MAX);
+
+        let (bound_seq, comparator) = match selector {
+            Some(selector) => match selector_bound(&selector) {
+                (MessagePosition::Id(id), comparator) => {
+                    match self.query_opt(SEQ_QUERY, &[&id.0, &channel.0]).await? {
+                        Some(row) => (row.try_get::<&str, i64>("seq")?, comparator),
+                        None => return Ok(Err(InvalidSelector)),
+                    }
+                }
+                (MessagePosition::Seq(seq), comparator) => (seq as i64, comparator),
+            },
+            None => (i64::MAX, "<=".to_owned()),
+        };
+
+        let query = format!(
+            "SELECT direct_messages.*, users.profile_version FROM direct_messages
+            INNER JOIN users ON direct_messages.author = users.id
+                WHERE direct_messages.channel = $1
+                AND direct_messages.seq {} $3
+                ORDER BY seq {}
+                LIMIT $2",
+            comparator,
+            if ascending { "ASC" } else { "DESC" },
+        );
+
+        // One extra message is fetched to tell whether there are more past the page
+        let args: &[&(dyn ToSql + Sync)] = &[&channel.0, &(count as i64 + 1), &bound_seq];
+        let stream = self.query_stream(&query, args).await?;
+        let messages = stream
+            .and_then(|row| async move {
+                Ok(Message {
+                    id: MessageId(row.try_get("id")?),
+                    author: UserId(row.try_get("author")?),
+                    author_profile_version: ProfileVersion(
+                        row.try_get::<&str, i32>("profile_version")? as u32,
+                    ),
+                    time_sent: row.try_get("date")?,
+                    content: row.try_get("content")?,
+                    reactions: Vec::new(),
+                    attachments: Vec::new(),
+                    reply_to: None,
+                    kind: MessageKind::Normal,
+                    seq: row.try_get::<&str, i64>("seq")? as u64,
+                    signature: None,
+                })
+            })
+            .try_collect()
+            .await?;
+
+        Ok(Ok(history_page(messages, ascending, count)))
+    }
+}
@@ -2,6 +2,7 @@ use std::io::Cursor;
 
 use byteorder::{LittleEndian, ReadBytesExt};
 use chrono::{DateTime, Utc};
+use futures::TryStreamExt;
 use rand::Rng;
 use tokio_postgres::types::ToSql;
 use tokio_postgres::IsolationLevel;
@@ -14,7 +15,10 @@ pub(super) const CREATE_INVITE_CODES_TABLE: &str = "
     CREATE TABLE IF NOT EXISTS invite_codes (
         id BIGINT PRIMARY KEY,
         community UUID NOT NULL REFERENCES communities(id) ON DELETE CASCADE,
-        expiration_date TIMESTAMP WITH TIME ZONE
+        expiration_date TIMESTAMP WITH TIME ZONE,
+        max_uses INTEGER,
+        uses INTEGER NOT NULL DEFAULT 0,
+        vanity TEXT UNIQUE
     )";
 
 #[derive(Copy, Clone, Debug)]
@@ -24,6 +28,7 @@ pub struct MalformedInviteCode;
 pub struct InviteCodeRecord {
     pub id: i64,
     pub expiration_date: Option<DateTime<Utc>>,
+    pub vanity: Option<String>,
 }
 
 impl InviteCodeRecord {
@@ -35,45 +40,82 @@ impl InviteCodeRecord {
             .read_i64::<LittleEndian>()
             .map_err(|_| MalformedInviteCode)
     }
+
+    /// Parses the code into the id and vanity code to look it up by. Generated codes encode the
+    /// id, while vanity codes are looked up as they are.
+    fn parse_key(code: &InviteCode) -> Result<(Option<i64>, Option<&str>), MalformedInviteCode> {
+        if InviteCode::is_valid_vanity(&code.0) {
+            Ok((None, Some(&code.0)))
+        } else {
+            InviteCodeRecord::parse_id(code.clone()).map(|id| (Some(id), None))
+        }
+    }
 }
 
 impl Into<String> for InviteCodeRecord {
     fn into(self) -> String {
-        base64::encode_config(&self.id.to_le_bytes(), base64::URL_SAFE_NO_PAD)
+        match self.vanity {
+            Some(vanity) => vanity,
+            None => base64::encode_config(&self.id.to_le_bytes(), base64::URL_SAFE_NO_PAD),
+        }
     }
 }
 
-pub struct TooManyInviteCodes;
+pub enum CreateInviteCodeError {
+    TooManyInviteCodes,
+    VanityTaken,
+}
 
 impl Database {
+    /// Creates an invite code, unless the community already has `max_per_community` codes which
+    /// can still be used. Expired and used up codes do not count.
     pub async fn create_invite_code(
         &self,
         community: CommunityId,
         expiration_date: Option<DateTime<Utc>>,
+        max_uses: Option<u32>,
+        vanity: Option<String>,
         max_per_community: i64,
-    ) -> DbResult<Result<InviteCode, TooManyInviteCodes>> {
+    ) -> DbResult<Result<InviteCode, CreateInviteCodeError>> {
         // From https://stackoverflow.com/a/26448803/4871468
         const INSERT: &str = "
-            INSERT INTO invite_codes (id, community, expiration_date)
+            INSERT INTO invite_codes (id, community, expiration_date, max_uses, vanity)
             SELECT
               $1 AS id,
               $2 AS community,
-              $3 AS expiration_date
+              $3 AS expiration_date,
+              $5 AS max_uses,
+              $6 AS vanity
             FROM
               invite_codes
             WHERE community = $2
+                AND (expiration_date IS NULL OR expiration_date > NOW())
+                AND (max_uses IS NULL OR uses < max_uses)
             HAVING
               COUNT(*) < $4
             ON CONFLICT DO NOTHING;
         ";
-        const COUNT: &str = "SELECT COUNT(*) FROM invite_codes WHERE community = $1;";
+        const COUNT: &str = "
+            SELECT COUNT(*) FROM invite_codes
+                WHERE community = $1
+                    AND (expiration_date IS NULL OR expiration_date > NOW())
+                    AND (max_uses IS NULL OR uses < max_uses)
+        ";
+        const VANITY_TAKEN: &str = "SELECT EXISTS(SELECT 1 FROM invite_codes WHERE vanity = $1)";
 
+        let max_uses = max_uses.map(|max| max.min(i32::MAX as u32) as i32);
         let mut conn = self.pool.connection().await?;
 
         let id = loop {
             let id = rand::thread_rng().gen::<i64>();
-            let args: &[&(dyn ToSql + Sync)] =
-                &[&id, &community.0, &expiration_date, &max_per_community];
+            let args: &[&(dyn ToSql + Sync)] = &[
+                &id,
+                &community.0,
+                &expiration_date,
+                &max_per_community,
+                &max_uses,
+                &vanity,
+            ];
 
             let builder = conn.client.build_transaction();
             let insert_transaction = builder
@@ -102,7 +144,14 @@ impl Database {
 
                 if count >= max_per_community {
                     // Failed because of many invite codes
-                    return Ok(Err(TooManyInviteCodes));
+                    return Ok(Err(CreateInviteCodeError::TooManyInviteCodes));
+                }
+
+                if let Some(vanity) = &vanity {
+                    let row = conn.client.query_one(VANITY_TAKEN, &[vanity]).await?;
+                    if row.try_get(0)? {
+                        return Ok(Err(CreateInviteCodeError::VanityTaken));
+                    }
                 } // ... or else it failed because of conflicting ID
             }
         };
@@ -110,6 +159,7 @@ impl Database {
         let record = InviteCodeRecord {
             id,
             expiration_date,
+            vanity,
         };
         Ok(Ok(InviteCode(record.into())))
     }
@@ -119,19 +169,94 @@ impl Database {
         code: InviteCode,
     ) -> DbResult<Result<Option<CommunityId>, MalformedInviteCode>> {
         const QUERY: &str = "
-            SELECT community FROM invite_codes WHERE id=$1
+            SELECT community FROM invite_codes WHERE id = $1 OR vanity = $2
         ";
 
-        let id = match InviteCodeRecord::parse_id(code) {
-            Ok(id) => id,
+        let (id, vanity) = match InviteCodeRecord::parse_key(&code) {
+            Ok(key) => key,
             Err(e) => return Ok(Err(e)),
         };
 
-        let community = match self.query_opt(QUERY, &[&id]).await? {
+        let community = match self.query_opt(QUERY, &[&id, &vanity]).await? {
             Some(row) => Some(CommunityId(row.try_get("community")?)),
             None => None,
         };
 
         Ok(Ok(community))
     }
+
+    /// Uses up one use of an invite code, returning the community it is for. Returns `None` if the
+    /// code does not exist, has expired, or has no uses left. The check and the increment are done
+    /// in a single statement, so concurrent joins cannot use a code more times than its limit.
+    pub async fn use_invite_code(
+        &self,
+        code: &InviteCode,
+    ) -> DbResult<Result<Option<CommunityId>, MalformedInviteCode>> {
+        const STMT: &str = "
+            UPDATE invite_codes SET uses = uses + 1
+                WHERE (id = $1 OR vanity = $2)
+                    AND (expiration_date IS NULL OR expiration_date > NOW())
+                    AND (max_uses IS NULL OR uses < max_uses)
+                RETURNING community
+        ";
+
+        let (id, vanity) = match InviteCodeRecord::parse_key(code) {
+            Ok(key) => key,
+            Err(e) => return Ok(Err(e)),
+        };
+
+        let community = match self.query_opt(STMT, &[&id, &vanity]).await? {
+            Some(row) => Some(CommunityId(row.try_get("community")?)),
+            None => None,
+        };
+
+        Ok(Ok(community))
+    }
+
+    /// Gives back a use of an invite code taken by `use_invite_code`, for when the join it was
+    /// taken for failed
+    pub async fn refund_invite_code_use(&self, code: &InviteCode) -> DbResult<()> {
+        const STMT: &str = "
+            UPDATE invite_codes SET uses = uses - 1 WHERE (id = $1 OR vanity = $2) AND uses > 0
+        ";
+
+        if let Ok((id, vanity)) = InviteCodeRecord::parse_key(code) {
+            let conn = self.pool.connection().await?;
+            conn.client.execute(STMT, &[&id, &vanity]).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Gets the invite codes of a community that have neither expired nor been used up
+    pub async fn get_invite_codes(&self, community: CommunityId) -> DbResult<Vec<InviteCodeInfo>> {
+        const QUERY: &str = "
+            SELECT id, expiration_date, vanity, max_uses - uses AS remaining_uses FROM invite_codes
+                WHERE community = $1
+                    AND (expiration_date IS NULL OR expiration_date > NOW())
+                    AND (max_uses IS NULL OR uses < max_uses)
+                ORDER BY expiration_date ASC NULLS LAST
+        ";
+
+        let stream = self.query_stream(QUERY, &[&community.0]).await?;
+        let codes = stream
+            .and_then(|row| async move {
+                let record = InviteCodeRecord {
+                    id: row.try_get("id")?,
+                    expiration_date: row.try_get("expiration_date")?,
+                    vanity: row.try_get("vanity")?,
+                };
+                let remaining_uses: Option<i32> = row.try_get("remaining_uses")?;
+
+                Ok(InviteCodeInfo {
+                    expiration_date: record.expiration_date,
+                    code: InviteCode(record.into()),
+                    remaining_uses: remaining_uses.map(|n| n as u32),
+                })
+            })
+            .try_collect()
+            .await?;
+
+        Ok(codes)
+    }
 }
@@ -0,0 +1,27 @@
+use vertex::prelude::*;
+
+use super::*;
+
+pub(super) const CREATE_MENTIONS_TABLE: &str = r#"
+    CREATE TABLE IF NOT EXISTS mentions (
+        message     UUID NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+        user_id     UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+
+        PRIMARY KEY (message, user_id)
+    )"#;
+
+impl Database {
+    /// Records that the user was mentioned in the message. Returns whether they had not already
+    /// been recorded as mentioned in it.
+    pub async fn add_mention(&self, message: MessageId, user: UserId) -> DbResult<bool> {
+        const STMT: &str = "
+            INSERT INTO mentions (message, user_id) VALUES ($1, $2)
+                ON CONFLICT DO NOTHING
+        ";
+
+        let conn = self.pool.connection().await?;
+        let res = conn.client.execute(STMT, &[&message.0, &user.0]).await?;
+
+        Ok(res == 1)
+    }
+}
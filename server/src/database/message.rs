@@ -2,13 +2,21 @@ use std::convert::TryFrom;
 
 use chrono::{DateTime, Utc};
 use futures::{Stream, TryStream, TryStreamExt};
+use tokio_postgres::types::ToSql;
 use tokio_postgres::Row;
+use uuid::Uuid;
 
-use crate::database::{Database, DatabaseError, DbResult};
+use crate::database::{Database, DatabaseError, DbResult, InvalidAttachment};
 use vertex::prelude::*;
 
 /// Max messages the server will return at one time
-const SERVER_MAX: usize = 50;
+pub(super) const SERVER_MAX: usize = 50;
+
+/// Shortest search query the server will accept, to avoid matching nearly every message
+pub const MIN_SEARCH_QUERY_LEN: usize = 3;
+
+/// Longest that the quoted content of a replied-to message will be, in characters
+pub const REPLY_PREVIEW_LEN: usize = 100;
 
 #[derive(Debug, Copy, Clone)]
 pub struct InvalidSelector;
@@ -20,23 +28,61 @@ pub(super) const CREATE_MESSAGES_TABLE: &str = "
     CREATE TABLE IF NOT EXISTS messages (
         id          UUID PRIMARY KEY,
         ord         BIGSERIAL,
+        seq         BIGINT NOT NULL,
         author      UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
         community   UUID NOT NULL REFERENCES communities(id) ON DELETE CASCADE,
         room        UUID NOT NULL REFERENCES rooms(id) ON DELETE CASCADE,
         date        TIMESTAMP WITH TIME ZONE NOT NULL,
-        content     VARCHAR
+        content     VARCHAR,
+        reply_to    UUID REFERENCES messages(id) ON DELETE SET NULL,
+        kind        SMALLINT NOT NULL DEFAULT 0,
+        signature   BYTEA
     )
     ";
 
+pub(super) const CREATE_MESSAGES_SEARCH_INDEX: &str = "
+    CREATE INDEX IF NOT EXISTS messages_content_search
+        ON messages USING GIN (to_tsvector('english', content))
+    ";
+
+/// Messages in a room are ordered by their sequence number, which is unique within the room
+pub(super) const CREATE_MESSAGES_SEQ_INDEX: &str = "
+    CREATE UNIQUE INDEX IF NOT EXISTS messages_room_seq ON messages (room, seq)
+    ";
+
+/// Messages past their community's retention period are found by community and date
+pub(super) const CREATE_MESSAGES_DATE_INDEX: &str = "
+    CREATE INDEX IF NOT EXISTS messages_community_date ON messages (community, date)
+    ";
+
 #[derive(Debug)]
 pub struct MessageRecord {
     pub id: MessageId,
     pub ord: MessageOrdinal,
+    pub seq: u64,
     pub author: UserId,
     pub community: CommunityId,
     pub room: RoomId,
     pub date: DateTime<Utc>,
     pub content: Option<String>,
+    pub reply_to: Option<MessageId>,
+    pub kind: MessageKind,
+    /// The server's signature of the message as it was sent, if its community signs messages
+    pub signature: Option<Vec<u8>>,
+}
+
+impl MessageRecord {
+    /// A preview of this message, to be quoted by replies to it
+    pub fn reply_preview(&self) -> ReplyPreview {
+        ReplyPreview {
+            message: self.id,
+            author: self.author,
+            content: self
+                .content
+                .as_ref()
+                .map(|content| content.chars().take(REPLY_PREVIEW_LEN).collect()),
+        }
+    }
 }
 
 impl TryFrom<Row> for MessageRecord {
@@ -46,16 +92,37 @@ impl TryFrom<Row> for MessageRecord {
         Ok(MessageRecord {
             id: MessageId(row.try_get("id")?),
             ord: MessageOrdinal(row.try_get::<&str, i64>("ord")? as u64),
+            seq: row.try_get::<&str, i64>("seq")? as u64,
             author: UserId(row.try_get("author")?),
             community: CommunityId(row.try_get("community")?),
             room: RoomId(row.try_get("room")?),
             date: row.try_get("date")?,
             content: row.try_get("content")?,
+            reply_to: row.try_get::<&str, Option<_>>("reply_to")?.map(MessageId),
+            kind: message_kind_from_db(row.try_get("kind")?),
+            signature: row.try_get("signature")?,
         })
     }
 }
 
+fn message_kind_from_db(val: i16) -> MessageKind {
+    match val {
+        0 => MessageKind::Normal,
+        1 => MessageKind::System,
+        _ => panic!("Invalid message kind {}", val),
+    }
+}
+
 impl Database {
+    /// Creates a message with the given attachments, optionally as a reply to another message. The
+    /// attachments must have been uploaded by the author and not sent with any other message or
+    /// used as an avatar or icon, or else nothing is created.
+    ///
+    /// The message is dated no earlier than the last message sent in the room, even if the clock
+    /// has gone back since. It is numbered one past that message. Since dates never go back as
+    /// sequence numbers go up, the last message is found through the `messages_room_seq` index. The
+    /// sequence number and date it was given are returned.
+    #[allow(clippy::too_many_arguments)]
     pub async fn create_message(
         &self,
         id: MessageId,
@@ -64,35 +131,92 @@ impl Database {
         room: RoomId,
         date: DateTime<Utc>,
         content: String,
-    ) -> DbResult<(MessageOrdinal, ProfileVersion)> {
+        attachments: &[AttachmentId],
+        reply_to: Option<MessageId>,
+        kind: MessageKind,
+    ) -> DbResult<Result<(u64, DateTime<Utc>, ProfileVersion), InvalidAttachment>> {
         const QUERY: &str = "
-            WITH inserted AS
-                (INSERT INTO messages (id, author, community, room, date, content)
-                    VALUES ($1, $2, $3, $4, $5, $6)
-                    RETURNING ord, author
+            WITH next(seq) AS
+                (UPDATE rooms SET last_seq = last_seq + 1 WHERE id = $4 RETURNING last_seq),
+            inserted AS
+                (INSERT INTO messages
+                    (id, seq, author, community, room, date, content, reply_to, kind)
+                    VALUES (
+                        $1, (SELECT seq FROM next), $2, $3, $4,
+                        GREATEST($5, (
+                            SELECT date FROM messages WHERE room = $4 ORDER BY seq DESC LIMIT 1
+                        )),
+                        $6, $7, $8
+                    )
+                    RETURNING seq, author, date
                 )
-            SELECT inserted.ord, users.profile_version FROM inserted
+            SELECT inserted.seq, inserted.date, users.profile_version FROM inserted
             INNER JOIN users ON inserted.author = users.id
         ";
+        const ATTACH: &str = "
+            UPDATE attachments SET message = $1
+                WHERE id = ANY($2) AND uploader = $3 AND message IS NULL
+                AND NOT EXISTS(SELECT 1 FROM users WHERE users.avatar = attachments.id)
+                AND NOT EXISTS(SELECT 1 FROM communities WHERE communities.icon = attachments.id)
+        ";
 
-        let row = self
-            .query_one(
-                QUERY,
-                &[
-                    &id.0,
-                    &author.0,
-                    &community.0,
-                    &room.0,
-                    &date,
-                    &Some(content),
-                ],
-            )
-            .await?;
+        let mut conn = self.pool.connection().await?;
+        let transaction = conn.client.transaction().await?;
+
+        let reply_to = reply_to.map(|message| message.0);
+        let kind = kind as u8 as i16;
+        let args: &[&(dyn ToSql + Sync)] =
+            &[&id.0, &author.0, &community.0, &room.0, &date, &Some(content), &reply_to, &kind];
+        let row = transaction.query_one(QUERY, args).await?;
+
+        if !attachments.is_empty() {
+            let ids: Vec<Uuid> = attachments.iter().map(|a| a.0).collect();
+            let attached = transaction.execute(ATTACH, &[&id.0, &ids, &author.0]).await?;
+
+            if attached != attachments.len() as u64 {
+                return Ok(Err(InvalidAttachment)); // Transaction is rolled back on drop
+            }
+        }
+
+        transaction.commit().await?;
 
-        let ord = MessageOrdinal(row.try_get::<&str, i64>("ord")? as u64);
+        let seq = row.try_get::<&str, i64>("seq")? as u64;
+        let date = row.try_get("date")?;
         let profile_version = ProfileVersion(row.try_get::<&str, i32>("profile_version")? as u32);
 
-        Ok((ord, profile_version))
+        Ok(Ok((seq, date, profile_version)))
+    }
+
+    /// Soft-deletes a message by clearing its content, edit history and attachments, so that it
+    /// still keeps its place in the history. Returns the attachments, whose files should be
+    /// removed, or `None` if the message was already deleted.
+    pub async fn delete_message(&self, id: MessageId) -> DbResult<Option<Vec<AttachmentId>>> {
+        const STMT: &str = "
+            UPDATE messages SET content = NULL, signature = NULL
+                WHERE id = $1 AND content IS NOT NULL
+        ";
+        const DELETE_EDITS: &str = "DELETE FROM message_edits WHERE message = $1";
+        const DELETE_PIN: &str = "DELETE FROM pinned_messages WHERE message = $1";
+        const DELETE_ATTACHMENTS: &str = "DELETE FROM attachments WHERE message = $1 RETURNING id";
+
+        let mut conn = self.pool.connection().await?;
+        let transaction = conn.client.transaction().await?;
+        let modified = transaction.execute(STMT, &[&id.0]).await?;
+        if modified == 0 {
+            return Ok(None); // Transaction is rolled back on drop
+        }
+
+        transaction.execute(DELETE_EDITS, &[&id.0]).await?;
+        transaction.execute(DELETE_PIN, &[&id.0]).await?;
+        let attachments = transaction
+            .query(DELETE_ATTACHMENTS, &[&id.0])
+            .await?
+            .iter()
+            .map(|row| row.try_get("id").map(AttachmentId))
+            .collect::<Result<_, _>>()?;
+        transaction.commit().await?;
+
+        Ok(Some(attachments))
     }
 
     pub async fn get_newest_message(
@@ -116,6 +240,97 @@ impl Database {
         }
     }
 
+    /// Searches the content of messages in the community, newest first. Only rooms that the user
+    /// is a member of are searched.
+    pub async fn search_messages(
+        &self,
+        community: CommunityId,
+        user: UserId,
+        query: &str,
+        limit: usize,
+    ) -> DbResult<Vec<SearchResult>> {
+        const QUERY: &str = "
+            SELECT messages.id, messages.room,
+                ts_headline('english', messages.content, plainto_tsquery('english', $3)) AS snippet
+            FROM messages
+                INNER JOIN user_room_states
+                    ON user_room_states.room = messages.room AND user_room_states.user_id = $2
+            WHERE messages.community = $1
+                AND messages.content IS NOT NULL
+                AND to_tsvector('english', messages.content) @@ plainto_tsquery('english', $3)
+            ORDER BY messages.ord DESC
+            LIMIT $4
+        ";
+
+        let args: &[&(dyn ToSql + Sync)] =
+            &[&community.0, &user.0, &query, &(limit.min(SERVER_MAX) as i64)];
+        let stream = self.query_stream(QUERY, args).await?;
+        let results = stream
+            .and_then(|row| async move {
+                Ok(SearchResult {
+                    message: MessageId(row.try_get("id")?),
+                    room: RoomId(row.try_get("room")?),
+                    snippet: row.try_get("snippet")?,
+                })
+            })
+            .try_collect()
+            .await?;
+
+        Ok(results)
+    }
+
+    /// Deletes up to `limit` messages which are older than their community's retention period,
+    /// returning how many were deleted and the attachments which belonged to them
+    pub async fn delete_expired_messages(
+        &self,
+        limit: usize,
+    ) -> DbResult<(usize, Vec<AttachmentId>)> {
+        const QUERY: &str = "
+            SELECT messages.id FROM communities
+                INNER JOIN messages ON messages.community = communities.id
+            WHERE communities.retention_days > 0
+                AND messages.date < NOW() - communities.retention_days * INTERVAL '1 day'
+            LIMIT $1
+        ";
+        const DELETE_ATTACHMENTS: &str =
+            "DELETE FROM attachments WHERE message = ANY($1) RETURNING id";
+        const DELETE_MESSAGES: &str = "DELETE FROM messages WHERE id = ANY($1)";
+
+        let mut conn = self.pool.connection().await?;
+        let transaction = conn.client.transaction().await?;
+
+        let expired: Vec<Uuid> = transaction
+            .query(QUERY, &[&(limit as i64)])
+            .await?
+            .iter()
+            .map(|row| row.try_get("id"))
+            .collect::<Result<_, _>>()?;
+
+        if expired.is_empty() {
+            return Ok((0, Vec::new()));
+        }
+
+        let attachments = transaction
+            .query(DELETE_ATTACHMENTS, &[&expired])
+            .await?
+            .iter()
+            .map(|row| row.try_get("id").map(AttachmentId))
+            .collect::<Result<_, _>>()?;
+        transaction.execute(DELETE_MESSAGES, &[&expired]).await?;
+        transaction.commit().await?;
+
+        Ok((expired.len(), attachments))
+    }
+
+    /// Stores the signature of a message which has just been created
+    pub async fn set_message_signature(&self, id: MessageId, signature: &[u8]) -> DbResult<()> {
+        const STMT: &str = "UPDATE messages SET signature = $2 WHERE id = $1";
+
+        let conn = self.pool.connection().await?;
+        conn.client.execute(STMT, &[&id.0, &signature]).await?;
+        Ok(())
+    }
+
     pub async fn get_message_by_id(&self, id: MessageId) -> DbResult<Option<MessageRecord>> {
         const QUERY: &str = "SELECT * FROM messages WHERE id = $1";
         match self.query_opt(QUERY, &[&id.0]).await? {
@@ -132,82 +347,185 @@ impl Database {
         }
     }
 
-    pub async fn get_message_ord(&self, id: MessageId) -> DbResult<Option<MessageOrdinal>> {
-        const QUERY: &str = "SELECT ord FROM messages WHERE id = $1";
-        match self.query_opt(QUERY, &[&id.0]).await? {
-            Some(row) => Ok(Some(
-                MessageOrdinal(row.try_get::<&str, i64>("ord")? as u64),
-            )),
-            None => Ok(None),
-        }
-    }
 
+    /// Gets a page of up to `count` messages in a room next to the selector's bound, newest first.
+    /// Messages are ordered by their sequence number. Messages from users that the viewer has
+    /// blocked are left out.
     pub async fn get_messages(
         &self,
+        viewer: UserId,
         community: CommunityId,
         room: RoomId,
         selector: MessageSelector,
         count: usize,
-    ) -> DbResult<
-        Result<impl Stream<Item = DbResult<(ProfileVersion, MessageRecord)>>, InvalidSelector>,
-    > {
-        let bound = match selector {
-            MessageSelector::Before(bound) => bound,
-            MessageSelector::After(bound) => bound,
-        };
-
-        let bound_message = match self.get_message_ord(*bound.get()).await? {
-            Some(message) => message,
-            None => return Ok(Err(InvalidSelector)),
-        };
-
-        let comparator = match selector {
-            MessageSelector::Before(_) => "<",
-            MessageSelector::After(_) => ">",
-        };
+    ) -> DbResult<Result<MessageHistory, InvalidSelector>> {
+        let (bound, comparator) = selector_bound(&selector);
+        let ascending = selects_ascending(&selector);
+        let count = count.min(SERVER_MAX);
 
-        let comparator = match bound {
-            Bound::Inclusive(_) => format!("{}=", comparator),
-            _ => comparator.to_owned(),
+        let bound_seq = match bound {
+            MessagePosition::Id(id) => match self.get_message_by_id(id).await? {
+                Some(message) if message.room == room => message.seq,
+                _ => return Ok(Err(InvalidSelector)),
+            },
+            MessagePosition::Seq(seq) => seq,
         };
 
         let query = format!(
-            "SELECT messages.*, users.profile_version FROM messages
+            "SELECT {}
+            FROM messages
             INNER JOIN users ON messages.author = users.id
+            LEFT JOIN messages parent ON messages.reply_to = parent.id
                 WHERE messages.community = $1 AND messages.room = $2
-                AND messages.ord {} $4
-                ORDER BY ord DESC
+                AND messages.seq {} $4
+                AND NOT EXISTS(
+                    SELECT 1 FROM blocks
+                        WHERE blocks.blocker = $5 AND blocks.blocked = messages.author
+                )
+                ORDER BY messages.seq {order}
                 LIMIT $3",
-            comparator
+            message_row_columns(),
+            comparator,
+            order = if ascending { "ASC" } else { "DESC" },
         );
 
-        let stream = self
-            .query_stream(
-                &query,
-                &[
-                    &community.0,
-                    &room.0,
-                    &(count.min(SERVER_MAX) as i64),
-                    &(bound_message.0 as i64),
-                ],
-            )
+        // One extra message is fetched to tell whether there are more past the page
+        let args: &[&(dyn ToSql + Sync)] = &[
+            &community.0,
+            &room.0,
+            &(count as i64 + 1),
+            &(bound_seq as i64),
+            &viewer.0,
+        ];
+        let stream = self.query_stream(&query, args).await?;
+        let messages = stream
+            .and_then(|row| async move { MessageRow::try_from(row) })
+            .map_err(|e| e.into())
+            .map_messages()
+            .try_collect()
             .await?;
 
-        let stream = stream
-            .and_then(|row| async move {
-                let profile_version = row.try_get::<&str, i32>("profile_version")?;
-                Ok((
-                    ProfileVersion(profile_version as u32),
-                    MessageRecord::try_from(row)?,
-                ))
+        Ok(Ok(history_page(messages, ascending, count)))
+    }
+}
+
+/// Whether messages for the selector are fetched from oldest to newest, so that the page starts
+/// right after the bound rather than at the newest messages
+pub(super) fn selects_ascending(selector: &MessageSelector) -> bool {
+    matches!(selector, MessageSelector::After(_))
+}
+
+/// Builds a page of history from messages ordered from nearest to furthest from the bound, which
+/// were fetched with a limit of one more than `count`
+pub(super) fn history_page(
+    messages: Vec<Message>,
+    ascending: bool,
+    count: usize,
+) -> MessageHistory {
+    let mut messages = messages;
+    let has_more = messages.len() > count;
+    messages.truncate(count);
+
+    if ascending {
+        messages.reverse();
+    }
+
+    MessageHistory::from_newest_to_oldest(messages, has_more)
+}
+
+/// The position bounding the selector, and the SQL comparator to select messages by their
+/// sequence number relative to it
+pub(super) fn selector_bound(selector: &MessageSelector) -> (MessagePosition, String) {
+    let bound = match selector {
+        MessageSelector::Before(bound) => bound,
+        MessageSelector::After(bound) => bound,
+    };
+
+    let comparator = match selector {
+        MessageSelector::Before(_) => "<",
+        MessageSelector::After(_) => ">",
+    };
+
+    let comparator = match bound {
+        Bound::Inclusive(_) => format!("{}=", comparator),
+        _ => comparator.to_owned(),
+    };
+
+    (*bound.get(), comparator)
+}
+
+/// The columns selected to build a [`MessageRow`]. The query must join `users` on the author and
+/// left join the replied-to message as `parent`.
+pub(super) fn message_row_columns() -> String {
+    format!(
+        "messages.*, users.profile_version,
+        ARRAY(
+            SELECT emoji FROM reactions WHERE reactions.message = messages.id
+            GROUP BY emoji ORDER BY emoji
+        ) AS reaction_emoji,
+        ARRAY(
+            SELECT COUNT(*) FROM reactions WHERE reactions.message = messages.id
+            GROUP BY emoji ORDER BY emoji
+        ) AS reaction_counts,
+        ARRAY(
+            SELECT id FROM attachments WHERE attachments.message = messages.id
+            ORDER BY uploaded_at
+        ) AS attachment_ids,
+        parent.author AS reply_author, LEFT(parent.content, {}) AS reply_content",
+        REPLY_PREVIEW_LEN
+    )
+}
+
+/// A message returned from a history query, along with the data needed to build a [`Message`]
+pub struct MessageRow {
+    pub profile_version: ProfileVersion,
+    pub record: MessageRecord,
+    pub reactions: Vec<ReactionSummary>,
+    pub attachments: Vec<AttachmentId>,
+    pub reply_to: Option<ReplyPreview>,
+}
+
+impl TryFrom<Row> for MessageRow {
+    type Error = tokio_postgres::Error;
+
+    fn try_from(row: Row) -> Result<MessageRow, tokio_postgres::Error> {
+        let profile_version = row.try_get::<&str, i32>("profile_version")?;
+        let emoji: Vec<String> = row.try_get("reaction_emoji")?;
+        let counts: Vec<i64> = row.try_get("reaction_counts")?;
+        let reactions = emoji
+            .into_iter()
+            .zip(counts)
+            .map(|(emoji, count)| ReactionSummary {
+                emoji,
+                count: count as u32,
             })
-            .map_err(|e| e.into());
+            .collect();
+        let attachments: Vec<Uuid> = row.try_get("attachment_ids")?;
+        let reply_author: Option<Uuid> = row.try_get("reply_author")?;
+        let reply_content: Option<String> = row.try_get("reply_content")?;
+        let record = MessageRecord::try_from(row)?;
 
-        Ok(Ok(stream))
+        // The parent is only gone if it was removed along with its room or author
+        let reply_to = record
+            .reply_to
+            .zip(reply_author)
+            .map(|(message, author)| ReplyPreview {
+                message,
+                author: UserId(author),
+                content: reply_content,
+            });
+
+        Ok(MessageRow {
+            profile_version: ProfileVersion(profile_version as u32),
+            reactions,
+            attachments: attachments.into_iter().map(AttachmentId).collect(),
+            reply_to,
+            record,
+        })
     }
 }
 
-pub trait MessageStreamExt: Stream<Item = DbResult<(ProfileVersion, MessageRecord)>> {
+pub trait MessageStreamExt: Stream<Item = DbResult<MessageRow>> {
     type Output: Stream<Item = DbResult<Message>> + Sized;
 
     fn map_messages(self) -> Self::Output
@@ -217,8 +535,8 @@ pub trait MessageStreamExt: Stream<Item = DbResult<(ProfileVersion, MessageRecor
 
 impl<S> MessageStreamExt for S
 where
-    S: Stream<Item = DbResult<(ProfileVersion, MessageRecord)>>,
-    S: TryStream<Ok = (ProfileVersion, MessageRecord), Error = DatabaseError>,
+    S: Stream<Item = DbResult<MessageRow>>,
+    S: TryStream<Ok = MessageRow, Error = DatabaseError>,
 {
     type Output = impl Stream<Item = DbResult<Message>> + Sized;
 
@@ -226,17 +544,157 @@ where
     where
         Self: Sized,
     {
-        self.try_filter_map(|(profile_version, record)| async move {
-            match record.content {
-                Some(content) => Ok(Some(Message {
-                    id: record.id,
-                    author: record.author,
-                    author_profile_version: profile_version,
-                    time_sent: record.date,
-                    content: Some(content),
-                })),
-                None => Ok(None),
-            }
+        // Deleted messages are kept (with no content) so that they still occupy their slot in the
+        // history
+        self.map_ok(|row| Message {
+            id: row.record.id,
+            author: row.record.author,
+            author_profile_version: row.profile_version,
+            time_sent: row.record.date,
+            content: row.record.content,
+            reactions: row.reactions,
+            attachments: row.attachments,
+            reply_to: row.reply_to,
+            kind: row.record.kind,
+            seq: row.record.seq,
+            signature: row.record.signature,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(seq: u64) -> Message {
+        Message {
+            id: MessageId(Uuid::from_u128(seq as u128)),
+            author: UserId(Uuid::nil()),
+            author_profile_version: ProfileVersion(0),
+            time_sent: Utc::now(),
+            content: Some(seq.to_string()),
+            reactions: Vec::new(),
+            attachments: Vec::new(),
+            reply_to: None,
+            kind: MessageKind::Normal,
+            seq,
+            signature: None,
+        }
+    }
+
+    fn seqs(history: &MessageHistory) -> Vec<u64> {
+        history.buffer.iter().map(|message| message.seq).collect()
+    }
+
+    /// Pages through an in-memory room the way `get_messages` does in SQL
+    fn get_messages(room: &[u64], selector: MessageSelector, count: usize) -> MessageHistory {
+        let (bound, comparator) = selector_bound(&selector);
+        let ascending = selects_ascending(&selector);
+        let bound = match bound {
+            MessagePosition::Id(id) => room
+                .iter()
+                .copied()
+                .find(|seq| message(*seq).id == id)
+                .unwrap(),
+            MessagePosition::Seq(seq) => seq,
+        };
+
+        let mut matching: Vec<u64> = room
+            .iter()
+            .copied()
+            .filter(|seq| match comparator.as_str() {
+                "<" => *seq < bound,
+                "<=" => *seq <= bound,
+                ">" => *seq > bound,
+                ">=" => *seq >= bound,
+                _ => unreachable!(),
+            })
+            .collect();
+
+        matching.sort_unstable();
+        if !ascending {
+            matching.reverse();
+        }
+
+        let messages = matching.into_iter().take(count + 1).map(message).collect();
+        history_page(messages, ascending, count)
+    }
+
+    #[test]
+    fn selector_bound_comparators() {
+        let position = MessagePosition::Seq(5);
+        let cases = vec![
+            (MessageSelector::Before(Bound::Exclusive(position)), "<"),
+            (MessageSelector::Before(Bound::Inclusive(position)), "<="),
+            (MessageSelector::After(Bound::Exclusive(position)), ">"),
+            (MessageSelector::After(Bound::Inclusive(position)), ">="),
+        ];
+
+        for (selector, expected) in cases {
+            let (bound, comparator) = selector_bound(&selector);
+            assert_eq!(bound, position);
+            assert_eq!(comparator, expected);
+        }
+    }
+
+    #[test]
+    fn history_page_has_more() {
+        let newest_first = (1..=5).rev().map(message).collect();
+        let history = history_page(newest_first, false, 3);
+
+        assert_eq!(seqs(&history), vec![3, 4, 5]);
+        assert!(history.cursor.has_more);
+        assert_eq!(history.cursor.oldest, Some(message(3).id));
+        assert_eq!(history.cursor.newest, Some(message(5).id));
+    }
+
+    #[test]
+    fn history_page_exactly_full() {
+        let oldest_first = (1..=3).map(message).collect();
+        let history = history_page(oldest_first, true, 3);
+
+        assert_eq!(seqs(&history), vec![1, 2, 3]);
+        assert!(!history.cursor.has_more);
+        assert_eq!(history.cursor.oldest, Some(message(1).id));
+        assert_eq!(history.cursor.newest, Some(message(3).id));
+    }
+
+    #[test]
+    fn history_page_empty() {
+        let history = history_page(Vec::new(), false, 3);
+
+        assert!(history.buffer.is_empty());
+        assert!(!history.cursor.has_more);
+        assert_eq!(history.cursor.oldest, None);
+        assert_eq!(history.cursor.newest, None);
+    }
+
+    #[test]
+    fn paging_back_while_messages_are_sent() {
+        let mut room: Vec<u64> = (1..=10).collect();
+
+        let newest = MessagePosition::Seq(10);
+        let first = get_messages(&room, MessageSelector::Before(Bound::Inclusive(newest)), 4);
+        assert_eq!(seqs(&first), vec![7, 8, 9, 10]);
+        assert!(first.cursor.has_more);
+
+        // New messages land between fetching the first page and the next
+        room.extend(11..=12);
+
+        let oldest = MessagePosition::Id(first.cursor.oldest.unwrap());
+        let second = get_messages(&room, MessageSelector::Before(Bound::Exclusive(oldest)), 4);
+        assert_eq!(seqs(&second), vec![3, 4, 5, 6]);
+        assert!(second.cursor.has_more);
+
+        let oldest = MessagePosition::Id(second.cursor.oldest.unwrap());
+        let third = get_messages(&room, MessageSelector::Before(Bound::Exclusive(oldest)), 4);
+        assert_eq!(seqs(&third), vec![1, 2]);
+        assert!(!third.cursor.has_more);
+
+        // The messages sent while paging are picked up after the newest one seen
+        let newest = MessagePosition::Id(first.cursor.newest.unwrap());
+        let newer = get_messages(&room, MessageSelector::After(Bound::Exclusive(newest)), 4);
+        assert_eq!(seqs(&newer), vec![11, 12]);
+        assert!(!newer.cursor.has_more);
+    }
+}
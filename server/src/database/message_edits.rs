@@ -0,0 +1,64 @@
+use vertex::prelude::*;
+
+use super::*;
+
+pub(super) const CREATE_MESSAGE_EDITS_TABLE: &str = r#"
+    CREATE TABLE IF NOT EXISTS message_edits (
+        message          UUID NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+        old_content      VARCHAR NOT NULL,
+        edited_at        TIMESTAMP WITH TIME ZONE NOT NULL
+    )"#;
+
+impl Database {
+    /// Replaces the content of a message, keeping the old content in its edit history. Returns the
+    /// number of times the message has now been edited, or `None` if it does not exist or was
+    /// deleted.
+    pub async fn edit_message(&self, id: MessageId, new_content: String) -> DbResult<Option<u32>> {
+        const UPDATE: &str = "
+            UPDATE messages SET content = $2, signature = NULL
+                FROM (SELECT id, content FROM messages WHERE id = $1 FOR UPDATE) old
+                WHERE messages.id = old.id AND old.content IS NOT NULL
+                RETURNING old.content AS old_content
+        ";
+        const INSERT_EDIT: &str = "
+            INSERT INTO message_edits (message, old_content, edited_at) VALUES ($1, $2, NOW())
+        ";
+        const COUNT: &str = "SELECT COUNT(*) AS count FROM message_edits WHERE message = $1";
+
+        let mut conn = self.pool.connection().await?;
+        let transaction = conn.client.transaction().await?;
+
+        let updated = transaction.query_opt(UPDATE, &[&id.0, &new_content]).await?;
+        let old_content: String = match updated {
+            Some(row) => row.try_get("old_content")?,
+            None => return Ok(None), // Transaction is rolled back on drop
+        };
+
+        transaction.execute(INSERT_EDIT, &[&id.0, &old_content]).await?;
+        let count: i64 = transaction.query_one(COUNT, &[&id.0]).await?.try_get("count")?;
+        transaction.commit().await?;
+
+        Ok(Some(count as u32))
+    }
+
+    pub async fn get_edit_history(&self, id: MessageId) -> DbResult<Vec<EditRecord>> {
+        const QUERY: &str = "
+            SELECT old_content, edited_at FROM message_edits
+                WHERE message = $1
+                ORDER BY edited_at ASC
+        ";
+
+        let stream = self.query_stream(QUERY, &[&id.0]).await?;
+        let edits = stream
+            .and_then(|row| async move {
+                Ok(EditRecord {
+                    old_content: row.try_get("old_content")?,
+                    edited_at: row.try_get("edited_at")?,
+                })
+            })
+            .try_collect()
+            .await?;
+
+        Ok(edits)
+    }
+}
@@ -1,36 +1,62 @@
 use std::convert::TryFrom;
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
+use crate::config::Config;
 use crate::{client, config};
 use futures::{Stream, TryStreamExt};
 use l337_postgres::PostgresConnectionManager;
-use log::{error, warn};
+use log::{error, info, warn};
 use tokio_postgres::types::ToSql;
-use tokio_postgres::{NoTls, Row, RowStream};
+use tokio_postgres::{Row, RowStream};
 use vertex::prelude::*;
 
+use tls::MakeDbTlsConnect;
+
 mod administrators;
+mod attachments;
+mod audit_log;
+mod bans;
+mod blocks;
 mod communities;
 mod community_membership;
+mod direct_channels;
 mod invite_code;
+mod mentions;
 mod message;
+mod message_edits;
+mod pinned_messages;
+mod reactions;
 mod reports;
+mod roles;
 mod rooms;
+mod tls;
 mod token;
 mod user;
 mod user_room_states;
 
 pub use administrators::*;
+pub use attachments::*;
 pub use communities::*;
 pub use community_membership::*;
+pub use direct_channels::*;
 pub use invite_code::*;
 pub use message::*;
+pub use pinned_messages::*;
+pub use reactions::*;
 pub use reports::*;
+pub use roles::*;
 pub use rooms::*;
 pub use token::*;
 pub use user::*;
 pub use user_room_states::*;
 
+use audit_log::*;
+use bans::*;
+use blocks::*;
+use mentions::*;
+use message_edits::*;
+
 pub type DbResult<T> = Result<T, DatabaseError>;
 
 #[derive(Debug)]
@@ -79,12 +105,14 @@ pub struct InvalidUser;
 
 #[derive(Clone)]
 pub struct Database {
-    pool: l337::Pool<PostgresConnectionManager<NoTls>>,
+    pool: l337::Pool<PostgresConnectionManager<MakeDbTlsConnect>>,
 }
 
 impl Database {
-    pub async fn new() -> DbResult<Self> {
-        let mgr = PostgresConnectionManager::new(config::db_config(), NoTls);
+    pub async fn new(config: &config::Config) -> DbResult<Self> {
+        let mut db_config = config::db_config(config)?;
+        let tls = MakeDbTlsConnect::from_config(config, &mut db_config);
+        let mgr = PostgresConnectionManager::new(db_config, tls);
 
         let pool = l337::Pool::new(mgr, Default::default())
             .await
@@ -95,6 +123,13 @@ impl Database {
         Ok(db)
     }
 
+    /// Checks that a connection can be taken from the pool and used
+    pub async fn ping(&self) -> DbResult<()> {
+        let conn = self.pool.connection().await?;
+        conn.client.simple_query("SELECT 1").await?;
+        Ok(())
+    }
+
     pub async fn query_one(&self, query: &str, args: &[&(dyn ToSql + Sync)]) -> DbResult<Row> {
         let conn = self.pool.connection().await?;
         let query = conn.client.prepare(query).await?;
@@ -128,12 +163,29 @@ impl Database {
             CREATE_TOKENS_TABLE,
             CREATE_COMMUNITIES_TABLE,
             CREATE_COMMUNITY_MEMBERSHIP_TABLE,
+            CREATE_ROLES_TABLE,
+            CREATE_MEMBER_ROLES_TABLE,
             CREATE_ROOMS_TABLE,
             CREATE_INVITE_CODES_TABLE,
             CREATE_MESSAGES_TABLE,
+            CREATE_MESSAGES_SEARCH_INDEX,
+            CREATE_MESSAGES_SEQ_INDEX,
+            CREATE_MESSAGES_DATE_INDEX,
+            CREATE_MESSAGE_EDITS_TABLE,
+            CREATE_MENTIONS_TABLE,
+            CREATE_PINNED_MESSAGES_TABLE,
+            CREATE_ATTACHMENTS_TABLE,
             CREATE_USER_ROOM_STATES_TABLE,
+            CREATE_DIRECT_CHANNELS_TABLE,
+            CREATE_DIRECT_MESSAGES_TABLE,
+            CREATE_DIRECT_MESSAGES_DATE_INDEX,
+            CREATE_DIRECT_MESSAGES_SEQ_INDEX,
+            CREATE_BLOCKS_TABLE,
             CREATE_ADMINISTRATORS_TABLE,
+            CREATE_AUDIT_LOG_TABLE,
+            CREATE_BANS_TABLE,
             CREATE_REPORTS_TABLE,
+            CREATE_REACTIONS_TABLE,
             "CREATE EXTENSION IF NOT EXISTS pg_trgm;", // Allow fuzzy searching
         ];
 
@@ -223,8 +275,78 @@ impl Database {
         }
     }
 
+    pub async fn sweep_bans_loop(self, interval: Duration) {
+        let mut timer = tokio::time::interval(interval);
+
+        loop {
+            timer.tick().await;
+            let begin = Instant::now();
+            let unbanned = self
+                .unban_expired()
+                .await
+                .expect("Database error while sweeping bans");
+
+            for user in unbanned {
+                info!("User {} unbanned as their ban expired", user.0);
+            }
+
+            let time_taken = Instant::now().duration_since(begin);
+            if time_taken > interval {
+                warn!(
+                    "Took {}s to sweep the database for expired bans, but the interval is {}s!",
+                    time_taken.as_secs(),
+                    interval.as_secs(),
+                );
+            }
+        }
+    }
+
+    /// Deletes messages past their community's retention period, along with the files of their
+    /// attachments. Messages are deleted in batches so that the tables are not locked for long.
+    pub async fn sweep_messages_loop(self, config: Arc<Config>, interval: Duration) {
+        const BATCH_SIZE: usize = 1000;
+        let mut timer = tokio::time::interval(interval);
+
+        loop {
+            timer.tick().await;
+            let begin = Instant::now();
+
+            loop {
+                let (deleted, attachments) = self
+                    .delete_expired_messages(BATCH_SIZE)
+                    .await
+                    .expect("Database error while sweeping messages");
+
+                for attachment in attachments {
+                    let path = crate::attachments::path_of(&config, attachment);
+                    if let Err(e) = tokio::fs::remove_file(path).await {
+                        warn!("Error deleting attachment {}: {:?}", attachment.0, e);
+                    }
+                }
+
+                if deleted < BATCH_SIZE {
+                    break;
+                }
+            }
+
+            let time_taken = Instant::now().duration_since(begin);
+            if time_taken > interval {
+                warn!(
+                    "Took {}s to sweep the database for expired messages, but the interval is {}s!",
+                    time_taken.as_secs(),
+                    interval.as_secs(),
+                );
+            }
+        }
+    }
+
+    /// Deletes invite codes which have expired or been used up, so that they stop counting towards
+    /// the community's limit and their vanity codes can be taken again
     async fn delete_expired_invite_codes(&self) -> DbResult<()> {
-        const STMT: &str = "DELETE FROM invite_codes WHERE expiration_date < NOW()::timestamp";
+        const STMT: &str = "
+            DELETE FROM invite_codes
+                WHERE expiration_date < NOW()::timestamp OR uses >= max_uses
+        ";
 
         let conn = self.pool.connection().await?;
         let stmt = conn.client.prepare(STMT).await?;
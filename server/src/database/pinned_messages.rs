@@ -0,0 +1,99 @@
+use chrono::Utc;
+use futures::{Stream, TryStreamExt};
+use tokio_postgres::types::ToSql;
+use vertex::prelude::*;
+
+use super::message::message_row_columns;
+use super::*;
+
+pub(super) const CREATE_PINNED_MESSAGES_TABLE: &str = r#"
+    CREATE TABLE IF NOT EXISTS pinned_messages (
+        message     UUID PRIMARY KEY REFERENCES messages(id) ON DELETE CASCADE,
+        room        UUID NOT NULL REFERENCES rooms(id) ON DELETE CASCADE,
+        pinned_by   UUID REFERENCES users(id) ON DELETE SET NULL,
+        pinned_at   TIMESTAMP WITH TIME ZONE NOT NULL
+    )"#;
+
+#[derive(Debug, Copy, Clone)]
+pub struct TooManyPins;
+
+impl Database {
+    /// Pins the message in the room, unless the room already has `max_pins` pinned messages.
+    /// Returns whether the message was not already pinned.
+    pub async fn pin_message(
+        &self,
+        room: RoomId,
+        message: MessageId,
+        pinned_by: UserId,
+        max_pins: i64,
+    ) -> DbResult<Result<bool, TooManyPins>> {
+        // Locking the room serializes concurrent pins to it, so the limit cannot be overshot
+        const LOCK: &str = "SELECT 1 FROM rooms WHERE id = $1 FOR UPDATE";
+        const EXISTS: &str = "SELECT 1 FROM pinned_messages WHERE message = $1";
+        const COUNT: &str = "SELECT COUNT(*) FROM pinned_messages WHERE room = $1";
+        const INSERT: &str = "
+            INSERT INTO pinned_messages (message, room, pinned_by, pinned_at)
+                VALUES ($1, $2, $3, $4)
+        ";
+
+        let mut conn = self.pool.connection().await?;
+        let transaction = conn.client.transaction().await?;
+
+        transaction.execute(LOCK, &[&room.0]).await?;
+
+        if transaction.query_opt(EXISTS, &[&message.0]).await?.is_some() {
+            return Ok(Ok(false));
+        }
+
+        let count: i64 = transaction.query_one(COUNT, &[&room.0]).await?.try_get(0)?;
+        if count >= max_pins {
+            return Ok(Err(TooManyPins));
+        }
+
+        let args: &[&(dyn ToSql + Sync)] = &[&message.0, &room.0, &pinned_by.0, &Utc::now()];
+        transaction.execute(INSERT, args).await?;
+        transaction.commit().await?;
+
+        Ok(Ok(true))
+    }
+
+    /// Unpins the message, returning whether it was pinned
+    pub async fn unpin_message(&self, message: MessageId) -> DbResult<bool> {
+        const STMT: &str = "DELETE FROM pinned_messages WHERE message = $1";
+
+        let conn = self.pool.connection().await?;
+        let res = conn.client.execute(STMT, &[&message.0]).await?;
+
+        Ok(res == 1)
+    }
+
+    /// Gets the messages pinned in the room, most recently pinned first, leaving out those by
+    /// users whom the viewer has blocked
+    pub async fn get_pinned_messages(
+        &self,
+        room: RoomId,
+        viewer: UserId,
+    ) -> DbResult<impl Stream<Item = DbResult<MessageRow>>> {
+        let query = format!(
+            "SELECT {}
+            FROM pinned_messages
+            INNER JOIN messages ON pinned_messages.message = messages.id
+            INNER JOIN users ON messages.author = users.id
+            LEFT JOIN messages parent ON messages.reply_to = parent.id
+                WHERE pinned_messages.room = $1
+                AND NOT EXISTS(
+                    SELECT 1 FROM blocks
+                        WHERE blocks.blocker = $2 AND blocks.blocked = messages.author
+                )
+                ORDER BY pinned_messages.pinned_at DESC",
+            message_row_columns(),
+        );
+
+        let stream = self.query_stream(&query, &[&room.0, &viewer.0]).await?;
+        let stream = stream
+            .and_then(|row| async move { MessageRow::try_from(row) })
+            .map_err(|e| e.into());
+
+        Ok(stream)
+    }
+}
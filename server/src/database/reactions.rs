@@ -0,0 +1,96 @@
+use std::error::Error;
+use tokio_postgres::error::{DbError, SqlState};
+use vertex::prelude::*;
+
+use super::*;
+
+pub(super) const CREATE_REACTIONS_TABLE: &str = r#"
+    CREATE TABLE IF NOT EXISTS reactions (
+        message          UUID NOT NULL REFERENCES messages(id) ON DELETE CASCADE,
+        user_id          UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+        emoji            VARCHAR NOT NULL,
+
+        UNIQUE(message, user_id, emoji)
+    )"#;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum AddReactionError {
+    InvalidMessage,
+    InvalidUser,
+}
+
+impl Database {
+    /// Adds a reaction to a message. Reacting twice with the same emoji is a no-op.
+    pub async fn add_reaction(
+        &self,
+        message: MessageId,
+        user: UserId,
+        emoji: &str,
+    ) -> DbResult<Result<(), AddReactionError>> {
+        const STMT: &str = "
+            INSERT INTO reactions (message, user_id, emoji)
+                VALUES ($1, $2, $3)
+                ON CONFLICT DO NOTHING
+        ";
+
+        let conn = self.pool.connection().await?;
+        let stmt = conn.client.prepare(STMT).await?;
+        let res = conn.client.execute(&stmt, &[&message.0, &user.0, &emoji]).await;
+
+        match res {
+            Ok(_) => Ok(Ok(())),
+            Err(err) => {
+                if err.code() == Some(&SqlState::FOREIGN_KEY_VIOLATION) {
+                    let constraint = err
+                        .source()
+                        .and_then(|e| e.downcast_ref::<DbError>())
+                        .and_then(|e| e.constraint());
+
+                    match constraint {
+                        Some("reactions_message_fkey") => Ok(Err(AddReactionError::InvalidMessage)),
+                        Some("reactions_user_id_fkey") => Ok(Err(AddReactionError::InvalidUser)),
+                        Some(_) | None => Err(err.into()),
+                    }
+                } else {
+                    Err(err.into())
+                }
+            }
+        }
+    }
+
+    pub async fn remove_reaction(
+        &self,
+        message: MessageId,
+        user: UserId,
+        emoji: &str,
+    ) -> DbResult<()> {
+        const STMT: &str = "DELETE FROM reactions WHERE message = $1 AND user_id = $2 AND emoji = $3";
+
+        let conn = self.pool.connection().await?;
+        let stmt = conn.client.prepare(STMT).await?;
+        conn.client.execute(&stmt, &[&message.0, &user.0, &emoji]).await?;
+        Ok(())
+    }
+
+    pub async fn get_reactions(&self, message: MessageId) -> DbResult<Vec<ReactionSummary>> {
+        const QUERY: &str = "
+            SELECT emoji, COUNT(*) AS count FROM reactions
+                WHERE message = $1
+                GROUP BY emoji
+                ORDER BY emoji
+        ";
+
+        let stream = self.query_stream(QUERY, &[&message.0]).await?;
+        let reactions = stream
+            .and_then(|row| async move {
+                Ok(ReactionSummary {
+                    emoji: row.try_get("emoji")?,
+                    count: row.try_get::<&str, i64>("count")? as u32,
+                })
+            })
+            .try_collect()
+            .await?;
+
+        Ok(reactions)
+    }
+}
@@ -23,7 +23,9 @@ pub(super) const CREATE_REPORTS_TABLE: &str = r#"
         short_desc     VARCHAR NOT NULL,
         extended_desc  VARCHAR NOT NULL,
         msg_sent_at    TIMESTAMP WITH TIME ZONE NOT NULL,
-        status         "char" NOT NULL
+        status         "char" NOT NULL,
+
+        UNIQUE(reporter_user, message_id)
     )"#;
 
 #[derive(Debug, Clone)]
@@ -105,6 +107,7 @@ macro_rules! build_where_clause {
 pub enum ReportUserError {
     InvalidMessage,
     InvalidReporter,
+    AlreadyReported,
 }
 
 fn row_to_report(row: &Row) -> Result<VertexReport, tokio_postgres::Error> {
@@ -180,7 +183,7 @@ impl Database {
             .execute(
                 &stmt,
                 &[
-                    &msg.date,
+                    &Utc::now(),
                     &msg.author.0,
                     &reporter.0,
                     &msg.community.0,
@@ -215,6 +218,8 @@ impl Database {
                         | Some("reports_room_fkey") => Ok(Err(ReportUserError::InvalidReporter)),
                         Some(_) | None => Err(err.into()),
                     }
+                } else if err.code() == Some(&SqlState::UNIQUE_VIOLATION) {
+                    Ok(Err(ReportUserError::AlreadyReported))
                 } else {
                     Err(err.into())
                 }
@@ -222,6 +227,15 @@ impl Database {
         }
     }
 
+    pub async fn get_reported_user(&self, id: i32) -> DbResult<Option<UserId>> {
+        const QUERY: &str = "SELECT reported_user FROM reports WHERE id = $1";
+
+        match self.query_opt(QUERY, &[&id]).await? {
+            Some(row) => Ok(Some(UserId(row.try_get("reported_user")?))),
+            None => Ok(None),
+        }
+    }
+
     pub async fn set_report_status(&self, id: i32, status: ReportStatus) -> DbResult<()> {
         const STMT: &str = "UPDATE reports SET status = $1 WHERE id = $2";
         let conn = self.pool.connection().await?;
@@ -265,7 +279,7 @@ impl Database {
         let trimmed = &criteria.words.trim();
 
         let query = SELECT_QUERY.replace("%where%", &where_clause);
-        let order = if trimmed.len() == 0 {
+        let order = if trimmed.is_empty() {
             "ORDER BY reports.id DESC".to_string()
         } else {
             args.push(&trimmed);
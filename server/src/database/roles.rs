@@ -0,0 +1,139 @@
+use std::error::Error;
+use tokio_postgres::error::{DbError, SqlState};
+use tokio_postgres::types::ToSql;
+use uuid::Uuid;
+use vertex::prelude::*;
+
+use super::*;
+
+pub(super) const CREATE_ROLES_TABLE: &str = r#"
+    CREATE TABLE IF NOT EXISTS roles (
+        id               UUID PRIMARY KEY,
+        community        UUID NOT NULL REFERENCES communities(id) ON DELETE CASCADE,
+        name             VARCHAR NOT NULL,
+        permission_flags BIGINT NOT NULL
+    )"#;
+
+pub(super) const CREATE_MEMBER_ROLES_TABLE: &str = r#"
+    CREATE TABLE IF NOT EXISTS member_roles (
+        role             UUID NOT NULL REFERENCES roles(id) ON DELETE CASCADE,
+        user_id          UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+
+        UNIQUE(user_id, role)
+    )"#;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct InvalidCommunity;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum AssignRoleError {
+    InvalidRole,
+    InvalidUser,
+}
+
+impl Database {
+    pub async fn create_role(
+        &self,
+        community: CommunityId,
+        name: String,
+        permissions: CommunityPermissionFlags,
+    ) -> DbResult<Result<RoleId, InvalidCommunity>> {
+        const STMT: &str = "
+            INSERT INTO roles (id, community, name, permission_flags) VALUES ($1, $2, $3, $4)
+        ";
+
+        let id = Uuid::new_v4();
+        let conn = self.pool.connection().await?;
+        let stmt = conn.client.prepare(STMT).await?;
+        let args: &[&(dyn ToSql + Sync)] = &[&id, &community.0, &name, &permissions.bits()];
+        let res = conn.client.execute(&stmt, args).await;
+
+        match res {
+            Ok(_) => Ok(Ok(RoleId(id))),
+            Err(err) => {
+                if err.code() == Some(&SqlState::FOREIGN_KEY_VIOLATION) {
+                    Ok(Err(InvalidCommunity))
+                } else {
+                    Err(err.into())
+                }
+            }
+        }
+    }
+
+    /// Gets the permissions granted by a role, or `None` if there is no such role in the community
+    pub async fn get_role_permissions(
+        &self,
+        community: CommunityId,
+        role: RoleId,
+    ) -> DbResult<Option<CommunityPermissionFlags>> {
+        const QUERY: &str = "SELECT permission_flags FROM roles WHERE id = $1 AND community = $2";
+
+        match self.query_opt(QUERY, &[&role.0, &community.0]).await? {
+            Some(row) => {
+                let flags: i64 = row.try_get("permission_flags")?;
+                Ok(Some(CommunityPermissionFlags::from_bits_truncate(flags)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Gives a role to a user. The role must belong to the given community. Assigning a role that
+    /// the user already has is a no-op.
+    pub async fn assign_role(
+        &self,
+        community: CommunityId,
+        role: RoleId,
+        user: UserId,
+    ) -> DbResult<Result<(), AssignRoleError>> {
+        const STMT: &str = "
+            INSERT INTO member_roles (role, user_id)
+                SELECT roles.id, $3 FROM roles WHERE roles.id = $1 AND roles.community = $2
+        ";
+
+        let conn = self.pool.connection().await?;
+        let stmt = conn.client.prepare(STMT).await?;
+        let args: &[&(dyn ToSql + Sync)] = &[&role.0, &community.0, &user.0];
+        let res = conn.client.execute(&stmt, args).await;
+
+        match res {
+            Ok(0) => Ok(Err(AssignRoleError::InvalidRole)), // No such role in the community
+            Ok(_) => Ok(Ok(())),
+            Err(err) if err.code() == Some(&SqlState::UNIQUE_VIOLATION) => Ok(Ok(())),
+            Err(err) => {
+                if err.code() == Some(&SqlState::FOREIGN_KEY_VIOLATION) {
+                    let constraint = err
+                        .source()
+                        .and_then(|e| e.downcast_ref::<DbError>())
+                        .and_then(|e| e.constraint());
+
+                    match constraint {
+                        Some("member_roles_user_id_fkey") => Ok(Err(AssignRoleError::InvalidUser)),
+                        Some("member_roles_role_fkey") => Ok(Err(AssignRoleError::InvalidRole)),
+                        Some(_) | None => Err(err.into()),
+                    }
+                } else {
+                    Err(err.into())
+                }
+            }
+        }
+    }
+
+    /// Gets the union of the permissions of all roles the user has in the community, or `None` if
+    /// they have no roles in it.
+    pub async fn get_community_permissions(
+        &self,
+        community: CommunityId,
+        user: UserId,
+    ) -> DbResult<Option<CommunityPermissionFlags>> {
+        const QUERY: &str = "
+            SELECT BIT_OR(roles.permission_flags) AS permission_flags FROM member_roles
+                INNER JOIN roles ON roles.id = member_roles.role
+                WHERE roles.community = $1 AND member_roles.user_id = $2
+        ";
+
+        let row = self.query_one(QUERY, &[&community.0, &user.0]).await?;
+        let flags: Option<i64> = row.try_get("permission_flags")?;
+
+        Ok(flags.map(CommunityPermissionFlags::from_bits_truncate))
+    }
+}
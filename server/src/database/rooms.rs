@@ -1,6 +1,8 @@
 use crate::database::{Database, DbResult};
+use chrono::{DateTime, Utc};
 use futures::{Stream, TryStreamExt};
 use std::convert::TryFrom;
+use tokio_postgres::types::ToSql;
 use tokio_postgres::Row;
 use uuid::Uuid;
 use vertex::prelude::*;
@@ -9,7 +11,12 @@ pub(super) const CREATE_ROOMS_TABLE: &str = "
     CREATE TABLE IF NOT EXISTS rooms (
         id         UUID PRIMARY KEY,
         community  UUID NOT NULL REFERENCES communities(id) ON DELETE CASCADE,
-        name       VARCHAR NOT NULL
+        name       VARCHAR NOT NULL,
+        topic      VARCHAR,
+        slow_mode_secs INTEGER NOT NULL DEFAULT 0,
+        archived   BOOLEAN NOT NULL DEFAULT FALSE,
+        last_seq   BIGINT NOT NULL DEFAULT 0,
+        created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT CLOCK_TIMESTAMP()
     )";
 // TODO(sql): indexing
 
@@ -18,6 +25,10 @@ pub struct RoomRecord {
     pub id: RoomId,
     pub community: CommunityId,
     pub name: String,
+    pub topic: Option<String>,
+    pub slow_mode_secs: u32,
+    pub archived: bool,
+    pub created_at: DateTime<Utc>,
 }
 
 impl TryFrom<Row> for RoomRecord {
@@ -28,6 +39,10 @@ impl TryFrom<Row> for RoomRecord {
             id: RoomId(row.try_get("id")?),
             community: CommunityId(row.try_get("community")?),
             name: row.try_get("name")?,
+            topic: row.try_get("topic")?,
+            slow_mode_secs: row.try_get::<&str, i32>("slow_mode_secs")? as u32,
+            archived: row.try_get("archived")?,
+            created_at: row.try_get("created_at")?,
         })
     }
 }
@@ -55,11 +70,77 @@ impl Database {
         Ok(RoomId(id))
     }
 
+    /// Sets the topic and slow mode of a room. Returns whether the room existed in the given
+    /// community.
+    pub async fn edit_room(
+        &self,
+        community: CommunityId,
+        room: RoomId,
+        topic: Option<String>,
+        slow_mode_secs: u32,
+    ) -> DbResult<bool> {
+        const STMT: &str = "
+            UPDATE rooms SET topic = $1, slow_mode_secs = $2
+                WHERE id = $3 AND community = $4
+        ";
+        let conn = self.pool.connection().await?;
+        let args: &[&(dyn ToSql + Sync)] =
+            &[&topic, &(slow_mode_secs as i32), &room.0, &community.0];
+        let edited = conn.client.execute(STMT, args).await?;
+        Ok(edited == 1)
+    }
+
+    /// Archives or unarchives a room. Returns whether the room existed in the given community.
+    pub async fn set_room_archived(
+        &self,
+        community: CommunityId,
+        room: RoomId,
+        archived: bool,
+    ) -> DbResult<bool> {
+        const STMT: &str = "UPDATE rooms SET archived = $1 WHERE id = $2 AND community = $3";
+        let conn = self.pool.connection().await?;
+        let edited = conn.client.execute(STMT, &[&archived, &room.0, &community.0]).await?;
+        Ok(edited == 1)
+    }
+
+    /// Deletes a room and, through cascading, all of its messages. Returns the attachments of its
+    /// messages, whose files should be removed, or `None` if the room did not exist in the given
+    /// community.
+    pub async fn delete_room(
+        &self,
+        community: CommunityId,
+        room: RoomId,
+    ) -> DbResult<Option<Vec<AttachmentId>>> {
+        const DELETE_ATTACHMENTS: &str = "
+            DELETE FROM attachments
+                WHERE message IN (SELECT id FROM messages WHERE room = $1 AND community = $2)
+                RETURNING id
+        ";
+        const DELETE_ROOM: &str = "DELETE FROM rooms WHERE id = $1 AND community = $2";
+
+        let mut conn = self.pool.connection().await?;
+        let transaction = conn.client.transaction().await?;
+
+        let attachments = transaction
+            .query(DELETE_ATTACHMENTS, &[&room.0, &community.0])
+            .await?
+            .iter()
+            .map(|row| row.try_get("id").map(AttachmentId))
+            .collect::<Result<_, _>>()?;
+
+        if transaction.execute(DELETE_ROOM, &[&room.0, &community.0]).await? == 0 {
+            return Ok(None); // Transaction is rolled back on drop
+        }
+
+        transaction.commit().await?;
+        Ok(Some(attachments))
+    }
+
     pub async fn get_rooms_in_community(
         &self,
         community: CommunityId,
     ) -> DbResult<impl Stream<Item = DbResult<RoomRecord>>> {
-        const QUERY: &str = "SELECT * FROM rooms WHERE community = $1";
+        const QUERY: &str = "SELECT * FROM rooms WHERE community = $1 ORDER BY created_at";
 
         let stream = self.query_stream(QUERY, &[&community.0]).await?;
         let stream = stream
@@ -0,0 +1,153 @@
+//! Connecting to the database over TLS with rustls. TLS is only used if `db_tls` is set in the
+//! config; otherwise TLS is disabled on the connection config, so the connector is never asked to
+//! make a connection and existing plaintext setups keep working.
+
+use std::fs::File;
+use std::future::Future;
+use std::io::{self, BufReader};
+use std::mem::MaybeUninit;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_postgres::config::SslMode;
+use tokio_postgres::tls::{ChannelBinding, MakeTlsConnect, TlsConnect, TlsStream};
+use tokio_rustls::rustls::ClientConfig;
+use tokio_rustls::webpki::{DNSName, DNSNameRef};
+use tokio_rustls::TlsConnector;
+
+use crate::config::Config;
+
+#[derive(Clone)]
+pub struct MakeDbTlsConnect {
+    client_config: Option<Arc<ClientConfig>>,
+}
+
+impl MakeDbTlsConnect {
+    /// Creates the connector described by the config, disabling TLS on the connection config if
+    /// it is not enabled.
+    pub fn from_config(config: &Config, db_config: &mut tokio_postgres::Config) -> Self {
+        if !config.db_tls {
+            db_config.ssl_mode(SslMode::Disable);
+            return MakeDbTlsConnect {
+                client_config: None,
+            };
+        }
+
+        let path = config
+            .db_tls_ca_cert
+            .as_ref()
+            .expect("A CA certificate is needed to connect to the database over TLS");
+        let file = File::open(path).unwrap_or_else(|e| {
+            panic!(
+                "Error opening database CA certificate ({}): {:?}",
+                path.to_string_lossy(),
+                e,
+            )
+        });
+
+        let mut client_config = ClientConfig::new();
+        let (valid, _) = client_config
+            .root_store
+            .add_pem_file(&mut BufReader::new(file))
+            .expect("Invalid database CA certificate");
+        if valid == 0 {
+            panic!("No valid certificates in database CA certificate file");
+        }
+
+        MakeDbTlsConnect {
+            client_config: Some(Arc::new(client_config)),
+        }
+    }
+}
+
+impl<S> MakeTlsConnect<S> for MakeDbTlsConnect
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    type Stream = DbTlsStream<S>;
+    type TlsConnect = DbTlsConnect;
+    type Error = io::Error;
+
+    fn make_tls_connect(&mut self, domain: &str) -> io::Result<DbTlsConnect> {
+        let client_config = self.client_config.clone().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::Other, "TLS is not enabled for the database")
+        })?;
+        let domain = DNSNameRef::try_from_ascii_str(domain)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Invalid database host"))?;
+
+        Ok(DbTlsConnect {
+            domain: domain.to_owned(),
+            connector: client_config.into(),
+        })
+    }
+}
+
+pub struct DbTlsConnect {
+    domain: DNSName,
+    connector: TlsConnector,
+}
+
+impl<S> TlsConnect<S> for DbTlsConnect
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    type Stream = DbTlsStream<S>;
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = io::Result<DbTlsStream<S>>> + Send>>;
+
+    fn connect(self, stream: S) -> Self::Future {
+        let connect = self.connector.connect(self.domain.as_ref(), stream);
+        Box::pin(async move { Ok(DbTlsStream(connect.await?)) })
+    }
+}
+
+pub struct DbTlsStream<S>(tokio_rustls::client::TlsStream<S>);
+
+impl<S> AsyncRead for DbTlsStream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [MaybeUninit<u8>]) -> bool {
+        self.0.prepare_uninitialized_buffer(buf)
+    }
+
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+impl<S> AsyncWrite for DbTlsStream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
+
+impl<S> TlsStream for DbTlsStream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    fn channel_binding(&self) -> ChannelBinding {
+        ChannelBinding::none()
+    }
+}
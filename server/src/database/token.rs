@@ -1,9 +1,11 @@
 use crate::auth::HashSchemeVersion;
 use crate::database::{Database, DbResult};
 use chrono::{DateTime, Utc};
+use futures::{Stream, TryStreamExt};
 use std::convert::TryFrom;
 use tokio_postgres::types::ToSql;
 use tokio_postgres::Row;
+use uuid::Uuid;
 use vertex::prelude::*;
 
 pub(super) const CREATE_TOKENS_TABLE: &str = "
@@ -15,7 +17,9 @@ pub(super) const CREATE_TOKENS_TABLE: &str = "
         user_id              UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
         last_used            TIMESTAMP WITH TIME ZONE NOT NULL,
         expiration_date      TIMESTAMP WITH TIME ZONE,
-        permission_flags     BIGINT NOT NULL
+        permission_flags     BIGINT NOT NULL,
+        selected_community   UUID,
+        selected_room        UUID
     )";
 
 #[derive(Debug)]
@@ -69,6 +73,21 @@ impl Database {
         }
     }
 
+    /// Gets the tokens of every device the user is logged in on, most recently used first
+    pub async fn get_tokens_for_user(
+        &self,
+        user: UserId,
+    ) -> DbResult<impl Stream<Item = DbResult<Token>>> {
+        const QUERY: &str = "SELECT * FROM login_tokens WHERE user_id = $1 ORDER BY last_used DESC";
+
+        let stream = self.query_stream(QUERY, &[&user.0]).await?;
+        let stream = stream
+            .and_then(|row| async move { Ok(Token::try_from(row)?) })
+            .map_err(|e| e.into());
+
+        Ok(stream)
+    }
+
     pub async fn create_token(&self, token: Token) -> DbResult<Result<(), DeviceIdConflict>> {
         const STMT: &str = "
             INSERT INTO login_tokens
@@ -130,6 +149,39 @@ impl Database {
         res.map_err(Into::into)
     }
 
+    /// Remembers the room that the device has selected, so that it can be selected again when the
+    /// device next connects
+    pub async fn set_selected_room(
+        &self,
+        device: DeviceId,
+        selected: Option<(CommunityId, RoomId)>,
+    ) -> DbResult<()> {
+        const STMT: &str =
+            "UPDATE login_tokens SET selected_community = $1, selected_room = $2 WHERE device = $3";
+
+        let (community, room) = selected.map_or((None, None), |(c, r)| (Some(c.0), Some(r.0)));
+        let conn = self.pool.connection().await?;
+        conn.client.execute(STMT, &[&community, &room, &device.0]).await?;
+        Ok(())
+    }
+
+    pub async fn get_selected_room(
+        &self,
+        device: DeviceId,
+    ) -> DbResult<Option<(CommunityId, RoomId)>> {
+        const QUERY: &str =
+            "SELECT selected_community, selected_room FROM login_tokens WHERE device = $1";
+
+        let row = match self.query_opt(QUERY, &[&device.0]).await? {
+            Some(row) => row,
+            None => return Ok(None),
+        };
+
+        let community: Option<Uuid> = row.try_get("selected_community")?;
+        let room: Option<Uuid> = row.try_get("selected_room")?;
+        Ok(community.zip(room).map(|(c, r)| (CommunityId(c), RoomId(r))))
+    }
+
     /// Returns whether any token existed with the given ID in the first place
     pub async fn refresh_token(
         &self,
@@ -1,6 +1,8 @@
 use super::*;
 use crate::auth::HashSchemeVersion;
+use chrono::{DateTime, Utc};
 use std::convert::TryFrom;
+use std::time::Duration;
 use tokio_postgres::{error::SqlState, row::Row, types::ToSql};
 use uuid::Uuid;
 
@@ -14,7 +16,15 @@ pub(super) const CREATE_USERS_TABLE: &str = "
         hash_scheme_version  SMALLINT NOT NULL,
         compromised          BOOLEAN NOT NULL,
         locked               BOOLEAN NOT NULL,
-        banned               BOOLEAN NOT NULL
+        banned               BOOLEAN NOT NULL,
+        avatar               UUID,
+        avatar_version       INTEGER NOT NULL DEFAULT 0,
+        username_changed_at      TIMESTAMP WITH TIME ZONE,
+        display_name_changed_at  TIMESTAMP WITH TIME ZONE,
+        last_seen                TIMESTAMP WITH TIME ZONE,
+        show_online_status       BOOLEAN NOT NULL DEFAULT TRUE,
+        show_last_seen           BOOLEAN NOT NULL DEFAULT FALSE,
+        allow_stranger_dms       BOOLEAN NOT NULL DEFAULT FALSE
     )";
 
 pub struct UserRecord {
@@ -27,6 +37,9 @@ pub struct UserRecord {
     pub compromised: bool,
     pub locked: bool,
     pub banned: bool,
+    pub avatar: Option<AttachmentId>,
+    pub avatar_version: u32,
+    pub privacy: PrivacySettings,
 }
 
 impl UserRecord {
@@ -46,6 +59,9 @@ impl UserRecord {
             compromised: false,
             locked: false,
             banned: false,
+            avatar: None,
+            avatar_version: 0,
+            privacy: PrivacySettings::default(),
         }
     }
 }
@@ -66,6 +82,13 @@ impl TryFrom<Row> for UserRecord {
             compromised: row.try_get("compromised")?,
             locked: row.try_get("locked")?,
             banned: row.try_get("banned")?,
+            avatar: row.try_get::<&str, Option<_>>("avatar")?.map(AttachmentId),
+            avatar_version: row.try_get::<&str, i32>("avatar_version")? as u32,
+            privacy: PrivacySettings {
+                show_online_status: row.try_get("show_online_status")?,
+                show_last_seen: row.try_get("show_last_seen")?,
+                allow_stranger_dms: row.try_get("allow_stranger_dms")?,
+            },
         })
     }
 }
@@ -90,6 +113,21 @@ pub struct NonexistentUser;
 pub enum ChangeUsernameError {
     NonexistentUser,
     UsernameConflict,
+    Cooldown { ready_in: Duration },
+}
+
+pub enum ChangeDisplayNameError {
+    NonexistentUser,
+    Cooldown { ready_in: Duration },
+}
+
+/// Gets how long is left of the cooldown since something was last changed, if it has not passed
+fn cooldown_remaining(
+    changed_at: Option<DateTime<Utc>>,
+    cooldown: chrono::Duration,
+) -> Option<Duration> {
+    let ready_at = changed_at? + cooldown;
+    (ready_at - Utc::now()).to_std().ok()
 }
 
 impl Database {
@@ -113,15 +151,27 @@ impl Database {
         }
     }
 
+    /// Gets the profile of a user. The time they were last seen is left out unless they have chosen
+    /// to show it.
     pub async fn get_user_profile(&self, id: UserId) -> DbResult<Option<Profile>> {
-        let query = "SELECT username, display_name, profile_version FROM users WHERE id=$1";
+        let query = "
+            SELECT username, display_name, profile_version, avatar, last_seen, show_last_seen
+                FROM users WHERE id=$1";
         let opt = self.query_opt(query, &[&id.0]).await?;
         if let Some(row) = opt {
+            let last_seen = if row.try_get("show_last_seen")? {
+                row.try_get("last_seen")?
+            } else {
+                None
+            };
+
             // Can't opt::map because of ?
             Ok(Some(Profile {
                 version: ProfileVersion(row.try_get::<&str, i32>("profile_version")? as u32),
                 username: row.try_get("username")?,
                 display_name: row.try_get("display_name")?,
+                avatar: row.try_get::<&str, Option<_>>("avatar")?.map(AttachmentId),
+                last_seen,
             }))
         } else {
             Ok(None)
@@ -171,28 +221,41 @@ impl Database {
         })
     }
 
+    /// Changes the username of a user, unless they last changed it less than `cooldown` ago.
     pub async fn change_username(
         &self,
         user: UserId,
         new_username: String,
+        cooldown: chrono::Duration,
     ) -> DbResult<Result<(), ChangeUsernameError>> {
+        const QUERY: &str = "SELECT username_changed_at FROM users WHERE id = $1 FOR UPDATE";
         const STMT: &str = "
             UPDATE users
-                SET username = $1, profile_version = profile_version + 1
-                WHERE id = $2
+                SET username = $1,
+                    username_changed_at = $2,
+                    profile_version = profile_version + 1
+                WHERE id = $3
         ";
 
-        let conn = self.pool.connection().await?;
-        let stmt = conn.client.prepare(STMT).await?;
-        let res = conn.client.execute(&stmt, &[&new_username, &user.0]).await;
+        let mut conn = self.pool.connection().await?;
+        let transaction = conn.client.transaction().await?;
+
+        let changed_at = match transaction.query_opt(QUERY, &[&user.0]).await? {
+            Some(row) => row.try_get("username_changed_at")?,
+            None => return Ok(Err(ChangeUsernameError::NonexistentUser)),
+        };
+
+        if let Some(ready_in) = cooldown_remaining(changed_at, cooldown) {
+            return Ok(Err(ChangeUsernameError::Cooldown { ready_in }));
+        }
+
+        let args: &[&(dyn ToSql + Sync)] = &[&new_username, &Utc::now(), &user.0];
+        let res = transaction.execute(STMT, args).await;
 
         match res {
-            Ok(ret) => {
-                if ret == 1 {
-                    Ok(Ok(()))
-                } else {
-                    Ok(Err(ChangeUsernameError::NonexistentUser))
-                }
+            Ok(_) => {
+                transaction.commit().await?;
+                Ok(Ok(()))
             }
             Err(e) => {
                 if e.code() == Some(&SqlState::INTEGRITY_CONSTRAINT_VIOLATION)
@@ -206,29 +269,69 @@ impl Database {
         }
     }
 
-    /// Changes the display name of a user, returning whether the user existed at all.
+    /// Changes the display name of a user, unless they last changed it less than `cooldown` ago.
     pub async fn change_display_name(
         &self,
         user: UserId,
         new_display_name: String,
-    ) -> DbResult<Result<(), NonexistentUser>> {
+        cooldown: chrono::Duration,
+    ) -> DbResult<Result<(), ChangeDisplayNameError>> {
+        const QUERY: &str = "SELECT display_name_changed_at FROM users WHERE id = $1 FOR UPDATE";
+        const STMT: &str = "
+            UPDATE users
+                SET display_name = $1,
+                    display_name_changed_at = $2,
+                    profile_version = profile_version + 1
+                WHERE id = $3
+        ";
+
+        let mut conn = self.pool.connection().await?;
+        let transaction = conn.client.transaction().await?;
+
+        let changed_at = match transaction.query_opt(QUERY, &[&user.0]).await? {
+            Some(row) => row.try_get("display_name_changed_at")?,
+            None => return Ok(Err(ChangeDisplayNameError::NonexistentUser)),
+        };
+
+        if let Some(ready_in) = cooldown_remaining(changed_at, cooldown) {
+            return Ok(Err(ChangeDisplayNameError::Cooldown { ready_in }));
+        }
+
+        let args: &[&(dyn ToSql + Sync)] = &[&new_display_name, &Utc::now(), &user.0];
+        transaction.execute(STMT, args).await?;
+        transaction.commit().await?;
+
+        Ok(Ok(()))
+    }
+
+    /// Sets the avatar of a user, bumping their profile version so that clients refetch it.
+    /// Returns the previous avatar, if any, so that it can be deleted.
+    pub async fn set_avatar(
+        &self,
+        user: UserId,
+        avatar: AttachmentId,
+    ) -> DbResult<Result<Option<AttachmentId>, NonexistentUser>> {
+        const QUERY: &str = "SELECT avatar FROM users WHERE id = $1 FOR UPDATE";
         const STMT: &str = "
             UPDATE users
-                SET display_name = $1, profile_version = profile_version + 1
+                SET avatar = $1,
+                    avatar_version = avatar_version + 1,
+                    profile_version = profile_version + 1
                 WHERE id = $2
         ";
 
-        let conn = self.pool.connection().await?;
-        let stmt = conn.client.prepare(STMT).await?;
-        let res = conn
-            .client
-            .execute(&stmt, &[&new_display_name, &user.0])
-            .await?;
-        Ok(if res == 1 {
-            Ok(())
-        } else {
-            Err(NonexistentUser)
-        })
+        let mut conn = self.pool.connection().await?;
+        let transaction = conn.client.transaction().await?;
+
+        let old = match transaction.query_opt(QUERY, &[&user.0]).await? {
+            Some(row) => row.try_get::<&str, Option<_>>("avatar")?.map(AttachmentId),
+            None => return Ok(Err(NonexistentUser)),
+        };
+
+        transaction.execute(STMT, &[&avatar.0, &user.0]).await?;
+        transaction.commit().await?;
+
+        Ok(Ok(old))
     }
 
     /// Changes the password of a user, returning whether the user existed at all.
@@ -260,23 +363,33 @@ impl Database {
         })
     }
 
-    pub async fn set_banned(
+    /// Replaces an outdated password hash by a new hash of the same password. Unlike
+    /// `change_password`, this leaves the compromised flag alone. The hash is only replaced if the
+    /// stored one is still the outdated hash, so that a password changed in the meantime is not
+    /// overwritten.
+    pub async fn upgrade_password_hash(
         &self,
         user: UserId,
-        banned: bool,
-    ) -> DbResult<Result<(), NonexistentUser>> {
-        const STMT: &str = "UPDATE users SET banned = $1 WHERE id = $2";
+        old_password_hash: String,
+        new_password_hash: String,
+        hash_scheme_version: HashSchemeVersion,
+    ) -> DbResult<()> {
+        const STMT: &str = "
+            UPDATE users
+                SET password_hash = $1, hash_scheme_version = $2
+                WHERE id = $3 AND password_hash = $4";
 
         let conn = self.pool.connection().await?;
         let stmt = conn.client.prepare(STMT).await?;
-        let args: &[&(dyn ToSql + Sync)] = &[&banned, &user.0];
+        let args: &[&(dyn ToSql + Sync)] = &[
+            &new_password_hash,
+            &(hash_scheme_version as i16),
+            &user.0,
+            &old_password_hash,
+        ];
 
-        let res = conn.client.execute(&stmt, args).await?;
-        Ok(if res == 1 {
-            Ok(())
-        } else {
-            Err(NonexistentUser)
-        })
+        conn.client.execute(&stmt, args).await?;
+        Ok(())
     }
 
     pub async fn set_locked(
@@ -298,6 +411,61 @@ impl Database {
         })
     }
 
+    /// Records that the user was last seen now, when their last session disconnects
+    pub async fn set_last_seen(&self, user: UserId) -> DbResult<Result<(), NonexistentUser>> {
+        const STMT: &str = "UPDATE users SET last_seen = NOW() WHERE id = $1";
+
+        let conn = self.pool.connection().await?;
+        let res = conn.client.execute(STMT, &[&user.0]).await?;
+        Ok(if res == 1 {
+            Ok(())
+        } else {
+            Err(NonexistentUser)
+        })
+    }
+
+    pub async fn get_privacy_settings(&self, user: UserId) -> DbResult<Option<PrivacySettings>> {
+        const QUERY: &str = "
+            SELECT show_online_status, show_last_seen, allow_stranger_dms FROM users WHERE id = $1";
+
+        match self.query_opt(QUERY, &[&user.0]).await? {
+            Some(row) => Ok(Some(PrivacySettings {
+                show_online_status: row.try_get("show_online_status")?,
+                show_last_seen: row.try_get("show_last_seen")?,
+                allow_stranger_dms: row.try_get("allow_stranger_dms")?,
+            })),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn set_privacy_settings(
+        &self,
+        user: UserId,
+        privacy: PrivacySettings,
+    ) -> DbResult<Result<(), NonexistentUser>> {
+        const STMT: &str = "
+            UPDATE users
+                SET show_online_status = $1,
+                    show_last_seen = $2,
+                    allow_stranger_dms = $3
+                WHERE id = $4
+        ";
+
+        let conn = self.pool.connection().await?;
+        let args: &[&(dyn ToSql + Sync)] = &[
+            &privacy.show_online_status,
+            &privacy.show_last_seen,
+            &privacy.allow_stranger_dms,
+            &user.0,
+        ];
+        let res = conn.client.execute(STMT, args).await?;
+        Ok(if res == 1 {
+            Ok(())
+        } else {
+            Err(NonexistentUser)
+        })
+    }
+
     pub async fn search_user(
         &self,
         name: String,
@@ -314,6 +482,44 @@ impl Database {
         Ok(stream)
     }
 
+    /// Searches users by how closely their username or display name matches the query, best match
+    /// first. Banned and locked users are left out unless `include_restricted` is set.
+    pub async fn search_users(
+        &self,
+        query: &str,
+        limit: usize,
+        include_restricted: bool,
+    ) -> DbResult<Vec<UserSearchResult>> {
+        const QUERY: &str = "
+            SELECT id, username, display_name, profile_version, avatar FROM users
+                WHERE ($1 % username OR $1 % display_name)
+                    AND ($3 OR NOT (banned OR locked))
+                ORDER BY GREATEST(SIMILARITY($1, username), SIMILARITY($1, display_name)) DESC
+                LIMIT $2";
+
+        let limit = limit.min(super::message::SERVER_MAX) as i64;
+        let args: &[&(dyn ToSql + Sync)] = &[&query, &limit, &include_restricted];
+        let stream = self.query_stream(QUERY, args).await?;
+        let results = stream
+            .and_then(|row| async move {
+                let version: i32 = row.try_get("profile_version")?;
+                Ok(UserSearchResult {
+                    user: UserId(row.try_get("id")?),
+                    profile: Profile {
+                        version: ProfileVersion(version as u32),
+                        username: row.try_get("username")?,
+                        display_name: row.try_get("display_name")?,
+                        avatar: row.try_get::<&str, Option<_>>("avatar")?.map(AttachmentId),
+                        last_seen: None,
+                    },
+                })
+            })
+            .try_collect()
+            .await?;
+
+        Ok(results)
+    }
+
     pub async fn list_all_server_users(
         &self,
     ) -> DbResult<impl Stream<Item = DbResult<UserRecord>>> {
@@ -1,10 +1,12 @@
 use crate::database::{Database, DbResult, InvalidUser, MessageOrdinal};
 use futures::{Stream, TryStreamExt};
+use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::error::Error as ErrorTrait;
 use tokio_postgres::error::{DbError, Error, SqlState};
 use tokio_postgres::types::ToSql;
 use tokio_postgres::Row;
+use uuid::Uuid;
 use vertex::prelude::*;
 
 pub(super) const CREATE_USER_ROOM_STATES_TABLE: &str = r#"
@@ -17,6 +19,13 @@ pub(super) const CREATE_USER_ROOM_STATES_TABLE: &str = r#"
         UNIQUE(user_id, room)
     )"#;
 
+/// The newest message in a room and the last one the user has read in it
+#[derive(Debug, Copy, Clone, Default)]
+pub struct RoomHead {
+    pub newest_message: Option<MessageId>,
+    pub last_read: Option<MessageId>,
+}
+
 pub struct UserRoomState {
     pub room: RoomId,
     pub watch_level: WatchLevel,
@@ -27,11 +36,11 @@ impl TryFrom<Row> for UserRoomState {
     type Error = tokio_postgres::Error;
 
     fn try_from(row: Row) -> Result<UserRoomState, tokio_postgres::Error> {
-        let ws = row.try_get::<&str, i8>("watch_level")? as u8;
+        let ws = row.try_get::<&str, i8>("watch_level")?;
 
         Ok(UserRoomState {
             room: RoomId(row.try_get("room")?),
-            watch_level: WatchLevel::from(ws),
+            watch_level: watch_level_from_db(ws),
             unread: row
                 .try_get::<&str, Option<bool>>("unread")?
                 .unwrap_or(false),
@@ -39,27 +48,12 @@ impl TryFrom<Row> for UserRoomState {
     }
 }
 
-#[derive(Eq, PartialEq, Debug)]
-#[repr(u8)]
-pub enum WatchLevel {
-    Watching = 0,
-    NotWatching = 1,
-    MentionsOnly = 2,
-}
-
-impl Default for WatchLevel {
-    fn default() -> Self {
-        WatchLevel::NotWatching
-    }
-}
-
-impl From<u8> for WatchLevel {
-    fn from(val: u8) -> Self {
-        match val {
-            0 => WatchLevel::Watching,
-            1 => WatchLevel::NotWatching,
-            _ => WatchLevel::default(),
-        }
+fn watch_level_from_db(val: i8) -> WatchLevel {
+    match val {
+        0 => WatchLevel::All,
+        1 => WatchLevel::MentionsOnly,
+        2 => WatchLevel::Nothing,
+        _ => WatchLevel::default(),
     }
 }
 
@@ -145,6 +139,43 @@ impl Database {
         handle_sql_error(res)
     }
 
+    /// Marks every given room as read in one statement, like `set_room_read`. Rooms which the
+    /// user has no state for are skipped. Returns the last read message of each room updated.
+    pub async fn set_rooms_read(
+        &self,
+        rooms: &[RoomId],
+        user: UserId,
+    ) -> DbResult<Vec<(RoomId, Option<MessageId>)>> {
+        const STMT: &str = "
+            WITH updated AS (
+                UPDATE user_room_states
+                    SET last_read = COALESCE(
+                        (SELECT MAX(ord) FROM messages WHERE messages.room = user_room_states.room),
+                        0::BIGINT
+                    )
+                    WHERE user_id = $1 AND room = ANY($2)
+                    RETURNING room, last_read
+            )
+            SELECT updated.room, messages.id AS last_read_id
+                FROM updated LEFT JOIN messages ON messages.ord = updated.last_read
+            ";
+
+        let rooms: Vec<Uuid> = rooms.iter().map(|room| room.0).collect();
+        let args: &[&(dyn ToSql + Sync)] = &[&user.0, &rooms];
+        let stream = self.query_stream(STMT, args).await?;
+
+        let read = stream
+            .and_then(|row| async move {
+                let room = RoomId(row.try_get("room")?);
+                let last_read = row.try_get::<&str, Option<Uuid>>("last_read_id")?;
+                Ok((room, last_read.map(MessageId)))
+            })
+            .try_collect()
+            .await?;
+
+        Ok(read)
+    }
+
     pub async fn get_last_read(&self, user: UserId, room: RoomId) -> DbResult<Option<MessageId>> {
         const QUERY: &str =
             "SELECT last_read FROM user_room_states WHERE user_id = $1 AND room = $2";
@@ -169,9 +200,8 @@ impl Database {
         level: WatchLevel,
     ) -> DbResult<Result<(), SetUserRoomStateError>> {
         const STMT: &str = "
-            UPDATE user_room_states
-                SET watch_level = $3
-                WHERE user_id = $1 AND room = $2
+            INSERT INTO user_room_states (user_id, room, watch_level) VALUES ($1, $2, $3)
+                ON CONFLICT (user_id, room) DO UPDATE SET watch_level = $3
             ";
 
         let conn = self.pool.connection().await?;
@@ -209,6 +239,109 @@ impl Database {
 
         Ok(stream)
     }
+
+    /// Gets the heads of the given rooms for the user in one query. Rooms which do not exist are
+    /// left out.
+    pub async fn get_room_heads(
+        &self,
+        user: UserId,
+        rooms: &[RoomId],
+    ) -> DbResult<HashMap<RoomId, RoomHead>> {
+        const QUERY: &str = "
+            SELECT rooms.id AS room, newest.id AS newest_message, last_read.id AS last_read
+            FROM rooms
+            LEFT JOIN user_room_states
+                ON user_room_states.room = rooms.id AND user_room_states.user_id = $1
+            LEFT JOIN messages last_read ON last_read.ord = user_room_states.last_read
+            LEFT JOIN LATERAL (
+                SELECT id FROM messages WHERE messages.room = rooms.id
+                    ORDER BY ord DESC LIMIT 1
+            ) newest ON TRUE
+            WHERE rooms.id = ANY($2)
+        ";
+
+        let rooms: Vec<Uuid> = rooms.iter().map(|room| room.0).collect();
+        let stream = self.query_stream(QUERY, &[&user.0, &rooms]).await?;
+        let heads = stream
+            .and_then(|row| async move {
+                let head = RoomHead {
+                    newest_message: row
+                        .try_get::<&str, Option<_>>("newest_message")?
+                        .map(MessageId),
+                    last_read: row.try_get::<&str, Option<_>>("last_read")?.map(MessageId),
+                };
+                Ok((RoomId(row.try_get("room")?), head))
+            })
+            .try_collect()
+            .await?;
+
+        Ok(heads)
+    }
+
+    /// Counts the messages sent since the user last read each room in every community they are in.
+    /// Rooms with nothing unread are left out.
+    pub async fn get_unread_counts(&self, user: UserId) -> DbResult<HashMap<RoomId, u32>> {
+        const QUERY: &str = "
+            SELECT rooms.id AS room, COUNT(messages.ord) AS unread_count
+            FROM community_membership
+            INNER JOIN rooms ON rooms.community = community_membership.community
+            LEFT JOIN user_room_states
+                ON user_room_states.room = rooms.id AND user_room_states.user_id = $1
+            INNER JOIN messages
+                ON messages.room = rooms.id
+                AND messages.ord > COALESCE(user_room_states.last_read, 0)
+            WHERE community_membership.user_id = $1
+            GROUP BY rooms.id
+        ";
+
+        let stream = self.query_stream(QUERY, &[&user.0]).await?;
+        let counts = stream
+            .and_then(|row| async move {
+                let count = row.try_get::<&str, i64>("unread_count")?;
+                Ok((RoomId(row.try_get("room")?), count.min(u32::MAX as i64) as u32))
+            })
+            .try_collect()
+            .await?;
+
+        Ok(counts)
+    }
+
+    /// Gets the newest message and unread state of every room in every community the user is in
+    pub async fn get_room_sync_states(&self, user: UserId) -> DbResult<Vec<RoomSyncState>> {
+        const QUERY: &str = "
+            SELECT
+                rooms.community,
+                rooms.id AS room,
+                newest.id AS newest_message,
+                user_room_states.last_read IS DISTINCT FROM newest.ord AS unread
+            FROM community_membership
+            INNER JOIN rooms ON rooms.community = community_membership.community
+            LEFT JOIN user_room_states
+                ON user_room_states.room = rooms.id AND user_room_states.user_id = $1
+            LEFT JOIN LATERAL (
+                SELECT id, ord FROM messages WHERE messages.room = rooms.id
+                    ORDER BY ord DESC LIMIT 1
+            ) newest ON TRUE
+            WHERE community_membership.user_id = $1
+        ";
+
+        let stream = self.query_stream(QUERY, &[&user.0]).await?;
+        let states = stream
+            .and_then(|row| async move {
+                Ok(RoomSyncState {
+                    community: CommunityId(row.try_get("community")?),
+                    room: RoomId(row.try_get("room")?),
+                    newest_message: row
+                        .try_get::<&str, Option<_>>("newest_message")?
+                        .map(MessageId),
+                    unread: row.try_get("unread")?,
+                })
+            })
+            .try_collect()
+            .await?;
+
+        Ok(states)
+    }
 }
 
 fn handle_sql_error(res: Result<u64, Error>) -> DbResult<Result<(), SetUserRoomStateError>> {
@@ -0,0 +1,46 @@
+//! A health check for load balancers and monitoring, served as JSON. It is polled often, so it
+//! only reads counts which are already kept in memory and sends the database a trivial query.
+
+use std::convert::Infallible;
+use std::time::Duration;
+
+use http::StatusCode;
+use serde::Serialize;
+
+use crate::client::session;
+use crate::community::COMMUNITIES;
+use crate::Global;
+
+/// How long to wait for a database connection before reporting the database as unavailable
+const DATABASE_PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Serialize)]
+struct Health {
+    uptime_secs: u64,
+    active_sessions: usize,
+    communities: usize,
+    database_available: bool,
+}
+
+/// Reports on the server's health. Responds with 503 Service Unavailable if the database cannot
+/// be reached.
+pub async fn health(global: Global) -> Result<Box<dyn warp::Reply>, Infallible> {
+    let probe = tokio::time::timeout(DATABASE_PROBE_TIMEOUT, global.database.ping()).await;
+    let database_available = matches!(probe, Ok(Ok(())));
+
+    let health = Health {
+        uptime_secs: global.started_at.elapsed().as_secs(),
+        active_sessions: session::session_count(),
+        communities: COMMUNITIES.len(),
+        database_available,
+    };
+
+    let status = if database_available {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    let reply = warp::reply::with_status(warp::reply::json(&health), status);
+    Ok(Box::new(reply))
+}
@@ -4,14 +4,16 @@ use std::convert::Infallible;
 use std::num::NonZeroU32;
 use std::str::FromStr;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use arc_swap::ArcSwap;
 use futures::StreamExt;
-use governor::clock::DefaultClock;
+use governor::clock::{Clock, DefaultClock};
 use governor::state::keyed::DashMapStateStore;
-use governor::{Quota, RateLimiter};
+use governor::{NotUntil, Quota, RateLimiter};
 use log::{info, LevelFilter};
+use tokio::sync::oneshot;
+use uuid::Uuid;
 use warp::reply::Reply;
 use warp::Filter;
 use xtra::prelude::*;
@@ -25,64 +27,70 @@ use crate::client::Authenticator;
 use crate::community::{Community, CommunityActor};
 use crate::config::Config;
 use crate::database::{DbResult, MalformedInviteCode};
+use crate::shutdown::ServerHandle;
+use crate::signing::SigningKey;
 use clap::{App, Arg};
 use crate::client::session::WsMessage;
 use vertex::RATELIMIT_BURST_PER_MIN;
 
+mod attachments;
 mod auth;
 mod client;
 mod community;
 mod config;
 mod database;
+mod health;
+mod shutdown;
+mod signing;
 
 #[derive(Clone)]
 pub struct Global {
     pub database: Database,
     pub config: Arc<Config>,
-    pub ratelimiter: ArcSwap<RateLimiter<DeviceId, DashMapStateStore<DeviceId>, DefaultClock>>,
+    pub ratelimiter: ArcSwap<RateLimiters>,
+    pub signing_key: Arc<SigningKey>,
+    pub started_at: Instant,
 }
 
-/// Marker trait for `vertex_common` structs that are actor messages too
-trait VertexActorMessage: Send + 'static {
-    type Result: Send;
-}
-
-impl VertexActorMessage for ClientSentMessage {
-    type Result = MessageConfirmation;
-}
+pub type DeviceRateLimiter = RateLimiter<DeviceId, DashMapStateStore<DeviceId>, DefaultClock>;
 
-impl VertexActorMessage for Edit {
-    type Result = ();
+/// Per-device quotas. `global` applies to every websocket message, and the others apply to
+/// particular requests on top of it.
+pub struct RateLimiters {
+    pub global: DeviceRateLimiter,
+    pub send_message: DeviceRateLimiter,
+    pub create_community: DeviceRateLimiter,
+    pub create_invite: DeviceRateLimiter,
 }
 
-struct IdentifiedMessage<T: VertexActorMessage> {
-    user: UserId,
-    device: DeviceId,
-    message: T,
+impl RateLimiters {
+    fn new(config: &Config) -> RateLimiters {
+        RateLimiters {
+            global: new_ratelimiter(RATELIMIT_BURST_PER_MIN),
+            send_message: new_ratelimiter(config.send_message_burst_per_min),
+            create_community: new_ratelimiter(config.create_community_burst_per_min),
+            create_invite: new_ratelimiter(config.create_invite_burst_per_min),
+        }
+    }
 }
 
-impl<T> xtra::Message for IdentifiedMessage<T>
-where
-    T: VertexActorMessage,
-    T::Result: 'static,
-{
-    type Result = Result<T::Result, Error>;
+fn new_ratelimiter(burst_per_min: u32) -> DeviceRateLimiter {
+    RateLimiter::dashmap(Quota::per_minute(NonZeroU32::new(burst_per_min).unwrap()))
 }
 
-fn new_ratelimiter() -> RateLimiter<DeviceId, DashMapStateStore<DeviceId>, DefaultClock> {
-    RateLimiter::dashmap(Quota::per_minute(NonZeroU32::new(RATELIMIT_BURST_PER_MIN).unwrap()))
+/// How long until a rate limited device may try again, measured by the clock of the rate limiters
+fn ready_in(not_until: NotUntil<'_, <DefaultClock as Clock>::Instant>) -> Duration {
+    not_until.wait_time_from(DefaultClock::default().now())
 }
 
-async fn refresh_ratelimiter(
-    rl: ArcSwap<RateLimiter<DeviceId, DashMapStateStore<DeviceId>, DefaultClock>>,
-) {
+async fn refresh_ratelimiter(rl: ArcSwap<RateLimiters>, config: Arc<Config>) {
     use tokio::time::Instant;
     let duration = Duration::from_secs(60 * 60); // 1/hr
     let mut timer = tokio::time::interval_at(Instant::now() + duration, duration);
 
     loop {
         timer.tick().await;
-        rl.store(Arc::new(new_ratelimiter()));
+        rl.store(Arc::new(RateLimiters::new(&config)));
     }
 }
 
@@ -96,7 +104,8 @@ fn handle_disconnected(actor_name: &'static str) -> impl Fn(Disconnected) -> Err
     }
 }
 
-async fn load_communities(db: Database) {
+async fn load_communities(db: Database, config: &Config, signing_key: Arc<SigningKey>) {
+    let room_quota = community::room_message_quota(config);
     let stream = db
         .get_all_communities()
         .await
@@ -105,7 +114,12 @@ async fn load_communities(db: Database) {
 
     while let Some(res) = stream.next().await {
         let community_record = res.expect("Error loading community");
-        CommunityActor::load_and_spawn(community_record, db.clone())
+        CommunityActor::load_and_spawn(
+            community_record,
+            db.clone(),
+            room_quota,
+            signing_key.clone(),
+        )
             .await
             .expect("Error loading community!");
     }
@@ -135,16 +149,33 @@ async fn main() {
         )
         .get_matches();
 
+    let server = start(args).await;
+    shutdown::wait_for_signal().await;
+    server.shutdown().await;
+}
+
+/// Set up everything and start serving, returning a handle that can shut the server down
+async fn start(args: clap::ArgMatches<'static>) -> ServerHandle {
     println!("Vertex server starting...");
 
     let config = config::load_config();
-    vertex::setup_logging(
+    vertex::setup_logging_in(
         "vertex_server",
+        &config::DIRS.data.join("logs"),
         LevelFilter::from_str(&config.log_level).unwrap(),
     );
+    info!(
+        "Using config directory {} and data directory {}",
+        config::DIRS.config.to_string_lossy(),
+        config::DIRS.data.to_string_lossy(),
+    );
 
     let (cert_path, key_path) = config::ssl_config();
-    let database = Database::new().await.expect("Error in database setup");
+    if config.https {
+        config::require_path(&cert_path, "TLS certificate");
+        config::require_path(&key_path, "TLS private key");
+    }
+    let database = Database::new(&config).await.expect("Error in database setup");
     tokio::spawn(database.clone().sweep_tokens_loop(
         config.token_expiry_days,
         Duration::from_secs(config.tokens_sweep_interval_secs),
@@ -154,19 +185,38 @@ async fn main() {
             .clone()
             .sweep_invite_codes_loop(Duration::from_secs(config.invite_codes_sweep_interval_secs)),
     );
+    tokio::spawn(
+        database
+            .clone()
+            .sweep_bans_loop(Duration::from_secs(config.bans_sweep_interval_secs)),
+    );
 
     promote_and_demote(args, &database).await;
 
-    load_communities(database.clone()).await;
+    let signing_key = Arc::new(SigningKey::load_or_generate(&config.signing_key_path));
+    load_communities(database.clone(), &config, signing_key.clone()).await;
+
+    std::fs::create_dir_all(&config.attachments_dir).unwrap_or_else(|_| {
+        panic!(
+            "Error creating attachments directory ({})",
+            config.attachments_dir.to_string_lossy(),
+        )
+    });
 
     let config = Arc::new(config);
     let global = Global {
         database,
         config: config.clone(),
-        ratelimiter: ArcSwap::from_pointee(new_ratelimiter()),
+        ratelimiter: ArcSwap::from_pointee(RateLimiters::new(&config)),
+        signing_key,
+        started_at: Instant::now(),
     };
 
-    tokio::spawn(refresh_ratelimiter(global.ratelimiter.clone()));
+    tokio::spawn(refresh_ratelimiter(global.ratelimiter.clone(), config.clone()));
+    tokio::spawn(global.database.clone().sweep_messages_loop(
+        config.clone(),
+        Duration::from_secs(config.messages_sweep_interval_secs),
+    ));
 
     let global = warp::any().map(move || global.clone());
 
@@ -225,31 +275,105 @@ async fn main() {
             reply_protobuf(self::change_password(global, bytes).await)
         });
 
+    let account_rules = warp::path("account_rules")
+        .and(warp::get())
+        .and(global.clone())
+        .and_then(|global: Global| async move {
+            reply_protobuf(AuthResponse::Ok(AuthOk::AccountRules(global.config.account_rules())))
+        });
+
     let invite = warp::path!("invite" / String)
-        //  .and(warp::header::<String>("host")) // https://github.com/seanmonstar/warp/issues/432
         .and(global.clone())
         .and_then(|invite, global| self::invite_reply(global, invite));
 
+    let upload = warp::path("upload")
+        .and(global.clone())
+        .and(warp::post())
+        .and(warp::query())
+        .and(warp::header::optional::<String>("content-type"))
+        .and(warp::body::content_length_limit(config.max_upload_size))
+        .and(warp::body::bytes())
+        .and_then(|global, login, content_type, bytes| async move {
+            reply_protobuf(attachments::upload(global, login, content_type, bytes).await)
+        });
+
+    let avatar = warp::path("avatar")
+        .and(global.clone())
+        .and(warp::post())
+        .and(warp::query())
+        .and(warp::header::optional::<String>("content-type"))
+        .and(warp::body::content_length_limit(config.max_upload_size))
+        .and(warp::body::bytes())
+        .and_then(|global, login, content_type, bytes| async move {
+            reply_protobuf(attachments::upload_avatar(global, login, content_type, bytes).await)
+        });
+
+    let attachment = warp::path!("attachment" / Uuid)
+        .and(global.clone())
+        .and_then(|id, global| attachments::download(global, AttachmentId(id)));
+
+    let health = warp::path!("health")
+        .and(warp::get())
+        .and(global.clone())
+        .and_then(health::health);
+
+    let signing_key = warp::path!("signing_key")
+        .and(warp::get())
+        .and(global)
+        .and_then(signing::public_key);
+
     let token = warp::path("token").and(create_token.or(revoke_token).or(refresh_token));
-    let auth = authenticate.or(register.or(token.or(change_password)));
-    let client = warp::path("client").and(auth);
-    let routes = invite.or(client);
-    let routes = warp::path("vertex").and(routes);
+    let auth = authenticate.or(register.or(token.or(change_password)).or(account_rules));
+    let client = warp::path("client").and(auth.or(upload).or(avatar));
+    let api = attachment.or(health).or(signing_key).or(client);
+
+    // The invite page is plain HTML for browsers to open, so it is not subject to CORS
+    let api = match cors(&config) {
+        Some(cors) => api.with(cors).map(|reply| Box::new(reply) as Box<dyn Reply>).boxed(),
+        None => api.map(|reply| Box::new(reply) as Box<dyn Reply>).boxed(),
+    };
+    let routes = warp::path("vertex").and(invite.or(api));
 
     info!("Vertex server starting on addr {}", config.ip);
 
-    if config.https {
-        warp::serve(routes)
+    let (stop_accepting, stopped) = oneshot::channel();
+    let stopped = async {
+        let _ = stopped.await;
+    };
+
+    let server = if config.https {
+        let (_, server) = warp::serve(routes)
             .tls()
             .cert_path(cert_path)
             .key_path(key_path)
-            .run(config.ip)
-            .await;
+            .bind_with_graceful_shutdown(config.ip, stopped);
+        tokio::spawn(server)
     } else {
-        warp::serve(routes).run(config.ip).await;
+        let (_, server) = warp::serve(routes).bind_with_graceful_shutdown(config.ip, stopped);
+        tokio::spawn(server)
+    };
+
+    ServerHandle {
+        stop_accepting,
+        server,
+        drain_timeout: Duration::from_secs(config.shutdown_drain_timeout_secs),
     }
 }
 
+/// CORS for the origins allowed in the config, or `None` to leave the routes same-origin only
+fn cors(config: &Config) -> Option<warp::cors::Builder> {
+    if config.cors_allowed_origins.is_empty() {
+        return None;
+    }
+
+    let cors = warp::cors()
+        .allow_origins(config.cors_allowed_origins.iter().map(String::as_str))
+        .allow_methods(config.cors_allowed_methods.iter().map(String::as_str))
+        .allow_headers(config.cors_allowed_headers.iter().map(String::as_str));
+
+    Some(cors)
+}
+
 async fn promote_and_demote(args: clap::ArgMatches<'_>, database: &Database) {
     for name in args.values_of("add-admin").into_iter().flatten() {
         let id = database
@@ -265,6 +389,15 @@ async fn promote_and_demote(args: clap::ArgMatches<'_>, database: &Database) {
             .unwrap_or_else(|e| panic!("Error promoting user {} to admin: {:?}", name, e))
             .unwrap_or_else(|e| panic!("Error promoting user {} to admin: {:?}", name, e));
 
+        let action = AuditAction::Promote {
+            user: id,
+            permissions: AdminPermissionFlags::ALL,
+        };
+        database
+            .record_audit_event(None, &action)
+            .await
+            .expect("Error recording promotion in audit log");
+
         info!(
             "User {} successfully promoted to admin with all permissions!",
             name
@@ -285,16 +418,23 @@ async fn promote_and_demote(args: clap::ArgMatches<'_>, database: &Database) {
             .unwrap_or_else(|e| panic!("Error demoting user {}: {:?}", name, e))
             .unwrap_or_else(|e| panic!("Error demoting user {}: {:?}", name, e));
 
+        database
+            .record_audit_event(None, &AuditAction::Demote(id))
+            .await
+            .expect("Error recording demotion in audit log");
+
         info!("User {} successfully demoted!", name);
     }
 }
 
 #[inline]
+#[allow(clippy::unnecessary_wraps)] // Returned directly from warp handlers
 fn reply_err(err: AuthError) -> Result<Box<dyn warp::Reply>, Infallible> {
     Ok(Box::new(AuthResponse::Err(err).into(): Vec<u8>))
 }
 
 #[inline]
+#[allow(clippy::unnecessary_wraps)]
 fn reply_protobuf(res: AuthResponse) -> Result<Box<dyn warp::Reply>, Infallible> {
     Ok(Box::new(res.into(): Vec<u8>))
 }
@@ -304,10 +444,16 @@ async fn login(
     ws: warp::ws::Ws,
     login: Login,
 ) -> Result<impl warp::Reply, AuthError> {
+    match login.protocol {
+        Some(version) if version.is_compatible_with(PROTOCOL_VERSION) => {}
+        _ => return Err(AuthError::IncompatibleProtocol { server: PROTOCOL_VERSION }),
+    }
+
     let authenticator = Authenticator {
         global: global.clone(),
     };
 
+    let compression = login.compression;
     let details = authenticator.login(login.device, login.token).await?;
     let (user, device, perms, hsv) = details;
 
@@ -316,7 +462,8 @@ async fn login(
             let upgrade = ws.on_upgrade(move |websocket| {
                 let (sink, stream) = websocket.split();
 
-                let session = ActiveSession::new(sink, global, user, device, perms);
+                let session =
+                    ActiveSession::new(sink, global, user, device, perms, compression);
                 session.clone().into_address().attach_stream(stream.map(WsMessage));
 
                 // if the session fails to spawn, that means it has since been removed. we can ignore the error.
@@ -401,7 +548,6 @@ async fn change_password(global: Global, bytes: bytes::Bytes) -> AuthResponse {
 
 async fn invite_reply(
     global: Global,
-    //  hostname: String, // https://github.com/seanmonstar/warp/issues/432
     invite_code: String,
 ) -> Result<Box<dyn Reply>, Infallible> {
     let res = invite(global, invite_code).await;
@@ -418,9 +564,27 @@ async fn invite_reply(
     }
 }
 
+/// Escapes text so that it can be placed inside HTML elements and attribute values
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Builds the invite landing page, which sends browsers on to the `vertex://` link for the invite.
+/// The link is made from the configured public host. Without one, the page falls back to making it
+/// from its own URL with JS.
 async fn invite(
     global: Global,
-    //  hostname: String, // https://github.com/seanmonstar/warp/issues/432
     invite_code: String,
 ) -> DbResult<Result<String, MalformedInviteCode>> {
     let code = InviteCode(invite_code.clone());
@@ -433,12 +597,26 @@ async fn invite(
         None => return Ok(Err(MalformedInviteCode)),
     };
 
+    let urls = global.config.public_host.as_ref().map(|host| {
+        let scheme = if global.config.https { "https" } else { "http" };
+        let path = format!("{}/vertex/invite/{}", host, invite_code);
+        let web_url = escape_html(&format!("{}://{}", scheme, path));
+        let vertex_url = escape_html(&format!("vertex://{}", path));
+
+        format!(
+            r#"<meta property="og:url" content="{}">
+                <meta property="vertex:invite_url" content="{}">"#,
+            web_url, vertex_url,
+        )
+    });
+
     let html = format!(
         r#"
         <!DOCTYPE html>
         <html>
             <head>
                 <meta charset="UTF-8">
+                {urls}
                 <meta property="vertex:invite_code" content="{invite_code}">
                 <meta property="vertex:invite_name" content="{community}">
                 <meta property="vertex:invite_description" content="{description}">
@@ -448,19 +626,22 @@ async fn invite(
             <body>
                 <script type="text/javascript">
                     // Redirect to vertex://...
-                    const url = new URL(location);
-                    url.protocol = "vertex:";
-                    alert(url);
-                    location.replace(url);
+                    const link = document.querySelector('meta[property="vertex:invite_url"]');
+                    if (link) {{
+                        location.replace(link.content);
+                    }} else {{
+                        const url = new URL(location);
+                        url.protocol = "vertex:";
+                        location.replace(url);
+                    }}
                 </script>
             </body>
         </html>
         "#,
-        //        hostname = hostname, // TODO https://github.com/seanmonstar/warp/issues/432
-        // We just use JS as a workaround
-        invite_code = invite_code,
-        community = community_record.name,
-        description = Community::desc_or_default(&community_record.description),
+        urls = urls.unwrap_or_default(),
+        invite_code = escape_html(&invite_code),
+        community = escape_html(&community_record.name),
+        description = escape_html(&Community::desc_or_default(&community_record.description)),
     );
 
     Ok(Ok(html))
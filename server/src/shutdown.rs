@@ -0,0 +1,64 @@
+//! Shutting the server down cleanly: stop accepting connections, tell connected clients, and give
+//! the communities a bounded amount of time to finish what they are doing.
+
+use std::time::Duration;
+
+use futures::future;
+use log::{info, warn};
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use xtra::prelude::*;
+
+use vertex::prelude::*;
+
+use crate::client::session;
+use crate::community::{Drain, COMMUNITIES};
+
+/// A handle to the running server, used to shut it down
+pub struct ServerHandle {
+    pub(crate) stop_accepting: oneshot::Sender<()>,
+    pub(crate) server: JoinHandle<()>,
+    pub(crate) drain_timeout: Duration,
+}
+
+impl ServerHandle {
+    pub async fn shutdown(self) {
+        info!("Vertex server shutting down...");
+
+        let _ = self.stop_accepting.send(());
+        session::send_to_all_sessions(ServerMessage::Event(ServerEvent::ServerShuttingDown));
+
+        let communities: Vec<_> = COMMUNITIES.iter().map(|c| c.actor.clone()).collect();
+        let server = self.server;
+        let drain = async move {
+            // Disconnected communities have nothing left to finish
+            future::join_all(communities.iter().map(|actor| actor.send(Drain))).await;
+            let _ = server.await;
+        };
+
+        match tokio::time::timeout(self.drain_timeout, drain).await {
+            Ok(()) => info!("Vertex server shut down"),
+            Err(_) => warn!("Timed out waiting for the server to finish up; exiting anyway"),
+        }
+    }
+}
+
+/// Wait until the process is asked to stop, through SIGINT (ctrl-c) or SIGTERM
+pub async fn wait_for_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate = signal(SignalKind::terminate()).expect("Error listening for SIGTERM");
+
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {},
+            _ = terminate.recv() => {},
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
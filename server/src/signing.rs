@@ -0,0 +1,58 @@
+//! The server's Ed25519 key, used to sign messages in communities which ask for it. The key is
+//! generated the first time the server starts and kept in `signing_key_path`, so that signatures
+//! stay valid across restarts. Its public half is served at `/vertex/signing_key`.
+
+use std::convert::Infallible;
+use std::fs;
+use std::path::Path;
+
+use http::header;
+use log::info;
+use ring::rand::SystemRandom;
+use ring::signature::{Ed25519KeyPair, KeyPair};
+
+use crate::Global;
+
+pub struct SigningKey {
+    pair: Ed25519KeyPair,
+}
+
+impl SigningKey {
+    /// Loads the key from the given PKCS#8 file, generating and saving a new one if there is none
+    pub fn load_or_generate(path: &Path) -> SigningKey {
+        if !path.exists() {
+            info!("Generating message signing key at {}", path.to_string_lossy());
+
+            let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+                .expect("Error generating message signing key");
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir).expect("Error creating message signing key directory");
+            }
+            fs::write(path, pkcs8.as_ref()).expect("Error saving message signing key");
+        }
+
+        let pkcs8 = fs::read(path).expect("Error reading message signing key");
+        let pair = Ed25519KeyPair::from_pkcs8(&pkcs8).expect("Invalid message signing key");
+
+        SigningKey { pair }
+    }
+
+    pub fn sign(&self, payload: &[u8]) -> Vec<u8> {
+        self.pair.sign(payload).as_ref().to_vec()
+    }
+
+    /// The 32 byte Ed25519 public key which signatures can be checked against
+    pub fn public_key(&self) -> &[u8] {
+        self.pair.public_key().as_ref()
+    }
+}
+
+/// Serves the public key as raw bytes
+pub async fn public_key(global: Global) -> Result<Box<dyn warp::Reply>, Infallible> {
+    let response = http::Response::builder()
+        .header(header::CONTENT_TYPE, "application/octet-stream")
+        .body(global.signing_key.public_key().to_vec())
+        .unwrap();
+
+    Ok(Box::new(response))
+}